@@ -0,0 +1,51 @@
+use std::env;
+
+use anyhow::{Context, Result};
+
+/// Server settings, read from the environment (and `.env`) at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address clients reach the server at, used for the links in TileJSON
+    /// documents and OGC capabilities rather than the request's own `Host`
+    pub public_url: String,
+    /// Directory of the on-disk tile cache
+    pub tile_cache_dir: String,
+    pub google_api_key: String,
+    /// Directory of SRTM `.hgt` tiles; elevation profiles, grade-aware
+    /// routing and terrain tiles are off without one
+    pub dem_directory: Option<String>,
+    /// Directory of map style documents; it must hold the default style
+    pub styles_dir: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            public_url: "http://localhost:8080".to_string(),
+            tile_cache_dir: "./cache/tiles".to_string(),
+            google_api_key: String::new(),
+            dem_directory: None,
+            styles_dir: "./assets/styles".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// `PUBLIC_URL`, `TILE_CACHE_DIR`, `GOOGLE_API_KEY`, `DEM_DIRECTORY` and
+    /// `STYLES_DIR`, each falling back to its default when unset
+    pub fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+
+        let public_url = env::var("PUBLIC_URL").unwrap_or(defaults.public_url);
+        let public_url = public_url.trim_end_matches('/').to_string();
+        reqwest::Url::parse(&public_url).with_context(|| format!("PUBLIC_URL is not a valid URL: {}", public_url))?;
+
+        Ok(Self {
+            public_url,
+            tile_cache_dir: env::var("TILE_CACHE_DIR").unwrap_or(defaults.tile_cache_dir),
+            google_api_key: env::var("GOOGLE_API_KEY").unwrap_or(defaults.google_api_key),
+            dem_directory: env::var("DEM_DIRECTORY").ok().filter(|directory| !directory.is_empty()),
+            styles_dir: env::var("STYLES_DIR").unwrap_or(defaults.styles_dir),
+        })
+    }
+}
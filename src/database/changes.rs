@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use tokio::time::{timeout, Duration};
+use tracing::warn;
+
+use super::Database;
+use crate::utils::tile_utils::LatLng;
+
+/// Channel the `locations` and `points_of_interest` triggers notify on
+pub const MAP_DATA_CHANNEL: &str = "map_data_changes";
+
+/// Notifications arriving within this window of each other are delivered as
+/// one batch, so a bulk import is handled once rather than per row
+const BATCH_WINDOW: Duration = Duration::from_millis(500);
+
+/// One row changed in a map data table. `points` holds the row's position
+/// before and after the change, whichever exist.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MapDataChange {
+    pub table: String,
+    pub operation: String,
+    pub points: Vec<LatLng>,
+}
+
+pub struct MapDataChanges {
+    listener: PgListener,
+}
+
+impl MapDataChanges {
+    /// Wait for the next change, then collect the ones that follow it within
+    /// `BATCH_WINDOW`. Malformed payloads are logged and skipped.
+    pub async fn next_batch(&mut self) -> Result<Vec<MapDataChange>> {
+        let mut batch = Vec::new();
+        let first = self.listener.recv().await.context("Lost map data change listener")?;
+        push_change(&mut batch, first.payload());
+
+        while let Ok(next) = timeout(BATCH_WINDOW, self.listener.recv()).await {
+            let notification = next.context("Lost map data change listener")?;
+            push_change(&mut batch, notification.payload());
+        }
+
+        Ok(batch)
+    }
+}
+
+fn push_change(batch: &mut Vec<MapDataChange>, payload: &str) {
+    match serde_json::from_str(payload) {
+        Ok(change) => batch.push(change),
+        Err(e) => warn!("Ignoring malformed map data change {:?}: {}", payload, e),
+    }
+}
+
+impl Database {
+    /// Subscribe to inserts, updates and deletes on `locations` and
+    /// `points_of_interest`, including those made by other processes such as
+    /// the data importer
+    pub async fn listen_map_changes(&self) -> Result<MapDataChanges> {
+        let mut listener = PgListener::connect_with(&self.pool)
+            .await
+            .context("Failed to connect map data change listener")?;
+        listener
+            .listen(MAP_DATA_CHANNEL)
+            .await
+            .context("Failed to listen for map data changes")?;
+
+        Ok(MapDataChanges { listener })
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sqlx::Row;
+use uuid::Uuid;
+
+use super::Database;
+use crate::utils::gpx::{GpxDocument, GpxPoint, GpxRoute, GpxTrack};
+use crate::utils::tile_utils::LatLng;
+use crate::utils::{geohash, polyline};
+
+const IMPORTED_PLACE_TYPE: &str = "waypoint";
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GpxImportSummary {
+    pub location_ids: Vec<Uuid>,
+    pub route_ids: Vec<Uuid>,
+}
+
+impl Database {
+    /// A stored route as GPX: the full path as a track and the maneuvers from
+    /// `route_steps` as a route, so devices can show both the line and the turns.
+    /// Routes a user created are only exported for `viewer_id` being that user;
+    /// `None` for those and for unknown routes alike.
+    pub async fn route_as_gpx(&self, route_id: Uuid, viewer_id: Option<Uuid>) -> Result<Option<GpxDocument>> {
+        let Some(route) = sqlx::query("SELECT name FROM routes WHERE id = $1 AND (created_by IS NULL OR created_by = $2)")
+            .bind(route_id)
+            .bind(viewer_id)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to fetch route")?
+        else {
+            return Ok(None);
+        };
+
+        let name: Option<String> = route.get("name");
+        let path = self.get_route_geometry(route_id).await?.unwrap_or_default();
+
+        let steps = sqlx::query(
+            r#"
+            SELECT instruction, maneuver, start_latitude, start_longitude, end_latitude, end_longitude
+            FROM route_steps
+            WHERE route_id = $1
+            ORDER BY step_order
+            "#,
+        )
+        .bind(route_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch route steps")?;
+
+        let mut route_points: Vec<GpxPoint> = steps
+            .iter()
+            .map(|step| {
+                let mut point = GpxPoint::new(step.get("start_latitude"), step.get("start_longitude"));
+                point.name = step.get("maneuver");
+                point.description = Some(step.get("instruction"));
+                point
+            })
+            .collect();
+        if let Some(last) = steps.last() {
+            let mut arrival = GpxPoint::new(last.get("end_latitude"), last.get("end_longitude"));
+            arrival.name = Some("arrive".to_string());
+            route_points.push(arrival);
+        }
+
+        let mut document = GpxDocument {
+            name: name.clone(),
+            ..Default::default()
+        };
+        if !path.is_empty() {
+            document.tracks.push(GpxTrack {
+                name: name.clone(),
+                description: None,
+                segments: vec![path.iter().map(|&(lat, lng)| GpxPoint::new(lat, lng)).collect()],
+            });
+        }
+        if !route_points.is_empty() {
+            document.routes.push(GpxRoute {
+                name,
+                description: None,
+                points: route_points,
+            });
+        }
+
+        Ok(Some(document))
+    }
+
+    pub async fn favorites_as_gpx(&self, user_id: Uuid) -> Result<GpxDocument> {
+        let rows = sqlx::query(
+            r#"
+            SELECT COALESCE(f.name, l.name) AS name, l.address, l.latitude, l.longitude
+            FROM favorites f
+            JOIN locations l ON l.id = f.location_id
+            WHERE f.user_id = $1
+            ORDER BY f.created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch favorites")?;
+
+        let waypoints = rows
+            .iter()
+            .map(|row| {
+                let mut point = GpxPoint::new(row.get("latitude"), row.get("longitude"));
+                point.name = row.get("name");
+                point.description = row.get("address");
+                point
+            })
+            .collect();
+
+        Ok(GpxDocument {
+            name: Some("Favorites".to_string()),
+            waypoints,
+            ..Default::default()
+        })
+    }
+
+    /// Store GPX waypoints as locations and GPX routes and track segments as
+    /// routes, all owned by `user_id`. Everything is written in one
+    /// transaction, so a failure imports nothing.
+    pub async fn import_gpx(&self, document: &GpxDocument, user_id: Uuid) -> Result<GpxImportSummary> {
+        let mut tx = self.pool.begin().await.context("Failed to start import transaction")?;
+        let mut summary = GpxImportSummary::default();
+        let mut paths = Vec::new();
+
+        for waypoint in &document.waypoints {
+            let name = waypoint.name.clone().unwrap_or_else(|| "Waypoint".to_string());
+            let id = insert_location(&mut tx, &name, waypoint, user_id).await?;
+            summary.location_ids.push(id);
+        }
+
+        for GpxRoute { name, points, .. } in document.lines() {
+            let (Some(first), Some(last)) = (points.first(), points.last()) else {
+                continue;
+            };
+            if points.len() < 2 {
+                continue;
+            }
+
+            let start_id = insert_location(&mut tx, "Start", first, user_id).await?;
+            let end_id = insert_location(&mut tx, "End", last, user_id).await?;
+
+            let path: Vec<(f64, f64)> = points.iter().map(|p| (p.lat, p.lon)).collect();
+            let distance: f64 = points
+                .windows(2)
+                .map(|w| LatLng::new(w[0].lat, w[0].lon).distance_to(&LatLng::new(w[1].lat, w[1].lon)))
+                .sum();
+            let duration = match (first.time, last.time) {
+                (Some(start), Some(end)) if end > start => (end - start).num_seconds(),
+                _ => 0,
+            };
+
+            let row = sqlx::query(
+                r#"
+                INSERT INTO routes (name, start_location_id, end_location_id, distance_meters,
+                                    duration_seconds, geometry, created_by)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                RETURNING id
+                "#,
+            )
+            .bind(name)
+            .bind(start_id)
+            .bind(end_id)
+            .bind(distance.round() as i64)
+            .bind(duration)
+            .bind(polyline::encode(&path, polyline::PRECISION_5))
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to insert imported route")?;
+
+            let route_id: Uuid = row.get("id");
+            summary.route_ids.push(route_id);
+            paths.push((route_id, path));
+        }
+
+        tx.commit().await.context("Failed to commit import")?;
+
+        for (route_id, path) in paths {
+            self.set_route_path(route_id, &path).await?;
+        }
+
+        Ok(summary)
+    }
+}
+
+async fn insert_location(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    name: &str,
+    point: &GpxPoint,
+    user_id: Uuid,
+) -> Result<Uuid> {
+    let hash = geohash::encode(point.lat, point.lon, geohash::MAX_PRECISION)
+        .context("Invalid waypoint coordinates")?;
+
+    let row = sqlx::query(
+        r#"
+        INSERT INTO locations (name, description, latitude, longitude, place_type, geohash, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#,
+    )
+    .bind(name)
+    .bind(&point.description)
+    .bind(point.lat)
+    .bind(point.lon)
+    .bind(IMPORTED_PLACE_TYPE)
+    .bind(hash)
+    .bind(user_id)
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to insert imported location")?;
+
+    Ok(row.get("id"))
+}
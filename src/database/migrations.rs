@@ -3,6 +3,9 @@ use sqlx::{Pool, Postgres, Row};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+/// The PostGIS migration that creates the extension itself
+const POSTGIS_EXTENSION_VERSION: i32 = 200;
+
 pub struct Migration {
     pub version: i32,
     pub name: String,
@@ -13,6 +16,7 @@ pub struct Migration {
 pub struct MigrationRunner {
     pool: Pool<Postgres>,
     migrations: Vec<Migration>,
+    postgis_migrations: Vec<Migration>,
 }
 
 impl MigrationRunner {
@@ -20,11 +24,255 @@ impl MigrationRunner {
         let mut runner = Self {
             pool,
             migrations: Vec::new(),
+            postgis_migrations: Vec::new(),
         };
         runner.register_migrations();
+        runner.register_geohash_migrations();
+        runner.register_change_notification_migrations();
+        runner.register_account_migrations();
+        runner.register_postgis_migrations();
         runner
     }
 
+    fn register_geohash_migrations(&mut self) {
+        self.migrations.push(Migration {
+            version: 100,
+            name: "add_locations_geohash".to_string(),
+            up_sql: "ALTER TABLE locations ADD COLUMN IF NOT EXISTS geohash VARCHAR(12)".to_string(),
+            down_sql: "ALTER TABLE locations DROP COLUMN IF EXISTS geohash".to_string(),
+        });
+        self.migrations.push(Migration {
+            version: 101,
+            name: "index_locations_geohash".to_string(),
+            up_sql: r#"CREATE INDEX IF NOT EXISTS idx_locations_geohash ON locations (geohash COLLATE "C")"#.to_string(),
+            down_sql: "DROP INDEX IF EXISTS idx_locations_geohash".to_string(),
+        });
+    }
+
+    /// Triggers that announce edits to the map data tables on the
+    /// `map_data_changes` channel, with the positions they affect, so cached
+    /// tiles can be invalidated
+    fn register_change_notification_migrations(&mut self) {
+        let migrations = [
+            (
+                102,
+                "create_notify_location_change",
+                r#"CREATE OR REPLACE FUNCTION notify_location_change() RETURNS trigger AS $$
+                   DECLARE
+                       points jsonb := '[]'::jsonb;
+                   BEGIN
+                       IF TG_OP IN ('UPDATE', 'DELETE') THEN
+                           points := points || jsonb_build_object('lat', OLD.latitude, 'lng', OLD.longitude);
+                       END IF;
+                       IF TG_OP IN ('INSERT', 'UPDATE') THEN
+                           points := points || jsonb_build_object('lat', NEW.latitude, 'lng', NEW.longitude);
+                       END IF;
+                       PERFORM pg_notify('map_data_changes', jsonb_build_object(
+                           'table', TG_TABLE_NAME, 'operation', TG_OP, 'points', points)::text);
+                       RETURN NULL;
+                   END;
+                   $$ LANGUAGE plpgsql"#,
+                "DROP FUNCTION IF EXISTS notify_location_change()",
+            ),
+            (
+                103,
+                "create_locations_change_trigger",
+                r#"CREATE TRIGGER locations_map_data_change
+                   AFTER INSERT OR UPDATE OR DELETE ON locations
+                   FOR EACH ROW EXECUTE FUNCTION notify_location_change()"#,
+                "DROP TRIGGER IF EXISTS locations_map_data_change ON locations",
+            ),
+            (
+                104,
+                "create_notify_poi_change",
+                r#"CREATE OR REPLACE FUNCTION notify_poi_change() RETURNS trigger AS $$
+                   DECLARE
+                       location_ids uuid[];
+                       points jsonb;
+                   BEGIN
+                       IF TG_OP = 'INSERT' THEN
+                           location_ids := ARRAY[NEW.location_id];
+                       ELSIF TG_OP = 'DELETE' THEN
+                           location_ids := ARRAY[OLD.location_id];
+                       ELSE
+                           location_ids := ARRAY[OLD.location_id, NEW.location_id];
+                       END IF;
+                       SELECT COALESCE(jsonb_agg(jsonb_build_object('lat', latitude, 'lng', longitude)), '[]'::jsonb)
+                       INTO points
+                       FROM locations
+                       WHERE id = ANY(location_ids);
+                       PERFORM pg_notify('map_data_changes', jsonb_build_object(
+                           'table', TG_TABLE_NAME, 'operation', TG_OP, 'points', points)::text);
+                       RETURN NULL;
+                   END;
+                   $$ LANGUAGE plpgsql"#,
+                "DROP FUNCTION IF EXISTS notify_poi_change()",
+            ),
+            (
+                105,
+                "create_points_of_interest_change_trigger",
+                r#"CREATE TRIGGER points_of_interest_map_data_change
+                   AFTER INSERT OR UPDATE OR DELETE ON points_of_interest
+                   FOR EACH ROW EXECUTE FUNCTION notify_poi_change()"#,
+                "DROP TRIGGER IF EXISTS points_of_interest_map_data_change ON points_of_interest",
+            ),
+        ];
+
+        for (version, name, up_sql, down_sql) in migrations {
+            self.migrations.push(Migration {
+                version,
+                name: name.to_string(),
+                up_sql: up_sql.to_string(),
+                down_sql: down_sql.to_string(),
+            });
+        }
+    }
+
+    /// Case-insensitive uniqueness for sign-in names, one favorite per user and
+    /// location, and the refresh tokens handed out at login. Each token is one
+    /// row; tokens from the same login share a `session_id` so a whole session
+    /// can be revoked at once.
+    fn register_account_migrations(&mut self) {
+        let migrations = [
+            (
+                106,
+                "index_users_email_unique",
+                "CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email_lower ON users (LOWER(email))",
+                "DROP INDEX IF EXISTS idx_users_email_lower",
+            ),
+            (
+                107,
+                "index_users_username_unique",
+                "CREATE UNIQUE INDEX IF NOT EXISTS idx_users_username_lower ON users (LOWER(username))",
+                "DROP INDEX IF EXISTS idx_users_username_lower",
+            ),
+            (
+                108,
+                "create_refresh_tokens",
+                r#"CREATE TABLE IF NOT EXISTS refresh_tokens (
+                       id UUID PRIMARY KEY,
+                       user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+                       session_id UUID NOT NULL,
+                       expires_at TIMESTAMPTZ NOT NULL,
+                       created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                       revoked_at TIMESTAMPTZ,
+                       replaced_by UUID
+                   )"#,
+                "DROP TABLE IF EXISTS refresh_tokens",
+            ),
+            (
+                109,
+                "index_refresh_tokens_session",
+                "CREATE INDEX IF NOT EXISTS idx_refresh_tokens_session ON refresh_tokens (session_id)",
+                "DROP INDEX IF EXISTS idx_refresh_tokens_session",
+            ),
+            (
+                110,
+                "index_refresh_tokens_user",
+                "CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user ON refresh_tokens (user_id)",
+                "DROP INDEX IF EXISTS idx_refresh_tokens_user",
+            ),
+            (
+                111,
+                "index_favorites_user_location_unique",
+                "CREATE UNIQUE INDEX IF NOT EXISTS idx_favorites_user_location ON favorites (user_id, location_id)",
+                "DROP INDEX IF EXISTS idx_favorites_user_location",
+            ),
+        ];
+
+        for (version, name, up_sql, down_sql) in migrations {
+            self.migrations.push(Migration {
+                version,
+                name: name.to_string(),
+                up_sql: up_sql.to_string(),
+                down_sql: down_sql.to_string(),
+            });
+        }
+    }
+
+    /// Spatial columns and indexes that only apply when the PostGIS extension
+    /// is installed on the server. These run separately via `run_postgis_migrations`.
+    fn register_postgis_migrations(&mut self) {
+        let migrations = [
+            (
+                POSTGIS_EXTENSION_VERSION,
+                "create_postgis_extension",
+                "CREATE EXTENSION IF NOT EXISTS postgis",
+                "DROP EXTENSION IF EXISTS postgis",
+            ),
+            (
+                201,
+                "add_locations_geog",
+                r#"ALTER TABLE locations ADD COLUMN IF NOT EXISTS geog geography(Point, 4326)
+                   GENERATED ALWAYS AS (ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)::geography) STORED"#,
+                "ALTER TABLE locations DROP COLUMN IF EXISTS geog",
+            ),
+            (
+                202,
+                "index_locations_geog",
+                "CREATE INDEX IF NOT EXISTS idx_locations_geog ON locations USING GIST (geog)",
+                "DROP INDEX IF EXISTS idx_locations_geog",
+            ),
+            (
+                203,
+                "add_routes_path",
+                "ALTER TABLE routes ADD COLUMN IF NOT EXISTS path geography(LineString, 4326)",
+                "ALTER TABLE routes DROP COLUMN IF EXISTS path",
+            ),
+            (
+                204,
+                "index_routes_path",
+                "CREATE INDEX IF NOT EXISTS idx_routes_path ON routes USING GIST (path)",
+                "DROP INDEX IF EXISTS idx_routes_path",
+            ),
+        ];
+
+        for (version, name, up_sql, down_sql) in migrations {
+            self.postgis_migrations.push(Migration {
+                version,
+                name: name.to_string(),
+                up_sql: up_sql.to_string(),
+                down_sql: down_sql.to_string(),
+            });
+        }
+    }
+
+    /// Apply the PostGIS migrations if the extension is available on the server.
+    /// Returns `false` without touching the schema when it is not, or when it
+    /// cannot be created.
+    pub async fn run_postgis_migrations(&self) -> Result<bool, sqlx::Error> {
+        let available: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'postgis')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        if !available {
+            warn!("PostGIS extension is not available, skipping spatial migrations");
+            return Ok(false);
+        }
+
+        self.create_migration_table().await?;
+        let applied_migrations = self.get_applied_migrations().await?;
+
+        for migration in &self.postgis_migrations {
+            if !applied_migrations.contains(&migration.version) {
+                info!("Applying PostGIS migration {}: {}", migration.version, migration.name);
+                match self.apply_migration(migration).await {
+                    Ok(()) => {}
+                    // Typically the database role may not create extensions
+                    Err(e) if migration.version == POSTGIS_EXTENSION_VERSION => {
+                        warn!("Could not create the PostGIS extension, skipping spatial migrations: {}", e);
+                        return Ok(false);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
     pub async fn run_migrations(&self) -> Result<(), sqlx::Error> {
         info!("Starting database migrations");
         
@@ -48,8 +296,11 @@ impl MigrationRunner {
         info!("Rolling back migrations to version {}", target_version);
         
         let applied_migrations = self.get_applied_migrations().await?;
+        // PostGIS migrations sort after the plain ones, so a rollback to a
+        // version below 200 drops the spatial columns first
         let mut migrations_to_rollback: Vec<&Migration> = self.migrations
             .iter()
+            .chain(&self.postgis_migrations)
             .filter(|m| m.version > target_version && applied_migrations.contains(&m.version))
             .collect();
         
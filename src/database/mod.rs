@@ -2,15 +2,28 @@ use sqlx::{PgPool, Row};
 use serde::{Deserialize, Serialize};
 use std::env;
 use anyhow::{Result, Context};
+use tracing::info;
 
+use crate::utils::geohash;
+
+pub mod changes;
+pub mod exchange;
+pub mod migrations;
 pub mod models;
 pub mod queries;
+pub mod spatial;
+pub mod users;
 
+pub use changes::{MapDataChange, MapDataChanges};
+pub use migrations::MigrationRunner;
 pub use models::*;
+pub use spatial::SpatialBackend;
+pub use users::{Favorite, Review, SearchHistoryEntry, User};
 
 #[derive(Debug, Clone)]
 pub struct Database {
     pool: PgPool,
+    spatial_backend: SpatialBackend,
 }
 
 impl Database {
@@ -27,20 +40,54 @@ impl Database {
             .run(&pool)
             .await
             .context("Failed to run database migrations")?;
+
+        MigrationRunner::new(pool.clone())
+            .run_migrations()
+            .await
+            .context("Failed to run schema migrations")?;
+
+        let postgis_enabled = env::var("ENABLE_POSTGIS")
+            .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        let spatial_backend = if postgis_enabled {
+            SpatialBackend::detect(&pool).await?
+        } else {
+            SpatialBackend::Plain
+        };
         
-        Ok(Database { pool })
+        let db = Database { pool, spatial_backend };
+
+        let backfilled = db.backfill_location_geohashes().await?;
+        if backfilled > 0 {
+            info!("Backfilled geohashes for {} locations", backfilled);
+        }
+
+        let backfilled = db.backfill_route_paths().await?;
+        if backfilled > 0 {
+            info!("Backfilled spatial paths for {} routes", backfilled);
+        }
+
+        Ok(db)
     }
 
     pub fn pool(&self) -> &PgPool {
         &self.pool
     }
 
+    pub fn spatial_backend(&self) -> SpatialBackend {
+        self.spatial_backend
+    }
+
     // Location operations
     pub async fn create_location(&self, location: &CreateLocation) -> Result<Location> {
+        let geohash = geohash::encode(location.latitude, location.longitude, geohash::MAX_PRECISION)
+            .context("Invalid location coordinates")?;
+
         let row = sqlx::query!(
             r#"
-            INSERT INTO locations (name, latitude, longitude, address, place_type, rating)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            INSERT INTO locations (name, latitude, longitude, address, place_type, rating, geohash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
             RETURNING id, name, latitude, longitude, address, place_type, rating, created_at, updated_at
             "#,
             location.name,
@@ -48,7 +95,8 @@ impl Database {
             location.longitude,
             location.address,
             location.place_type,
-            location.rating
+            location.rating,
+            geohash
         )
         .fetch_one(&self.pool)
         .await
@@ -94,7 +142,7 @@ impl Database {
             r#"
             SELECT id, name, latitude, longitude, address, place_type, rating, created_at, updated_at
             FROM locations
-            WHERE name ILIKE $1 OR address ILIKE $1
+            WHERE (name ILIKE $1 OR address ILIKE $1) AND created_by IS NULL
             ORDER BY name
             LIMIT $2
             "#,
@@ -0,0 +1,332 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::de::{value::Error as ValueError, IntoDeserializer};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use uuid::Uuid;
+
+use super::{Database, Location, MapBounds};
+use crate::models::route::{Coordinate, ManeuverType, Route, RouteSegment, RouteType};
+use crate::utils::tile_utils::LatLng;
+use crate::utils::{geohash, polyline};
+
+/// Number of rows updated per statement when backfilling geohashes
+const GEOHASH_BACKFILL_BATCH: i64 = 1000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NearbyLocation {
+    #[serde(flatten)]
+    pub location: Location,
+    pub distance_meters: f64,
+}
+
+impl Database {
+    /// Find shared locations within `radius_m` of a point, nearest first,
+    /// optionally only those of `place_type` or whose name contains `name`.
+    /// Locations users imported for themselves are left out.
+    ///
+    /// Candidates are fetched by geohash prefix ranges covering the search circle,
+    /// then filtered by exact haversine distance, so no spatial extension is needed.
+    pub async fn search_nearby(
+        &self,
+        lat: f64,
+        lng: f64,
+        radius_m: f64,
+        place_type: Option<&str>,
+        name: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<NearbyLocation>> {
+        let cells = geohash::cover_radius(lat, lng, radius_m)
+            .context("Invalid nearby search center")?;
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT l.id, l.name, l.latitude, l.longitude, l.address, l.place_type, l.rating,
+                   l.created_at, l.updated_at
+            FROM UNNEST($1::text[]) AS cells(prefix)
+            JOIN locations l
+              ON l.geohash COLLATE "C" >= cells.prefix
+             AND l.geohash COLLATE "C" < cells.prefix || '{'
+            WHERE l.created_by IS NULL
+              AND ($2::varchar IS NULL OR l.place_type = $2)
+              AND ($3::varchar IS NULL OR l.name ILIKE $3)
+            "#,
+            &cells,
+            place_type,
+            name.map(|name| format!("%{}%", name))
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to search nearby locations")?;
+
+        let center = LatLng::new(lat, lng);
+        let mut results: Vec<NearbyLocation> = rows
+            .into_iter()
+            .filter_map(|row| {
+                let distance_meters = center.distance_to(&LatLng::new(row.latitude, row.longitude));
+                if distance_meters > radius_m {
+                    return None;
+                }
+                Some(NearbyLocation {
+                    location: Location {
+                        id: row.id,
+                        name: row.name,
+                        latitude: row.latitude,
+                        longitude: row.longitude,
+                        address: row.address,
+                        place_type: row.place_type,
+                        rating: row.rating,
+                        created_at: row.created_at,
+                        updated_at: row.updated_at,
+                    },
+                    distance_meters,
+                })
+            })
+            .collect();
+
+        results.sort_by(|a, b| a.distance_meters.total_cmp(&b.distance_meters));
+        results.truncate(limit.max(0) as usize);
+        Ok(results)
+    }
+
+    /// Populate the geohash column for rows created before it existed.
+    /// Returns the number of rows updated.
+    pub async fn backfill_location_geohashes(&self) -> Result<u64> {
+        let mut updated = 0;
+
+        loop {
+            let rows = sqlx::query!(
+                "SELECT id, latitude, longitude FROM locations WHERE geohash IS NULL LIMIT $1",
+                GEOHASH_BACKFILL_BATCH
+            )
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to fetch locations without geohash")?;
+
+            if rows.is_empty() {
+                break;
+            }
+
+            let mut ids = Vec::with_capacity(rows.len());
+            let mut hashes = Vec::with_capacity(rows.len());
+            for row in rows {
+                let hash = geohash::encode(row.latitude, row.longitude, geohash::MAX_PRECISION)
+                    .with_context(|| format!("Location {} has invalid coordinates", row.id))?;
+                ids.push(row.id);
+                hashes.push(hash);
+            }
+
+            let result = sqlx::query!(
+                r#"
+                UPDATE locations l
+                SET geohash = v.geohash
+                FROM UNNEST($1::uuid[], $2::text[]) AS v(id, geohash)
+                WHERE l.id = v.id
+                "#,
+                &ids,
+                &hashes
+            )
+            .execute(&self.pool)
+            .await
+            .context("Failed to backfill location geohashes")?;
+
+            updated += result.rows_affected();
+        }
+
+        Ok(updated)
+    }
+
+    /// Store a route path in `routes.geometry` as a precision 5 encoded polyline
+    pub async fn save_route_geometry(&self, route_id: Uuid, path: &[(f64, f64)]) -> Result<()> {
+        let encoded = polyline::encode(path, polyline::PRECISION_5);
+
+        sqlx::query!(
+            "UPDATE routes SET geometry = $2, updated_at = NOW() WHERE id = $1",
+            route_id,
+            encoded
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save route geometry")?;
+
+        self.set_route_path(route_id, path).await
+    }
+
+    pub async fn get_route_geometry(&self, route_id: Uuid) -> Result<Option<Vec<(f64, f64)>>> {
+        let row = sqlx::query!("SELECT geometry FROM routes WHERE id = $1", route_id)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to fetch route geometry")?;
+
+        row.map(|r| polyline::decode(&r.geometry, polyline::PRECISION_5))
+            .transpose()
+            .with_context(|| format!("Route {} has an invalid geometry", route_id))
+    }
+
+    /// A stored route with its steps as segments, for turn-by-turn
+    /// navigation. Routes without steps, like imported tracks, get a segment
+    /// per stretch of their geometry. Routes a user created are only returned
+    /// to that user; `None` for those, unknown routes and empty ones alike.
+    pub async fn navigation_route(&self, route_id: Uuid, viewer_id: Uuid) -> Result<Option<Route>> {
+        let Some(row) = sqlx::query(
+            r#"
+            SELECT distance_meters, duration_seconds, geometry, created_at
+            FROM routes
+            WHERE id = $1 AND (created_by IS NULL OR created_by = $2)
+            "#,
+        )
+        .bind(route_id)
+        .bind(viewer_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to fetch route")?
+        else {
+            return Ok(None);
+        };
+
+        let steps = sqlx::query(
+            r#"
+            SELECT instruction, distance_meters, duration_seconds, maneuver,
+                   start_latitude, start_longitude, end_latitude, end_longitude
+            FROM route_steps
+            WHERE route_id = $1
+            ORDER BY step_order
+            "#,
+        )
+        .bind(route_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch route steps")?;
+
+        let total_distance = row.get::<i64, _>("distance_meters") as f64;
+        let total_duration = row.get::<i64, _>("duration_seconds") as i32;
+
+        let segments: Vec<RouteSegment> = if steps.is_empty() {
+            let geometry: String = row.get("geometry");
+            let path = polyline::decode(&geometry, polyline::PRECISION_5)
+                .with_context(|| format!("Route {} has an invalid geometry", route_id))?;
+            let seconds_per_meter = if total_distance > 0.0 { total_duration as f64 / total_distance } else { 0.0 };
+            path.windows(2)
+                .map(|pair| {
+                    let start = Coordinate::new(pair[0].0, pair[0].1);
+                    let end = Coordinate::new(pair[1].0, pair[1].1);
+                    let distance = start.distance_to(&end);
+                    RouteSegment {
+                        start,
+                        end,
+                        distance,
+                        duration: (distance * seconds_per_meter).round() as i32,
+                        instruction: "Continue".to_string(),
+                        street_name: None,
+                        maneuver: ManeuverType::Straight,
+                    }
+                })
+                .collect()
+        } else {
+            steps
+                .iter()
+                .map(|step| RouteSegment {
+                    start: Coordinate::new(step.get("start_latitude"), step.get("start_longitude")),
+                    end: Coordinate::new(step.get("end_latitude"), step.get("end_longitude")),
+                    distance: step.get::<i64, _>("distance_meters") as f64,
+                    duration: step.get::<i64, _>("duration_seconds") as i32,
+                    instruction: step.get("instruction"),
+                    street_name: None,
+                    maneuver: step
+                        .get::<Option<String>, _>("maneuver")
+                        .and_then(|name| parse_maneuver(&name))
+                        .unwrap_or(ManeuverType::Straight),
+                })
+                .collect()
+        };
+
+        let (Some(first), Some(last)) = (segments.first(), segments.last()) else {
+            return Ok(None);
+        };
+
+        Ok(Some(Route {
+            id: route_id.to_string(),
+            origin: first.start.clone(),
+            destination: last.end.clone(),
+            waypoints: Vec::new(),
+            total_distance,
+            total_duration,
+            segments,
+            route_type: RouteType::Fastest,
+            created_at: row.get("created_at"),
+            traffic_info: None,
+            elevation: None,
+        }))
+    }
+
+    /// Where searches since `since` were made from inside `bounds`, as
+    /// `(lat, lng, weight)`. Searches within `cell_deg` degrees of each other
+    /// are merged into one point weighted by their count.
+    pub async fn search_heat_points(
+        &self,
+        bounds: &MapBounds,
+        cell_deg: f64,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<(f64, f64, f64)>> {
+        sqlx::query_as(
+            r#"
+            SELECT AVG(latitude), AVG(longitude), COUNT(*)::float8
+            FROM search_history
+            WHERE latitude BETWEEN $1 AND $2
+              AND longitude BETWEEN $3 AND $4
+              AND created_at >= $6
+            GROUP BY FLOOR(latitude / $5), FLOOR(longitude / $5)
+            "#,
+        )
+        .bind(bounds.south)
+        .bind(bounds.north)
+        .bind(bounds.west)
+        .bind(bounds.east)
+        .bind(cell_deg)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch search heat points")
+    }
+
+    /// Congestion at locations inside `bounds`, from each location's latest
+    /// reading since `since`, as `(lat, lng, congestion_factor)` merged into
+    /// `cell_deg` degree cells like `search_heat_points`
+    pub async fn traffic_heat_points(
+        &self,
+        bounds: &MapBounds,
+        cell_deg: f64,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<(f64, f64, f64)>> {
+        sqlx::query_as(
+            r#"
+            SELECT AVG(latitude), AVG(longitude), SUM(congestion_factor)
+            FROM (
+                SELECT DISTINCT ON (t.location_id) l.latitude, l.longitude, t.congestion_factor
+                FROM traffic_data t
+                JOIN locations l ON l.id = t.location_id
+                WHERE l.latitude BETWEEN $1 AND $2
+                  AND l.longitude BETWEEN $3 AND $4
+                  AND t.timestamp >= $6
+                ORDER BY t.location_id, t.timestamp DESC
+            ) latest
+            WHERE congestion_factor > 0
+            GROUP BY FLOOR(latitude / $5), FLOOR(longitude / $5)
+            "#,
+        )
+        .bind(bounds.south)
+        .bind(bounds.north)
+        .bind(bounds.west)
+        .bind(bounds.east)
+        .bind(cell_deg)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch traffic heat points")
+    }
+}
+
+/// `route_steps.maneuver` holds `ManeuverType` variant names, e.g. `TurnLeft`
+fn parse_maneuver(name: &str) -> Option<ManeuverType> {
+    ManeuverType::deserialize(IntoDeserializer::<ValueError>::into_deserializer(name)).ok()
+}
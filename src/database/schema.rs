@@ -28,6 +28,7 @@ table! {
         country -> Nullable<Varchar>,
         postal_code -> Nullable<Varchar>,
         place_type -> Varchar,
+        geohash -> Nullable<Varchar>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         created_by -> Nullable<Uuid>,
@@ -42,6 +43,7 @@ table! {
         end_location_id -> Uuid,
         distance_meters -> Int8,
         duration_seconds -> Int8,
+        /// Route path as an encoded polyline with precision 5
         geometry -> Text,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
@@ -0,0 +1,420 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{PgPool, Row};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::migrations::MigrationRunner;
+use super::queries::NearbyLocation;
+use super::{Database, Location, MapBounds};
+use crate::utils::polyline;
+
+/// Number of routes updated per statement when backfilling paths
+const ROUTE_PATH_BACKFILL_BATCH: i64 = 500;
+
+/// How spatial queries are answered.
+///
+/// `PostGis` uses the geography columns and GiST indexes added by the PostGIS
+/// migrations. `Plain` uses the latitude/longitude columns and geohash prefixes,
+/// and is what every query falls back to when the extension is missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpatialBackend {
+    PostGis,
+    Plain,
+}
+
+impl SpatialBackend {
+    pub async fn detect(pool: &PgPool) -> Result<Self> {
+        let migrated = MigrationRunner::new(pool.clone())
+            .run_postgis_migrations()
+            .await
+            .context("Failed to run PostGIS migrations")?;
+
+        if !migrated {
+            return Ok(SpatialBackend::Plain);
+        }
+
+        let installed: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'postgis')",
+        )
+        .fetch_one(pool)
+        .await
+        .context("Failed to check for PostGIS extension")?;
+
+        if installed {
+            info!("Using PostGIS for spatial queries");
+            Ok(SpatialBackend::PostGis)
+        } else {
+            warn!("PostGIS migrations ran but the extension is not installed, using plain spatial queries");
+            Ok(SpatialBackend::Plain)
+        }
+    }
+}
+
+impl Database {
+    pub async fn locations_in_bounds(&self, bounds: &MapBounds) -> Result<Vec<Location>> {
+        if self.spatial_backend != SpatialBackend::PostGis {
+            return self.get_locations_in_bounds(bounds).await;
+        }
+
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, latitude, longitude, address, place_type, rating, created_at, updated_at
+            FROM locations
+            WHERE geog && ST_MakeEnvelope($1, $2, $3, $4, 4326)::geography
+            "#,
+        )
+        .bind(bounds.west)
+        .bind(bounds.south)
+        .bind(bounds.east)
+        .bind(bounds.north)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch locations in bounds")?;
+
+        Ok(rows.iter().map(location_from_row).collect())
+    }
+
+    /// Shared locations inside `bounds` to label on map tiles, best rated
+    /// first. Locations users imported for themselves are left out.
+    pub async fn tile_locations(&self, bounds: &MapBounds, limit: i64) -> Result<Vec<Location>> {
+        let area = if self.spatial_backend == SpatialBackend::PostGis {
+            "geog && ST_MakeEnvelope($1, $2, $3, $4, 4326)::geography"
+        } else {
+            "longitude BETWEEN $1 AND $3 AND latitude BETWEEN $2 AND $4"
+        };
+
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT id, name, latitude, longitude, address, place_type, rating, created_at, updated_at
+            FROM locations
+            WHERE {}
+              AND created_by IS NULL
+            ORDER BY rating DESC NULLS LAST, id
+            LIMIT $5
+            "#,
+            area
+        ))
+        .bind(bounds.west)
+        .bind(bounds.south)
+        .bind(bounds.east)
+        .bind(bounds.north)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch tile locations")?;
+
+        Ok(rows.iter().map(location_from_row).collect())
+    }
+
+    /// Locations within `radius_m` of a point, nearest first, with the same
+    /// filters as `search_nearby`
+    pub async fn locations_within_radius(
+        &self,
+        lat: f64,
+        lng: f64,
+        radius_m: f64,
+        place_type: Option<&str>,
+        name: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<NearbyLocation>> {
+        if self.spatial_backend != SpatialBackend::PostGis {
+            return self.search_nearby(lat, lng, radius_m, place_type, name, limit).await;
+        }
+
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, latitude, longitude, address, place_type, rating, created_at, updated_at,
+                   ST_Distance(geog, center.point) AS distance_meters
+            FROM locations,
+                 (SELECT ST_SetSRID(ST_MakePoint($2, $1), 4326)::geography AS point) AS center
+            WHERE ST_DWithin(geog, center.point, $3)
+              AND created_by IS NULL
+              AND ($4::varchar IS NULL OR place_type = $4)
+              AND ($6::varchar IS NULL OR name ILIKE $6)
+            ORDER BY geog <-> center.point
+            LIMIT $5
+            "#,
+        )
+        .bind(lat)
+        .bind(lng)
+        .bind(radius_m)
+        .bind(place_type)
+        .bind(limit)
+        .bind(name.map(|name| format!("%{}%", name)))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch locations within radius")?;
+
+        Ok(rows
+            .iter()
+            .map(|row| NearbyLocation {
+                location: location_from_row(row),
+                distance_meters: row.get("distance_meters"),
+            })
+            .collect())
+    }
+
+    /// Locations inside a polygon given as a ring of `(lat, lng)` vertices
+    pub async fn locations_in_polygon(&self, ring: &[(f64, f64)]) -> Result<Vec<Location>> {
+        if ring.len() < 3 {
+            anyhow::bail!("Polygon must have at least 3 vertices");
+        }
+
+        if self.spatial_backend != SpatialBackend::PostGis {
+            let bounds = ring_bounds(ring);
+            let candidates = self.get_locations_in_bounds(&bounds).await?;
+            return Ok(candidates
+                .into_iter()
+                .filter(|location| point_in_ring(location.latitude, location.longitude, ring))
+                .collect());
+        }
+
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, latitude, longitude, address, place_type, rating, created_at, updated_at
+            FROM locations
+            WHERE ST_Covers(ST_GeogFromText($1), geog)
+            "#,
+        )
+        .bind(polygon_wkt(ring))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch locations in polygon")?;
+
+        Ok(rows.iter().map(location_from_row).collect())
+    }
+
+    /// Store the route path as geography so it can be found by spatial queries.
+    /// Does nothing without PostGIS.
+    pub async fn set_route_path(&self, route_id: Uuid, path: &[(f64, f64)]) -> Result<()> {
+        if self.spatial_backend != SpatialBackend::PostGis || path.len() < 2 {
+            return Ok(());
+        }
+
+        sqlx::query("UPDATE routes SET path = ST_GeogFromText($2) WHERE id = $1")
+            .bind(route_id)
+            .bind(linestring_wkt(path))
+            .execute(&self.pool)
+            .await
+            .context("Failed to update route path")?;
+
+        Ok(())
+    }
+
+    /// Fill `routes.path` from the encoded `routes.geometry` of routes stored
+    /// before PostGIS was enabled. Routes whose geometry does not decode are
+    /// skipped. Returns the number of routes updated.
+    pub async fn backfill_route_paths(&self) -> Result<u64> {
+        if self.spatial_backend != SpatialBackend::PostGis {
+            return Ok(0);
+        }
+
+        let mut updated = 0;
+        let mut after = Uuid::nil();
+
+        loop {
+            let rows = sqlx::query(
+                "SELECT id, geometry FROM routes WHERE path IS NULL AND id > $1 ORDER BY id LIMIT $2",
+            )
+            .bind(after)
+            .bind(ROUTE_PATH_BACKFILL_BATCH)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to fetch routes without path")?;
+
+            let Some(last) = rows.last() else {
+                break;
+            };
+            after = last.get("id");
+
+            let mut ids = Vec::with_capacity(rows.len());
+            let mut lines = Vec::with_capacity(rows.len());
+            for row in &rows {
+                let id: Uuid = row.get("id");
+                let geometry: String = row.get("geometry");
+                match polyline::decode(&geometry, polyline::PRECISION_5) {
+                    Ok(path) if path.len() >= 2 => {
+                        ids.push(id);
+                        lines.push(linestring_wkt(&path));
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Route {} has an invalid geometry, not backfilling its path: {}", id, e),
+                }
+            }
+
+            let result = sqlx::query(
+                r#"
+                UPDATE routes r
+                SET path = ST_GeogFromText(v.line)
+                FROM UNNEST($1::uuid[], $2::text[]) AS v(id, line)
+                WHERE r.id = v.id
+                "#,
+            )
+            .bind(&ids)
+            .bind(&lines)
+            .execute(&self.pool)
+            .await
+            .context("Failed to backfill route paths")?;
+
+            updated += result.rows_affected();
+        }
+
+        Ok(updated)
+    }
+
+    /// Routes passing through the bounds. Without PostGIS only routes that start
+    /// or end inside the bounds are found.
+    pub async fn routes_in_bounds(&self, bounds: &MapBounds) -> Result<Vec<Uuid>> {
+        let query = match self.spatial_backend {
+            SpatialBackend::PostGis => {
+                r#"
+                SELECT id FROM routes
+                WHERE path && ST_MakeEnvelope($1, $2, $3, $4, 4326)::geography
+                "#
+            }
+            SpatialBackend::Plain => {
+                r#"
+                SELECT DISTINCT r.id FROM routes r
+                JOIN locations l ON l.id IN (r.start_location_id, r.end_location_id)
+                WHERE l.longitude BETWEEN $1 AND $3 AND l.latitude BETWEEN $2 AND $4
+                "#
+            }
+        };
+
+        let rows = sqlx::query(query)
+            .bind(bounds.west)
+            .bind(bounds.south)
+            .bind(bounds.east)
+            .bind(bounds.north)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to fetch routes in bounds")?;
+
+        Ok(rows.iter().map(|row| row.get("id")).collect())
+    }
+}
+
+fn location_from_row(row: &PgRow) -> Location {
+    Location {
+        id: row.get("id"),
+        name: row.get("name"),
+        latitude: row.get("latitude"),
+        longitude: row.get("longitude"),
+        address: row.get("address"),
+        place_type: row.get("place_type"),
+        rating: row.get("rating"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+fn ring_bounds(ring: &[(f64, f64)]) -> MapBounds {
+    let mut bounds = MapBounds {
+        north: f64::MIN,
+        south: f64::MAX,
+        east: f64::MIN,
+        west: f64::MAX,
+    };
+    for &(lat, lng) in ring {
+        bounds.north = bounds.north.max(lat);
+        bounds.south = bounds.south.min(lat);
+        bounds.east = bounds.east.max(lng);
+        bounds.west = bounds.west.min(lng);
+    }
+    bounds
+}
+
+fn point_in_ring(lat: f64, lng: f64, ring: &[(f64, f64)]) -> bool {
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (lat_i, lng_i) = ring[i];
+        let (lat_j, lng_j) = ring[j];
+        if (lat_i > lat) != (lat_j > lat)
+            && lng < (lng_j - lng_i) * (lat - lat_i) / (lat_j - lat_i) + lng_i
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn polygon_wkt(ring: &[(f64, f64)]) -> String {
+    let mut points: Vec<String> = ring.iter().map(|(lat, lng)| format!("{} {}", lng, lat)).collect();
+    if ring.first() != ring.last() {
+        points.push(points[0].clone());
+    }
+    format!("SRID=4326;POLYGON(({}))", points.join(", "))
+}
+
+fn linestring_wkt(path: &[(f64, f64)]) -> String {
+    let points: Vec<String> = path.iter().map(|(lat, lng)| format!("{} {}", lng, lat)).collect();
+    format!("SRID=4326;LINESTRING({})", points.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2° x 4° rectangle around the origin, as `(lat, lng)`
+    const RECTANGLE: [(f64, f64); 4] = [(-1.0, -2.0), (-1.0, 2.0), (1.0, 2.0), (1.0, -2.0)];
+
+    /// An L shape whose notch covers the north-east quarter of the unit square
+    const L_SHAPE: [(f64, f64); 6] = [(0.0, 0.0), (0.0, 1.0), (0.5, 1.0), (0.5, 0.5), (1.0, 0.5), (1.0, 0.0)];
+
+    #[test]
+    fn finds_points_inside_a_ring() {
+        assert!(point_in_ring(0.0, 0.0, &RECTANGLE));
+        assert!(point_in_ring(0.9, -1.9, &RECTANGLE));
+        assert!(!point_in_ring(1.1, 0.0, &RECTANGLE));
+        assert!(!point_in_ring(0.0, 2.5, &RECTANGLE));
+        assert!(!point_in_ring(-5.0, -5.0, &RECTANGLE));
+    }
+
+    #[test]
+    fn excludes_points_in_a_concave_notch() {
+        assert!(point_in_ring(0.25, 0.25, &L_SHAPE));
+        assert!(point_in_ring(0.75, 0.25, &L_SHAPE));
+        assert!(point_in_ring(0.25, 0.75, &L_SHAPE));
+        assert!(!point_in_ring(0.75, 0.75, &L_SHAPE));
+    }
+
+    #[test]
+    fn closed_and_open_rings_agree() {
+        let mut closed = RECTANGLE.to_vec();
+        closed.push(RECTANGLE[0]);
+        for (lat, lng) in [(0.0, 0.0), (0.5, 1.5), (1.5, 0.0), (0.0, -3.0)] {
+            assert_eq!(point_in_ring(lat, lng, &closed), point_in_ring(lat, lng, &RECTANGLE));
+        }
+    }
+
+    #[test]
+    fn bounds_cover_every_vertex() {
+        let bounds = ring_bounds(&L_SHAPE);
+        assert_eq!((bounds.south, bounds.north), (0.0, 1.0));
+        assert_eq!((bounds.west, bounds.east), (0.0, 1.0));
+
+        let bounds = ring_bounds(&[(48.1, 11.6), (48.2, 11.5), (48.15, 11.7)]);
+        assert_eq!((bounds.south, bounds.north), (48.1, 48.2));
+        assert_eq!((bounds.west, bounds.east), (11.5, 11.7));
+    }
+
+    #[test]
+    fn polygon_wkt_closes_the_ring_in_lng_lat_order() {
+        assert_eq!(
+            polygon_wkt(&[(1.0, 2.0), (3.0, 4.0), (5.0, 2.0)]),
+            "SRID=4326;POLYGON((2 1, 4 3, 2 5, 2 1))"
+        );
+    }
+
+    #[test]
+    fn polygon_wkt_keeps_an_already_closed_ring() {
+        assert_eq!(
+            polygon_wkt(&[(1.0, 2.0), (3.0, 4.0), (5.0, 2.0), (1.0, 2.0)]),
+            "SRID=4326;POLYGON((2 1, 4 3, 2 5, 2 1))"
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use super::Database;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A saved location, named by the user or after the location itself
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Favorite {
+    pub id: Uuid,
+    pub location_id: Uuid,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SearchHistoryEntry {
+    pub id: Uuid,
+    pub query: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub results_count: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Review {
+    pub id: Uuid,
+    pub poi_id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub username: Option<String>,
+    pub rating: i32,
+    pub comment: Option<String>,
+    pub helpful_count: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+const USER_COLUMNS: &str = "id, username, email, password_hash, is_active, created_at, updated_at";
+
+impl Database {
+    /// Insert a new active user. Returns `None` when the username or email
+    /// is already taken, ignoring case.
+    pub async fn create_user(&self, username: &str, email: &str, password_hash: &str) -> Result<Option<User>> {
+        let result = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO users (username, email, password_hash, is_active)
+            VALUES ($1, $2, $3, TRUE)
+            RETURNING {}
+            "#,
+            USER_COLUMNS
+        ))
+        .bind(username)
+        .bind(email)
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await;
+
+        match result {
+            Ok(user) => Ok(Some(user)),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(None),
+            Err(e) => Err(e).context("Failed to create user"),
+        }
+    }
+
+    /// The user whose username or email matches `login`, ignoring case
+    pub async fn user_by_login(&self, login: &str) -> Result<Option<User>> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM users WHERE LOWER(username) = LOWER($1) OR LOWER(email) = LOWER($1)",
+            USER_COLUMNS
+        ))
+        .bind(login)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to fetch user")
+    }
+
+    pub async fn user_by_id(&self, id: Uuid) -> Result<Option<User>> {
+        sqlx::query_as(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to fetch user")
+    }
+
+    pub async fn insert_refresh_token(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+        session_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query("INSERT INTO refresh_tokens (id, user_id, session_id, expires_at) VALUES ($1, $2, $3, $4)")
+            .bind(id)
+            .bind(user_id)
+            .bind(session_id)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await
+            .context("Failed to store refresh token")?;
+        Ok(())
+    }
+
+    /// Replace refresh token `id` with `new_id` in the same session. The old
+    /// token is marked revoked rather than deleted, so presenting it again can
+    /// be recognised as reuse. Returns the token's user and session, or `None`
+    /// when `id` is unknown, expired or already revoked.
+    pub async fn rotate_refresh_token(
+        &self,
+        id: Uuid,
+        new_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<(Uuid, Uuid)>> {
+        let mut tx = self.pool.begin().await.context("Failed to start token rotation")?;
+
+        let current: Option<(Uuid, Uuid)> = sqlx::query_as(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW(), replaced_by = $2
+            WHERE id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            RETURNING user_id, session_id
+            "#,
+        )
+        .bind(id)
+        .bind(new_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to revoke rotated refresh token")?;
+
+        let Some((user_id, session_id)) = current else {
+            return Ok(None);
+        };
+
+        sqlx::query("INSERT INTO refresh_tokens (id, user_id, session_id, expires_at) VALUES ($1, $2, $3, $4)")
+            .bind(new_id)
+            .bind(user_id)
+            .bind(session_id)
+            .bind(expires_at)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to store refresh token")?;
+
+        tx.commit().await.context("Failed to commit token rotation")?;
+        Ok(Some((user_id, session_id)))
+    }
+
+    /// Whether the user of a login session is active, or `None` when the
+    /// session has no refresh token left that is unrevoked and unexpired
+    pub async fn session_user_active(&self, session_id: Uuid, user_id: Uuid) -> Result<Option<bool>> {
+        sqlx::query_scalar(
+            r#"
+            SELECT u.is_active
+            FROM refresh_tokens t
+            JOIN users u ON u.id = t.user_id
+            WHERE t.session_id = $1 AND t.user_id = $2 AND t.revoked_at IS NULL AND t.expires_at > NOW()
+            LIMIT 1
+            "#,
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to check session")
+    }
+
+    /// Revoke every outstanding refresh token of one login session
+    pub async fn revoke_session(&self, session_id: Uuid) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE session_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(session_id)
+        .execute(&self.pool)
+        .await
+        .context("Failed to revoke session")?;
+        Ok(result.rows_affected())
+    }
+
+    /// Revoke every outstanding refresh token of a user, returning the
+    /// sessions that were still live
+    pub async fn revoke_user_sessions(&self, user_id: Uuid) -> Result<Vec<Uuid>> {
+        let mut sessions: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            RETURNING session_id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to revoke user sessions")?;
+
+        sessions.sort_unstable();
+        sessions.dedup();
+        Ok(sessions)
+    }
+
+    /// Drop refresh tokens that expired before `before`
+    pub async fn delete_expired_refresh_tokens(&self, before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < $1")
+            .bind(before)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired refresh tokens")?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn favorites(&self, user_id: Uuid) -> Result<Vec<Favorite>> {
+        sqlx::query_as(
+            r#"
+            SELECT f.id, f.location_id, COALESCE(f.name, l.name) AS name,
+                   l.latitude, l.longitude, l.address, f.created_at
+            FROM favorites f
+            JOIN locations l ON l.id = f.location_id
+            WHERE f.user_id = $1
+            ORDER BY f.created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch favorites")
+    }
+
+    /// Save `location_id` as a favorite of `user_id`, renaming it if it is
+    /// one already. Returns `None` when the location does not exist.
+    pub async fn save_favorite(&self, user_id: Uuid, location_id: Uuid, name: Option<&str>) -> Result<Option<Favorite>> {
+        sqlx::query_as(
+            r#"
+            WITH saved AS (
+                INSERT INTO favorites (user_id, location_id, name)
+                SELECT $1, id, $3 FROM locations WHERE id = $2
+                ON CONFLICT (user_id, location_id) DO UPDATE SET name = EXCLUDED.name
+                RETURNING id, location_id, name, created_at
+            )
+            SELECT s.id, s.location_id, COALESCE(s.name, l.name) AS name,
+                   l.latitude, l.longitude, l.address, s.created_at
+            FROM saved s
+            JOIN locations l ON l.id = s.location_id
+            "#,
+        )
+        .bind(user_id)
+        .bind(location_id)
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to save favorite")
+    }
+
+    pub async fn delete_favorite(&self, user_id: Uuid, location_id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM favorites WHERE user_id = $1 AND location_id = $2")
+            .bind(user_id)
+            .bind(location_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete favorite")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn record_search(
+        &self,
+        user_id: Option<Uuid>,
+        query: &str,
+        position: Option<(f64, f64)>,
+        results_count: i32,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO search_history (user_id, query, latitude, longitude, results_count)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(user_id)
+        .bind(query)
+        .bind(position.map(|(lat, _)| lat))
+        .bind(position.map(|(_, lng)| lng))
+        .bind(results_count)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record search")?;
+        Ok(())
+    }
+
+    /// A user's searches, most recent first
+    pub async fn search_history(&self, user_id: Uuid, limit: i64, offset: i64) -> Result<Vec<SearchHistoryEntry>> {
+        sqlx::query_as(
+            r#"
+            SELECT id, query, latitude, longitude, results_count, created_at
+            FROM search_history
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch search history")
+    }
+
+    /// Delete one entry of a user's search history, or all of it when `id`
+    /// is `None`
+    pub async fn delete_search_history(&self, user_id: Uuid, id: Option<Uuid>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM search_history WHERE user_id = $1 AND ($2::uuid IS NULL OR id = $2)")
+            .bind(user_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete search history")?;
+        Ok(result.rows_affected())
+    }
+
+    /// Reviews of a point of interest, newest first
+    pub async fn reviews(&self, poi_id: Uuid, limit: i64, offset: i64) -> Result<Vec<Review>> {
+        sqlx::query_as(
+            r#"
+            SELECT r.id, r.poi_id, r.user_id, u.username, r.rating, r.comment,
+                   r.helpful_count, r.created_at
+            FROM reviews r
+            LEFT JOIN users u ON u.id = r.user_id
+            WHERE r.poi_id = $1
+            ORDER BY r.created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(poi_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch reviews")
+    }
+
+    /// Returns `None` when the point of interest does not exist
+    pub async fn create_review(
+        &self,
+        poi_id: Uuid,
+        user_id: Uuid,
+        rating: i32,
+        comment: Option<&str>,
+    ) -> Result<Option<Review>> {
+        sqlx::query_as(
+            r#"
+            WITH created AS (
+                INSERT INTO reviews (poi_id, user_id, rating, comment, helpful_count)
+                SELECT id, $2, $3, $4, 0 FROM points_of_interest WHERE id = $1
+                RETURNING id, poi_id, user_id, rating, comment, helpful_count, created_at
+            )
+            SELECT c.id, c.poi_id, c.user_id, u.username, c.rating, c.comment,
+                   c.helpful_count, c.created_at
+            FROM created c
+            LEFT JOIN users u ON u.id = c.user_id
+            "#,
+        )
+        .bind(poi_id)
+        .bind(user_id)
+        .bind(rating)
+        .bind(comment)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to create review")
+    }
+
+    /// Delete a review written by `user_id`. Returns `false` when there is
+    /// no such review or someone else wrote it.
+    pub async fn delete_review(&self, id: Uuid, user_id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM reviews WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete review")?;
+        Ok(result.rows_affected() > 0)
+    }
+}
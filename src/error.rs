@@ -0,0 +1,52 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+use thiserror::Error;
+
+/// Failures inside the tile, terrain and map rendering services
+#[derive(Debug, Error)]
+pub enum MapError {
+    #[error("I/O error: {0}")]
+    IoError(String),
+}
+
+pub type Result<T> = std::result::Result<T, MapError>;
+
+/// An error returned from a handler, sent as a JSON body with the matching
+/// status code. Handlers log the underlying cause themselves, so internal
+/// errors only carry a message safe to show the client.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    InternalServerError(String),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        (
+            status,
+            Json(json!({
+                "error": status.canonical_reason().unwrap_or("Error"),
+                "message": self.to_string(),
+            })),
+        )
+            .into_response()
+    }
+}
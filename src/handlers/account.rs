@@ -0,0 +1,130 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+    routing::{delete, get},
+    Router,
+};
+use serde::Deserialize;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    database::{Favorite, SearchHistoryEntry},
+    error::AppError,
+    handlers::auth::AuthUser,
+    AppState,
+};
+
+const DEFAULT_HISTORY_LIMIT: i64 = 50;
+const MAX_HISTORY_LIMIT: i64 = 500;
+const MAX_FAVORITE_NAME_LEN: usize = 255;
+
+#[derive(Debug, Deserialize)]
+pub struct SaveFavoriteRequest {
+    pub location_id: Uuid,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// The signed in user's own data. Every route requires an access token.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/me/favorites", get(list_favorites).post(save_favorite))
+        .route("/me/favorites/:location_id", delete(delete_favorite))
+        .route("/me/history", get(list_history).delete(clear_history))
+        .route("/me/history/:id", delete(delete_history_entry))
+}
+
+pub async fn list_favorites(user: AuthUser, State(state): State<AppState>) -> Result<Json<Vec<Favorite>>, AppError> {
+    let favorites = state.db.favorites(user.id).await.map_err(|e| {
+        error!("Failed to fetch favorites for user {}: {:#}", user.id, e);
+        AppError::InternalServerError("Failed to fetch favorites".to_string())
+    })?;
+    Ok(Json(favorites))
+}
+
+/// `POST /me/favorites`, save a location, or rename it if it is saved already
+pub async fn save_favorite(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Json(request): Json<SaveFavoriteRequest>,
+) -> Result<Json<Favorite>, AppError> {
+    let name = request.name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+    if name.is_some_and(|name| name.chars().count() > MAX_FAVORITE_NAME_LEN) {
+        return Err(AppError::BadRequest(format!(
+            "Favorite name must be at most {} characters long",
+            MAX_FAVORITE_NAME_LEN
+        )));
+    }
+
+    let favorite = state
+        .db
+        .save_favorite(user.id, request.location_id, name)
+        .await
+        .map_err(|e| {
+            error!("Failed to save favorite for user {}: {:#}", user.id, e);
+            AppError::InternalServerError("Failed to save favorite".to_string())
+        })?
+        .ok_or_else(|| AppError::NotFound(format!("Location {} not found", request.location_id)))?;
+    Ok(Json(favorite))
+}
+
+pub async fn delete_favorite(
+    user: AuthUser,
+    Path(location_id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    let deleted = state.db.delete_favorite(user.id, location_id).await.map_err(|e| {
+        error!("Failed to delete favorite for user {}: {:#}", user.id, e);
+        AppError::InternalServerError("Failed to delete favorite".to_string())
+    })?;
+    if !deleted {
+        return Err(AppError::NotFound(format!("Location {} is not a favorite", location_id)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /me/history?limit=50&offset=0`, recent searches, newest first
+pub async fn list_history(
+    user: AuthUser,
+    Query(params): Query<HistoryQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SearchHistoryEntry>>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let history = state.db.search_history(user.id, limit, offset).await.map_err(|e| {
+        error!("Failed to fetch search history for user {}: {:#}", user.id, e);
+        AppError::InternalServerError("Failed to fetch search history".to_string())
+    })?;
+    Ok(Json(history))
+}
+
+pub async fn clear_history(user: AuthUser, State(state): State<AppState>) -> Result<StatusCode, AppError> {
+    delete_history(&state, user, None).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_history_entry(
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    if delete_history(&state, user, Some(id)).await? == 0 {
+        return Err(AppError::NotFound(format!("Search history entry {} not found", id)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_history(state: &AppState, user: AuthUser, id: Option<Uuid>) -> Result<u64, AppError> {
+    state.db.delete_search_history(user.id, id).await.map_err(|e| {
+        error!("Failed to delete search history for user {}: {:#}", user.id, e);
+        AppError::InternalServerError("Failed to delete search history".to_string())
+    })
+}
@@ -0,0 +1,179 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    database::User,
+    services::auth::{AuthError, TokenPair},
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    /// Username or email
+    #[serde(alias = "username", alias = "email")]
+    pub login: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthResponse {
+    pub user: User,
+    #[serde(flatten)]
+    pub tokens: TokenPair,
+}
+
+/// The user signed in with the request's `Authorization: Bearer` access
+/// token. Handlers taking it reject anonymous requests with 401; take
+/// `Option<AuthUser>` where signing in is optional.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser {
+    pub id: Uuid,
+    pub session_id: Uuid,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let (scheme, token) = value.split_once(' ')?;
+                scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+            })
+            .ok_or(AuthError::MissingToken)?;
+
+        let claims = state.auth_service.authenticate(token).await?;
+        Ok(AuthUser {
+            id: claims.sub,
+            session_id: claims.sid,
+        })
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            AuthError::Invalid(_) => StatusCode::BAD_REQUEST,
+            AuthError::AlreadyRegistered => StatusCode::CONFLICT,
+            AuthError::InvalidCredentials | AuthError::MissingToken | AuthError::InvalidToken => {
+                StatusCode::UNAUTHORIZED
+            }
+            AuthError::AccountDisabled => StatusCode::FORBIDDEN,
+            AuthError::Internal(e) => {
+                error!("Authentication failed: {:#}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        let message = match &self {
+            AuthError::Internal(_) => "Internal server error".to_string(),
+            other => other.to_string(),
+        };
+
+        let mut response = (
+            status,
+            Json(json!({
+                "error": status.canonical_reason().unwrap_or("Error"),
+                "message": message,
+            })),
+        )
+            .into_response();
+
+        // RFC 6750: tell the client which scheme to use, and whether the
+        // token it sent was the problem
+        let challenge = match &self {
+            AuthError::MissingToken => Some("Bearer"),
+            AuthError::InvalidToken => Some("Bearer error=\"invalid_token\""),
+            _ => None,
+        };
+        if let Some(challenge) = challenge {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+        }
+        response
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/auth/register", post(register))
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+        .route("/auth/logout-all", post(logout_all))
+        .route("/auth/me", get(me))
+}
+
+pub async fn register(
+    State(state): State<AppState>,
+    Json(request): Json<RegisterRequest>,
+) -> Result<(StatusCode, Json<AuthResponse>), AuthError> {
+    let (user, tokens) = state
+        .auth_service
+        .register(&request.username, &request.email, &request.password)
+        .await?;
+    Ok((StatusCode::CREATED, Json(AuthResponse { user, tokens })))
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    Json(request): Json<LoginRequest>,
+) -> Result<Json<AuthResponse>, AuthError> {
+    let (user, tokens) = state.auth_service.login(&request.login, &request.password).await?;
+    Ok(Json(AuthResponse { user, tokens }))
+}
+
+/// `POST /auth/refresh`, a new token pair for a refresh token, which can
+/// only be used once
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<Json<TokenPair>, AuthError> {
+    Ok(Json(state.auth_service.refresh(&request.refresh_token).await?))
+}
+
+/// `POST /auth/logout`, revoke the session of a refresh token
+pub async fn logout(
+    State(state): State<AppState>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<StatusCode, AuthError> {
+    state.auth_service.logout(&request.refresh_token).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `POST /auth/logout-all`, revoke every session of the signed in user
+pub async fn logout_all(user: AuthUser, State(state): State<AppState>) -> Result<StatusCode, AuthError> {
+    state.auth_service.logout_all(user.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn me(user: AuthUser, State(state): State<AppState>) -> Result<Json<User>, AuthError> {
+    let user = state.auth_service.user(user.id).await?.ok_or(AuthError::InvalidToken)?;
+    Ok(Json(user))
+}
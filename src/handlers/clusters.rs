@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use googlemaps_clone::constants::MAX_ZOOM;
+
+use crate::{
+    database::Location,
+    error::AppError,
+    utils::cluster::{ClusterId, ClusterIndex, ClusterItem},
+    utils::BoundingBox,
+    AppState,
+};
+
+const DEFAULT_LEAVES_LIMIT: usize = 10;
+const MAX_LEAVES_LIMIT: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ClustersQuery {
+    pub north: f64,
+    pub south: f64,
+    pub east: f64,
+    pub west: f64,
+    pub zoom: u8,
+    pub place_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClusterQuery {
+    pub place_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeavesQuery {
+    pub place_type: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClustersResponse<'a> {
+    pub zoom: u8,
+    pub clusters: Vec<ClusterItem<'a, Location>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClusterChildrenResponse<'a> {
+    pub id: ClusterId,
+    pub expansion_zoom: u8,
+    pub children: Vec<ClusterItem<'a, Location>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClusterLeavesResponse<'a> {
+    pub id: ClusterId,
+    pub leaves: Vec<&'a Location>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/clusters", get(get_clusters))
+        .route("/clusters/:id/children", get(get_cluster_children))
+        .route("/clusters/:id/leaves", get(get_cluster_leaves))
+}
+
+/// `GET /clusters?north=..&south=..&east=..&west=..&zoom=..[&place_type=..]`,
+/// the clusters and single locations to draw in the view
+pub async fn get_clusters(
+    Query(params): Query<ClustersQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let bounds = BoundingBox::new(params.north, params.south, params.east, params.west);
+    if !(-90.0..=90.0).contains(&bounds.south) || !(-90.0..=90.0).contains(&bounds.north) || bounds.south > bounds.north {
+        return Err(AppError::BadRequest("Invalid latitude bounds".to_string()));
+    }
+    if !(-180.0..=180.0).contains(&bounds.west) || !(-180.0..=180.0).contains(&bounds.east) {
+        return Err(AppError::BadRequest("Invalid longitude bounds".to_string()));
+    }
+    if params.zoom > MAX_ZOOM {
+        return Err(AppError::BadRequest(format!("Zoom must be at most {}", MAX_ZOOM)));
+    }
+
+    let index = cluster_index(&state, params.place_type.as_deref()).await?;
+    let response = ClustersResponse {
+        zoom: params.zoom,
+        clusters: index.clusters(&bounds, params.zoom),
+    };
+    Ok(Json(&response).into_response())
+}
+
+/// `GET /clusters/{id}/children`, what a cluster splits into when zooming in
+pub async fn get_cluster_children(
+    Path(id): Path<ClusterId>,
+    Query(params): Query<ClusterQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let index = cluster_index(&state, params.place_type.as_deref()).await?;
+    let (children, expansion_zoom) = index
+        .children(id)
+        .zip(index.expansion_zoom(id))
+        .ok_or_else(|| not_found(id))?;
+
+    Ok(Json(&ClusterChildrenResponse { id, expansion_zoom, children }).into_response())
+}
+
+/// `GET /clusters/{id}/leaves?limit=10&offset=0`, the locations in a cluster
+pub async fn get_cluster_leaves(
+    Path(id): Path<ClusterId>,
+    Query(params): Query<LeavesQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LEAVES_LIMIT).min(MAX_LEAVES_LIMIT);
+    let index = cluster_index(&state, params.place_type.as_deref()).await?;
+    let leaves = index
+        .leaves(id, limit, params.offset.unwrap_or(0))
+        .ok_or_else(|| not_found(id))?;
+
+    Ok(Json(&ClusterLeavesResponse { id, leaves }).into_response())
+}
+
+async fn cluster_index(state: &AppState, place_type: Option<&str>) -> Result<Arc<ClusterIndex<Location>>, AppError> {
+    state.cluster_service.index(place_type).await.map_err(|e| {
+        error!("Failed to build cluster index: {:#}", e);
+        AppError::InternalServerError("Failed to load locations".to_string())
+    })
+}
+
+fn not_found(id: ClusterId) -> AppError {
+    AppError::NotFound(format!("Cluster {} not found", id))
+}
@@ -0,0 +1,135 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    database::exchange::GpxImportSummary,
+    error::AppError,
+    handlers::auth::AuthUser,
+    utils::{gpx::GpxDocument, kml},
+    AppState,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Gpx,
+    Kml,
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Gpx => "application/gpx+xml",
+            ExportFormat::Kml => "application/vnd.google-earth.kml+xml",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Gpx => "gpx",
+            ExportFormat::Kml => "kml",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/routes/:route_id/export", get(export_route))
+        .route("/me/favorites/export", get(export_favorites))
+        .route("/routes/import", post(import_gpx))
+}
+
+/// `GET /routes/{id}/export?format=gpx|kml`. A route a user saved is only
+/// exported to that user; anyone else gets a 404.
+pub async fn export_route(
+    user: Option<AuthUser>,
+    Path(route_id): Path<Uuid>,
+    Query(params): Query<ExportQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let document = state
+        .db
+        .route_as_gpx(route_id, user.map(|user| user.id))
+        .await
+        .map_err(|e| {
+            error!("Failed to export route {}: {}", route_id, e);
+            AppError::InternalServerError("Failed to export route".to_string())
+        })?
+        .ok_or_else(|| AppError::NotFound(format!("Route {} not found", route_id)))?;
+
+    Ok(render(&document, params.format, &format!("route-{}", route_id)))
+}
+
+pub async fn export_favorites(
+    user: AuthUser,
+    Query(params): Query<ExportQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let document = state.db.favorites_as_gpx(user.id).await.map_err(|e| {
+        error!("Failed to export favorites for user {}: {}", user.id, e);
+        AppError::InternalServerError("Failed to export favorites".to_string())
+    })?;
+
+    Ok(render(&document, params.format, "favorites"))
+}
+
+/// `POST /routes/import`, store a GPX file's waypoints and routes, owned by
+/// the signed in user
+pub async fn import_gpx(
+    user: AuthUser,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<GpxImportSummary>), AppError> {
+    let input = std::str::from_utf8(&body)
+        .map_err(|_| AppError::BadRequest("GPX file must be UTF-8 encoded".to_string()))?;
+
+    let document = GpxDocument::parse(input)
+        .map_err(|e| AppError::BadRequest(format!("Invalid GPX file: {}", e)))?;
+
+    let summary = state.db.import_gpx(&document, user.id).await.map_err(|e| {
+        error!("Failed to import GPX file: {}", e);
+        AppError::InternalServerError("Failed to import GPX file".to_string())
+    })?;
+
+    info!(
+        "Imported {} locations and {} routes from GPX",
+        summary.location_ids.len(),
+        summary.route_ids.len()
+    );
+    Ok((StatusCode::CREATED, Json(summary)))
+}
+
+fn render(document: &GpxDocument, format: ExportFormat, file_stem: &str) -> Response {
+    let body = match format {
+        ExportFormat::Gpx => document.to_xml(),
+        ExportFormat::Kml => kml::to_kml(document),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    if let Ok(disposition) = HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}.{}\"",
+        file_stem,
+        format.extension()
+    )) {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+
+    (headers, body).into_response()
+}
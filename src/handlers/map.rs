@@ -1,12 +1,23 @@
-use actix_web::{web, HttpResponse, Result};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use tracing::error;
 use uuid::Uuid;
-use crate::models::{Location, Route, Place};
-use crate::services::geocoding::GeocodingService;
-use crate::services::routing::RoutingService;
-use crate::services::places::PlacesService;
-use crate::errors::AppError;
+
+use crate::{
+    database::{Database, MapBounds, NearbyLocation},
+    error::AppError,
+    models::route::{GeometryFormat, RouteGeometry},
+    models::{Location, Route},
+    services::geocoding::GeocodingService,
+    services::RoutingService,
+    AppState,
+};
 
 #[derive(Deserialize)]
 pub struct SearchQuery {
@@ -25,6 +36,11 @@ pub struct RouteQuery {
     pub travel_mode: Option<String>,
     pub avoid_tolls: Option<bool>,
     pub avoid_highways: Option<bool>,
+    pub geometry_format: Option<String>,
+    /// Include turn-by-turn `segments` in each route. Defaults to true, except
+    /// when the client explicitly asks for an encoded polyline format to keep
+    /// responses small.
+    pub steps: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -36,9 +52,23 @@ pub struct NearbyQuery {
     pub limit: Option<i32>,
 }
 
+#[derive(Deserialize)]
+pub struct BoundsQuery {
+    pub north: f64,
+    pub south: f64,
+    pub east: f64,
+    pub west: f64,
+}
+
+/// A polygon as a ring of `[lat, lng]` vertices; the ring is closed implicitly
+#[derive(Deserialize)]
+pub struct AreaRequest {
+    pub ring: Vec<(f64, f64)>,
+}
+
 #[derive(Serialize)]
-pub struct SearchResponse {
-    pub results: Vec<Place>,
+pub struct SearchResponse<T> {
+    pub results: Vec<T>,
     pub status: String,
 }
 
@@ -48,11 +78,51 @@ pub struct RouteResponse {
     pub status: String,
     pub distance: Option<f64>,
     pub duration: Option<i32>,
+    pub geometry_format: GeometryFormat,
+    /// Path of each entry in `routes`, in the requested `geometry_format`
+    pub geometries: Vec<RouteGeometry>,
+}
+
+impl RouteResponse {
+    /// Summarise `routes`, the first being the preferred one. Without
+    /// `include_steps` each route's segments are dropped, leaving the path
+    /// to `geometries`.
+    pub fn new(mut routes: Vec<Route>, status: &str, geometry_format: GeometryFormat, include_steps: bool) -> Self {
+        let geometries = routes.iter().map(|route| route.geometry(geometry_format)).collect();
+        let distance = routes.first().map(|route| route.total_distance);
+        let duration = routes.first().map(|route| route.total_duration);
+        if !include_steps {
+            for route in &mut routes {
+                route.segments.clear();
+            }
+        }
+
+        Self {
+            routes,
+            status: status.to_string(),
+            distance,
+            duration,
+            geometry_format,
+            geometries,
+        }
+    }
 }
 
 #[derive(Serialize)]
 pub struct NearbyResponse {
-    pub places: Vec<Place>,
+    pub places: Vec<NearbyLocation>,
+    pub status: String,
+}
+
+#[derive(Serialize)]
+pub struct LocationsResponse {
+    pub locations: Vec<Location>,
+    pub status: String,
+}
+
+#[derive(Serialize)]
+pub struct RoutesInViewResponse {
+    pub route_ids: Vec<Uuid>,
     pub status: String,
 }
 
@@ -62,49 +132,196 @@ pub struct GeocodeResponse {
     pub status: String,
 }
 
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/places/search", get(search_places))
+        .route("/places/nearby", get(nearby_places))
+        .route("/maps/locations", get(locations_in_view))
+        .route("/maps/locations/area", post(locations_in_area))
+        .route("/maps/routes", get(routes_in_view))
+        .route("/directions", get(get_directions))
+}
+
+/// `GET /places/search`, places matching `q`, nearest first when a position
+/// is given
 pub async fn search_places(
-    query: web::Query<SearchQuery>,
-    pool: web::Data<PgPool>,
-) -> Result<HttpResponse, AppError> {
-    let places_service = PlacesService::new(pool.get_ref());
-    
+    Query(query): Query<SearchQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
     let limit = query.limit.unwrap_or(20).min(50);
     let radius = query.radius.unwrap_or(5000);
-    
-    let results = if let (Some(lat), Some(lng)) = (query.lat, query.lng) {
-        places_service
-            .search_nearby(&query.q, lat, lng, radius, limit)
-            .await?
-    } else {
-        places_service
-            .search_global(&query.q, limit)
-            .await?
-    };
 
-    Ok(HttpResponse::Ok().json(SearchResponse {
+    if let (Some(lat), Some(lng)) = (query.lat, query.lng) {
+        let results = nearby(&state.db, lat, lng, radius, None, Some(&query.q), limit).await?;
+        return Ok(Json(SearchResponse {
+            results,
+            status: "OK".to_string(),
+        })
+        .into_response());
+    }
+
+    let results = state.db.search_locations(&query.q, limit.max(1) as i64).await.map_err(|e| {
+        error!("Failed to search places: {:#}", e);
+        AppError::InternalServerError("Failed to search places".to_string())
+    })?;
+
+    Ok(Json(SearchResponse {
         results,
         status: "OK".to_string(),
+    })
+    .into_response())
+}
+
+/// `GET /places/nearby`, places within `radius` meters, nearest first
+pub async fn nearby_places(
+    Query(query): Query<NearbyQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<NearbyResponse>, AppError> {
+    let limit = query.limit.unwrap_or(20).min(50);
+    let radius = query.radius.unwrap_or(1000);
+
+    let places = nearby(&state.db, query.lat, query.lng, radius, query.place_type.as_deref(), None, limit).await?;
+
+    Ok(Json(NearbyResponse {
+        places,
+        status: "OK".to_string(),
     }))
 }
 
+async fn nearby(
+    db: &Database,
+    lat: f64,
+    lng: f64,
+    radius: i32,
+    place_type: Option<&str>,
+    name: Option<&str>,
+    limit: i32,
+) -> Result<Vec<NearbyLocation>, AppError> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return Err(AppError::BadRequest("Invalid coordinates".to_string()));
+    }
+    if radius <= 0 {
+        return Err(AppError::BadRequest("Radius must be positive".to_string()));
+    }
+
+    db.locations_within_radius(lat, lng, radius as f64, place_type, name, limit.max(1) as i64)
+        .await
+        .map_err(|e| {
+            error!("Failed to search nearby places: {:#}", e);
+            AppError::InternalServerError("Failed to search nearby places".to_string())
+        })
+}
+
+/// Locations inside the map view, using the spatial index when PostGIS is enabled
+pub async fn locations_in_view(
+    Query(query): Query<BoundsQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<LocationsResponse>, AppError> {
+    let bounds = map_bounds(&query)?;
+    let locations = state.db.locations_in_bounds(&bounds).await.map_err(|e| {
+        error!("Failed to fetch locations in bounds: {:#}", e);
+        AppError::InternalServerError("Failed to fetch locations".to_string())
+    })?;
+
+    Ok(Json(LocationsResponse {
+        locations,
+        status: "OK".to_string(),
+    }))
+}
+
+/// Locations inside a drawn polygon
+pub async fn locations_in_area(
+    State(state): State<AppState>,
+    Json(request): Json<AreaRequest>,
+) -> Result<Json<LocationsResponse>, AppError> {
+    if request.ring.len() < 3 {
+        return Err(AppError::BadRequest("Polygon must have at least 3 vertices".to_string()));
+    }
+    if request.ring.iter().any(|&(lat, lng)| !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng)) {
+        return Err(AppError::BadRequest("Invalid polygon coordinates".to_string()));
+    }
+
+    let locations = state.db.locations_in_polygon(&request.ring).await.map_err(|e| {
+        error!("Failed to fetch locations in polygon: {:#}", e);
+        AppError::InternalServerError("Failed to fetch locations".to_string())
+    })?;
+
+    Ok(Json(LocationsResponse {
+        locations,
+        status: "OK".to_string(),
+    }))
+}
+
+/// Stored routes crossing the map view
+pub async fn routes_in_view(
+    Query(query): Query<BoundsQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<RoutesInViewResponse>, AppError> {
+    let bounds = map_bounds(&query)?;
+    let route_ids = state.db.routes_in_bounds(&bounds).await.map_err(|e| {
+        error!("Failed to fetch routes in bounds: {:#}", e);
+        AppError::InternalServerError("Failed to fetch routes".to_string())
+    })?;
+
+    Ok(Json(RoutesInViewResponse {
+        route_ids,
+        status: "OK".to_string(),
+    }))
+}
+
+fn map_bounds(query: &BoundsQuery) -> Result<MapBounds, AppError> {
+    if !(-90.0..=90.0).contains(&query.south) || !(-90.0..=90.0).contains(&query.north) || query.south > query.north {
+        return Err(AppError::BadRequest("Invalid latitude bounds".to_string()));
+    }
+    if !(-180.0..=180.0).contains(&query.west) || !(-180.0..=180.0).contains(&query.east) {
+        return Err(AppError::BadRequest("Invalid longitude bounds".to_string()));
+    }
+
+    Ok(MapBounds {
+        north: query.north,
+        south: query.south,
+        east: query.east,
+        west: query.west,
+    })
+}
+
 pub async fn get_directions(
-    query: web::Query<RouteQuery>,
-    pool: web::Data<PgPool>,
-) -> Result<HttpResponse, AppError> {
-    let routing_service = RoutingService::new(pool.get_ref());
-    let geocoding_service = GeocodingService::new();
+    Query(query): Query<RouteQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let routing_service = RoutingService::new(state.db.pool());
+    let geocoding_service = GeocodingService::new(&state.config.google_api_key);
+
+    let geometry_format = match query.geometry_format.as_deref() {
+        Some(format) => match format.parse::<GeometryFormat>() {
+            Ok(format) => format,
+            Err(message) => {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "status": "INVALID_REQUEST",
+                        "error": message,
+                    })),
+                )
+                    .into_response());
+            }
+        },
+        None => GeometryFormat::default(),
+    };
+    let include_steps = query
+        .steps
+        .unwrap_or(!matches!(geometry_format, GeometryFormat::Polyline5 | GeometryFormat::Polyline6));
 
     // Geocode origin and destination
-    let origin_location = geocoding_service.geocode(&query.origin).await?;
-    let destination_location = geocoding_service.geocode(&query.destination).await?;
+    let origin_location = geocode(&geocoding_service, &query.origin).await?;
+    let destination_location = geocode(&geocoding_service, &query.destination).await?;
 
     if origin_location.is_empty() || destination_location.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(RouteResponse {
-            routes: vec![],
-            status: "ZERO_RESULTS".to_string(),
-            distance: None,
-            duration: None,
-        }));
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(RouteResponse::new(vec![], "ZERO_RESULTS", geometry_format, include_steps)),
+        )
+            .into_response());
     }
 
     let origin = &origin_location[0];
@@ -114,6 +331,35 @@ pub async fn get_directions(
     let mut waypoint_locations = Vec::new();
     if let Some(waypoints) = &query.waypoints {
         for waypoint in waypoints {
-            let locations = geocoding_service.geocode(waypoint).await?;
+            let locations = geocode(&geocoding_service, waypoint).await?;
             if !locations.is_empty() {
-                waypoint_locations.push(locations[0].
\ No newline at end of file
+                waypoint_locations.push(locations[0].clone());
+            }
+        }
+    }
+
+    let routes = routing_service
+        .calculate_routes(
+            origin,
+            destination,
+            &waypoint_locations,
+            query.travel_mode.as_deref().unwrap_or("driving"),
+            query.avoid_tolls.unwrap_or(false),
+            query.avoid_highways.unwrap_or(false),
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to calculate routes: {}", e);
+            AppError::InternalServerError("Failed to calculate routes".to_string())
+        })?;
+
+    let status = if routes.is_empty() { "ZERO_RESULTS" } else { "OK" };
+    Ok(Json(RouteResponse::new(routes, status, geometry_format, include_steps)).into_response())
+}
+
+async fn geocode(geocoding_service: &GeocodingService, address: &str) -> Result<Vec<Location>, AppError> {
+    geocoding_service.geocode(address).await.map_err(|e| {
+        error!("Failed to geocode {:?}: {}", address, e);
+        AppError::InternalServerError("Failed to geocode address".to_string())
+    })
+}
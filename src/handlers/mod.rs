@@ -1,57 +1,52 @@
+pub mod account;
 pub mod auth;
-pub mod maps;
-pub mod routes;
+pub mod clusters;
+pub mod export;
+pub mod map;
+pub mod navigation;
+pub mod ogc;
+pub mod static_map;
+pub mod reviews;
 pub mod search;
-pub mod directions;
-pub mod places;
-pub mod geocoding;
+pub mod tiles;
 
-use actix_web::{web, HttpResponse, Result};
-use serde_json::json;
+use axum::{http::StatusCode, response::Json, routing::get, Router};
+use serde_json::{json, Value};
 
-pub async fn health_check() -> Result<HttpResponse> {
-    Ok(HttpResponse::Ok().json(json!({
+use crate::AppState;
+
+pub async fn health_check() -> Json<Value> {
+    Json(json!({
         "status": "healthy",
         "service": "googlemaps-clone",
         "version": "1.0.0"
-    })))
+    }))
 }
 
-pub async fn not_found() -> Result<HttpResponse> {
-    Ok(HttpResponse::NotFound().json(json!({
-        "error": "Not Found",
-        "message": "The requested resource was not found"
-    })))
+pub async fn not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": "Not Found",
+            "message": "The requested resource was not found"
+        })),
+    )
 }
 
-pub fn configure_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/api/v1")
-            .service(
-                web::scope("/auth")
-                    .configure(auth::configure_auth_routes)
-            )
-            .service(
-                web::scope("/maps")
-                    .configure(maps::configure_maps_routes)
-            )
-            .service(
-                web::scope("/search")
-                    .configure(search::configure_search_routes)
-            )
-            .service(
-                web::scope("/directions")
-                    .configure(directions::configure_directions_routes)
-            )
-            .service(
-                web::scope("/places")
-                    .configure(places::configure_places_routes)
-            )
-            .service(
-                web::scope("/geocoding")
-                    .configure(geocoding::configure_geocoding_routes)
-            )
-            .route("/health", web::get().to(health_check))
-    )
-    .default_service(web::route().to(not_found));
-}
\ No newline at end of file
+/// Every API endpoint, mounted under `/api/v1`
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/health", get(health_check))
+        .merge(account::routes())
+        .merge(auth::routes())
+        .merge(clusters::routes())
+        .merge(export::routes())
+        .merge(map::routes())
+        .merge(navigation::routes())
+        .merge(ogc::routes())
+        .merge(reviews::routes())
+        .merge(search::routes())
+        .merge(static_map::routes())
+        .merge(tiles::routes())
+        .fallback(not_found)
+}
@@ -0,0 +1,158 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    handlers::auth::AuthUser,
+    services::navigation_service::{NavigationEvent, PositionUpdate},
+    services::voice_guidance::VoiceGuidance,
+    AppState,
+};
+
+/// Messages sent by the client over the navigation socket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Follow a stored route; the server loads it, so clients can't steer
+    /// rerouting or progress with a route of their own making
+    Start {
+        route_id: Uuid,
+        #[serde(default)]
+        voice: VoiceGuidance,
+    },
+    Position(PositionUpdate),
+    Stop,
+}
+
+/// Messages sent by the server in addition to `NavigationEvent`s
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    SessionStarted { session_id: String },
+    SessionEnded { session_id: String },
+    Error { message: String },
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/navigation/ws", get(navigation_socket))
+}
+
+/// `GET /navigation/ws`, upgraded to the navigation socket for a signed in user
+pub async fn navigation_socket(user: AuthUser, ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, state, user))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: AppState, user: AuthUser) {
+    let navigation = state.navigation_service.clone();
+    let mut session_id: Option<String> = None;
+
+    while let Some(message) = socket.recv().await {
+        let text = match message {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Navigation socket error: {}", e);
+                break;
+            }
+        };
+
+        let client_message = match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                let reply = ServerMessage::Error {
+                    message: format!("Invalid message: {}", e),
+                };
+                if send_json(&mut socket, &reply).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let sent = match client_message {
+            ClientMessage::Start { route_id, voice } => {
+                if let Some(previous) = session_id.take() {
+                    navigation.end_session(&previous).await;
+                }
+                match state.db.navigation_route(route_id, user.id).await {
+                    Ok(Some(route)) => {
+                        let id = navigation.start_session(route, voice).await;
+                        session_id = Some(id.clone());
+                        send_json(&mut socket, &ServerMessage::SessionStarted { session_id: id }).await
+                    }
+                    Ok(None) => {
+                        let reply = ServerMessage::Error {
+                            message: format!("Route {} not found", route_id),
+                        };
+                        send_json(&mut socket, &reply).await
+                    }
+                    Err(e) => {
+                        error!("Failed to load route {} for navigation: {:#}", route_id, e);
+                        let reply = ServerMessage::Error {
+                            message: "Failed to load route".to_string(),
+                        };
+                        send_json(&mut socket, &reply).await
+                    }
+                }
+            }
+            ClientMessage::Position(position) => {
+                let Some(id) = session_id.as_deref() else {
+                    let reply = ServerMessage::Error {
+                        message: "No active navigation session".to_string(),
+                    };
+                    if send_json(&mut socket, &reply).await.is_err() {
+                        break;
+                    }
+                    continue;
+                };
+
+                let events = navigation.update_position(id, &position).await.unwrap_or_default();
+                send_events(&mut socket, &events).await
+            }
+            ClientMessage::Stop => {
+                if let Some(id) = session_id.take() {
+                    navigation.end_session(&id).await;
+                    send_json(&mut socket, &ServerMessage::SessionEnded { session_id: id }).await
+                } else {
+                    Ok(())
+                }
+            }
+        };
+
+        if sent.is_err() {
+            break;
+        }
+    }
+
+    if let Some(id) = session_id {
+        navigation.end_session(&id).await;
+    }
+    info!("Navigation socket closed");
+}
+
+async fn send_events(socket: &mut WebSocket, events: &[NavigationEvent]) -> Result<(), axum::Error> {
+    for event in events {
+        send_json(socket, event).await?;
+    }
+    Ok(())
+}
+
+async fn send_json<T: Serialize>(socket: &mut WebSocket, message: &T) -> Result<(), axum::Error> {
+    match serde_json::to_string(message) {
+        Ok(text) => socket.send(Message::Text(text)).await,
+        Err(e) => {
+            error!("Failed to serialize navigation message: {}", e);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use quick_xml::escape::escape;
+use tracing::error;
+
+use crate::{
+    handlers::tiles::{content_type, split_extension},
+    models::tile::TileFormat,
+    services::ogc_service::{MapRequest, OgcError},
+    utils::tile_matrix::Extent,
+    utils::tile_utils::TileCoord,
+    AppState,
+};
+
+const XML_CONTENT_TYPE: &str = "application/xml";
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/wmts", get(wmts))
+        .route("/wmts/1.0.0/WMTSCapabilities.xml", get(wmts_capabilities))
+        .route("/wmts/:layer/:style/:tile_matrix_set/:tile_matrix/:row/:col", get(wmts_rest_tile))
+        .route("/wms", get(wms))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Service {
+    Wmts,
+    Wms,
+}
+
+/// An error reported as an OGC exception document: an OWS
+/// `ExceptionReport` for WMTS and a `ServiceExceptionReport` for WMS
+pub struct OgcException {
+    service: Service,
+    error: OgcError,
+}
+
+impl OgcException {
+    fn wmts(error: OgcError) -> Self {
+        Self { service: Service::Wmts, error }
+    }
+
+    fn wms(error: OgcError) -> Self {
+        Self { service: Service::Wms, error }
+    }
+}
+
+impl IntoResponse for OgcException {
+    fn into_response(self) -> Response {
+        let status = match &self.error {
+            OgcError::Unavailable(_) => StatusCode::NOT_FOUND,
+            OgcError::Internal(message) => {
+                error!("OGC request failed: {}", message);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let message = match &self.error {
+            OgcError::Internal(_) => "Internal server error".to_string(),
+            other => other.to_string(),
+        };
+        let locator = self
+            .error
+            .locator()
+            .map(|locator| format!(" locator=\"{}\"", escape(locator)))
+            .unwrap_or_default();
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        match self.service {
+            Service::Wmts => {
+                xml.push_str("<ows:ExceptionReport xmlns:ows=\"http://www.opengis.net/ows/1.1\" version=\"1.1.0\">\n");
+                let _ = writeln!(
+                    xml,
+                    "  <ows:Exception exceptionCode=\"{}\"{}><ows:ExceptionText>{}</ows:ExceptionText></ows:Exception>",
+                    self.error.code(),
+                    locator,
+                    escape(&message)
+                );
+                xml.push_str("</ows:ExceptionReport>\n");
+            }
+            Service::Wms => {
+                xml.push_str("<ServiceExceptionReport xmlns=\"http://www.opengis.net/ogc\" version=\"1.3.0\">\n");
+                let _ = writeln!(
+                    xml,
+                    "  <ServiceException code=\"{}\"{}>{}</ServiceException>",
+                    self.error.code(),
+                    locator,
+                    escape(&message)
+                );
+                xml.push_str("</ServiceExceptionReport>\n");
+            }
+        }
+
+        (status, [(header::CONTENT_TYPE, XML_CONTENT_TYPE)], xml).into_response()
+    }
+}
+
+/// Key-value pair request parameters. OGC parameter names are case
+/// insensitive, so they are looked up lowercased.
+struct KvpParams(HashMap<String, String>);
+
+impl KvpParams {
+    fn new(raw: HashMap<String, String>) -> Self {
+        Self(raw.into_iter().map(|(key, value)| (key.to_ascii_lowercase(), value)).collect())
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str).filter(|value| !value.is_empty())
+    }
+
+    fn required(&self, name: &str) -> Result<&str, OgcError> {
+        self.get(name).ok_or_else(|| OgcError::MissingParameter(name.to_string()))
+    }
+
+    fn parse<T: FromStr>(&self, name: &str) -> Result<T, OgcError> {
+        parse_value(name, self.required(name)?)
+    }
+
+    /// Check `SERVICE` when given; some clients leave it out
+    fn expect_service(&self, service: &str) -> Result<(), OgcError> {
+        match self.get("service") {
+            Some(value) if !value.eq_ignore_ascii_case(service) => {
+                Err(OgcError::InvalidParameter("service".to_string(), value.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, OgcError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| OgcError::InvalidParameter(name.to_string(), value.to_string()))
+}
+
+/// `image/png`, also with parameters such as `image/png; mode=8bit`
+fn format_from_mime(mime: &str) -> Result<TileFormat, OgcError> {
+    let base = mime.split(';').next().unwrap_or_default().trim();
+    match base.to_ascii_lowercase().as_str() {
+        "image/png" | "png" => Ok(TileFormat::Png),
+        "image/jpeg" | "image/jpg" | "jpeg" | "jpg" => Ok(TileFormat::Jpeg),
+        "image/webp" | "webp" => Ok(TileFormat::Webp),
+        _ => Err(OgcError::InvalidFormat(mime.to_string())),
+    }
+}
+
+fn xml_response(xml: String) -> Response {
+    ([(header::CONTENT_TYPE, XML_CONTENT_TYPE)], xml).into_response()
+}
+
+fn image_response(state: &AppState, image: Vec<u8>, format: &TileFormat, zoom: Option<u8>) -> Response {
+    let cache_control = match zoom {
+        Some(zoom) => state.tile_service.cache_control(zoom),
+        None => "public, max-age=3600".to_string(),
+    };
+    (
+        [(header::CONTENT_TYPE, content_type(format).to_string()), (header::CACHE_CONTROL, cache_control)],
+        image,
+    )
+        .into_response()
+}
+
+/// `GET /wmts?SERVICE=WMTS&REQUEST=GetCapabilities|GetTile&...`
+pub async fn wmts(
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Result<Response, OgcException> {
+    let params = KvpParams::new(params);
+    params.expect_service("WMTS").map_err(OgcException::wmts)?;
+
+    let request = params.required("request").map_err(OgcException::wmts)?;
+    if request.eq_ignore_ascii_case("GetCapabilities") {
+        return Ok(xml_response(state.ogc_service.wmts_capabilities()));
+    }
+    if !request.eq_ignore_ascii_case("GetTile") {
+        return Err(OgcException::wmts(OgcError::InvalidParameter(
+            "request".to_string(),
+            request.to_string(),
+        )));
+    }
+
+    let tile = parse_tile_request(&params).map_err(OgcException::wmts)?;
+    let image = state
+        .ogc_service
+        .get_tile(&tile.layer, tile.style.as_deref(), &tile.tile_matrix_set, tile.coord, tile.format.clone())
+        .await
+        .map_err(OgcException::wmts)?;
+    Ok(image_response(&state, image, &tile.format, Some(tile.coord.z)))
+}
+
+/// `GET /wmts/1.0.0/WMTSCapabilities.xml`
+pub async fn wmts_capabilities(State(state): State<AppState>) -> Response {
+    xml_response(state.ogc_service.wmts_capabilities())
+}
+
+/// `GET /wmts/{layer}/{style}/{tile_matrix_set}/{tile_matrix}/{row}/{col}.{png|jpg|webp}`,
+/// the RESTful template advertised in the capabilities
+pub async fn wmts_rest_tile(
+    Path((layer, style, tile_matrix_set, tile_matrix, row, col)): Path<(String, String, String, String, String, String)>,
+    State(state): State<AppState>,
+) -> Result<Response, OgcException> {
+    let (col, extension) = split_extension(&col);
+    let format = format_from_mime(extension.unwrap_or("png")).map_err(OgcException::wmts)?;
+    let coord = tile_coord(&tile_matrix, &row, col).map_err(OgcException::wmts)?;
+
+    let image = state
+        .ogc_service
+        .get_tile(&layer, Some(&style), &tile_matrix_set, coord, format.clone())
+        .await
+        .map_err(OgcException::wmts)?;
+    Ok(image_response(&state, image, &format, Some(coord.z)))
+}
+
+/// `GET /wms?SERVICE=WMS&REQUEST=GetCapabilities|GetMap&...`
+pub async fn wms(
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Result<Response, OgcException> {
+    let params = KvpParams::new(params);
+    params.expect_service("WMS").map_err(OgcException::wms)?;
+
+    let request = params.required("request").map_err(OgcException::wms)?;
+    if request.eq_ignore_ascii_case("GetCapabilities") {
+        return Ok(xml_response(state.ogc_service.wms_capabilities()));
+    }
+    if !request.eq_ignore_ascii_case("GetMap") {
+        return Err(OgcException::wms(OgcError::InvalidParameter(
+            "request".to_string(),
+            request.to_string(),
+        )));
+    }
+
+    let map = parse_map_request(&params).map_err(OgcException::wms)?;
+    let image = state.ogc_service.get_map(&map).await.map_err(OgcException::wms)?;
+    Ok(image_response(&state, image, &map.format, None))
+}
+
+/// A WMTS GetTile request in KVP encoding
+struct TileRequest {
+    layer: String,
+    style: Option<String>,
+    tile_matrix_set: String,
+    coord: TileCoord,
+    format: TileFormat,
+}
+
+fn parse_tile_request(params: &KvpParams) -> Result<TileRequest, OgcError> {
+    Ok(TileRequest {
+        layer: params.required("layer")?.to_string(),
+        style: params.get("style").map(str::to_string),
+        tile_matrix_set: params.required("tilematrixset")?.to_string(),
+        coord: tile_coord(params.required("tilematrix")?, params.required("tilerow")?, params.required("tilecol")?)?,
+        format: format_from_mime(params.get("format").unwrap_or("image/png"))?,
+    })
+}
+
+/// Tile matrix identifiers are the zoom levels
+fn tile_coord(tile_matrix: &str, row: &str, col: &str) -> Result<TileCoord, OgcError> {
+    Ok(TileCoord::new(
+        parse_value("tilecol", col)?,
+        parse_value("tilerow", row)?,
+        parse_value("tilematrix", tile_matrix)?,
+    ))
+}
+
+/// Read a GetMap request. WMS 1.3.0 takes `CRS` and orders the bounding box
+/// by the CRS's axes, so EPSG:4326 boxes come latitude first; 1.1.x takes
+/// `SRS` and always puts x first.
+fn parse_map_request(params: &KvpParams) -> Result<MapRequest, OgcError> {
+    let version = params.get("version").unwrap_or("1.3.0");
+    let legacy = version.starts_with("1.1");
+    let crs = if legacy { params.required("srs")? } else { params.required("crs")? };
+
+    let bbox_value = params.required("bbox")?;
+    let bbox: Vec<f64> = bbox_value
+        .split(',')
+        .map(|value| value.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| OgcError::InvalidParameter("bbox".to_string(), bbox_value.to_string()))?;
+    let [a, b, c, d] = bbox[..] else {
+        return Err(OgcError::InvalidParameter("bbox".to_string(), bbox_value.to_string()));
+    };
+    let extent = if !legacy && crs.eq_ignore_ascii_case("EPSG:4326") {
+        Extent { min_x: b, min_y: a, max_x: d, max_y: c }
+    } else {
+        Extent { min_x: a, min_y: b, max_x: c, max_y: d }
+    };
+
+    let names: Vec<&str> = params.required("layers")?.split(',').collect();
+    let styles: Vec<&str> = params.get("styles").map(|styles| styles.split(',').collect()).unwrap_or_default();
+    let layers = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let style = styles.get(i).filter(|style| !style.is_empty()).map(|style| style.to_string());
+            (name.to_string(), style)
+        })
+        .collect();
+
+    let transparent = params
+        .get("transparent")
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+    let background = match params.get("bgcolor") {
+        Some(value) => {
+            let hex = value.trim_start_matches("0x").trim_start_matches("0X").trim_start_matches('#');
+            let rgb = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)
+                .ok_or_else(|| OgcError::InvalidParameter("bgcolor".to_string(), value.to_string()))?;
+            [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]
+        }
+        None => [255, 255, 255],
+    };
+
+    Ok(MapRequest {
+        layers,
+        crs: crs.to_string(),
+        extent,
+        width: params.parse("width")?,
+        height: params.parse("height")?,
+        format: format_from_mime(params.required("format")?)?,
+        transparent,
+        background,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(query: &[(&str, &str)]) -> KvpParams {
+        KvpParams::new(query.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect())
+    }
+
+    fn get_map(extra: &[(&str, &str)]) -> Result<MapRequest, OgcError> {
+        let mut query = vec![
+            ("SERVICE", "WMS"),
+            ("REQUEST", "GetMap"),
+            ("LAYERS", "basemap"),
+            ("CRS", "EPSG:3857"),
+            ("BBOX", "-20037508,-20037508,20037508,20037508"),
+            ("WIDTH", "512"),
+            ("HEIGHT", "256"),
+            ("FORMAT", "image/png"),
+        ];
+        for (key, value) in extra {
+            query.retain(|(existing, _)| !existing.eq_ignore_ascii_case(key));
+            query.push((key, value));
+        }
+        parse_map_request(&params(&query))
+    }
+
+    #[test]
+    fn parameter_names_are_case_insensitive() {
+        let params = params(&[("Service", "wmts"), ("LAYER", "basemap"), ("style", "")]);
+        assert_eq!(params.get("layer"), Some("basemap"));
+        assert_eq!(params.get("style"), None, "empty values count as missing");
+        assert!(params.expect_service("WMTS").is_ok());
+        assert!(matches!(params.expect_service("WMS"), Err(OgcError::InvalidParameter(name, _)) if name == "service"));
+    }
+
+    #[test]
+    fn reads_get_map_requests() {
+        let request = get_map(&[("LAYERS", "basemap,hillshade"), ("STYLES", "dark,")]).unwrap();
+        assert_eq!(
+            request.layers,
+            [("basemap".to_string(), Some("dark".to_string())), ("hillshade".to_string(), None)]
+        );
+        assert_eq!(request.crs, "EPSG:3857");
+        assert_eq!((request.width, request.height), (512, 256));
+        assert_eq!(request.format, TileFormat::Png);
+        assert!(!request.transparent);
+        assert_eq!(request.background, [255, 255, 255]);
+    }
+
+    #[test]
+    fn epsg_4326_boxes_are_latitude_first_in_wms_1_3() {
+        let request = get_map(&[("CRS", "EPSG:4326"), ("BBOX", "40,-10,60,20")]).unwrap();
+        assert_eq!(request.extent, Extent { min_x: -10.0, min_y: 40.0, max_x: 20.0, max_y: 60.0 });
+
+        let request = get_map(&[("CRS", "CRS:84"), ("BBOX", "-10,40,20,60")]).unwrap();
+        assert_eq!(request.extent, Extent { min_x: -10.0, min_y: 40.0, max_x: 20.0, max_y: 60.0 });
+    }
+
+    #[test]
+    fn wms_1_1_uses_srs_and_x_first_boxes() {
+        let mut query = vec![
+            ("VERSION", "1.1.1"),
+            ("LAYERS", "basemap"),
+            ("SRS", "EPSG:4326"),
+            ("BBOX", "-10,40,20,60"),
+            ("WIDTH", "256"),
+            ("HEIGHT", "256"),
+            ("FORMAT", "image/jpeg"),
+        ];
+        let request = parse_map_request(&params(&query)).unwrap();
+        assert_eq!(request.crs, "EPSG:4326");
+        assert_eq!(request.extent, Extent { min_x: -10.0, min_y: 40.0, max_x: 20.0, max_y: 60.0 });
+        assert_eq!(request.format, TileFormat::Jpeg);
+
+        query.retain(|(key, _)| *key != "SRS");
+        query.push(("CRS", "EPSG:4326"));
+        assert!(matches!(parse_map_request(&params(&query)), Err(OgcError::MissingParameter(name)) if name == "srs"));
+    }
+
+    #[test]
+    fn reads_transparency_and_background_colors() {
+        let request = get_map(&[("TRANSPARENT", "TRUE"), ("BGCOLOR", "0x1A2b3C")]).unwrap();
+        assert!(request.transparent);
+        assert_eq!(request.background, [0x1a, 0x2b, 0x3c]);
+
+        for color in ["0xFFF", "0xGGGGGG", "red"] {
+            assert!(matches!(get_map(&[("BGCOLOR", color)]), Err(OgcError::InvalidParameter(name, _)) if name == "bgcolor"));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_get_map_requests() {
+        for bbox in ["1,2,3", "1,2,3,4,5", "a,b,c,d"] {
+            assert!(matches!(get_map(&[("BBOX", bbox)]), Err(OgcError::InvalidParameter(name, _)) if name == "bbox"));
+        }
+        assert!(matches!(get_map(&[("WIDTH", "wide")]), Err(OgcError::InvalidParameter(name, _)) if name == "width"));
+        assert!(matches!(get_map(&[("FORMAT", "image/gif")]), Err(OgcError::InvalidFormat(_))));
+        assert!(matches!(get_map(&[("LAYERS", "")]), Err(OgcError::MissingParameter(name)) if name == "layers"));
+    }
+
+    #[test]
+    fn reads_get_tile_requests() {
+        let request = parse_tile_request(&params(&[
+            ("layer", "basemap"),
+            ("tilematrixset", "WebMercatorQuad"),
+            ("TileMatrix", "14"),
+            ("TileRow", "6332"),
+            ("TileCol", "2620"),
+            ("Format", "image/png; mode=8bit"),
+        ]))
+        .unwrap();
+        assert_eq!(request.coord, TileCoord::new(2620, 6332, 14));
+        assert_eq!(request.style, None);
+        assert_eq!(request.format, TileFormat::Png);
+
+        let missing = parse_tile_request(&params(&[("layer", "basemap"), ("tilematrixset", "WebMercatorQuad")]));
+        assert!(matches!(missing, Err(OgcError::MissingParameter(name)) if name == "tilematrix"));
+        assert!(matches!(tile_coord("14", "-1", "2620"), Err(OgcError::InvalidParameter(name, _)) if name == "tilerow"));
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_errors_as_exception_documents() {
+        let response = OgcException::wms(OgcError::LayerNotDefined("roads & rails".to_string())).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], XML_CONTENT_TYPE);
+        assert!(body(response).await.contains(
+            "<ServiceException code=\"LayerNotDefined\" locator=\"layer\">Unknown layer: roads &amp; rails</ServiceException>"
+        ));
+
+        let response = OgcException::wmts(OgcError::MissingParameter("tilerow".to_string())).into_response();
+        assert!(body(response).await.contains(
+            "<ows:Exception exceptionCode=\"MissingParameterValue\" locator=\"tilerow\">"
+        ));
+
+        let response = OgcException::wmts(OgcError::Unavailable("No data".to_string())).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = OgcException::wmts(OgcError::Internal("disk on fire".to_string())).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!body(response).await.contains("disk on fire"), "internal details stay in the log");
+    }
+}
@@ -0,0 +1,97 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+    routing::{delete, get},
+    Router,
+};
+use serde::Deserialize;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{database::Review, error::AppError, handlers::auth::AuthUser, AppState};
+
+const DEFAULT_REVIEWS_LIMIT: i64 = 20;
+const MAX_REVIEWS_LIMIT: i64 = 100;
+const MAX_COMMENT_LEN: usize = 5000;
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewsQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateReviewRequest {
+    pub rating: i32,
+    pub comment: Option<String>,
+}
+
+/// Reading reviews is public; writing and deleting them requires an
+/// access token
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/pois/:poi_id/reviews", get(list_reviews).post(create_review))
+        .route("/reviews/:id", delete(delete_review))
+}
+
+/// `GET /pois/{poi_id}/reviews?limit=20&offset=0`, newest first
+pub async fn list_reviews(
+    Path(poi_id): Path<Uuid>,
+    Query(params): Query<ReviewsQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Review>>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_REVIEWS_LIMIT).clamp(1, MAX_REVIEWS_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let reviews = state.db.reviews(poi_id, limit, offset).await.map_err(|e| {
+        error!("Failed to fetch reviews of {}: {:#}", poi_id, e);
+        AppError::InternalServerError("Failed to fetch reviews".to_string())
+    })?;
+    Ok(Json(reviews))
+}
+
+pub async fn create_review(
+    user: AuthUser,
+    Path(poi_id): Path<Uuid>,
+    State(state): State<AppState>,
+    Json(request): Json<CreateReviewRequest>,
+) -> Result<(StatusCode, Json<Review>), AppError> {
+    if !(1..=5).contains(&request.rating) {
+        return Err(AppError::BadRequest("Rating must be between 1 and 5".to_string()));
+    }
+    let comment = request.comment.as_deref().map(str::trim).filter(|comment| !comment.is_empty());
+    if comment.is_some_and(|comment| comment.chars().count() > MAX_COMMENT_LEN) {
+        return Err(AppError::BadRequest(format!(
+            "Comment must be at most {} characters long",
+            MAX_COMMENT_LEN
+        )));
+    }
+
+    let review = state
+        .db
+        .create_review(poi_id, user.id, request.rating, comment)
+        .await
+        .map_err(|e| {
+            error!("Failed to create review of {} for user {}: {:#}", poi_id, user.id, e);
+            AppError::InternalServerError("Failed to create review".to_string())
+        })?
+        .ok_or_else(|| AppError::NotFound(format!("Point of interest {} not found", poi_id)))?;
+    Ok((StatusCode::CREATED, Json(review)))
+}
+
+/// `DELETE /reviews/{id}`, only by the review's author
+pub async fn delete_review(
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    let deleted = state.db.delete_review(id, user.id).await.map_err(|e| {
+        error!("Failed to delete review {}: {:#}", id, e);
+        AppError::InternalServerError("Failed to delete review".to_string())
+    })?;
+    if !deleted {
+        return Err(AppError::NotFound(format!("Review {} not found", id)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -2,13 +2,16 @@ use axum::{
     extract::{Query, State},
     http::StatusCode,
     response::Json,
+    routing::get,
+    Router,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
     error::AppError,
+    handlers::auth::AuthUser,
     models::{Location, SearchResult},
     services::geocoding::GeocodingService,
     AppState,
@@ -83,7 +86,14 @@ pub struct StructuredFormatting {
     pub secondary_text: String,
 }
 
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/search", get(search_locations))
+        .route("/geocoding/geocode", get(geocode_address))
+}
+
 pub async fn search_locations(
+    user: Option<AuthUser>,
     Query(params): Query<SearchQuery>,
     State(state): State<AppState>,
 ) -> Result<Json<SearchResponse>, AppError> {
@@ -126,6 +136,21 @@ pub async fn search_locations(
     };
 
     info!("Found {} results for query: {}", response.total_count, params.q);
+
+    // Signed in users get the search in their history; anonymous searches
+    // still count towards the search heatmap. Recording happens after the
+    // response, so a slow or failing insert does not hold it up.
+    let position = params.lat.zip(params.lng);
+    let user_id = user.map(|user| user.id);
+    let results_count = response.total_count as i32;
+    let db = state.db.clone();
+    let query = params.q.clone();
+    tokio::spawn(async move {
+        if let Err(e) = db.record_search(user_id, &query, position, results_count).await {
+            warn!("Failed to record search: {:#}", e);
+        }
+    });
+
     Ok(Json(response))
 }
 
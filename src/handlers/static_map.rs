@@ -0,0 +1,411 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use tiny_skia::Color;
+use tracing::error;
+
+use googlemaps_clone::constants::{MAX_TILE_SCALE, MAX_ZOOM, MIN_ZOOM};
+
+use crate::{
+    error::AppError,
+    services::map_renderer::parse_color,
+    services::tile_service::{
+        StaticMapRequest, StaticMarker, StaticPath, StaticView, DEFAULT_PADDING, MAX_STATIC_MAP_PIXELS, MAX_STATIC_MAP_SIZE,
+        MAX_STATIC_MARKERS, MAX_STATIC_PATH_POINTS,
+    },
+    utils::polyline::{self, PRECISION_5},
+    utils::tile_utils::LatLng,
+    AppState,
+};
+
+const DEFAULT_SIZE: u32 = 600;
+const DEFAULT_PATH_WEIGHT: f32 = 5.0;
+const DEFAULT_MARKER_COLOR: &str = "red";
+const DEFAULT_PATH_COLOR: &str = "#3367d6";
+/// Static maps change with the map data, but much slower than tiles are
+/// refreshed; emails and reports only need them to load once
+const STATIC_MAP_MAX_AGE: u32 = 3600;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/maps/static", get(get_static_map))
+}
+
+/// `GET /maps/static?center=lat,lng&zoom=14&size=600x400&markers=...&path=...`
+///
+/// The view is `center` and `zoom`, or `bounds=south,west,north,east`; with
+/// neither it fits every marker and path. `size` (or `width` and `height`)
+/// is in CSS pixels and `scale` is the device pixel ratio.
+///
+/// `markers` and `path` may be repeated. Each is a `|`-separated list of
+/// `key:value` options followed by points:
+/// - `markers=color:blue|label:A|52.52,13.40|52.51,13.38`
+/// - `path=color:0xff0000cc|weight:4|fillcolor:0xff000033|52.52,13.40|52.51,13.38`
+/// - `path=color:blue|enc:_p~iF~ps|U_ulLnnqC` for an encoded polyline
+pub async fn get_static_map(
+    Query(params): Query<Vec<(String, String)>>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let request = parse_static_map_request(&params)?;
+
+    // A proxied base map has no styles to pick from
+    if state.tile_service.upstream().is_none() && state.tile_service.style(request.style.as_deref()).is_none() {
+        return Err(AppError::NotFound(format!(
+            "Unknown style: {}",
+            request.style.as_deref().unwrap_or("default")
+        )));
+    }
+
+    let image = state.tile_service.render_static_map(&request).await.map_err(|e| {
+        error!("Failed to render static map: {}", e);
+        AppError::InternalServerError("Failed to render static map".to_string())
+    })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png".to_string()),
+            (header::CACHE_CONTROL, format!("public, max-age={}", STATIC_MAP_MAX_AGE)),
+        ],
+        image,
+    )
+        .into_response())
+}
+
+/// Build a render request from query parameters, in the order given
+pub fn parse_static_map_request(params: &[(String, String)]) -> Result<StaticMapRequest, AppError> {
+    let get = |name: &str| {
+        params
+            .iter()
+            .rev()
+            .find(|(key, value)| key == name && !value.is_empty())
+            .map(|(_, value)| value.as_str())
+    };
+
+    let (mut width, mut height) = match get("size") {
+        Some(size) => parse_size(size)?,
+        None => (DEFAULT_SIZE, DEFAULT_SIZE),
+    };
+    if let Some(value) = get("width") {
+        width = parse_number("width", value)?;
+    }
+    if let Some(value) = get("height") {
+        height = parse_number("height", value)?;
+    }
+    if width == 0 || height == 0 || width > MAX_STATIC_MAP_SIZE || height > MAX_STATIC_MAP_SIZE {
+        return Err(AppError::BadRequest(format!(
+            "Map size must be between 1 and {} pixels per side",
+            MAX_STATIC_MAP_SIZE
+        )));
+    }
+
+    let scale = match get("scale") {
+        Some(value) => parse_number("scale", value)?,
+        None => 1,
+    };
+    if !(1..=MAX_TILE_SCALE).contains(&scale) {
+        return Err(AppError::BadRequest(format!("Scale must be between 1 and {}", MAX_TILE_SCALE)));
+    }
+
+    let padding = match get("padding") {
+        Some(value) => parse_number::<f64>("padding", value)?.max(0.0),
+        None => DEFAULT_PADDING,
+    };
+
+    let view = match (get("center"), get("bounds")) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::BadRequest("Give either center or bounds, not both".to_string()));
+        }
+        (Some(center), None) => {
+            let zoom = get("zoom")
+                .ok_or_else(|| AppError::BadRequest("zoom is required with center".to_string()))
+                .and_then(|value| parse_number::<u8>("zoom", value))?;
+            if !(MIN_ZOOM..=MAX_ZOOM).contains(&zoom) {
+                return Err(AppError::BadRequest(format!("Zoom must be between {} and {}", MIN_ZOOM, MAX_ZOOM)));
+            }
+            StaticView::Center { center: parse_point(center)?, zoom }
+        }
+        (None, Some(bounds)) => {
+            let values = bounds
+                .split(',')
+                .map(|value| parse_number::<f64>("bounds", value.trim()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let [south, west, north, east] = values[..] else {
+                return Err(AppError::BadRequest(format!("bounds must be south,west,north,east, got {}", bounds)));
+            };
+            if south > north {
+                return Err(AppError::BadRequest("bounds south must not be greater than north".to_string()));
+            }
+            StaticView::Bounds {
+                south_west: checked_point(south, west)?,
+                north_east: checked_point(north, east)?,
+            }
+        }
+        (None, None) => StaticView::Auto,
+    };
+
+    let mut markers = Vec::new();
+    let mut paths = Vec::new();
+    for (key, value) in params.iter().filter(|(_, value)| !value.is_empty()) {
+        match key.as_str() {
+            "markers" => markers.extend(parse_markers(value)?),
+            "path" => paths.push(parse_path(value)?),
+            _ => {}
+        }
+    }
+
+    let request = StaticMapRequest {
+        view,
+        width,
+        height,
+        scale,
+        style: get("style").map(str::to_string),
+        markers,
+        paths,
+        padding,
+    };
+    if request.device_pixels() > MAX_STATIC_MAP_PIXELS {
+        return Err(AppError::BadRequest(format!(
+            "Map size times scale squared must be at most {} pixels",
+            MAX_STATIC_MAP_PIXELS
+        )));
+    }
+    if request.markers.len() > MAX_STATIC_MARKERS {
+        return Err(AppError::BadRequest(format!("At most {} markers are allowed", MAX_STATIC_MARKERS)));
+    }
+    if request.path_points() > MAX_STATIC_PATH_POINTS {
+        return Err(AppError::BadRequest(format!(
+            "At most {} path points are allowed over all paths",
+            MAX_STATIC_PATH_POINTS
+        )));
+    }
+
+    Ok(request)
+}
+
+/// `color:blue|label:A|lat,lng|lat,lng`, one marker per point
+fn parse_markers(value: &str) -> Result<Vec<StaticMarker>, AppError> {
+    let mut color = parse_overlay_color(DEFAULT_MARKER_COLOR)?;
+    let mut label = None;
+    let mut markers = Vec::new();
+
+    for part in value.split('|').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.split_once(':') {
+            Some(("color", value)) => color = parse_overlay_color(value)?,
+            Some(("label", value)) => label = Some(value.chars().take(3).collect::<String>()),
+            Some((option, _)) => return Err(AppError::BadRequest(format!("Unknown marker option: {}", option))),
+            None => markers.push(StaticMarker {
+                position: parse_point(part)?,
+                color,
+                label: label.clone(),
+            }),
+        }
+    }
+
+    Ok(markers)
+}
+
+/// `color:..|weight:..|fillcolor:..|lat,lng|lat,lng` or `...|enc:polyline`.
+/// Encoded polylines may themselves contain `|`, so `enc:` takes the rest of
+/// the value.
+fn parse_path(value: &str) -> Result<StaticPath, AppError> {
+    let mut path = StaticPath {
+        points: Vec::new(),
+        color: parse_overlay_color(DEFAULT_PATH_COLOR)?,
+        weight: DEFAULT_PATH_WEIGHT,
+        fill: None,
+    };
+
+    let (value, encoded) = match value.find("enc:") {
+        Some(index) => (&value[..index], Some(&value[index + "enc:".len()..])),
+        None => (value, None),
+    };
+    for part in value.split('|').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.split_once(':') {
+            Some(("color", value)) => path.color = parse_overlay_color(value)?,
+            Some(("fillcolor", value)) => path.fill = Some(parse_overlay_color(value)?),
+            Some(("weight", value)) => path.weight = parse_number::<f32>("weight", value)?.clamp(0.0, 64.0),
+            Some((option, _)) => return Err(AppError::BadRequest(format!("Unknown path option: {}", option))),
+            None => path.points.push(parse_point(part)?),
+        }
+    }
+    if let Some(encoded) = encoded {
+        let points = polyline::decode(encoded.trim(), PRECISION_5)
+            .map_err(|e| AppError::BadRequest(format!("Invalid encoded path: {}", e)))?;
+        path.points.extend(points.into_iter().map(|(lat, lng)| LatLng::new(lat, lng)));
+    }
+
+    if path.points.len() < 2 {
+        return Err(AppError::BadRequest("A path needs at least two points".to_string()));
+    }
+    Ok(path)
+}
+
+/// A named color, `0xRRGGBB[AA]` or `#RRGGBB[AA]`
+fn parse_overlay_color(value: &str) -> Result<Color, AppError> {
+    let hex = match value.to_ascii_lowercase().as_str() {
+        "black" => "#000000".to_string(),
+        "white" => "#ffffff".to_string(),
+        "gray" | "grey" => "#808080".to_string(),
+        "red" => "#ea4335".to_string(),
+        "orange" => "#fb8c00".to_string(),
+        "yellow" => "#fbbc04".to_string(),
+        "green" => "#34a853".to_string(),
+        "blue" => "#4285f4".to_string(),
+        "purple" => "#9c27b0".to_string(),
+        "brown" => "#795548".to_string(),
+        other => match other.strip_prefix("0x") {
+            Some(hex) => format!("#{}", hex),
+            None => other.to_string(),
+        },
+    };
+    parse_color(&hex).ok_or_else(|| AppError::BadRequest(format!("Invalid color: {}", value)))
+}
+
+/// `600x400`
+fn parse_size(value: &str) -> Result<(u32, u32), AppError> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| AppError::BadRequest(format!("size must be WIDTHxHEIGHT, got {}", value)))?;
+    Ok((parse_number("size", width)?, parse_number("size", height)?))
+}
+
+/// `lat,lng`
+fn parse_point(value: &str) -> Result<LatLng, AppError> {
+    let (lat, lng) = value
+        .split_once(',')
+        .ok_or_else(|| AppError::BadRequest(format!("Expected lat,lng, got {}", value)))?;
+    checked_point(parse_number("latitude", lat.trim())?, parse_number("longitude", lng.trim())?)
+}
+
+fn checked_point(lat: f64, lng: f64) -> Result<LatLng, AppError> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return Err(AppError::BadRequest(format!("Coordinate out of range: {},{}", lat, lng)));
+    }
+    Ok(LatLng::new(lat, lng))
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, AppError> {
+    value
+        .parse()
+        .map_err(|_| AppError::BadRequest(format!("Invalid {}: {}", name, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::tile_service::StaticView;
+
+    fn parse(query: &[(&str, &str)]) -> Result<StaticMapRequest, AppError> {
+        let params: Vec<(String, String)> = query.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        parse_static_map_request(&params)
+    }
+
+    fn points(count: usize) -> String {
+        (0..count).map(|i| format!("52.0,{:.4}", 13.0 + i as f64 * 0.0001)).collect::<Vec<_>>().join("|")
+    }
+
+    #[test]
+    fn reads_the_size_and_view() {
+        let request = parse(&[("center", "52.52,13.40"), ("zoom", "12"), ("size", "640x480"), ("scale", "2")]).unwrap();
+        assert_eq!((request.width, request.height, request.scale), (640, 480, 2));
+        assert!(matches!(request.view, StaticView::Center { zoom: 12, .. }));
+        assert_eq!(request.padding, DEFAULT_PADDING);
+
+        let request = parse(&[("size", "640x480"), ("width", "300"), ("bounds", "52.4,13.1,52.6,13.6")]).unwrap();
+        assert_eq!((request.width, request.height), (300, 480), "width overrides size");
+        assert!(matches!(request.view, StaticView::Bounds { .. }));
+
+        let request = parse(&[]).unwrap();
+        assert_eq!((request.width, request.height, request.scale), (DEFAULT_SIZE, DEFAULT_SIZE, 1));
+        assert!(matches!(request.view, StaticView::Auto));
+    }
+
+    #[test]
+    fn rejects_sizes_and_scales_out_of_range() {
+        let too_wide = format!("{}x10", MAX_STATIC_MAP_SIZE + 1);
+        let too_dense = format!("{0}x{0}", MAX_STATIC_MAP_SIZE);
+        let too_scaled = (MAX_TILE_SCALE + 1).to_string();
+        for query in [
+            vec![("size", "0x10")],
+            vec![("size", too_wide.as_str())],
+            vec![("size", "600")],
+            vec![("scale", "0")],
+            vec![("scale", too_scaled.as_str())],
+            vec![("size", too_dense.as_str()), ("scale", "3")],
+        ] {
+            assert!(matches!(parse(&query), Err(AppError::BadRequest(_))), "{:?} was accepted", query);
+        }
+        // At 2x the largest side is exactly at the device pixel limit
+        assert!(parse(&[("size", too_dense.as_str()), ("scale", "2")]).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_views() {
+        for query in [
+            vec![("center", "52.52,13.40")],
+            vec![("center", "52.52,13.40"), ("zoom", "40")],
+            vec![("center", "95,13.40"), ("zoom", "12")],
+            vec![("center", "52.52,13.40"), ("zoom", "12"), ("bounds", "52.4,13.1,52.6,13.6")],
+            vec![("bounds", "52.4,13.1,52.6")],
+            vec![("bounds", "52.6,13.1,52.4,13.6")],
+        ] {
+            assert!(matches!(parse(&query), Err(AppError::BadRequest(_))), "{:?} was accepted", query);
+        }
+    }
+
+    #[test]
+    fn reads_markers_and_paths() {
+        let request = parse(&[
+            ("markers", "color:blue|label:Abcd|52.52,13.40|52.51,13.38"),
+            ("markers", "52.50,13.35"),
+            ("path", "color:0xff0000cc|weight:4|fillcolor:0xff000033|52.52,13.40|52.51,13.38|52.50,13.35"),
+            ("path", "color:blue|enc:_p~iF~ps|U_ulLnnqC_mqNvxq`@"),
+        ])
+        .unwrap();
+
+        assert_eq!(request.markers.len(), 3);
+        assert_eq!(request.markers[0].label.as_deref(), Some("Abc"), "labels keep three characters");
+        assert_eq!(request.markers[2].label, None);
+        assert_eq!(request.markers[2].color, parse_overlay_color(DEFAULT_MARKER_COLOR).unwrap());
+
+        assert_eq!(request.paths[0].points.len(), 3);
+        assert_eq!(request.paths[0].weight, 4.0);
+        assert!(request.paths[0].fill.is_some());
+        // The encoded polyline keeps its `|`
+        assert_eq!(request.paths[1].points.len(), 3);
+        assert_eq!(request.paths[1].weight, DEFAULT_PATH_WEIGHT);
+    }
+
+    #[test]
+    fn rejects_malformed_overlays() {
+        for query in [
+            vec![("markers", "color:nope|52.52,13.40")],
+            vec![("markers", "size:tiny|52.52,13.40")],
+            vec![("markers", "52.52")],
+            vec![("path", "52.52,13.40")],
+            vec![("path", "enc:!!")],
+        ] {
+            assert!(matches!(parse(&query), Err(AppError::BadRequest(_))), "{:?} was accepted", query);
+        }
+    }
+
+    #[test]
+    fn limits_markers_and_path_points() {
+        let markers = points(MAX_STATIC_MARKERS);
+        assert!(parse(&[("markers", markers.as_str())]).is_ok());
+        let markers = points(MAX_STATIC_MARKERS + 1);
+        assert!(matches!(parse(&[("markers", markers.as_str())]), Err(AppError::BadRequest(_))));
+
+        // The path limit is over every path together
+        let half = points(MAX_STATIC_PATH_POINTS / 2);
+        assert!(parse(&[("path", half.as_str()), ("path", half.as_str())]).is_ok());
+        let over = points(MAX_STATIC_PATH_POINTS / 2 + 1);
+        assert!(matches!(
+            parse(&[("path", half.as_str()), ("path", over.as_str())]),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,342 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use googlemaps_clone::constants::MAX_TILE_SCALE;
+
+use crate::{
+    error::AppError,
+    models::tile::{Tile, TileCoordinate, TileFormat},
+    services::tile_service::{
+        vector_layers, ColorRamp, HeatmapLayer, HeatmapOptions, PyramidConfig, TerrainLayer, TileCacheStats,
+        TileJson, VectorLayer, DEFAULT_HEATMAP_RADIUS, MAX_HEATMAP_RADIUS,
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct TileQuery {
+    /// Style name, e.g. `dark`; the default style when omitted
+    pub style: Option<String>,
+    /// Device pixel ratio, also accepted as an `@2x` suffix on the tile row
+    pub scale: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HeatmapQuery {
+    /// Kernel radius in CSS pixels
+    pub radius: Option<f32>,
+    /// A named ramp such as `fire`, or stops like `0:#0000ff00,1:#ff0000`
+    pub ramp: Option<String>,
+    pub scale: Option<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StylesResponse {
+    pub styles: Vec<String>,
+    /// The layers of map data a style's `source-layer`s can draw from
+    pub source_layers: Vec<VectorLayer>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/tiles/:z/:x/:y", get(get_tile))
+        .route("/tiles/terrain/:layer/:z/:x/:y", get(get_terrain_tile))
+        .route("/tiles/heatmap/:layer/:z/:x/:y", get(get_heatmap_tile))
+        .route("/tiles/cache/stats", get(cache_stats))
+        .route("/styles", get(list_styles))
+        .route("/tilesets", get(list_tilesets))
+        .route("/tilesets/:name", get(get_tilejson))
+}
+
+/// `GET /tiles/{z}/{x}/{y}[@2x].{png|jpg|webp}?style=dark`, or the upstream
+/// server's tile when the service is proxying one
+pub async fn get_tile(
+    Path((z, x, y)): Path<(u8, u32, String)>,
+    Query(params): Query<TileQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let (row, extension) = split_extension(&y);
+    let (row, suffix_scale) = split_scale(row)?;
+    let scale = tile_scale(suffix_scale.or(params.scale))?;
+    let format = raster_format(extension.unwrap_or("png"))?;
+    let coordinate = parse_tile_coordinate(z, x, row, &state.tile_service.pyramid())?;
+
+    // A proxy serves the upstream's tiles; styles only apply to local renders
+    if state.tile_service.upstream().is_some() {
+        let tile = state
+            .tile_service
+            .get_proxied_tile(&coordinate, scale)
+            .await
+            .map_err(|e| {
+                error!("Failed to fetch upstream tile {}/{}/{}: {}", z, x, row, e);
+                AppError::InternalServerError("Failed to fetch tile".to_string())
+            })?
+            .ok_or_else(|| AppError::NotFound(format!("Tile {}/{}/{} not found", z, x, row)))?;
+        let content_type = content_type(&tile.format);
+        return Ok(tile_response(&state, &headers, tile, content_type, state.tile_service.cache_control(z)));
+    }
+
+    let style = state
+        .tile_service
+        .style(params.style.as_deref())
+        .ok_or_else(|| AppError::NotFound(format!("Unknown style: {}", params.style.as_deref().unwrap_or("default"))))?;
+
+    let tile = state
+        .tile_service
+        .get_styled_tile(&coordinate, format.clone(), style, scale)
+        .await
+        .map_err(|e| {
+            error!("Failed to render tile {}/{}/{}: {}", z, x, row, e);
+            AppError::InternalServerError("Failed to render tile".to_string())
+        })?
+        .ok_or_else(|| AppError::NotFound("Map data is not available".to_string()))?;
+
+    Ok(tile_response(&state, &headers, tile, content_type(&format), state.tile_service.cache_control(z)))
+}
+
+/// `GET /tiles/terrain/{hillshade|contours}/{z}/{x}/{y}[@2x].png`
+pub async fn get_terrain_tile(
+    Path((layer, z, x, y)): Path<(TerrainLayer, u8, u32, String)>,
+    Query(params): Query<TileQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let (row, _) = split_extension(&y);
+    let (row, suffix_scale) = split_scale(row)?;
+    let scale = tile_scale(suffix_scale.or(params.scale))?;
+    let coordinate = parse_tile_coordinate(z, x, row, &state.tile_service.pyramid())?;
+
+    let tile = state
+        .tile_service
+        .get_terrain_tile(&coordinate, layer, scale)
+        .await
+        .map_err(|e| {
+            error!("Failed to render {} tile: {}", layer.as_str(), e);
+            AppError::InternalServerError("Failed to render terrain tile".to_string())
+        })?
+        .ok_or_else(|| AppError::NotFound("Terrain tiles are not available".to_string()))?;
+
+    Ok(tile_response(&state, &headers, tile, "image/png", state.tile_service.cache_control(z)))
+}
+
+/// `GET /tiles/heatmap/{searches|traffic}/{z}/{x}/{y}[@2x].png?radius=20&ramp=fire`
+pub async fn get_heatmap_tile(
+    Path((layer, z, x, y)): Path<(HeatmapLayer, u8, u32, String)>,
+    Query(params): Query<HeatmapQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let (row, _) = split_extension(&y);
+    let (row, suffix_scale) = split_scale(row)?;
+    let scale = tile_scale(suffix_scale.or(params.scale))?;
+    let coordinate = parse_tile_coordinate(z, x, row, &state.tile_service.pyramid())?;
+
+    let radius = params.radius.unwrap_or(DEFAULT_HEATMAP_RADIUS);
+    if !(1.0..=MAX_HEATMAP_RADIUS).contains(&radius) {
+        return Err(AppError::BadRequest(format!("Radius must be between 1 and {}", MAX_HEATMAP_RADIUS)));
+    }
+    let ramp = match params.ramp.as_deref() {
+        Some(ramp) => ColorRamp::named(ramp)
+            .or_else(|| ColorRamp::parse(ramp))
+            .ok_or_else(|| AppError::BadRequest(format!("Invalid color ramp: {}", ramp)))?,
+        None => ColorRamp::default(),
+    };
+
+    let tile = state
+        .tile_service
+        .get_heatmap_tile(&coordinate, layer, &HeatmapOptions { radius, ramp }, scale)
+        .await
+        .map_err(|e| {
+            error!("Failed to render {} heatmap tile: {}", layer.as_str(), e);
+            AppError::InternalServerError("Failed to render heatmap tile".to_string())
+        })?
+        .ok_or_else(|| AppError::NotFound("Heatmaps are not available".to_string()))?;
+
+    // Heatmaps follow live data, so they are only cached until the layer's
+    // next refresh rather than for the zoom's usual lifetime
+    let cache_control = state.tile_service.live_cache_control(layer.fresh_for(Utc::now()));
+    Ok(tile_response(&state, &headers, tile, "image/png", cache_control))
+}
+
+/// The tile with its validators and `cache_control`, or a bare
+/// `304 Not Modified` when the client's conditional headers still match
+fn tile_response(
+    state: &AppState,
+    headers: &HeaderMap,
+    tile: Tile,
+    content_type: &'static str,
+    cache_control: String,
+) -> Response {
+    let validators = state.tile_service.validators(&tile);
+    let cache_headers = [
+        (header::ETAG, validators.etag.clone()),
+        (header::LAST_MODIFIED, validators.last_modified_header()),
+        (header::CACHE_CONTROL, cache_control),
+    ];
+
+    let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    let if_modified_since = headers.get(header::IF_MODIFIED_SINCE).and_then(|value| value.to_str().ok());
+    if validators.not_modified(if_none_match, if_modified_since) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    (cache_headers, [(header::CONTENT_TYPE, content_type)], Vec::<u8>::from(tile.data)).into_response()
+}
+
+/// `GET /tiles/cache/stats`
+pub async fn cache_stats(State(state): State<AppState>) -> Json<TileCacheStats> {
+    Json(state.tile_service.cache_stats())
+}
+
+/// `GET /styles`
+pub async fn list_styles(State(state): State<AppState>) -> Json<StylesResponse> {
+    Json(StylesResponse {
+        styles: state.tile_service.style_names(),
+        source_layers: vector_layers(),
+    })
+}
+
+/// `GET /tilesets`, TileJSON for every published tileset
+pub async fn list_tilesets(State(state): State<AppState>) -> Json<Vec<TileJson>> {
+    let base_url = base_url(&state);
+    let tilesets = state
+        .tile_service
+        .tilesets()
+        .iter()
+        .filter_map(|name| state.tile_service.tilejson(name, &base_url))
+        .collect();
+    Json(tilesets)
+}
+
+/// `GET /tilesets/{name}[.json]`, the tileset's TileJSON 3.0 document
+pub async fn get_tilejson(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<TileJson>, AppError> {
+    let base_url = base_url(&state);
+    let tileset = name.strip_suffix(".json").unwrap_or(&name);
+    state
+        .tile_service
+        .tilejson(tileset, &base_url)
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Unknown tileset: {}", tileset)))
+}
+
+/// Absolute URL these routes are mounted under, at the configured public
+/// address so that tile URLs do not depend on request headers
+fn base_url(state: &AppState) -> String {
+    format!("{}/api/v1", state.config.public_url)
+}
+
+/// Split `"123.png"` into `("123", Some("png"))`
+pub fn split_extension(y: &str) -> (&str, Option<&str>) {
+    match y.split_once('.') {
+        Some((row, extension)) => (row, Some(extension)),
+        None => (y, None),
+    }
+}
+
+/// Split `"123@2x"` into `("123", Some(2))`
+pub fn split_scale(row: &str) -> Result<(&str, Option<u8>), AppError> {
+    match row.split_once('@') {
+        Some((row, suffix)) => {
+            let scale = suffix
+                .strip_suffix('x')
+                .and_then(|factor| factor.parse().ok())
+                .ok_or_else(|| AppError::BadRequest(format!("Invalid scale suffix: @{}", suffix)))?;
+            Ok((row, Some(scale)))
+        }
+        None => Ok((row, None)),
+    }
+}
+
+pub fn tile_scale(scale: Option<u8>) -> Result<u8, AppError> {
+    match scale.unwrap_or(1) {
+        scale @ 1..=MAX_TILE_SCALE => Ok(scale),
+        other => Err(AppError::BadRequest(format!(
+            "Scale must be between 1 and {}, got {}",
+            MAX_TILE_SCALE, other
+        ))),
+    }
+}
+
+pub fn raster_format(extension: &str) -> Result<TileFormat, AppError> {
+    match extension {
+        "png" => Ok(TileFormat::Png),
+        "jpg" | "jpeg" => Ok(TileFormat::Jpeg),
+        "webp" => Ok(TileFormat::Webp),
+        other => Err(AppError::BadRequest(format!("Unsupported tile format: {}", other))),
+    }
+}
+
+pub fn content_type(format: &TileFormat) -> &'static str {
+    match format {
+        TileFormat::Png => "image/png",
+        TileFormat::Jpeg => "image/jpeg",
+        TileFormat::Webp => "image/webp",
+        TileFormat::Vector => "application/x-protobuf",
+    }
+}
+
+/// Validate a `z/x/y` tile address against the zoom levels `pyramid` serves
+pub fn parse_tile_coordinate(z: u8, x: u32, y: &str, pyramid: &PyramidConfig) -> Result<TileCoordinate, AppError> {
+    let y: u32 = y
+        .parse()
+        .map_err(|_| AppError::BadRequest(format!("Invalid tile row: {}", y)))?;
+
+    let zooms = pyramid.served_zooms();
+    if !zooms.contains(&z) {
+        return Err(AppError::BadRequest(format!(
+            "Zoom must be between {} and {}",
+            zooms.start(),
+            zooms.end()
+        )));
+    }
+    // From zoom 32 on a tile row outgrows u32, so every parsed address exists
+    if 1u32.checked_shl(z.into()).is_some_and(|tiles| x >= tiles || y >= tiles) {
+        return Err(AppError::BadRequest(format!("Tile {}/{}/{} is out of range", z, x, y)));
+    }
+
+    Ok(TileCoordinate { x, y, z })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale_of(y: &str) -> Result<(&str, u8), AppError> {
+        let (row, _) = split_extension(y);
+        let (row, scale) = split_scale(row)?;
+        Ok((row, tile_scale(scale)?))
+    }
+
+    #[test]
+    fn reads_the_scale_from_the_tile_row_suffix() {
+        assert_eq!(scale_of("6332.png").unwrap(), ("6332", 1));
+        assert_eq!(scale_of("6332@2x.png").unwrap(), ("6332", 2));
+        assert_eq!(scale_of("6332@3x").unwrap(), ("6332", 3));
+    }
+
+    #[test]
+    fn rejects_malformed_and_out_of_range_scales() {
+        for y in ["6332@2.png", "6332@x.png", "6332@twox.png", "6332@0x.png", "6332@4x.png", "6332@300x.png"] {
+            assert!(matches!(scale_of(y), Err(AppError::BadRequest(_))), "{y}");
+        }
+    }
+
+    #[test]
+    fn query_scale_applies_when_there_is_no_suffix() {
+        assert_eq!(tile_scale(None).unwrap(), 1);
+        assert_eq!(tile_scale(Some(MAX_TILE_SCALE)).unwrap(), MAX_TILE_SCALE);
+        assert!(tile_scale(Some(MAX_TILE_SCALE + 1)).is_err());
+    }
+}
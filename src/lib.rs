@@ -88,11 +88,14 @@ pub mod constants {
     
     /// Tile server configuration
     pub const TILE_SIZE: u32 = 256;
+    /// Largest device pixel ratio tiles are rendered at (768px tiles)
+    pub const MAX_TILE_SCALE: u8 = 3;
     pub const MAX_ZOOM: u8 = 18;
     pub const MIN_ZOOM: u8 = 1;
     
     /// Cache configuration
     pub const DEFAULT_CACHE_SIZE: usize = 100 * 1024 * 1024; // 100MB
+    pub const DEFAULT_DISK_CACHE_SIZE: u64 = 1024 * 1024 * 1024; // 1GB
     pub const CACHE_EXPIRY_HOURS: u64 = 24;
     
     /// Network timeouts (in seconds)
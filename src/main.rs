@@ -1,45 +1,109 @@
-use actix_web::{web, App, HttpServer, Result, HttpResponse, middleware::Logger};
-use actix_files as fs;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Mutex;
-use env_logger;
-
-mod routes;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{response::Html, routing::get, Router};
+use tower_http::services::ServeDir;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+mod config;
+mod database;
+mod error;
+mod handlers;
 mod models;
 mod services;
+mod utils;
+
+use crate::config::Config;
+use database::Database;
+use services::map_style::{StyleRegistry, DEFAULT_STYLE};
+use services::{
+    AuthService, ClusterService, ElevationService, NavigationService, OgcService, ReroutingService, RoutingService,
+    TileService,
+};
 
-use routes::{map_routes, search_routes, directions_routes};
-use models::{Location, SearchResult, Route};
-use services::{MapService, SearchService, DirectionsService};
+/// How often expired refresh tokens are deleted
+const TOKEN_PRUNING_PERIOD: Duration = Duration::from_secs(60 * 60);
 
+/// Shared by every handler; cloned per request, so each field is cheap to clone
 #[derive(Clone)]
 pub struct AppState {
-    pub map_service: MapService,
-    pub search_service: SearchService,
-    pub directions_service: DirectionsService,
+    pub config: Arc<Config>,
+    pub db: Database,
+    pub auth_service: Arc<AuthService>,
+    pub cluster_service: Arc<ClusterService>,
+    pub navigation_service: Arc<NavigationService>,
+    pub ogc_service: Arc<OgcService>,
+    pub tile_service: Arc<TileService>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
-        Self {
-            map_service: MapService::new(),
-            search_service: SearchService::new(),
-            directions_service: DirectionsService::new(),
+    pub async fn new() -> anyhow::Result<Self> {
+        let config = Arc::new(Config::from_env()?);
+        let db = Database::new().await?;
+
+        // Signs access and refresh tokens with JWT_SECRET
+        let auth_service = Arc::new(AuthService::from_env(Arc::new(db.clone()))?);
+        auth_service.spawn_token_pruning(TOKEN_PRUNING_PERIOD);
+
+        // SRTM tiles for elevation profiles, grade-aware walking and cycling,
+        // and the hillshade and contour overlays
+        let elevation_service = config
+            .dem_directory
+            .clone()
+            .map(|directory| Arc::new(ElevationService::new(directory)));
+
+        let styles = StyleRegistry::load(&config.styles_dir)?;
+        anyhow::ensure!(
+            styles.get(None).is_some(),
+            "The default map style {:?} is missing from {}",
+            DEFAULT_STYLE,
+            config.styles_dir
+        );
+
+        // Base map tiles label the shared locations in the database, heatmap
+        // tiles draw the search and traffic points recorded there, and terrain
+        // tiles shade the DEM when there is one
+        let mut tile_service = TileService::new(config.clone())?
+            .with_source(Arc::new(db.clone()))
+            .with_styles(Arc::new(styles))
+            .with_heatmap_source(Arc::new(db.clone()));
+        if let Some(elevation) = &elevation_service {
+            tile_service = tile_service.with_terrain(elevation.dem());
         }
-    }
-}
+        let tile_service = Arc::new(tile_service);
+        tile_service.initialize().await?;
+        // Edits to the map data, including the importer's, drop the tiles drawn from it
+        tile_service.watch_data_changes(db.listen_map_changes().await?, true);
+
+        let cluster_service = Arc::new(ClusterService::new(db.clone()));
+        cluster_service.watch_data_changes(db.listen_map_changes().await?);
 
-async fn health_check() -> Result<HttpResponse> {
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy",
-        "service": "maps-clone",
-        "version": "1.0.0"
-    })))
+        // WMTS and WMS capabilities link back to the API at its public address
+        let ogc_service = Arc::new(OgcService::new(tile_service.clone(), format!("{}/api/v1", config.public_url)));
+
+        let mut routing_service = RoutingService::new();
+        if let Some(elevation) = &elevation_service {
+            routing_service.attach_elevation(elevation.clone());
+        }
+        let routing_service = Arc::new(routing_service);
+        let rerouting_service = Arc::new(ReroutingService::new(routing_service));
+        let navigation_service = Arc::new(NavigationService::new().with_rerouting(rerouting_service));
+
+        Ok(Self {
+            config,
+            db,
+            auth_service,
+            cluster_service,
+            navigation_service,
+            ogc_service,
+            tile_service,
+        })
+    }
 }
 
-async fn index() -> Result<HttpResponse> {
-    Ok(HttpResponse::Ok().content_type("text/html").body(
+async fn index() -> Html<&'static str> {
+    Html(
         r#"
         <!DOCTYPE html>
         <html>
@@ -70,37 +134,29 @@ async fn index() -> Result<HttpResponse> {
             <script src="/static/js/main.js"></script>
         </body>
         </html>
-        "#
-    ))
+        "#,
+    )
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-
-    let app_state = web::Data::new(AppState::new());
-    
-    log::info!("Starting Maps Clone server on http://localhost:8080");
-    
-    HttpServer::new(move || {
-        App::new()
-            .app_data(app_state.clone())
-            .wrap(Logger::default())
-            .route("/", web::get().to(index))
-            .route("/health", web::get().to(health_check))
-            .service(
-                web::scope("/api")
-                    .configure(map_routes::configure)
-                    .configure(search_routes::configure)
-                    .configure(directions_routes::configure)
-            )
-            .service(
-                fs::Files::new("/static", "./static")
-                    .show_files_listing()
-                    .use_last_modified(true)
-            )
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
-}
\ No newline at end of file
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    dotenvy::dotenv().ok();
+
+    let state = AppState::new().await?;
+
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/health", get(handlers::health_check))
+        .nest("/api/v1", handlers::routes())
+        .nest_service("/static", ServeDir::new("./static"))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+    info!("Starting Maps Clone server on http://localhost:8080");
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
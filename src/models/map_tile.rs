@@ -53,6 +53,18 @@ pub enum FeatureType {
     Natural,
 }
 
+impl FeatureType {
+    pub const ALL: [FeatureType; 7] = [
+        FeatureType::Building,
+        FeatureType::Water,
+        FeatureType::Park,
+        FeatureType::Forest,
+        FeatureType::Administrative,
+        FeatureType::Landuse,
+        FeatureType::Natural,
+    ];
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Geometry {
     Point(LatLng),
@@ -95,6 +107,19 @@ pub enum RoadType {
     Cycleway,
 }
 
+impl RoadType {
+    pub const ALL: [RoadType; 8] = [
+        RoadType::Highway,
+        RoadType::Primary,
+        RoadType::Secondary,
+        RoadType::Tertiary,
+        RoadType::Residential,
+        RoadType::Service,
+        RoadType::Footway,
+        RoadType::Cycleway,
+    ];
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum RoadSurface {
     Paved,
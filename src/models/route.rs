@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::str::FromStr;
 use chrono::{DateTime, Utc};
 
+use crate::utils::polyline;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Coordinate {
     pub latitude: f64,
@@ -71,6 +74,97 @@ pub struct Route {
     pub route_type: RouteType,
     pub created_at: DateTime<Utc>,
     pub traffic_info: Option<TrafficInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<ElevationProfile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElevationProfile {
+    pub points: Vec<ElevationPoint>,
+    pub total_ascent: f64,
+    pub total_descent: f64,
+    pub min_elevation: f64,
+    pub max_elevation: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElevationPoint {
+    /// Distance from the start of the route in meters
+    pub distance: f64,
+    /// Elevation above sea level in meters
+    pub elevation: f64,
+}
+
+impl Route {
+    /// The full path of the route: the origin followed by the end of every segment
+    pub fn path(&self) -> Vec<Coordinate> {
+        let mut path = Vec::with_capacity(self.segments.len() + 1);
+        path.push(self.segments.first().map_or(self.origin.clone(), |s| s.start.clone()));
+        path.extend(self.segments.iter().map(|s| s.end.clone()));
+        path
+    }
+
+    pub fn geometry(&self, format: GeometryFormat) -> RouteGeometry {
+        let path = self.path();
+        match format {
+            GeometryFormat::Polyline5 | GeometryFormat::Polyline6 => {
+                let pairs: Vec<(f64, f64)> = path.iter().map(|c| (c.latitude, c.longitude)).collect();
+                RouteGeometry::Encoded(polyline::encode(&pairs, format.precision()))
+            }
+            GeometryFormat::GeoJson => {
+                let line = path.iter().map(|c| vec![c.longitude, c.latitude]).collect();
+                RouteGeometry::GeoJson(geojson::Geometry::new(geojson::Value::LineString(line)))
+            }
+            GeometryFormat::Coords => RouteGeometry::Coordinates(path),
+        }
+    }
+}
+
+/// Geometry representation requested by directions clients via `geometry_format`.
+/// Clients that don't ask get coordinates, the shape responses had before
+/// the encoded formats existed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GeometryFormat {
+    Polyline5,
+    Polyline6,
+    GeoJson,
+    #[default]
+    Coords,
+}
+
+impl GeometryFormat {
+    pub fn precision(&self) -> u32 {
+        match self {
+            GeometryFormat::Polyline6 => polyline::PRECISION_6,
+            _ => polyline::PRECISION_5,
+        }
+    }
+}
+
+impl FromStr for GeometryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "polyline" | "polyline5" => Ok(GeometryFormat::Polyline5),
+            "polyline6" => Ok(GeometryFormat::Polyline6),
+            "geojson" => Ok(GeometryFormat::GeoJson),
+            "coords" => Ok(GeometryFormat::Coords),
+            other => Err(format!(
+                "Unknown geometry format '{}', expected polyline5, polyline6, geojson or coords",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum RouteGeometry {
+    Encoded(String),
+    GeoJson(geojson::Geometry),
+    Coordinates(Vec<Coordinate>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,745 @@
+use std::env;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::database::{Database, User};
+
+/// Access tokens are checked against their session on every request, so
+/// their lifetime only bounds how long a leaked one is useful
+pub const DEFAULT_ACCESS_TOKEN_TTL: Duration = Duration::from_secs(15 * 60);
+pub const DEFAULT_REFRESH_TOKEN_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// HS256 keys shorter than the hash output weaken the signature
+const MIN_SECRET_LEN: usize = 32;
+
+const USERNAME_LEN: std::ops::RangeInclusive<usize> = 3..=32;
+const MAX_EMAIL_LEN: usize = 254;
+const MIN_PASSWORD_LEN: usize = 8;
+/// bcrypt ignores everything past the first 72 bytes
+const MAX_PASSWORD_LEN: usize = 72;
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("Username or email is already registered")]
+    AlreadyRegistered,
+    #[error("Invalid username or password")]
+    InvalidCredentials,
+    #[error("Account is disabled")]
+    AccountDisabled,
+    #[error("Missing bearer token")]
+    MissingToken,
+    #[error("Invalid or expired token")]
+    InvalidToken,
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+    Access,
+    Refresh,
+}
+
+/// JWT claims of both token kinds. `sid` identifies the login session, which
+/// every token issued by refreshing it shares.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub sid: Uuid,
+    pub jti: Uuid,
+    pub typ: TokenKind,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    /// Seconds until the access token expires
+    pub expires_in: u64,
+    pub refresh_expires_in: u64,
+}
+
+/// Where accounts and refresh tokens are kept
+pub trait AccountStore: Send + Sync {
+    /// `None` when the username or email is taken
+    fn create_user<'a>(
+        &'a self,
+        username: &'a str,
+        email: &'a str,
+        password_hash: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<Option<User>>>;
+    fn user_by_login<'a>(&'a self, login: &'a str) -> BoxFuture<'a, anyhow::Result<Option<User>>>;
+    fn user_by_id(&self, id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<User>>>;
+    fn insert_refresh_token(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+        session_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> BoxFuture<'_, anyhow::Result<()>>;
+    /// Revoke refresh token `id` and store `new_id` in its place, returning
+    /// its user and session; `None` when `id` is unknown, expired or revoked
+    fn rotate_refresh_token(
+        &self,
+        id: Uuid,
+        new_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> BoxFuture<'_, anyhow::Result<Option<(Uuid, Uuid)>>>;
+    /// Whether the session's user is active; `None` when the session has no
+    /// live refresh token
+    fn session_user_active(&self, session_id: Uuid, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<bool>>>;
+    fn revoke_session(&self, session_id: Uuid) -> BoxFuture<'_, anyhow::Result<u64>>;
+    /// The sessions that were still live
+    fn revoke_user_sessions(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Vec<Uuid>>>;
+    fn delete_expired_refresh_tokens(&self, before: DateTime<Utc>) -> BoxFuture<'_, anyhow::Result<u64>>;
+}
+
+impl AccountStore for Database {
+    fn create_user<'a>(
+        &'a self,
+        username: &'a str,
+        email: &'a str,
+        password_hash: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<Option<User>>> {
+        Database::create_user(self, username, email, password_hash).boxed()
+    }
+
+    fn user_by_login<'a>(&'a self, login: &'a str) -> BoxFuture<'a, anyhow::Result<Option<User>>> {
+        Database::user_by_login(self, login).boxed()
+    }
+
+    fn user_by_id(&self, id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<User>>> {
+        Database::user_by_id(self, id).boxed()
+    }
+
+    fn insert_refresh_token(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+        session_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Database::insert_refresh_token(self, id, user_id, session_id, expires_at).boxed()
+    }
+
+    fn rotate_refresh_token(
+        &self,
+        id: Uuid,
+        new_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> BoxFuture<'_, anyhow::Result<Option<(Uuid, Uuid)>>> {
+        Database::rotate_refresh_token(self, id, new_id, expires_at).boxed()
+    }
+
+    fn session_user_active(&self, session_id: Uuid, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<bool>>> {
+        Database::session_user_active(self, session_id, user_id).boxed()
+    }
+
+    fn revoke_session(&self, session_id: Uuid) -> BoxFuture<'_, anyhow::Result<u64>> {
+        Database::revoke_session(self, session_id).boxed()
+    }
+
+    fn revoke_user_sessions(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Vec<Uuid>>> {
+        Database::revoke_user_sessions(self, user_id).boxed()
+    }
+
+    fn delete_expired_refresh_tokens(&self, before: DateTime<Utc>) -> BoxFuture<'_, anyhow::Result<u64>> {
+        Database::delete_expired_refresh_tokens(self, before).boxed()
+    }
+}
+
+/// Accounts and their sessions. A login hands out a short-lived access token
+/// and a refresh token. Each refresh token can be exchanged once for a new
+/// pair; presenting one a second time means it leaked, and revokes the
+/// whole session.
+pub struct AuthService {
+    store: Arc<dyn AccountStore>,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    validation: Validation,
+    access_ttl: Duration,
+    refresh_ttl: Duration,
+    bcrypt_cost: u32,
+    /// Sessions known to be revoked whose access tokens may not have expired
+    /// yet, so those tokens are turned away without a database lookup
+    revoked_sessions: Cache<Uuid, ()>,
+}
+
+impl AuthService {
+    pub fn new(store: Arc<dyn AccountStore>, secret: &[u8]) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+
+        Self {
+            store,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+            validation,
+            access_ttl: DEFAULT_ACCESS_TOKEN_TTL,
+            refresh_ttl: DEFAULT_REFRESH_TOKEN_TTL,
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+            revoked_sessions: revoked_sessions_cache(DEFAULT_ACCESS_TOKEN_TTL),
+        }
+    }
+
+    /// Sign tokens with the `JWT_SECRET` environment variable
+    pub fn from_env(store: Arc<dyn AccountStore>) -> anyhow::Result<Self> {
+        let secret = env::var("JWT_SECRET").context("JWT_SECRET environment variable not set")?;
+        if secret.len() < MIN_SECRET_LEN {
+            bail!("JWT_SECRET must be at least {} bytes long", MIN_SECRET_LEN);
+        }
+        Ok(Self::new(store, secret.as_bytes()))
+    }
+
+    pub fn with_token_lifetimes(mut self, access: Duration, refresh: Duration) -> Self {
+        self.access_ttl = access;
+        self.refresh_ttl = refresh;
+        self.revoked_sessions = revoked_sessions_cache(access);
+        self
+    }
+
+    pub fn with_bcrypt_cost(mut self, cost: u32) -> Self {
+        self.bcrypt_cost = cost;
+        self
+    }
+
+    pub async fn register(&self, username: &str, email: &str, password: &str) -> Result<(User, TokenPair), AuthError> {
+        let username = username.trim();
+        let email = email.trim();
+        validate_username(username)?;
+        validate_email(email)?;
+        validate_password(password)?;
+
+        let password_hash = self.hash_password(password).await?;
+        let user = self
+            .store
+            .create_user(username, email, &password_hash)
+            .await?
+            .ok_or(AuthError::AlreadyRegistered)?;
+
+        info!("Registered user {}", user.id);
+        let tokens = self.start_session(user.id).await?;
+        Ok((user, tokens))
+    }
+
+    /// Sign in with a username or email. Unknown accounts take as long to
+    /// reject as wrong passwords, so the timing does not reveal which
+    /// accounts exist.
+    pub async fn login(&self, login: &str, password: &str) -> Result<(User, TokenPair), AuthError> {
+        let user = self.store.user_by_login(login.trim()).await?;
+
+        let password = password.to_string();
+        let cost = self.bcrypt_cost;
+        let stored_hash = user.as_ref().map(|user| user.password_hash.clone());
+        let verified = tokio::task::spawn_blocking(move || match stored_hash {
+            Some(hash) => bcrypt::verify(password, &hash).unwrap_or(false),
+            None => {
+                let _ = bcrypt::hash(password, cost);
+                false
+            }
+        })
+        .await
+        .context("Password check task failed")?;
+
+        let user = match user {
+            Some(user) if verified => user,
+            _ => return Err(AuthError::InvalidCredentials),
+        };
+        if !user.is_active {
+            return Err(AuthError::AccountDisabled);
+        }
+
+        let tokens = self.start_session(user.id).await?;
+        Ok((user, tokens))
+    }
+
+    /// Exchange a refresh token for a new token pair in the same session.
+    /// The presented token stops working; presenting it again revokes the
+    /// session.
+    pub async fn refresh(&self, refresh_token: &str) -> Result<TokenPair, AuthError> {
+        let claims = self.decode(refresh_token, TokenKind::Refresh)?;
+        let next_id = Uuid::new_v4();
+
+        let rotated = self
+            .store
+            .rotate_refresh_token(claims.jti, next_id, self.expires_at(self.refresh_ttl)?)
+            .await?;
+        let Some((user_id, session_id)) = rotated else {
+            warn!(
+                "Refresh token {} of user {} was reused, revoking session {}",
+                claims.jti, claims.sub, claims.sid
+            );
+            self.revoke_session(claims.sid).await?;
+            return Err(AuthError::InvalidToken);
+        };
+
+        match self.store.user_by_id(user_id).await? {
+            Some(user) if user.is_active => {}
+            _ => {
+                self.revoke_session(session_id).await?;
+                return Err(AuthError::AccountDisabled);
+            }
+        }
+
+        self.issue(user_id, session_id, next_id)
+    }
+
+    /// End the session a refresh token belongs to
+    pub async fn logout(&self, refresh_token: &str) -> Result<(), AuthError> {
+        let claims = self.decode(refresh_token, TokenKind::Refresh)?;
+        self.revoke_session(claims.sid).await
+    }
+
+    /// End every session of a user
+    pub async fn logout_all(&self, user_id: Uuid) -> Result<(), AuthError> {
+        for session_id in self.store.revoke_user_sessions(user_id).await? {
+            self.revoked_sessions.insert(session_id, ()).await;
+        }
+        Ok(())
+    }
+
+    /// Check an access token, returning its claims. Its session must still
+    /// hold a live refresh token and its user must be active, so logging out
+    /// in another process or disabling the account takes effect at once.
+    pub async fn authenticate(&self, access_token: &str) -> Result<Claims, AuthError> {
+        let claims = self.decode(access_token, TokenKind::Access)?;
+        if self.revoked_sessions.contains_key(&claims.sid) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        match self.store.session_user_active(claims.sid, claims.sub).await? {
+            Some(true) => Ok(claims),
+            Some(false) => Err(AuthError::AccountDisabled),
+            None => {
+                self.revoked_sessions.insert(claims.sid, ()).await;
+                Err(AuthError::InvalidToken)
+            }
+        }
+    }
+
+    pub async fn user(&self, user_id: Uuid) -> Result<Option<User>, AuthError> {
+        Ok(self.store.user_by_id(user_id).await?)
+    }
+
+    /// Delete expired refresh tokens every `period`
+    pub fn spawn_token_pruning(self: &Arc<Self>, period: Duration) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match service.store.delete_expired_refresh_tokens(Utc::now()).await {
+                    Ok(0) => {}
+                    Ok(deleted) => info!("Deleted {} expired refresh tokens", deleted),
+                    Err(e) => error!("Failed to prune refresh tokens: {:#}", e),
+                }
+            }
+        })
+    }
+
+    async fn start_session(&self, user_id: Uuid) -> Result<TokenPair, AuthError> {
+        let session_id = Uuid::new_v4();
+        let refresh_id = Uuid::new_v4();
+        self.store
+            .insert_refresh_token(refresh_id, user_id, session_id, self.expires_at(self.refresh_ttl)?)
+            .await?;
+        self.issue(user_id, session_id, refresh_id)
+    }
+
+    async fn revoke_session(&self, session_id: Uuid) -> Result<(), AuthError> {
+        self.store.revoke_session(session_id).await?;
+        self.revoked_sessions.insert(session_id, ()).await;
+        Ok(())
+    }
+
+    fn issue(&self, user_id: Uuid, session_id: Uuid, refresh_id: Uuid) -> Result<TokenPair, AuthError> {
+        let now = Utc::now().timestamp();
+        let claims = |typ, jti, ttl: Duration| Claims {
+            sub: user_id,
+            sid: session_id,
+            jti,
+            typ,
+            iat: now,
+            exp: now + ttl.as_secs() as i64,
+        };
+
+        Ok(TokenPair {
+            access_token: self.encode(&claims(TokenKind::Access, Uuid::new_v4(), self.access_ttl))?,
+            refresh_token: self.encode(&claims(TokenKind::Refresh, refresh_id, self.refresh_ttl))?,
+            token_type: "Bearer",
+            expires_in: self.access_ttl.as_secs(),
+            refresh_expires_in: self.refresh_ttl.as_secs(),
+        })
+    }
+
+    fn encode(&self, claims: &Claims) -> Result<String, AuthError> {
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), claims, &self.encoding_key)
+            .map_err(|e| AuthError::Internal(anyhow!("Failed to sign token: {}", e)))
+    }
+
+    fn decode(&self, token: &str, kind: TokenKind) -> Result<Claims, AuthError> {
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.decoding_key, &self.validation)
+            .map_err(|_| AuthError::InvalidToken)?
+            .claims;
+        if claims.typ != kind {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims)
+    }
+
+    fn expires_at(&self, ttl: Duration) -> anyhow::Result<DateTime<Utc>> {
+        Ok(Utc::now() + chrono::Duration::from_std(ttl).context("Token lifetime out of range")?)
+    }
+
+    async fn hash_password(&self, password: &str) -> anyhow::Result<String> {
+        let password = password.to_string();
+        let cost = self.bcrypt_cost;
+        tokio::task::spawn_blocking(move || bcrypt::hash(password, cost))
+            .await
+            .context("Password hashing task failed")?
+            .context("Failed to hash password")
+    }
+}
+
+/// Revoked sessions only need remembering until their last access token
+/// has expired
+fn revoked_sessions_cache(access_ttl: Duration) -> Cache<Uuid, ()> {
+    Cache::builder().time_to_live(access_ttl).build()
+}
+
+fn validate_username(username: &str) -> Result<(), AuthError> {
+    if !USERNAME_LEN.contains(&username.chars().count()) {
+        return Err(AuthError::Invalid(format!(
+            "Username must be {} to {} characters long",
+            USERNAME_LEN.start(),
+            USERNAME_LEN.end()
+        )));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return Err(AuthError::Invalid(
+            "Username may only contain letters, digits, '_', '-' and '.'".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_email(email: &str) -> Result<(), AuthError> {
+    let valid = email.len() <= MAX_EMAIL_LEN
+        && !email.chars().any(char::is_whitespace)
+        && match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty() && !domain.contains('@') && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+            }
+            None => false,
+        };
+    if !valid {
+        return Err(AuthError::Invalid("Invalid email address".to_string()));
+    }
+    Ok(())
+}
+
+fn validate_password(password: &str) -> Result<(), AuthError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(AuthError::Invalid(format!(
+            "Password must be at least {} characters long",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    if password.len() > MAX_PASSWORD_LEN {
+        return Err(AuthError::Invalid(format!(
+            "Password must be at most {} bytes long",
+            MAX_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const SECRET: &[u8] = b"an-hs256-test-secret-of-32-bytes";
+
+    struct StoredToken {
+        user_id: Uuid,
+        session_id: Uuid,
+        expires_at: DateTime<Utc>,
+        revoked: bool,
+    }
+
+    /// Accounts and refresh tokens kept in memory, with the semantics of the
+    /// `users` and `refresh_tokens` tables
+    #[derive(Default)]
+    struct MemoryStore {
+        users: Mutex<HashMap<Uuid, User>>,
+        tokens: Mutex<HashMap<Uuid, StoredToken>>,
+    }
+
+    impl MemoryStore {
+        fn set_active(&self, user_id: Uuid, active: bool) {
+            self.users.lock().unwrap().get_mut(&user_id).unwrap().is_active = active;
+        }
+
+        fn live(token: &StoredToken) -> bool {
+            !token.revoked && token.expires_at > Utc::now()
+        }
+    }
+
+    impl AccountStore for MemoryStore {
+        fn create_user<'a>(
+            &'a self,
+            username: &'a str,
+            email: &'a str,
+            password_hash: &'a str,
+        ) -> BoxFuture<'a, anyhow::Result<Option<User>>> {
+            async move {
+                let mut users = self.users.lock().unwrap();
+                if users.values().any(|u| u.username == username || u.email == email) {
+                    return Ok(None);
+                }
+                let user = User {
+                    id: Uuid::new_v4(),
+                    username: username.to_string(),
+                    email: email.to_string(),
+                    password_hash: password_hash.to_string(),
+                    is_active: true,
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                };
+                users.insert(user.id, user.clone());
+                Ok(Some(user))
+            }
+            .boxed()
+        }
+
+        fn user_by_login<'a>(&'a self, login: &'a str) -> BoxFuture<'a, anyhow::Result<Option<User>>> {
+            async move {
+                let users = self.users.lock().unwrap();
+                Ok(users.values().find(|u| u.username == login || u.email == login).cloned())
+            }
+            .boxed()
+        }
+
+        fn user_by_id(&self, id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<User>>> {
+            async move { Ok(self.users.lock().unwrap().get(&id).cloned()) }.boxed()
+        }
+
+        fn insert_refresh_token(
+            &self,
+            id: Uuid,
+            user_id: Uuid,
+            session_id: Uuid,
+            expires_at: DateTime<Utc>,
+        ) -> BoxFuture<'_, anyhow::Result<()>> {
+            async move {
+                let token = StoredToken { user_id, session_id, expires_at, revoked: false };
+                self.tokens.lock().unwrap().insert(id, token);
+                Ok(())
+            }
+            .boxed()
+        }
+
+        fn rotate_refresh_token(
+            &self,
+            id: Uuid,
+            new_id: Uuid,
+            expires_at: DateTime<Utc>,
+        ) -> BoxFuture<'_, anyhow::Result<Option<(Uuid, Uuid)>>> {
+            async move {
+                let mut tokens = self.tokens.lock().unwrap();
+                let Some(current) = tokens.get_mut(&id).filter(|token| Self::live(token)) else {
+                    return Ok(None);
+                };
+                current.revoked = true;
+                let (user_id, session_id) = (current.user_id, current.session_id);
+                tokens.insert(new_id, StoredToken { user_id, session_id, expires_at, revoked: false });
+                Ok(Some((user_id, session_id)))
+            }
+            .boxed()
+        }
+
+        fn session_user_active(&self, session_id: Uuid, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<bool>>> {
+            async move {
+                let live = self
+                    .tokens
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .any(|t| t.session_id == session_id && t.user_id == user_id && Self::live(t));
+                Ok(live.then(|| self.users.lock().unwrap()[&user_id].is_active))
+            }
+            .boxed()
+        }
+
+        fn revoke_session(&self, session_id: Uuid) -> BoxFuture<'_, anyhow::Result<u64>> {
+            async move {
+                let mut revoked = 0;
+                for token in self.tokens.lock().unwrap().values_mut() {
+                    if token.session_id == session_id && !token.revoked {
+                        token.revoked = true;
+                        revoked += 1;
+                    }
+                }
+                Ok(revoked)
+            }
+            .boxed()
+        }
+
+        fn revoke_user_sessions(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Vec<Uuid>>> {
+            async move {
+                let mut sessions = Vec::new();
+                for token in self.tokens.lock().unwrap().values_mut() {
+                    if token.user_id == user_id && Self::live(token) {
+                        token.revoked = true;
+                        sessions.push(token.session_id);
+                    }
+                }
+                sessions.sort_unstable();
+                sessions.dedup();
+                Ok(sessions)
+            }
+            .boxed()
+        }
+
+        fn delete_expired_refresh_tokens(&self, before: DateTime<Utc>) -> BoxFuture<'_, anyhow::Result<u64>> {
+            async move {
+                let mut tokens = self.tokens.lock().unwrap();
+                let count = tokens.len();
+                tokens.retain(|_, token| token.expires_at >= before);
+                Ok((count - tokens.len()) as u64)
+            }
+            .boxed()
+        }
+    }
+
+    fn service() -> (AuthService, Arc<MemoryStore>) {
+        let store = Arc::new(MemoryStore::default());
+        let service = AuthService::new(store.clone(), SECRET).with_bcrypt_cost(4);
+        (service, store)
+    }
+
+    async fn signed_up(service: &AuthService) -> (User, TokenPair) {
+        service.register("ada", "ada@example.com", "correct horse").await.unwrap()
+    }
+
+    fn is_invalid_token<T>(result: Result<T, AuthError>) -> bool {
+        matches!(result, Err(AuthError::InvalidToken))
+    }
+
+    #[tokio::test]
+    async fn issues_an_access_and_refresh_token_for_one_session() {
+        let (service, store) = service();
+        let (user, tokens) = signed_up(&service).await;
+
+        let access = service.decode(&tokens.access_token, TokenKind::Access).unwrap();
+        let refresh = service.decode(&tokens.refresh_token, TokenKind::Refresh).unwrap();
+        assert_eq!((access.sub, refresh.sub), (user.id, user.id));
+        assert_eq!(access.sid, refresh.sid);
+        assert_ne!(access.jti, refresh.jti);
+        assert_eq!(access.exp - access.iat, DEFAULT_ACCESS_TOKEN_TTL.as_secs() as i64);
+        assert_eq!(refresh.exp - refresh.iat, DEFAULT_REFRESH_TOKEN_TTL.as_secs() as i64);
+        assert_eq!(tokens.expires_in, DEFAULT_ACCESS_TOKEN_TTL.as_secs());
+        assert_eq!(store.tokens.lock().unwrap()[&refresh.jti].session_id, refresh.sid);
+
+        assert_eq!(service.authenticate(&tokens.access_token).await.unwrap().sub, user.id);
+        let (_, again) = service.login("ada@example.com", "correct horse").await.unwrap();
+        assert_ne!(service.decode(&again.access_token, TokenKind::Access).unwrap().sid, access.sid);
+    }
+
+    #[tokio::test]
+    async fn accepts_each_token_only_for_its_own_use() {
+        let (service, _) = service();
+        let (_, tokens) = signed_up(&service).await;
+
+        assert!(is_invalid_token(service.authenticate(&tokens.refresh_token).await));
+        assert!(is_invalid_token(service.refresh(&tokens.access_token).await));
+    }
+
+    #[tokio::test]
+    async fn rejects_forged_and_expired_tokens() {
+        let (service, _) = service();
+        let (_, tokens) = signed_up(&service).await;
+
+        let (unsigned, signature) = tokens.access_token.rsplit_once('.').unwrap();
+        let flipped = if signature.starts_with('A') { 'B' } else { 'A' };
+        let tampered = format!("{}.{}{}", unsigned, flipped, &signature[1..]);
+        assert!(is_invalid_token(service.authenticate(&tampered).await));
+
+        let other = AuthService::new(Arc::new(MemoryStore::default()), b"another-secret-that-is-32-bytes!");
+        assert!(is_invalid_token(other.authenticate(&tokens.access_token).await));
+
+        let mut claims = service.decode(&tokens.access_token, TokenKind::Access).unwrap();
+        claims.exp = Utc::now().timestamp() - 1;
+        let expired = service.encode(&claims).unwrap();
+        assert!(is_invalid_token(service.authenticate(&expired).await));
+    }
+
+    #[tokio::test]
+    async fn refreshing_rotates_the_refresh_token_within_the_session() {
+        let (service, _) = service();
+        let (_, first) = signed_up(&service).await;
+
+        let second = service.refresh(&first.refresh_token).await.unwrap();
+        let third = service.refresh(&second.refresh_token).await.unwrap();
+
+        let claims: Vec<Claims> = [&first, &second, &third]
+            .iter()
+            .map(|pair| service.decode(&pair.refresh_token, TokenKind::Refresh).unwrap())
+            .collect();
+        assert!(claims.iter().all(|c| c.sid == claims[0].sid));
+        assert_ne!(claims[0].jti, claims[1].jti);
+        assert_ne!(claims[1].jti, claims[2].jti);
+
+        // Access tokens issued before the rotation stay valid while the session lives
+        assert!(service.authenticate(&first.access_token).await.is_ok());
+        assert!(service.authenticate(&third.access_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reusing_a_refresh_token_revokes_its_session() {
+        let (service, _) = service();
+        let (_, first) = signed_up(&service).await;
+        let (_, other_session) = service.login("ada", "correct horse").await.unwrap();
+
+        let second = service.refresh(&first.refresh_token).await.unwrap();
+        assert!(is_invalid_token(service.refresh(&first.refresh_token).await));
+
+        // The token the legitimate holder got is revoked with the session
+        assert!(is_invalid_token(service.refresh(&second.refresh_token).await));
+        assert!(is_invalid_token(service.authenticate(&second.access_token).await));
+        assert!(is_invalid_token(service.authenticate(&first.access_token).await));
+
+        // Other sessions of the same user are left alone
+        assert!(service.authenticate(&other_session.access_token).await.is_ok());
+        assert!(service.refresh(&other_session.refresh_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn refreshing_fails_once_the_account_is_disabled() {
+        let (service, store) = service();
+        let (user, tokens) = signed_up(&service).await;
+
+        store.set_active(user.id, false);
+        assert!(matches!(service.refresh(&tokens.refresh_token).await, Err(AuthError::AccountDisabled)));
+        assert!(is_invalid_token(service.authenticate(&tokens.access_token).await));
+    }
+}
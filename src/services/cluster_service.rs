@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use moka::future::Cache;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tracing::{error, info};
+
+use crate::database::{Database, Location, MapBounds, MapDataChanges};
+use crate::utils::cluster::{ClusterIndex, ClusterOptions};
+use crate::utils::tile_utils::LatLng;
+
+/// Indexes are rebuilt at least this often even without change
+/// notifications, in case the listener is not running
+const INDEX_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// One index per place type filter, plus the unfiltered one
+const MAX_INDEXES: u64 = 32;
+
+/// Marker clusters over the `locations` table. Each index covers every
+/// location, optionally of one place type, and is built on first use.
+pub struct ClusterService {
+    db: Database,
+    options: ClusterOptions,
+    indexes: Cache<Option<String>, Arc<ClusterIndex<Location>>>,
+}
+
+impl ClusterService {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            options: ClusterOptions::default(),
+            indexes: Cache::builder().max_capacity(MAX_INDEXES).time_to_live(INDEX_TTL).build(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ClusterOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The index for `place_type`, built when missing. The unfiltered index
+    /// is loaded from the database; filtered ones are cut from it. Concurrent
+    /// requests for the same index share one build.
+    pub async fn index(&self, place_type: Option<&str>) -> Result<Arc<ClusterIndex<Location>>> {
+        let all = self
+            .indexes
+            .try_get_with(None, self.load_index())
+            .await
+            .map_err(|e| anyhow!("{:#}", e))?;
+        let Some(place_type) = place_type else {
+            return Ok(all);
+        };
+
+        self.indexes
+            .try_get_with(Some(place_type.to_string()), self.filter_index(all, place_type.to_string()))
+            .await
+            .map_err(|e| anyhow!("{:#}", e))
+    }
+
+    async fn load_index(&self) -> Result<Arc<ClusterIndex<Location>>> {
+        let world = MapBounds {
+            north: 90.0,
+            south: -90.0,
+            east: 180.0,
+            west: -180.0,
+        };
+        let locations = self.db.locations_in_bounds(&world).await?;
+        let options = self.options;
+
+        let index = tokio::task::spawn_blocking(move || {
+            let points = locations
+                .into_iter()
+                .map(|location| (LatLng::new(location.latitude, location.longitude), location));
+            ClusterIndex::new(points, options)
+        })
+        .await
+        .context("Cluster index task failed")?;
+
+        info!("Built marker cluster index over {} locations", index.len());
+        Ok(Arc::new(index))
+    }
+
+    async fn filter_index(&self, all: Arc<ClusterIndex<Location>>, place_type: String) -> Result<Arc<ClusterIndex<Location>>> {
+        let options = self.options;
+        let index = tokio::task::spawn_blocking(move || {
+            let points = all
+                .points()
+                .iter()
+                .filter(|(_, location)| location.place_type == place_type)
+                .cloned();
+            ClusterIndex::new(points, options)
+        })
+        .await
+        .context("Cluster index task failed")?;
+
+        info!("Built marker cluster index over {} filtered locations", index.len());
+        Ok(Arc::new(index))
+    }
+
+    /// Drop every index so the next request rebuilds it
+    pub fn invalidate(&self) {
+        self.indexes.invalidate_all();
+    }
+
+    /// Drop the indexes whenever `changes` reports an edit to `locations`
+    pub fn watch_data_changes(self: &Arc<Self>, mut changes: MapDataChanges) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match changes.next_batch().await {
+                    Ok(batch) => {
+                        if batch.iter().any(|change| change.table == "locations") {
+                            service.invalidate();
+                        }
+                    }
+                    Err(e) => {
+                        error!("Stopped watching map data changes: {:#}", e);
+                        return;
+                    }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::models::route::{ElevationPoint, ElevationProfile};
+use crate::utils::dem::DemStore;
+use crate::utils::tile_utils::LatLng;
+
+/// Spacing of elevation samples along a path
+pub const PROFILE_SAMPLE_INTERVAL_M: f64 = 30.0;
+
+/// Elevation changes smaller than this are treated as DEM noise when summing
+/// ascent and descent
+const ASCENT_NOISE_M: f64 = 2.0;
+
+/// Grades are clamped to this magnitude; steeper values come from DEM artifacts
+const MAX_GRADE: f64 = 0.4;
+
+/// Smallest value `grade_cost_factor` returns, reached cycling down an 8%
+/// grade. Route search scales its distance estimate by it to stay admissible.
+pub const MIN_GRADE_COST_FACTOR: f64 = 0.76;
+
+pub struct ElevationService {
+    dem: Arc<DemStore>,
+}
+
+impl ElevationService {
+    pub fn new(dem_directory: impl Into<PathBuf>) -> Self {
+        Self {
+            dem: Arc::new(DemStore::new(dem_directory)),
+        }
+    }
+
+    pub fn from_store(dem: Arc<DemStore>) -> Self {
+        Self { dem }
+    }
+
+    pub fn dem(&self) -> Arc<DemStore> {
+        self.dem.clone()
+    }
+
+    pub fn elevation_at(&self, lat: f64, lng: f64) -> Option<f64> {
+        self.dem.elevation_at(lat, lng)
+    }
+
+    /// Elevation profile along a path of `(lat, lng)` points, sampled every
+    /// `PROFILE_SAMPLE_INTERVAL_M`. `None` when the path has no DEM coverage.
+    pub fn profile(&self, path: &[(f64, f64)]) -> Option<ElevationProfile> {
+        let mut points = Vec::new();
+        let mut distance = 0.0;
+
+        for (index, window) in path.windows(2).enumerate() {
+            let (from, to) = (window[0], window[1]);
+            let length = LatLng::new(from.0, from.1).distance_to(&LatLng::new(to.0, to.1));
+            let samples = (length / PROFILE_SAMPLE_INTERVAL_M).ceil().max(1.0) as usize;
+
+            // Include the first point once, then every sample up to each edge end
+            let first = if index == 0 { 0 } else { 1 };
+            for step in first..=samples {
+                let t = step as f64 / samples as f64;
+                let lat = from.0 + (to.0 - from.0) * t;
+                let lng = from.1 + (to.1 - from.1) * t;
+                if let Some(elevation) = self.dem.elevation_at(lat, lng) {
+                    points.push(ElevationPoint {
+                        distance: distance + length * t,
+                        elevation,
+                    });
+                }
+            }
+            distance += length;
+        }
+
+        if points.is_empty() {
+            return None;
+        }
+
+        let (total_ascent, total_descent) = ascent_descent(&points);
+        let min_elevation = points.iter().map(|p| p.elevation).fold(f64::INFINITY, f64::min);
+        let max_elevation = points.iter().map(|p| p.elevation).fold(f64::NEG_INFINITY, f64::max);
+
+        Some(ElevationProfile {
+            points,
+            total_ascent,
+            total_descent,
+            min_elevation,
+            max_elevation,
+        })
+    }
+}
+
+/// Sum of climbs and drops, ignoring wiggles smaller than `ASCENT_NOISE_M`
+fn ascent_descent(points: &[ElevationPoint]) -> (f64, f64) {
+    let mut ascent = 0.0;
+    let mut descent = 0.0;
+    let mut reference = points[0].elevation;
+
+    for point in &points[1..] {
+        let change = point.elevation - reference;
+        if change >= ASCENT_NOISE_M {
+            ascent += change;
+            reference = point.elevation;
+        } else if change <= -ASCENT_NOISE_M {
+            descent -= change;
+            reference = point.elevation;
+        }
+    }
+
+    (ascent, descent)
+}
+
+/// Grade (rise over run) between two elevations `distance_m` apart
+pub fn grade(from_elevation: f64, to_elevation: f64, distance_m: f64) -> f64 {
+    if distance_m <= 0.0 {
+        return 0.0;
+    }
+    ((to_elevation - from_elevation) / distance_m).clamp(-MAX_GRADE, MAX_GRADE)
+}
+
+/// Multiplier applied to an edge's travel time for its grade.
+///
+/// Walking follows Tobler's hiking function relative to flat ground. Cycling
+/// penalizes climbs steeply and gives a limited speedup on gentle descents, with
+/// steep descents slowed again for braking. Other vehicles are unaffected.
+pub fn grade_cost_factor(vehicle_type: &str, grade: f64) -> f64 {
+    match vehicle_type {
+        "foot" | "walking" => (3.5 * ((grade + 0.05).abs() - 0.05)).exp(),
+        "bicycle" | "cycling" => {
+            if grade >= 0.0 {
+                1.0 + 12.0 * grade + 150.0 * grade * grade
+            } else if grade > -0.08 {
+                1.0 + 3.0 * grade
+            } else {
+                0.76 + 6.0 * (-grade - 0.08)
+            }
+        }
+        _ => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn grade_is_rise_over_run_within_limits() {
+        assert_close(grade(100.0, 105.0, 100.0), 0.05);
+        assert_close(grade(105.0, 100.0, 100.0), -0.05);
+        assert_close(grade(0.0, 500.0, 100.0), MAX_GRADE);
+        assert_close(grade(500.0, 0.0, 100.0), -MAX_GRADE);
+        assert_close(grade(0.0, 10.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn walking_is_fastest_on_a_gentle_descent() {
+        assert_close(grade_cost_factor("walking", 0.0), 1.0);
+        assert_close(grade_cost_factor("foot", -0.05), (-0.175f64).exp());
+        assert!(grade_cost_factor("foot", 0.1) > grade_cost_factor("foot", -0.1));
+    }
+
+    #[test]
+    fn cycling_penalizes_climbs_and_steep_descents() {
+        assert_close(grade_cost_factor("bicycle", 0.0), 1.0);
+        assert_close(grade_cost_factor("bicycle", 0.05), 1.975);
+        assert_close(grade_cost_factor("cycling", -0.04), 0.88);
+        assert_close(grade_cost_factor("bicycle", -0.08), MIN_GRADE_COST_FACTOR);
+        assert_close(grade_cost_factor("bicycle", -0.12), 1.0);
+    }
+
+    #[test]
+    fn other_vehicles_ignore_grades() {
+        assert_close(grade_cost_factor("car", MAX_GRADE), 1.0);
+        assert_close(grade_cost_factor("driving", -MAX_GRADE), 1.0);
+    }
+
+    #[test]
+    fn no_grade_beats_the_heuristic_factor() {
+        for step in -400..=400 {
+            let grade = step as f64 / 1000.0;
+            for vehicle_type in ["foot", "bicycle", "car"] {
+                assert!(grade_cost_factor(vehicle_type, grade) >= MIN_GRADE_COST_FACTOR - 1e-12);
+            }
+        }
+    }
+}
@@ -0,0 +1,727 @@
+//! Label placement and text rendering for raster tiles
+//!
+//! Candidates are placed in priority order and rejected when they overlap a
+//! label that is already placed. Placement runs over the tile plus a buffer and
+//! positions are derived from world coordinates only, so a label near a tile
+//! edge is placed identically by both neighboring tiles and drawn whole across
+//! the seam instead of being cut off.
+
+use std::collections::HashMap;
+
+use tiny_skia::{Color, FillRule, Paint, PathBuilder, Pixmap, Stroke, Transform};
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+use crate::models::map_tile::{Label, Road, TileMetadata};
+use crate::services::map_renderer::{parse_color, road_rank, Viewport};
+use crate::services::map_style::Filter;
+
+/// DejaVu Sans, see assets/fonts/LICENSE-DejaVu.txt
+static FONT_DATA: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+
+/// Extra margin around the tile, in CSS pixels, in which labels are placed so
+/// that placement agrees with neighboring tiles
+pub const TILE_BUFFER: f32 = 64.0;
+
+/// Street names are only drawn from this zoom level on
+pub const MIN_ROAD_LABEL_ZOOM: u8 = 13;
+
+const ROAD_LABEL_SIZE: f32 = 11.0;
+
+/// Line labels are dropped where consecutive glyphs would turn more than this
+const MAX_GLYPH_TURN_DEG: f32 = 35.0;
+
+/// Distance between successive positions tried for a line label, in CSS pixels
+const LINE_LABEL_STEP: f32 = 24.0;
+
+/// Space kept free around every label, in CSS pixels
+const LABEL_PADDING: f32 = 2.0;
+
+const HALO_WIDTH: f32 = 2.5;
+
+const COLLISION_CELL: f32 = 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BoxF {
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+}
+
+impl BoxF {
+    fn around(x: f32, y: f32, half_width: f32, half_height: f32) -> Self {
+        Self {
+            min_x: x - half_width,
+            min_y: y - half_height,
+            max_x: x + half_width,
+            max_y: y + half_height,
+        }
+    }
+
+    fn intersects(&self, other: &BoxF) -> bool {
+        self.min_x < other.max_x && other.min_x < self.max_x && self.min_y < other.max_y && other.min_y < self.max_y
+    }
+}
+
+/// Boxes of placed labels, bucketed on a uniform grid
+#[derive(Default)]
+struct CollisionIndex {
+    boxes: Vec<BoxF>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl CollisionIndex {
+    fn cells_for(bounds: &BoxF) -> impl Iterator<Item = (i32, i32)> {
+        let (x0, x1) = ((bounds.min_x / COLLISION_CELL).floor() as i32, (bounds.max_x / COLLISION_CELL).floor() as i32);
+        let (y0, y1) = ((bounds.min_y / COLLISION_CELL).floor() as i32, (bounds.max_y / COLLISION_CELL).floor() as i32);
+        (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+    }
+
+    fn collides(&self, candidate: &[BoxF]) -> bool {
+        candidate.iter().any(|bounds| {
+            Self::cells_for(bounds).any(|cell| {
+                self.cells
+                    .get(&cell)
+                    .is_some_and(|indices| indices.iter().any(|&i| self.boxes[i].intersects(bounds)))
+            })
+        })
+    }
+
+    fn insert(&mut self, placed: &[BoxF]) {
+        for bounds in placed {
+            let index = self.boxes.len();
+            self.boxes.push(*bounds);
+            for cell in Self::cells_for(bounds) {
+                self.cells.entry(cell).or_default().push(index);
+            }
+        }
+    }
+}
+
+/// How one kind of label is drawn; `None` fields fall back to the label's own
+/// values
+#[derive(Debug, Clone)]
+pub struct TextPaint {
+    pub color: Option<Color>,
+    pub halo: Color,
+    /// Halo width in CSS pixels
+    pub halo_width: f32,
+    /// Font size in CSS pixels
+    pub size: Option<f32>,
+    /// Only labels matching the filter are drawn
+    pub filter: Option<Filter>,
+}
+
+/// Text paint for point labels and street names; `None` hides that kind
+#[derive(Debug, Clone)]
+pub struct LabelPaint {
+    pub points: Option<TextPaint>,
+    pub roads: Option<TextPaint>,
+}
+
+impl Default for LabelPaint {
+    fn default() -> Self {
+        Self {
+            points: Some(TextPaint {
+                color: None,
+                halo: Color::WHITE,
+                halo_width: HALO_WIDTH,
+                size: None,
+                filter: None,
+            }),
+            roads: Some(TextPaint {
+                color: Some(Color::from_rgba8(0x44, 0x44, 0x44, 0xff)),
+                halo: Color::WHITE,
+                halo_width: HALO_WIDTH,
+                size: Some(ROAD_LABEL_SIZE),
+                filter: None,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlacedGlyph {
+    pub glyph_id: u16,
+    /// Baseline origin in device pixels
+    pub x: f32,
+    pub y: f32,
+    /// Rotation in degrees, clockwise
+    pub angle: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlacedLabel {
+    pub text: String,
+    pub glyphs: Vec<PlacedGlyph>,
+    /// Font size in device pixels
+    pub size: f32,
+    pub color: Color,
+    pub halo: Color,
+    /// Halo width in device pixels
+    pub halo_width: f32,
+}
+
+struct Candidate<'a> {
+    priority: f32,
+    id: &'a str,
+    kind: CandidateKind<'a>,
+}
+
+enum CandidateKind<'a> {
+    Point(&'a Label),
+    Line(&'a Road),
+}
+
+pub struct LabelEngine {
+    face: Face<'static>,
+}
+
+impl Default for LabelEngine {
+    fn default() -> Self {
+        Self::new(FONT_DATA).expect("bundled font is valid")
+    }
+}
+
+impl LabelEngine {
+    pub fn new(font_data: &'static [u8]) -> Option<Self> {
+        Face::parse(font_data, 0).ok().map(|face| Self { face })
+    }
+
+    /// Choose which labels of `data` to show in `viewport` and where
+    pub fn place(&self, viewport: &Viewport, data: &TileMetadata, paint: &LabelPaint) -> Vec<PlacedLabel> {
+        let mut candidates: Vec<Candidate> = Vec::new();
+
+        if let Some(points) = &paint.points {
+            candidates.extend(
+                data.labels
+                    .iter()
+                    .filter(|label| !label.text.trim().is_empty())
+                    .filter(|label| points.filter.as_ref().is_none_or(|f| f.matches(*label)))
+                    .map(|label| Candidate {
+                        priority: 100.0 + label.font_size,
+                        id: &label.id,
+                        kind: CandidateKind::Point(label),
+                    }),
+            );
+        }
+
+        if let Some(roads) = paint.roads.as_ref().filter(|_| viewport.zoom >= MIN_ROAD_LABEL_ZOOM) {
+            candidates.extend(
+                data.roads
+                    .iter()
+                    .filter(|road| !road.name.trim().is_empty() && road.geometry.len() >= 2)
+                    .filter(|road| roads.filter.as_ref().is_none_or(|f| f.matches(*road)))
+                    .map(|road| Candidate {
+                        priority: road_rank(&road.road_type) as f32 * 10.0 + road.style.width,
+                        id: &road.id,
+                        kind: CandidateKind::Line(road),
+                    }),
+            );
+        }
+
+        // Ties are broken by id so every tile orders candidates the same way
+        candidates.sort_by(|a, b| b.priority.total_cmp(&a.priority).then_with(|| a.id.cmp(b.id)));
+
+        let buffer = TILE_BUFFER * viewport.scale;
+        let area = BoxF {
+            min_x: -buffer,
+            min_y: -buffer,
+            max_x: viewport.pixel_width() as f32 + buffer,
+            max_y: viewport.pixel_height() as f32 + buffer,
+        };
+        let visible = BoxF {
+            min_x: 0.0,
+            min_y: 0.0,
+            max_x: viewport.pixel_width() as f32,
+            max_y: viewport.pixel_height() as f32,
+        };
+
+        let mut index = CollisionIndex::default();
+        let mut placed = Vec::new();
+
+        for candidate in candidates {
+            let layout = match (candidate.kind, &paint.points, &paint.roads) {
+                (CandidateKind::Point(label), Some(text), _) => self.layout_point(viewport, label, text),
+                (CandidateKind::Line(road), _, Some(text)) => self.layout_line(viewport, road, text, &index),
+                _ => None,
+            };
+            let Some((label, boxes)) = layout else {
+                continue;
+            };
+
+            if !boxes.iter().all(|b| b.intersects(&area)) || index.collides(&boxes) {
+                continue;
+            }
+            index.insert(&boxes);
+
+            if boxes.iter().any(|b| b.intersects(&visible)) {
+                placed.push(label);
+            }
+        }
+
+        placed
+    }
+
+    /// One line of `text` centred on `(x, y)` in device pixels, for overlays
+    /// that are drawn without placement. `paint.size` defaults to the road
+    /// label size; `scale` is the device pixel ratio.
+    pub fn centered_text(&self, text: &str, (x, y): (f32, f32), paint: &TextPaint, scale: f32) -> PlacedLabel {
+        let size = paint.size.unwrap_or(ROAD_LABEL_SIZE) * scale;
+        let advances = self.advances(text, size);
+        let width: f32 = advances.iter().map(|(_, advance)| advance).sum();
+
+        let mut pen = x - width / 2.0;
+        let baseline = y + size * 0.35;
+        let glyphs = advances
+            .into_iter()
+            .map(|(glyph_id, advance)| {
+                let glyph = PlacedGlyph { glyph_id, x: pen, y: baseline, angle: 0.0 };
+                pen += advance;
+                glyph
+            })
+            .collect();
+
+        PlacedLabel {
+            text: text.to_string(),
+            glyphs,
+            size,
+            color: paint.color.unwrap_or(Color::BLACK),
+            halo: paint.halo,
+            halo_width: paint.halo_width * scale,
+        }
+    }
+
+    /// Draw placed labels with a halo so they stay legible over any background
+    pub fn draw(&self, pixmap: &mut Pixmap, labels: &[PlacedLabel]) {
+        for label in labels {
+            let units = self.face.units_per_em() as f32;
+            let scale = label.size / units;
+
+            let mut builder = PathBuilder::new();
+            for glyph in &label.glyphs {
+                let mut outline = GlyphPath(PathBuilder::new());
+                if self.face.outline_glyph(GlyphId(glyph.glyph_id), &mut outline).is_none() {
+                    continue;
+                }
+                let transform = Transform::from_scale(scale, -scale)
+                    .post_rotate(glyph.angle)
+                    .post_translate(glyph.x, glyph.y);
+                if let Some(path) = outline.0.finish().and_then(|path| path.transform(transform)) {
+                    builder.push_path(&path);
+                }
+            }
+            let Some(path) = builder.finish() else {
+                continue;
+            };
+
+            let mut paint = Paint {
+                anti_alias: true,
+                ..Paint::default()
+            };
+            paint.set_color(label.halo);
+            let halo = Stroke {
+                width: label.halo_width,
+                line_join: tiny_skia::LineJoin::Round,
+                ..Stroke::default()
+            };
+            if label.halo_width > 0.0 {
+                pixmap.stroke_path(&path, &paint, &halo, Transform::identity(), None);
+            }
+
+            paint.set_color(label.color);
+            pixmap.fill_path(&path, &paint, FillRule::Winding, Transform::identity(), None);
+        }
+    }
+
+    fn layout_point(&self, viewport: &Viewport, label: &Label, text: &TextPaint) -> Option<(PlacedLabel, Vec<BoxF>)> {
+        let size = text.size.unwrap_or(label.font_size) * viewport.scale;
+        let (x, y) = viewport.project(&label.position);
+        let advances = self.advances(&label.text, size);
+        let width: f32 = advances.iter().map(|(_, advance)| advance).sum();
+
+        let mut pen = x - width / 2.0;
+        let baseline = y + size * 0.35;
+        let glyphs = advances
+            .into_iter()
+            .map(|(glyph_id, advance)| {
+                let glyph = PlacedGlyph { glyph_id, x: pen, y: baseline, angle: 0.0 };
+                pen += advance;
+                glyph
+            })
+            .collect();
+
+        let padding = LABEL_PADDING * viewport.scale;
+        let bounds = BoxF::around(x, y, width / 2.0 + padding, size * 0.6 + padding);
+        let label = PlacedLabel {
+            text: label.text.clone(),
+            glyphs,
+            size,
+            color: text
+                .color
+                .or_else(|| parse_color(&label.color))
+                .unwrap_or(Color::from_rgba8(0x33, 0x33, 0x33, 0xff)),
+            halo: text.halo,
+            halo_width: text.halo_width * viewport.scale,
+        };
+        Some((label, vec![bounds]))
+    }
+
+    /// Lay the road name out along its geometry, one glyph at a time following
+    /// the line. The middle of the line is tried first, then positions
+    /// alternating either side of it, until one is straight enough and free.
+    fn layout_line(
+        &self,
+        viewport: &Viewport,
+        road: &Road,
+        text: &TextPaint,
+        index: &CollisionIndex,
+    ) -> Option<(PlacedLabel, Vec<BoxF>)> {
+        let size = text.size.unwrap_or(ROAD_LABEL_SIZE) * viewport.scale;
+        let mut points: Vec<(f32, f32)> = road.geometry.iter().map(|p| viewport.project(p)).collect();
+
+        // Keep text upright by reading the line left to right
+        if points.last()?.0 < points.first()?.0 {
+            points.reverse();
+        }
+
+        let lengths: Vec<f32> = points.windows(2).map(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1)).collect();
+        let total: f32 = lengths.iter().sum();
+        let advances = self.advances(&road.name, size);
+        let width: f32 = advances.iter().map(|(_, advance)| advance).sum();
+        if width + 2.0 * size > total {
+            return None;
+        }
+
+        let center = (total - width) / 2.0;
+        let step = LINE_LABEL_STEP * viewport.scale;
+        let attempts = (center - size).max(0.0) / step;
+        let offsets = std::iter::once(center).chain((1..=attempts as usize).flat_map(|i| {
+            let shift = i as f32 * step;
+            [center - shift, center + shift]
+        }));
+
+        for start in offsets {
+            let Some((glyphs, boxes)) = self.glyphs_along(viewport, &points, &lengths, &advances, start, size) else {
+                continue;
+            };
+            if index.collides(&boxes) {
+                continue;
+            }
+
+            let label = PlacedLabel {
+                text: road.name.clone(),
+                glyphs,
+                size,
+                color: text.color.unwrap_or(Color::from_rgba8(0x44, 0x44, 0x44, 0xff)),
+                halo: text.halo,
+                halo_width: text.halo_width * viewport.scale,
+            };
+            return Some((label, boxes));
+        }
+        None
+    }
+
+    /// Glyphs of a line label starting `start` pixels along the line, or `None`
+    /// where the line bends too sharply under the text
+    fn glyphs_along(
+        &self,
+        viewport: &Viewport,
+        points: &[(f32, f32)],
+        lengths: &[f32],
+        advances: &[(u16, f32)],
+        start: f32,
+        size: f32,
+    ) -> Option<(Vec<PlacedGlyph>, Vec<BoxF>)> {
+        let padding = LABEL_PADDING * viewport.scale;
+        let mut offset = start;
+        let mut glyphs = Vec::with_capacity(advances.len());
+        let mut boxes = Vec::with_capacity(advances.len());
+        let mut previous_angle: Option<f32> = None;
+
+        for &(glyph_id, advance) in advances {
+            let (cx, cy, angle) = point_along(points, lengths, offset + advance / 2.0)?;
+            if let Some(previous) = previous_angle {
+                let mut turn = (angle - previous).abs() % 360.0;
+                if turn > 180.0 {
+                    turn = 360.0 - turn;
+                }
+                if turn > MAX_GLYPH_TURN_DEG {
+                    return None;
+                }
+            }
+            previous_angle = Some(angle);
+
+            // Glyph origin sits half an advance before the center, with the
+            // baseline shifted so the text is centered on the line
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let drop = size * 0.35;
+            glyphs.push(PlacedGlyph {
+                glyph_id,
+                x: cx - cos * advance / 2.0 - sin * drop,
+                y: cy - sin * advance / 2.0 + cos * drop,
+                angle,
+            });
+            boxes.push(BoxF::around(cx, cy, size * 0.6 + padding, size * 0.6 + padding));
+            offset += advance;
+        }
+
+        Some((glyphs, boxes))
+    }
+
+    /// Glyph ids and advances in device pixels. Characters missing from the
+    /// font fall back to the `.notdef` glyph.
+    fn advances(&self, text: &str, size: f32) -> Vec<(u16, f32)> {
+        let scale = size / self.face.units_per_em() as f32;
+        text.chars()
+            .map(|c| {
+                let glyph = self.face.glyph_index(c).unwrap_or(GlyphId(0));
+                let advance = self.face.glyph_hor_advance(glyph).unwrap_or(0) as f32 * scale;
+                (glyph.0, advance)
+            })
+            .collect()
+    }
+}
+
+/// Position and direction (degrees) at `distance` along a polyline
+fn point_along(points: &[(f32, f32)], lengths: &[f32], distance: f32) -> Option<(f32, f32, f32)> {
+    let mut remaining = distance;
+    for (segment, &length) in points.windows(2).zip(lengths) {
+        if remaining <= length && length > 0.0 {
+            let t = remaining / length;
+            let (a, b) = (segment[0], segment[1]);
+            let angle = (b.1 - a.1).atan2(b.0 - a.0).to_degrees();
+            return Some((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t, angle));
+        }
+        remaining -= length;
+    }
+    None
+}
+
+/// Collects a glyph outline into a tiny-skia path in font units
+struct GlyphPath(PathBuilder);
+
+impl OutlineBuilder for GlyphPath {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.cubic_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use googlemaps_clone::constants::TILE_SIZE;
+    use crate::models::map_tile::{LabelType, RoadStyle, RoadSurface, RoadType};
+    use crate::utils::geo::{BoundingBox, LatLng};
+    use crate::utils::tile_utils::TileCoord;
+
+    const TILE: TileCoord = TileCoord { x: 5241, y: 12665, z: 15 };
+
+    /// Position at fractions `(x, y)` across `tile`
+    fn at_in(tile: TileCoord, x: f64, y: f64) -> LatLng {
+        let n = 2_f64.powi(tile.z as i32);
+        let lng = (tile.x as f64 + x) / n * 360.0 - 180.0;
+        let lat = (PI * (1.0 - 2.0 * (tile.y as f64 + y) / n)).sinh().atan().to_degrees();
+        LatLng::new(lat, lng)
+    }
+
+    fn at(x: f64, y: f64) -> LatLng {
+        at_in(TILE, x, y)
+    }
+
+    fn label(id: &str, text: &str, position: LatLng, font_size: f32) -> Label {
+        Label {
+            id: id.to_string(),
+            text: text.to_string(),
+            position,
+            label_type: LabelType::Poi,
+            font_size,
+            font_family: "DejaVu Sans".to_string(),
+            color: "#333333".to_string(),
+        }
+    }
+
+    fn street(id: &str, name: &str, geometry: Vec<LatLng>) -> Road {
+        Road {
+            id: id.to_string(),
+            name: name.to_string(),
+            road_type: RoadType::Residential,
+            geometry,
+            lanes: 2,
+            speed_limit: None,
+            one_way: false,
+            surface: RoadSurface::Paved,
+            style: RoadStyle {
+                color: "#ffffff".to_string(),
+                width: 6.0,
+                dash_pattern: None,
+                border_color: None,
+                border_width: None,
+            },
+        }
+    }
+
+    fn tile_data(labels: Vec<Label>, roads: Vec<Road>) -> TileMetadata {
+        TileMetadata {
+            bounds: BoundingBox::new(0.0, 0.0, 0.0, 0.0),
+            features: Vec::new(),
+            roads,
+            labels,
+            pois: Vec::new(),
+            style_version: "test".to_string(),
+            data_version: "test".to_string(),
+        }
+    }
+
+    fn place(tile: TileCoord, data: &TileMetadata) -> Vec<PlacedLabel> {
+        LabelEngine::default().place(&Viewport::for_tile(tile, 1.0), data, &LabelPaint::default())
+    }
+
+    fn texts(placed: &[PlacedLabel]) -> Vec<&str> {
+        placed.iter().map(|label| label.text.as_str()).collect()
+    }
+
+    #[test]
+    fn boxes_that_only_touch_do_not_collide() {
+        let a = BoxF::around(0.0, 0.0, 10.0, 10.0);
+        assert!(a.intersects(&BoxF::around(15.0, 5.0, 10.0, 10.0)));
+        assert!(!a.intersects(&BoxF::around(20.0, 0.0, 10.0, 10.0)));
+        assert!(!a.intersects(&BoxF::around(0.0, -20.0, 10.0, 10.0)));
+    }
+
+    #[test]
+    fn collision_index_finds_boxes_across_grid_cells() {
+        let mut index = CollisionIndex::default();
+        index.insert(&[BoxF::around(COLLISION_CELL, COLLISION_CELL, 20.0, 20.0)]);
+
+        assert!(index.collides(&[BoxF::around(COLLISION_CELL - 25.0, COLLISION_CELL + 25.0, 10.0, 10.0)]));
+        assert!(index.collides(&[BoxF::around(0.0, 0.0, 1.0, 1.0), BoxF::around(80.0, 80.0, 1.0, 1.0)]));
+        assert!(!index.collides(&[BoxF::around(COLLISION_CELL - 40.0, COLLISION_CELL, 10.0, 10.0)]));
+        assert!(!index.collides(&[BoxF::around(-500.0, -500.0, 10.0, 10.0)]));
+    }
+
+    #[test]
+    fn larger_labels_win_collisions() {
+        let data = tile_data(
+            vec![
+                label("a", "Small Cafe", at(0.5, 0.5), 10.0),
+                label("b", "Big Museum", at(0.52, 0.5), 16.0),
+                label("c", "Far Park", at(0.5, 0.2), 10.0),
+            ],
+            Vec::new(),
+        );
+        assert_eq!(texts(&place(TILE, &data)), ["Big Museum", "Far Park"]);
+    }
+
+    #[test]
+    fn equal_priorities_are_broken_by_id() {
+        let forward = vec![
+            label("a", "First", at(0.5, 0.5), 12.0),
+            label("b", "Second", at(0.52, 0.5), 12.0),
+        ];
+        let reversed: Vec<Label> = forward.iter().rev().cloned().collect();
+
+        assert_eq!(texts(&place(TILE, &tile_data(forward, Vec::new()))), ["First"]);
+        assert_eq!(texts(&place(TILE, &tile_data(reversed, Vec::new()))), ["First"]);
+    }
+
+    #[test]
+    fn skips_blank_labels() {
+        let data = tile_data(vec![label("a", "  ", at(0.5, 0.5), 12.0)], Vec::new());
+        assert!(place(TILE, &data).is_empty());
+    }
+
+    #[test]
+    fn labels_across_a_tile_edge_are_placed_identically_on_both_sides() {
+        let east = TileCoord { x: TILE.x + 1, ..TILE };
+        let data = tile_data(
+            vec![
+                label("edge", "Harbor Station", at(0.99, 0.5), 14.0),
+                label("blocked", "Pier", at(1.03, 0.5), 10.0),
+                label("hidden", "Far West", at(-0.2, 0.5), 12.0),
+            ],
+            Vec::new(),
+        );
+
+        let west = place(TILE, &data);
+        let east = place(east, &data);
+        assert_eq!(texts(&west), ["Harbor Station"]);
+        assert_eq!(texts(&east), ["Harbor Station"]);
+
+        for (w, e) in west[0].glyphs.iter().zip(&east[0].glyphs) {
+            assert!((w.x - TILE_SIZE as f32 - e.x).abs() < 0.01);
+            assert!((w.y - e.y).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn street_names_follow_the_road_from_left_to_right() {
+        // Drawn right to left, so the label must be flipped to stay upright
+        let road = street("s", "Market Street", vec![at(0.9, 0.9), at(0.1, 0.1)]);
+        let placed = place(TILE, &tile_data(Vec::new(), vec![road]));
+
+        assert_eq!(texts(&placed), ["Market Street"]);
+        let glyphs = &placed[0].glyphs;
+        assert_eq!(glyphs.len(), "Market Street".len());
+        assert!(glyphs.iter().all(|glyph| (glyph.angle - 45.0).abs() < 0.5));
+        assert!(glyphs.windows(2).all(|pair| pair[1].x > pair[0].x && pair[1].y > pair[0].y));
+    }
+
+    #[test]
+    fn street_names_need_zoom_room_and_gentle_bends() {
+        let long_name = "Martin Luther King Junior Boulevard";
+        let short = street("short", long_name, vec![at(0.4, 0.5), at(0.6, 0.5)]);
+        let hairpin = street(
+            "hairpin",
+            "Lombard Street",
+            vec![at(0.2, 0.3), at(0.5, 0.3), at(0.2, 0.32), at(0.5, 0.34), at(0.2, 0.36)],
+        );
+        let straight = street("straight", "Main Street", vec![at(0.0, 0.8), at(1.0, 0.8)]);
+        let data = tile_data(Vec::new(), vec![short, hairpin, straight]);
+
+        assert_eq!(texts(&place(TILE, &data)), ["Main Street"]);
+
+        let zoomed_out = TileCoord { x: TILE.x >> 3, y: TILE.y >> 3, z: MIN_ROAD_LABEL_ZOOM - 1 };
+        let diagonal = vec![at_in(zoomed_out, 0.0, 0.0), at_in(zoomed_out, 1.0, 1.0)];
+        let data = tile_data(Vec::new(), vec![street("s", "Main Street", diagonal)]);
+        assert!(place(zoomed_out, &data).is_empty());
+    }
+
+    #[test]
+    fn draws_text_with_a_halo() {
+        let engine = LabelEngine::default();
+        let paint = TextPaint {
+            color: Some(Color::BLACK),
+            halo: Color::WHITE,
+            halo_width: 3.0,
+            size: Some(24.0),
+            filter: None,
+        };
+        let label = engine.centered_text("HH", (64.0, 64.0), &paint, 1.0);
+        let mut pixmap = Pixmap::new(128, 128).unwrap();
+        engine.draw(&mut pixmap, &[label]);
+
+        let colors: Vec<_> = pixmap.pixels().iter().map(|pixel| pixel.demultiply()).collect();
+        assert!(colors.iter().any(|c| c.alpha() == 0xff && c.red() == 0));
+        assert!(colors.iter().any(|c| c.alpha() == 0xff && c.red() == 0xff));
+        assert_eq!(pixmap.pixel(2, 2).unwrap().alpha(), 0);
+    }
+}
@@ -0,0 +1,682 @@
+//! 2D rasterizer for map data
+//!
+//! Draws `TileMetadata` features and roads into an anti-aliased RGBA canvas and
+//! encodes it as PNG, JPEG or WebP. Features are painted in `z_index` order,
+//! then roads: all casings first, then all road fills, minor roads below major
+//! ones, so junctions join cleanly. Labels are placed and drawn last.
+//!
+//! When a `StyleSheet` is given, its layers replace the per-feature styles and
+//! decide what is drawn, in which order and how.
+
+use std::f64::consts::PI;
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder, RgbaImage};
+use thiserror::Error;
+use tiny_skia::{
+    Color, ColorU8, FillRule, LineCap, LineJoin, Paint, Path, PathBuilder, Pixmap, Stroke, StrokeDash, Transform,
+};
+
+use googlemaps_clone::constants::TILE_SIZE;
+use crate::models::map_tile::{FeatureStyle, Geometry, MapFeature, Road, RoadType, TileFormat, TileMetadata};
+use crate::services::label_engine::{LabelEngine, LabelPaint, TextPaint};
+use crate::services::map_style::{LayerType, SourceLayer, StyleLayer, StyleSheet};
+use crate::utils::geo::LatLng;
+use crate::utils::tile_utils::{PixelCoord, TileCoord};
+
+/// JPEG and lossy WebP quality
+const LOSSY_QUALITY: u8 = 85;
+
+/// Radius of point features in CSS pixels
+const POINT_RADIUS: f32 = 3.0;
+
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("Cannot render a {0}x{1} image")]
+    InvalidSize(u32, u32),
+    #[error("Raster rendering does not produce {0:?} tiles")]
+    UnsupportedFormat(TileFormat),
+    #[error("Failed to encode image: {0}")]
+    Encode(#[from] image::ImageError),
+    #[error("Failed to decode image: {0}")]
+    Decode(image::ImageError),
+}
+
+/// The area of the Web Mercator plane being drawn
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    /// World pixel coordinate of the top-left corner at `zoom`
+    pub origin: PixelCoord,
+    pub zoom: u8,
+    /// Output size in CSS pixels
+    pub width: u32,
+    pub height: u32,
+    /// Device pixel ratio; the image is `width * scale` by `height * scale`
+    pub scale: f32,
+}
+
+impl Viewport {
+    pub fn for_tile(coord: TileCoord, scale: f32) -> Self {
+        Self {
+            origin: PixelCoord {
+                x: coord.x as f64 * TILE_SIZE as f64,
+                y: coord.y as f64 * TILE_SIZE as f64,
+            },
+            zoom: coord.z,
+            width: TILE_SIZE,
+            height: TILE_SIZE,
+            scale,
+        }
+    }
+
+    pub fn pixel_width(&self) -> u32 {
+        (self.width as f32 * self.scale).round() as u32
+    }
+
+    pub fn pixel_height(&self) -> u32 {
+        (self.height as f32 * self.scale).round() as u32
+    }
+
+    /// Device pixel position of `position` within the viewport
+    pub fn project(&self, position: &LatLng) -> (f32, f32) {
+        let world = TILE_SIZE as f64 * 2_f64.powi(self.zoom as i32);
+        let lat = position.lat.clamp(-85.051_128_78, 85.051_128_78).to_radians();
+        let x = (position.lng + 180.0) / 360.0 * world;
+        let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * world;
+
+        (
+            ((x - self.origin.x) * self.scale as f64) as f32,
+            ((y - self.origin.y) * self.scale as f64) as f32,
+        )
+    }
+}
+
+pub struct MapRenderer {
+    background: Color,
+    labels: LabelEngine,
+}
+
+impl Default for MapRenderer {
+    fn default() -> Self {
+        Self::new(Color::from_rgba8(0xf2, 0xef, 0xe9, 0xff))
+    }
+}
+
+impl MapRenderer {
+    pub fn new(background: Color) -> Self {
+        Self {
+            background,
+            labels: LabelEngine::default(),
+        }
+    }
+
+    /// The text engine labels are drawn with
+    pub fn label_engine(&self) -> &LabelEngine {
+        &self.labels
+    }
+
+    pub fn render(&self, viewport: &Viewport, data: &TileMetadata, style: Option<&StyleSheet>) -> Result<Pixmap, RenderError> {
+        let (width, height) = (viewport.pixel_width(), viewport.pixel_height());
+        let mut pixmap = Pixmap::new(width, height).ok_or(RenderError::InvalidSize(width, height))?;
+
+        match style {
+            Some(style) => {
+                let zoom = viewport.zoom as f64;
+                pixmap.fill(style.background(zoom).unwrap_or(self.background));
+                let label_paint = draw_styled(&mut pixmap, viewport, data, style);
+                let labels = self.labels.place(viewport, data, &label_paint);
+                self.labels.draw(&mut pixmap, &labels);
+            }
+            None => {
+                pixmap.fill(self.background);
+                self.draw_feature_styles(&mut pixmap, viewport, data);
+            }
+        }
+
+        Ok(pixmap)
+    }
+
+    /// Draw with the styles carried by each feature and road
+    fn draw_feature_styles(&self, pixmap: &mut Pixmap, viewport: &Viewport, data: &TileMetadata) {
+        let mut features: Vec<&MapFeature> = data.features.iter().collect();
+        features.sort_by_key(|feature| feature.style.z_index);
+        for feature in features {
+            draw_feature(pixmap, viewport, feature);
+        }
+
+        let mut roads: Vec<&Road> = data.roads.iter().filter(|road| road.geometry.len() >= 2).collect();
+        roads.sort_by_key(|road| road_rank(&road.road_type));
+        for road in &roads {
+            draw_road_casing(pixmap, viewport, road);
+        }
+        for road in &roads {
+            draw_road(pixmap, viewport, road);
+        }
+
+        let labels = self.labels.place(viewport, data, &LabelPaint::default());
+        self.labels.draw(pixmap, &labels);
+    }
+
+    pub fn render_encoded(
+        &self,
+        viewport: &Viewport,
+        data: &TileMetadata,
+        style: Option<&StyleSheet>,
+        format: &TileFormat,
+    ) -> Result<Vec<u8>, RenderError> {
+        let pixmap = self.render(viewport, data, style)?;
+        encode(&pixmap, format)
+    }
+}
+
+/// Draw every fill and line layer of `style` in order and collect the text
+/// paint of its symbol layers for label placement
+fn draw_styled(pixmap: &mut Pixmap, viewport: &Viewport, data: &TileMetadata, style: &StyleSheet) -> LabelPaint {
+    let zoom = viewport.zoom as f64;
+    let mut label_paint = LabelPaint { points: None, roads: None };
+
+    let mut features: Vec<&MapFeature> = data.features.iter().collect();
+    features.sort_by_key(|feature| feature.style.z_index);
+    let mut roads: Vec<&Road> = data.roads.iter().filter(|road| road.geometry.len() >= 2).collect();
+    roads.sort_by_key(|road| road_rank(&road.road_type));
+
+    for layer in style.layers.iter().filter(|layer| layer.visible_at(zoom)) {
+        match (layer.layer_type, layer.source_layer) {
+            (LayerType::Fill, Some(SourceLayer::Features)) => {
+                for feature in features.iter().filter(|f| layer.matches(**f)) {
+                    fill_styled(pixmap, viewport, feature, layer, zoom);
+                }
+            }
+            (LayerType::Line, Some(SourceLayer::Features)) => {
+                for feature in features.iter().filter(|f| layer.matches(**f)) {
+                    let path = match &feature.geometry {
+                        Geometry::LineString(points) => line_path(viewport, points),
+                        Geometry::Polygon(rings) => polygon_path(viewport, rings.iter()),
+                        Geometry::MultiPolygon(polygons) => polygon_path(viewport, polygons.iter().flatten()),
+                        Geometry::Point(_) => None,
+                    };
+                    if let Some(path) = path {
+                        stroke_styled(pixmap, viewport, &path, layer, zoom);
+                    }
+                }
+            }
+            (LayerType::Line, Some(SourceLayer::Roads)) => {
+                for road in roads.iter().filter(|r| layer.matches(**r)) {
+                    if let Some(path) = line_path(viewport, &road.geometry) {
+                        stroke_styled(pixmap, viewport, &path, layer, zoom);
+                    }
+                }
+            }
+            (LayerType::Symbol, Some(SourceLayer::Labels)) => label_paint.points = Some(text_paint(layer, zoom)),
+            (LayerType::Symbol, Some(SourceLayer::Roads)) => label_paint.roads = Some(text_paint(layer, zoom)),
+            _ => {}
+        }
+    }
+
+    label_paint
+}
+
+fn fill_styled(pixmap: &mut Pixmap, viewport: &Viewport, feature: &MapFeature, layer: &StyleLayer, zoom: f64) {
+    let path = match &feature.geometry {
+        Geometry::Polygon(rings) => polygon_path(viewport, rings.iter()),
+        Geometry::MultiPolygon(polygons) => polygon_path(viewport, polygons.iter().flatten()),
+        Geometry::Point(position) => {
+            let (x, y) = viewport.project(position);
+            PathBuilder::from_circle(x, y, POINT_RADIUS * viewport.scale)
+        }
+        Geometry::LineString(_) => None,
+    };
+    let Some(path) = path else {
+        return;
+    };
+
+    let paint_props = &layer.paint;
+    let opacity = paint_props.fill_opacity.as_ref().and_then(|o| o.number(zoom)).unwrap_or(1.0);
+    if let Some(color) = paint_props.fill_color.as_ref().and_then(|c| c.color(zoom)) {
+        pixmap.fill_path(&path, &solid(color, opacity), FillRule::EvenOdd, Transform::identity(), None);
+    }
+    if let Some(color) = paint_props.fill_outline_color.as_ref().and_then(|c| c.color(zoom)) {
+        let outline = Stroke {
+            width: viewport.scale,
+            ..Stroke::default()
+        };
+        pixmap.stroke_path(&path, &solid(color, opacity), &outline, Transform::identity(), None);
+    }
+}
+
+fn stroke_styled(pixmap: &mut Pixmap, viewport: &Viewport, path: &Path, layer: &StyleLayer, zoom: f64) {
+    let paint_props = &layer.paint;
+    let Some(color) = paint_props.line_color.as_ref().and_then(|c| c.color(zoom)) else {
+        return;
+    };
+    let opacity = paint_props.line_opacity.as_ref().and_then(|o| o.number(zoom)).unwrap_or(1.0);
+    let width = paint_props.line_width.as_ref().and_then(|w| w.number(zoom)).unwrap_or(1.0);
+    if width <= 0.0 {
+        return;
+    }
+
+    let dash = paint_props.line_dasharray.as_ref().and_then(|d| d.numbers(zoom)).and_then(|pattern| {
+        let scaled = pattern.iter().map(|length| length * width * viewport.scale).collect();
+        StrokeDash::new(scaled, 0.0)
+    });
+    pixmap.stroke_path(path, &solid(color, opacity), &line_stroke(width * viewport.scale, dash), Transform::identity(), None);
+}
+
+fn text_paint(layer: &StyleLayer, zoom: f64) -> TextPaint {
+    let paint_props = &layer.paint;
+    TextPaint {
+        color: paint_props.text_color.as_ref().and_then(|c| c.color(zoom)),
+        halo: paint_props.text_halo_color.as_ref().and_then(|c| c.color(zoom)).unwrap_or(Color::WHITE),
+        halo_width: paint_props.text_halo_width.as_ref().and_then(|w| w.number(zoom)).unwrap_or(0.0),
+        size: layer.layout.text_size.as_ref().and_then(|s| s.number(zoom)),
+        filter: layer.filter.clone(),
+    }
+}
+
+fn solid(mut color: Color, opacity: f32) -> Paint<'static> {
+    color.apply_opacity(opacity.clamp(0.0, 1.0));
+    let mut paint = Paint::default();
+    paint.set_color(color);
+    paint.anti_alias = true;
+    paint
+}
+
+/// Encode a rendered canvas in a raster `format`
+pub fn encode(pixmap: &Pixmap, format: &TileFormat) -> Result<Vec<u8>, RenderError> {
+    let (width, height) = (pixmap.width(), pixmap.height());
+    let mut out = Cursor::new(Vec::new());
+
+    match format {
+        TileFormat::Png => {
+            let rgba = to_rgba_image(pixmap);
+            PngEncoder::new(&mut out).write_image(rgba.as_raw(), width, height, ColorType::Rgba8)?;
+        }
+        TileFormat::Jpeg => {
+            let rgb = image::DynamicImage::ImageRgba8(to_rgba_image(pixmap)).to_rgb8();
+            JpegEncoder::new_with_quality(&mut out, LOSSY_QUALITY).write_image(rgb.as_raw(), width, height, ColorType::Rgb8)?;
+        }
+        TileFormat::Webp => {
+            let rgba = to_rgba_image(pixmap);
+            let encoded = webp::Encoder::from_rgba(rgba.as_raw(), width, height).encode(LOSSY_QUALITY as f32);
+            return Ok(encoded.to_vec());
+        }
+        TileFormat::Vector => return Err(RenderError::UnsupportedFormat(format.clone())),
+    }
+
+    Ok(out.into_inner())
+}
+
+/// Decode a PNG, JPEG or WebP tile into a pixmap
+pub fn decode(bytes: &[u8]) -> Result<Pixmap, RenderError> {
+    let rgba = image::load_from_memory(bytes).map_err(RenderError::Decode)?.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let mut pixmap = Pixmap::new(width, height).ok_or(RenderError::InvalidSize(width, height))?;
+    for (pixel, rgba) in pixmap.pixels_mut().iter_mut().zip(rgba.pixels()) {
+        let [r, g, b, a] = rgba.0;
+        *pixel = ColorU8::from_rgba(r, g, b, a).premultiply();
+    }
+    Ok(pixmap)
+}
+
+/// Convert tiny-skia's premultiplied pixels to straight alpha
+pub fn to_rgba_image(pixmap: &Pixmap) -> RgbaImage {
+    let mut data = Vec::with_capacity(pixmap.data().len());
+    for pixel in pixmap.pixels() {
+        let color = pixel.demultiply();
+        data.extend_from_slice(&[color.red(), color.green(), color.blue(), color.alpha()]);
+    }
+    RgbaImage::from_raw(pixmap.width(), pixmap.height(), data).expect("pixmap dimensions match its data")
+}
+
+fn draw_feature(pixmap: &mut Pixmap, viewport: &Viewport, feature: &MapFeature) {
+    let style = &feature.style;
+    let opacity = style.opacity.unwrap_or(1.0).clamp(0.0, 1.0);
+
+    match &feature.geometry {
+        Geometry::Point(position) => {
+            let (x, y) = viewport.project(position);
+            if let Some(path) = PathBuilder::from_circle(x, y, POINT_RADIUS * viewport.scale) {
+                fill_and_stroke(pixmap, viewport, &path, style, opacity);
+            }
+        }
+        Geometry::LineString(points) => {
+            if let Some(path) = line_path(viewport, points) {
+                let color = style.stroke_color.as_deref().or(style.fill_color.as_deref());
+                if let Some(paint) = color.and_then(|c| paint(c, opacity)) {
+                    let width = style.stroke_width.unwrap_or(1.0) * viewport.scale;
+                    pixmap.stroke_path(&path, &paint, &line_stroke(width, None), Transform::identity(), None);
+                }
+            }
+        }
+        Geometry::Polygon(rings) => {
+            if let Some(path) = polygon_path(viewport, rings.iter()) {
+                fill_and_stroke(pixmap, viewport, &path, style, opacity);
+            }
+        }
+        Geometry::MultiPolygon(polygons) => {
+            if let Some(path) = polygon_path(viewport, polygons.iter().flatten()) {
+                fill_and_stroke(pixmap, viewport, &path, style, opacity);
+            }
+        }
+    }
+}
+
+fn fill_and_stroke(pixmap: &mut Pixmap, viewport: &Viewport, path: &Path, style: &FeatureStyle, opacity: f32) {
+    if let Some(fill) = style.fill_color.as_deref().and_then(|c| paint(c, opacity)) {
+        pixmap.fill_path(path, &fill, FillRule::EvenOdd, Transform::identity(), None);
+    }
+    if let Some(stroke) = style.stroke_color.as_deref().and_then(|c| paint(c, opacity)) {
+        let width = style.stroke_width.unwrap_or(1.0) * viewport.scale;
+        let outline = Stroke {
+            width,
+            line_join: LineJoin::Round,
+            ..Stroke::default()
+        };
+        pixmap.stroke_path(path, &stroke, &outline, Transform::identity(), None);
+    }
+}
+
+fn draw_road_casing(pixmap: &mut Pixmap, viewport: &Viewport, road: &Road) {
+    let style = &road.style;
+    let (Some(color), Some(border_width)) = (style.border_color.as_deref(), style.border_width) else {
+        return;
+    };
+    let (Some(path), Some(paint)) = (line_path(viewport, &road.geometry), paint(color, 1.0)) else {
+        return;
+    };
+
+    let width = (style.width + 2.0 * border_width) * viewport.scale;
+    pixmap.stroke_path(&path, &paint, &line_stroke(width, None), Transform::identity(), None);
+}
+
+fn draw_road(pixmap: &mut Pixmap, viewport: &Viewport, road: &Road) {
+    let style = &road.style;
+    let (Some(path), Some(paint)) = (line_path(viewport, &road.geometry), paint(&style.color, 1.0)) else {
+        return;
+    };
+
+    let dash = style.dash_pattern.as_ref().and_then(|pattern| {
+        let scaled = pattern.iter().map(|length| length * viewport.scale).collect();
+        StrokeDash::new(scaled, 0.0)
+    });
+    let width = style.width * viewport.scale;
+    pixmap.stroke_path(&path, &paint, &line_stroke(width, dash), Transform::identity(), None);
+}
+
+/// Draw order of road types, from first (bottom) to last (top)
+pub fn road_rank(road_type: &RoadType) -> u8 {
+    match road_type {
+        RoadType::Footway => 0,
+        RoadType::Cycleway => 1,
+        RoadType::Service => 2,
+        RoadType::Residential => 3,
+        RoadType::Tertiary => 4,
+        RoadType::Secondary => 5,
+        RoadType::Primary => 6,
+        RoadType::Highway => 7,
+    }
+}
+
+fn line_stroke(width: f32, dash: Option<StrokeDash>) -> Stroke {
+    // Dashed lines get butt caps so the gaps stay visible
+    let line_cap = if dash.is_some() { LineCap::Butt } else { LineCap::Round };
+    Stroke {
+        width,
+        line_cap,
+        line_join: LineJoin::Round,
+        dash,
+        ..Stroke::default()
+    }
+}
+
+pub fn line_path(viewport: &Viewport, points: &[LatLng]) -> Option<Path> {
+    let mut builder = PathBuilder::new();
+    for (index, point) in points.iter().enumerate() {
+        let (x, y) = viewport.project(point);
+        if index == 0 {
+            builder.move_to(x, y);
+        } else {
+            builder.line_to(x, y);
+        }
+    }
+    builder.finish()
+}
+
+/// One path from all rings; holes are cut out by the even-odd fill rule
+pub fn polygon_path<'a>(viewport: &Viewport, rings: impl Iterator<Item = &'a Vec<LatLng>>) -> Option<Path> {
+    let mut builder = PathBuilder::new();
+    for ring in rings.filter(|ring| ring.len() >= 3) {
+        for (index, point) in ring.iter().enumerate() {
+            let (x, y) = viewport.project(point);
+            if index == 0 {
+                builder.move_to(x, y);
+            } else {
+                builder.line_to(x, y);
+            }
+        }
+        builder.close();
+    }
+    builder.finish()
+}
+
+fn paint(color: &str, opacity: f32) -> Option<Paint<'static>> {
+    parse_color(color).map(|color| solid(color, opacity))
+}
+
+/// Parse `#rgb`, `#rrggbb` or `#rrggbbaa` colors
+pub fn parse_color(value: &str) -> Option<Color> {
+    let hex = value.trim().strip_prefix('#')?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    let short = |i: usize| {
+        let digit = u8::from_str_radix(hex.get(i..i + 1)?, 16).ok()?;
+        Some(digit * 17)
+    };
+
+    let (r, g, b, a) = match hex.len() {
+        3 => (short(0)?, short(1)?, short(2)?, 255),
+        6 => (channel(0)?, channel(2)?, channel(4)?, 255),
+        8 => (channel(0)?, channel(2)?, channel(4)?, channel(6)?),
+        _ => return None,
+    };
+    Some(Color::from_rgba8(r, g, b, a))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::models::map_tile::{FeatureType, RoadSurface, RoadStyle};
+    use crate::utils::geo::BoundingBox;
+
+    /// Largest per-channel difference tolerated against a golden image
+    const TOLERANCE: u8 = 6;
+
+    /// A tile over San Francisco at street zoom
+    const TILE: TileCoord = TileCoord { x: 5241, y: 12665, z: 15 };
+
+    /// Position at fractions `(x, y)` across the test tile
+    fn at(x: f64, y: f64) -> LatLng {
+        let n = 2_f64.powi(TILE.z as i32);
+        let lng = (TILE.x as f64 + x) / n * 360.0 - 180.0;
+        let lat = (PI * (1.0 - 2.0 * (TILE.y as f64 + y) / n)).sinh().atan().to_degrees();
+        LatLng::new(lat, lng)
+    }
+
+    fn tile_data(features: Vec<MapFeature>, roads: Vec<Road>) -> TileMetadata {
+        let (top_left, bottom_right) = (at(0.0, 0.0), at(1.0, 1.0));
+        TileMetadata {
+            bounds: BoundingBox::new(top_left.lat, bottom_right.lat, bottom_right.lng, top_left.lng),
+            features,
+            roads,
+            labels: Vec::new(),
+            pois: Vec::new(),
+            style_version: "test".to_string(),
+            data_version: "test".to_string(),
+        }
+    }
+
+    /// An unnamed road, so no street label is drawn along it
+    fn road(id: &str, road_type: RoadType, geometry: Vec<LatLng>, style: RoadStyle) -> Road {
+        Road {
+            id: id.to_string(),
+            name: String::new(),
+            road_type,
+            geometry,
+            lanes: 2,
+            speed_limit: None,
+            one_way: false,
+            surface: RoadSurface::Paved,
+            style,
+        }
+    }
+
+    fn render(data: &TileMetadata) -> Pixmap {
+        MapRenderer::default()
+            .render(&Viewport::for_tile(TILE, 1.0), data, None)
+            .expect("test tile renders")
+    }
+
+    /// Compare against `tests/fixtures/render/<name>.png`; set `UPDATE_GOLDEN=1`
+    /// to rewrite the file from the current output instead
+    fn assert_matches_golden(pixmap: &Pixmap, name: &str) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", "render", &format!("{name}.png")]
+            .iter()
+            .collect();
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, encode(pixmap, &TileFormat::Png).unwrap()).unwrap();
+            return;
+        }
+
+        let golden = decode(&std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (golden.width(), golden.height()), "{name}: size");
+
+        let width = pixmap.width() as usize;
+        let mismatches: Vec<(usize, usize)> = pixmap
+            .pixels()
+            .iter()
+            .zip(golden.pixels())
+            .enumerate()
+            .filter(|(_, (actual, expected))| {
+                let (actual, expected) = (actual.demultiply(), expected.demultiply());
+                [
+                    actual.red().abs_diff(expected.red()),
+                    actual.green().abs_diff(expected.green()),
+                    actual.blue().abs_diff(expected.blue()),
+                    actual.alpha().abs_diff(expected.alpha()),
+                ]
+                .into_iter()
+                .any(|difference| difference > TOLERANCE)
+            })
+            .map(|(index, _)| (index % width, index / width))
+            .collect();
+        assert!(
+            mismatches.is_empty(),
+            "{name}: {} pixels differ from the golden image, first at {:?}",
+            mismatches.len(),
+            mismatches[0]
+        );
+    }
+
+    fn pixel(pixmap: &Pixmap, x: u32, y: u32) -> [u8; 4] {
+        let color = pixmap.pixel(x, y).unwrap().demultiply();
+        [color.red(), color.green(), color.blue(), color.alpha()]
+    }
+
+    #[test]
+    fn fills_polygons_with_holes() {
+        let ring = |min: f64, max: f64| vec![at(min, min), at(max, min), at(max, max), at(min, max)];
+        let lake = MapFeature {
+            id: "lake".to_string(),
+            feature_type: FeatureType::Water,
+            geometry: Geometry::Polygon(vec![ring(0.1, 0.9), ring(0.35, 0.65)]),
+            properties: HashMap::new(),
+            style: FeatureStyle {
+                fill_color: Some("#aad3df".to_string()),
+                stroke_color: Some("#6699aa".to_string()),
+                stroke_width: Some(2.0),
+                opacity: None,
+                z_index: 0,
+            },
+        };
+        let pixmap = render(&tile_data(vec![lake], Vec::new()));
+
+        assert_eq!(pixel(&pixmap, 64, 64), [0xaa, 0xd3, 0xdf, 0xff]);
+        assert_eq!(pixel(&pixmap, 128, 128), [0xf2, 0xef, 0xe9, 0xff], "the hole stays background");
+        assert_matches_golden(&pixmap, "polygon");
+    }
+
+    #[test]
+    fn draws_road_casings_beneath_every_fill() {
+        let casing = |color: &str, fill: &str, width: f32| RoadStyle {
+            color: fill.to_string(),
+            width,
+            dash_pattern: None,
+            border_color: Some(color.to_string()),
+            border_width: Some(2.0),
+        };
+        let roads = vec![
+            road("primary", RoadType::Primary, vec![at(0.0, 0.5), at(1.0, 0.5)], casing("#c99f60", "#fcd6a4", 10.0)),
+            road("residential", RoadType::Residential, vec![at(0.5, 0.0), at(0.5, 1.0)], casing("#bbbbbb", "#ffffff", 6.0)),
+        ];
+        let pixmap = render(&tile_data(Vec::new(), roads));
+
+        assert_eq!(pixel(&pixmap, 128, 128), [0xfc, 0xd6, 0xa4, 0xff], "the primary fill wins the junction");
+        assert_eq!(pixel(&pixmap, 128, 40), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(pixel(&pixmap, 40, 122), [0xc9, 0x9f, 0x60, 0xff]);
+        assert_eq!(pixel(&pixmap, 124, 40), [0xbb, 0xbb, 0xbb, 0xff]);
+        assert_matches_golden(&pixmap, "road_casing");
+    }
+
+    #[test]
+    fn scales_retina_tiles_by_the_device_pixel_ratio() {
+        let style = RoadStyle {
+            color: "#333333".to_string(),
+            width: 10.0,
+            dash_pattern: None,
+            border_color: None,
+            border_width: None,
+        };
+        let data = tile_data(Vec::new(), vec![road("main", RoadType::Primary, vec![at(0.0, 0.5), at(1.0, 0.5)], style)]);
+
+        for scale in [1, 2, 3] {
+            let viewport = Viewport::for_tile(TILE, scale as f32);
+            let (x, y) = viewport.project(&at(0.25, 0.75));
+            assert!((x - 64.0 * scale as f32).abs() < 0.01 && (y - 192.0 * scale as f32).abs() < 0.01);
+
+            let pixmap = MapRenderer::default().render(&viewport, &data, None).unwrap();
+            let size = 256 * scale;
+            assert_eq!((pixmap.width(), pixmap.height()), (size, size));
+
+            // The road is 10 CSS pixels wide at every scale
+            let center = size / 2;
+            assert_eq!(pixel(&pixmap, center, center + 4 * scale), [0x33, 0x33, 0x33, 0xff], "@{scale}x");
+            assert_eq!(pixel(&pixmap, center, center + 6 * scale), [0xf2, 0xef, 0xe9, 0xff], "@{scale}x");
+        }
+    }
+
+    #[test]
+    fn antialiases_diagonal_lines() {
+        let style = RoadStyle {
+            color: "#333333".to_string(),
+            width: 3.0,
+            dash_pattern: None,
+            border_color: None,
+            border_width: None,
+        };
+        let roads = vec![road("diagonal", RoadType::Service, vec![at(0.1, 0.2), at(0.9, 0.7)], style)];
+        let pixmap = render(&tile_data(Vec::new(), roads));
+
+        let partial = pixmap
+            .pixels()
+            .iter()
+            .map(|pixel| pixel.demultiply().red())
+            .filter(|red| (0x34..0xf2).contains(red))
+            .count();
+        assert!(partial > 100, "only {partial} edge pixels are blended");
+        assert_matches_golden(&pixmap, "diagonal");
+    }
+}
@@ -0,0 +1,640 @@
+//! Declarative map styles
+//!
+//! A style document is a JSON file modeled on the Mapbox GL style format: an
+//! ordered list of layers, each drawing one kind of geometry from a source
+//! layer (`features`, `roads` or `labels`), optionally narrowed by a filter and
+//! a zoom range. Paint properties are literals or zoom expressions
+//! (`interpolate`, `step`, or legacy `{"stops": ...}` functions).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use serde::Deserialize;
+use serde_json::Value as Json;
+use thiserror::Error;
+use tiny_skia::Color;
+use tracing::{info, warn};
+
+use crate::models::map_tile::{Geometry, Label, MapFeature, Road};
+use crate::services::map_renderer::parse_color;
+
+/// Style used when a request does not name one
+pub const DEFAULT_STYLE: &str = "light";
+
+#[derive(Debug, Error)]
+pub enum StyleError {
+    #[error("Failed to read style {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid style {0}: {1}")]
+    Parse(PathBuf, serde_json::Error),
+    #[error("No styles found in {0}")]
+    NoStyles(PathBuf),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StyleSheet {
+    pub version: u8,
+    pub name: String,
+    pub layers: Vec<StyleLayer>,
+    /// Identifies this exact style content; part of every tile cache key
+    #[serde(skip)]
+    pub revision: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerType {
+    Background,
+    Fill,
+    Line,
+    Symbol,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceLayer {
+    Features,
+    Roads,
+    Labels,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StyleLayer {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub layer_type: LayerType,
+    #[serde(rename = "source-layer")]
+    pub source_layer: Option<SourceLayer>,
+    pub filter: Option<Filter>,
+    pub minzoom: Option<f64>,
+    pub maxzoom: Option<f64>,
+    #[serde(default)]
+    pub paint: LayerPaint,
+    #[serde(default)]
+    pub layout: LayerLayout,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LayerPaint {
+    pub background_color: Option<StyleValue>,
+    pub fill_color: Option<StyleValue>,
+    pub fill_opacity: Option<StyleValue>,
+    pub fill_outline_color: Option<StyleValue>,
+    pub line_color: Option<StyleValue>,
+    pub line_width: Option<StyleValue>,
+    pub line_opacity: Option<StyleValue>,
+    /// Dash and gap lengths in multiples of the line width
+    pub line_dasharray: Option<StyleValue>,
+    pub text_color: Option<StyleValue>,
+    pub text_halo_color: Option<StyleValue>,
+    pub text_halo_width: Option<StyleValue>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LayerLayout {
+    pub visibility: Option<String>,
+    pub text_size: Option<StyleValue>,
+}
+
+impl StyleLayer {
+    pub fn visible_at(&self, zoom: f64) -> bool {
+        self.layout.visibility.as_deref() != Some("none")
+            && self.minzoom.is_none_or(|min| zoom >= min)
+            && self.maxzoom.is_none_or(|max| zoom < max)
+    }
+
+    pub fn matches(&self, item: &dyn StyleFeature) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.matches(item))
+    }
+}
+
+/// A paint or layout property: a literal, a legacy stops function, or an
+/// `interpolate` / `step` expression over `["zoom"]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StyleValue {
+    Stops {
+        stops: Vec<(f64, Json)>,
+        #[serde(default = "default_base")]
+        base: f64,
+    },
+    Value(Json),
+}
+
+fn default_base() -> f64 {
+    1.0
+}
+
+/// An evaluated property
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Number(f32),
+    Color(Color),
+    Numbers(Vec<f32>),
+}
+
+impl StyleValue {
+    pub fn number(&self, zoom: f64) -> Option<f32> {
+        match self.evaluate(zoom)? {
+            Output::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self, zoom: f64) -> Option<Color> {
+        match self.evaluate(zoom)? {
+            Output::Color(color) => Some(color),
+            _ => None,
+        }
+    }
+
+    pub fn numbers(&self, zoom: f64) -> Option<Vec<f32>> {
+        match self.evaluate(zoom)? {
+            Output::Numbers(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn evaluate(&self, zoom: f64) -> Option<Output> {
+        match self {
+            StyleValue::Stops { stops, base } => interpolate(stops.iter().map(|(z, v)| (*z, v)), *base, zoom),
+            StyleValue::Value(value) => evaluate_json(value, zoom),
+        }
+    }
+}
+
+fn evaluate_json(value: &Json, zoom: f64) -> Option<Output> {
+    let Some(items) = value.as_array() else {
+        return literal(value);
+    };
+
+    match items.first().and_then(Json::as_str) {
+        // ["interpolate", ["linear"] | ["exponential", base], ["zoom"], z0, v0, z1, v1, ...]
+        Some("interpolate") => {
+            let kind = items.get(1)?.as_array()?;
+            let base = match kind.first()?.as_str()? {
+                "linear" => 1.0,
+                "exponential" => kind.get(1)?.as_f64()?,
+                _ => return None,
+            };
+            let stops = items.get(3..)?.chunks_exact(2).filter_map(|pair| Some((pair[0].as_f64()?, &pair[1])));
+            interpolate(stops, base, zoom)
+        }
+        // ["step", ["zoom"], v0, z1, v1, ...]
+        Some("step") => {
+            let mut output = items.get(2)?;
+            for pair in items.get(3..)?.chunks_exact(2) {
+                if zoom >= pair[0].as_f64()? {
+                    output = &pair[1];
+                }
+            }
+            evaluate_json(output, zoom)
+        }
+        _ => literal(value),
+    }
+}
+
+fn literal(value: &Json) -> Option<Output> {
+    match value {
+        Json::Number(number) => number.as_f64().map(|n| Output::Number(n as f32)),
+        Json::String(color) => parse_color(color).map(Output::Color),
+        Json::Array(items) => items
+            .iter()
+            .map(|item| item.as_f64().map(|n| n as f32))
+            .collect::<Option<Vec<_>>>()
+            .map(Output::Numbers),
+        _ => None,
+    }
+}
+
+/// Interpolate between zoom stops. Numbers and colors are blended; other
+/// outputs step at each stop.
+fn interpolate<'a>(stops: impl Iterator<Item = (f64, &'a Json)>, base: f64, zoom: f64) -> Option<Output> {
+    let stops: Vec<(f64, &Json)> = stops.collect();
+    let (first, last) = (stops.first()?, stops.last()?);
+    if zoom <= first.0 {
+        return literal(first.1);
+    }
+    if zoom >= last.0 {
+        return literal(last.1);
+    }
+
+    let upper = stops.iter().position(|(z, _)| *z > zoom)?;
+    let (z0, v0) = stops[upper - 1];
+    let (z1, v1) = stops[upper];
+    let t = if (base - 1.0).abs() < f64::EPSILON {
+        (zoom - z0) / (z1 - z0)
+    } else {
+        (base.powf(zoom - z0) - 1.0) / (base.powf(z1 - z0) - 1.0)
+    } as f32;
+
+    match (literal(v0)?, literal(v1)?) {
+        (Output::Number(a), Output::Number(b)) => Some(Output::Number(a + (b - a) * t)),
+        (Output::Color(a), Output::Color(b)) => Color::from_rgba(
+            a.red() + (b.red() - a.red()) * t,
+            a.green() + (b.green() - a.green()) * t,
+            a.blue() + (b.blue() - a.blue()) * t,
+            a.alpha() + (b.alpha() - a.alpha()) * t,
+        )
+        .map(Output::Color),
+        (lower, _) => Some(lower),
+    }
+}
+
+/// Something a filter can be evaluated against
+pub trait StyleFeature {
+    /// `Point`, `LineString` or `Polygon`, exposed to filters as `$type`
+    fn geometry_type(&self) -> &'static str;
+    fn property(&self, key: &str) -> Option<Json>;
+}
+
+/// Lowercase name of a unit enum variant, e.g. `RoadType::Primary` -> `primary`
+pub fn class_of<T: serde::Serialize>(value: &T) -> Option<Json> {
+    match serde_json::to_value(value).ok()? {
+        Json::String(name) => Some(Json::String(name.to_lowercase())),
+        _ => None,
+    }
+}
+
+impl StyleFeature for MapFeature {
+    fn geometry_type(&self) -> &'static str {
+        match self.geometry {
+            Geometry::Point(_) => "Point",
+            Geometry::LineString(_) => "LineString",
+            Geometry::Polygon(_) | Geometry::MultiPolygon(_) => "Polygon",
+        }
+    }
+
+    fn property(&self, key: &str) -> Option<Json> {
+        match key {
+            "class" => class_of(&self.feature_type),
+            _ => self.properties.get(key).map(|value| Json::String(value.clone())),
+        }
+    }
+}
+
+impl StyleFeature for Road {
+    fn geometry_type(&self) -> &'static str {
+        "LineString"
+    }
+
+    fn property(&self, key: &str) -> Option<Json> {
+        match key {
+            "class" => class_of(&self.road_type),
+            "surface" => class_of(&self.surface),
+            "name" => Some(Json::String(self.name.clone())),
+            "oneway" => Some(Json::Bool(self.one_way)),
+            "lanes" => Some(Json::from(self.lanes)),
+            _ => None,
+        }
+    }
+}
+
+impl StyleFeature for Label {
+    fn geometry_type(&self) -> &'static str {
+        "Point"
+    }
+
+    fn property(&self, key: &str) -> Option<Json> {
+        match key {
+            "class" => class_of(&self.label_type),
+            "name" => Some(Json::String(self.text.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Layer filters in the Mapbox GL legacy filter syntax, e.g.
+/// `["all", ["==", "class", "primary"], ["!=", "surface", "gravel"]]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "Json")]
+pub enum Filter {
+    All(Vec<Filter>),
+    Any(Vec<Filter>),
+    None(Vec<Filter>),
+    Eq(String, Json),
+    Ne(String, Json),
+    Lt(String, f64),
+    Le(String, f64),
+    Gt(String, f64),
+    Ge(String, f64),
+    In(String, Vec<Json>),
+    NotIn(String, Vec<Json>),
+    Has(String),
+    NotHas(String),
+}
+
+impl TryFrom<Json> for Filter {
+    type Error = String;
+
+    fn try_from(value: Json) -> Result<Self, Self::Error> {
+        let items = value.as_array().ok_or_else(|| format!("Filter must be an array: {}", value))?;
+        let op = items
+            .first()
+            .and_then(Json::as_str)
+            .ok_or_else(|| format!("Filter must start with an operator: {}", value))?;
+        let key = || {
+            items
+                .get(1)
+                .and_then(Json::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| format!("Filter {} needs a property name", op))
+        };
+        let operand = || items.get(2).cloned().ok_or_else(|| format!("Filter {} needs a value", op));
+        let number = || {
+            items
+                .get(2)
+                .and_then(Json::as_f64)
+                .ok_or_else(|| format!("Filter {} needs a numeric value", op))
+        };
+        let children = || items[1..].iter().cloned().map(Filter::try_from).collect::<Result<Vec<_>, _>>();
+
+        Ok(match op {
+            "all" => Filter::All(children()?),
+            "any" => Filter::Any(children()?),
+            "none" => Filter::None(children()?),
+            "==" => Filter::Eq(key()?, operand()?),
+            "!=" => Filter::Ne(key()?, operand()?),
+            "<" => Filter::Lt(key()?, number()?),
+            "<=" => Filter::Le(key()?, number()?),
+            ">" => Filter::Gt(key()?, number()?),
+            ">=" => Filter::Ge(key()?, number()?),
+            "in" => Filter::In(key()?, items[2..].to_vec()),
+            "!in" => Filter::NotIn(key()?, items[2..].to_vec()),
+            "has" => Filter::Has(key()?),
+            "!has" => Filter::NotHas(key()?),
+            other => return Err(format!("Unknown filter operator: {}", other)),
+        })
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, item: &dyn StyleFeature) -> bool {
+        let get = |key: &str| match key {
+            "$type" => Some(Json::String(item.geometry_type().to_string())),
+            _ => item.property(key),
+        };
+        let number = |key: &str| get(key).and_then(|value| value_as_f64(&value));
+
+        match self {
+            Filter::All(filters) => filters.iter().all(|f| f.matches(item)),
+            Filter::Any(filters) => filters.iter().any(|f| f.matches(item)),
+            Filter::None(filters) => !filters.iter().any(|f| f.matches(item)),
+            Filter::Eq(key, value) => get(key).is_some_and(|actual| values_equal(&actual, value)),
+            Filter::Ne(key, value) => get(key).is_none_or(|actual| !values_equal(&actual, value)),
+            Filter::Lt(key, value) => number(key).is_some_and(|n| n < *value),
+            Filter::Le(key, value) => number(key).is_some_and(|n| n <= *value),
+            Filter::Gt(key, value) => number(key).is_some_and(|n| n > *value),
+            Filter::Ge(key, value) => number(key).is_some_and(|n| n >= *value),
+            Filter::In(key, values) => get(key).is_some_and(|actual| values.iter().any(|v| values_equal(&actual, v))),
+            Filter::NotIn(key, values) => get(key).is_none_or(|actual| !values.iter().any(|v| values_equal(&actual, v))),
+            Filter::Has(key) => get(key).is_some(),
+            Filter::NotHas(key) => get(key).is_none(),
+        }
+    }
+}
+
+/// Feature properties are often strings, so `"3"` equals `3` and `"true"`
+/// equals `true`
+fn values_equal(actual: &Json, expected: &Json) -> bool {
+    match (actual, expected) {
+        (Json::String(a), Json::String(b)) => a == b,
+        (_, Json::Number(_)) => value_as_f64(actual) == value_as_f64(expected),
+        (Json::String(a), Json::Bool(b)) => a.parse::<bool>().ok() == Some(*b),
+        (a, b) => a == b,
+    }
+}
+
+fn value_as_f64(value: &Json) -> Option<f64> {
+    match value {
+        Json::Number(number) => number.as_f64(),
+        Json::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+impl StyleSheet {
+    pub fn load(path: &Path) -> Result<Self, StyleError> {
+        let bytes = fs::read(path).map_err(|e| StyleError::Io(path.to_path_buf(), e))?;
+        let mut style: StyleSheet = serde_json::from_slice(&bytes).map_err(|e| StyleError::Parse(path.to_path_buf(), e))?;
+
+        let key = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or(&style.name);
+        style.revision = format!("{}-{:016x}", key, fnv1a(&bytes));
+        Ok(style)
+    }
+
+    pub fn background(&self, zoom: f64) -> Option<Color> {
+        self.layers
+            .iter()
+            .rfind(|layer| layer.layer_type == LayerType::Background && layer.visible_at(zoom))
+            .and_then(|layer| {
+                let mut color = layer.paint.background_color.as_ref()?.color(zoom)?;
+                color.apply_opacity(layer.paint.fill_opacity.as_ref().and_then(|o| o.number(zoom)).unwrap_or(1.0));
+                Some(color)
+            })
+    }
+}
+
+/// FNV-1a, used for style revisions and tile ETags because it is stable
+/// across builds
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// All styles in a directory, keyed by file name without `.json`
+pub struct StyleRegistry {
+    directory: PathBuf,
+    styles: RwLock<HashMap<String, Arc<StyleSheet>>>,
+}
+
+impl StyleRegistry {
+    pub fn load(directory: impl Into<PathBuf>) -> Result<Self, StyleError> {
+        let registry = Self {
+            directory: directory.into(),
+            styles: RwLock::new(HashMap::new()),
+        };
+        registry.reload()?;
+        Ok(registry)
+    }
+
+    /// Re-read every style from disk. Styles that fail to parse are skipped
+    /// so one broken file does not take the others down.
+    pub fn reload(&self) -> Result<usize, StyleError> {
+        let entries = fs::read_dir(&self.directory).map_err(|e| StyleError::Io(self.directory.clone(), e))?;
+
+        let mut styles = HashMap::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()).map(str::to_string) else {
+                continue;
+            };
+            match StyleSheet::load(&path) {
+                Ok(style) => {
+                    styles.insert(name, Arc::new(style));
+                }
+                Err(e) => warn!("Skipping style: {}", e),
+            }
+        }
+
+        if styles.is_empty() {
+            return Err(StyleError::NoStyles(self.directory.clone()));
+        }
+
+        let count = styles.len();
+        if let Ok(mut current) = self.styles.write() {
+            *current = styles;
+        }
+        info!("Loaded {} map styles from {:?}", count, self.directory);
+        Ok(count)
+    }
+
+    /// The named style, or the default style when `name` is `None`
+    pub fn get(&self, name: Option<&str>) -> Option<Arc<StyleSheet>> {
+        self.styles.read().ok()?.get(name.unwrap_or(DEFAULT_STYLE)).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .styles
+            .read()
+            .map(|styles| styles.keys().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A feature with string properties
+    struct Props(&'static str, Vec<(&'static str, Json)>);
+
+    impl StyleFeature for Props {
+        fn geometry_type(&self) -> &'static str {
+            self.0
+        }
+
+        fn property(&self, key: &str) -> Option<Json> {
+            self.1.iter().find(|(k, _)| *k == key).map(|(_, value)| value.clone())
+        }
+    }
+
+    fn value(json: Json) -> StyleValue {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn filter(json: Json) -> Filter {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn assert_close(actual: Option<f32>, expected: f32) {
+        let actual = actual.expect("a number");
+        assert!((actual - expected).abs() < 1e-5, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn interpolates_linearly_between_zoom_stops() {
+        let width = value(json!(["interpolate", ["linear"], ["zoom"], 10, 1, 14, 5]));
+        assert_close(width.number(8.0), 1.0);
+        assert_close(width.number(12.0), 3.0);
+        assert_close(width.number(13.0), 4.0);
+        assert_close(width.number(18.0), 5.0);
+    }
+
+    #[test]
+    fn interpolates_exponentially_and_blends_colors() {
+        let width = value(json!(["interpolate", ["exponential", 2], ["zoom"], 0, 0, 2, 3]));
+        assert_close(width.number(1.0), 1.0);
+
+        let color = value(json!(["interpolate", ["linear"], ["zoom"], 0, "#000000", 10, "#ffffff"]))
+            .color(5.0)
+            .unwrap();
+        assert!((color.red() - 0.5).abs() < 1e-3 && (color.blue() - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn steps_at_each_zoom_threshold() {
+        let width = value(json!(["step", ["zoom"], 1, 10, 2, 15, 3]));
+        assert_close(width.number(9.9), 1.0);
+        assert_close(width.number(10.0), 2.0);
+        assert_close(width.number(14.0), 2.0);
+        assert_close(width.number(20.0), 3.0);
+    }
+
+    #[test]
+    fn evaluates_legacy_stops_functions() {
+        let linear = value(json!({"stops": [[5, 1], [10, 6]]}));
+        assert_close(linear.number(7.5), 3.5);
+        assert_close(linear.number(0.0), 1.0);
+
+        let exponential = value(json!({"stops": [[0, 0], [2, 3]], "base": 2}));
+        assert_close(exponential.number(1.0), 1.0);
+    }
+
+    #[test]
+    fn evaluates_literals() {
+        assert_close(value(json!(2.5)).number(3.0), 2.5);
+        assert_eq!(value(json!([2, 1])).numbers(3.0), Some(vec![2.0, 1.0]));
+        assert!(value(json!("#ff0000")).color(3.0).is_some());
+        assert_eq!(value(json!("not a color")).evaluate(3.0), None);
+        assert_eq!(value(json!(["interpolate", ["cubic"], ["zoom"], 0, 1, 5, 2])).evaluate(3.0), None);
+    }
+
+    #[test]
+    fn parses_nested_filters() {
+        let parsed = filter(json!(["all", ["in", "class", "primary", "secondary"], ["!=", "surface", "gravel"], [">=", "lanes", 2]]));
+        let Filter::All(children) = parsed else {
+            panic!("expected an all filter");
+        };
+        assert!(matches!(&children[0], Filter::In(key, values) if key == "class" && values.len() == 2));
+        assert!(matches!(&children[1], Filter::Ne(key, value) if key == "surface" && value == "gravel"));
+        assert!(matches!(&children[2], Filter::Ge(key, value) if key == "lanes" && *value == 2.0));
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        for invalid in [json!("class"), json!([]), json!(["~=", "class", "x"]), json!(["==", "class"]), json!([">", "lanes", "two"])] {
+            assert!(serde_json::from_value::<Filter>(invalid.clone()).is_err(), "{} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn matches_filters_against_feature_properties() {
+        let road = Props("LineString", vec![("class", json!("primary")), ("lanes", json!("3")), ("oneway", json!("true"))]);
+
+        assert!(filter(json!(["==", "class", "primary"])).matches(&road));
+        assert!(filter(json!(["==", "$type", "LineString"])).matches(&road));
+        assert!(filter(json!(["==", "lanes", 3])).matches(&road));
+        assert!(filter(json!(["==", "oneway", true])).matches(&road));
+        assert!(filter(json!(["<", "lanes", 4])).matches(&road));
+        assert!(!filter(json!([">", "lanes", 3])).matches(&road));
+        assert!(filter(json!(["!in", "class", "footway", "cycleway"])).matches(&road));
+        assert!(filter(json!(["has", "class"])).matches(&road));
+        assert!(filter(json!(["!has", "name"])).matches(&road));
+        assert!(filter(json!(["!=", "name", "Main Street"])).matches(&road));
+        assert!(!filter(json!(["==", "name", "Main Street"])).matches(&road));
+        assert!(filter(json!(["any", ["==", "class", "service"], ["==", "class", "primary"]])).matches(&road));
+        assert!(!filter(json!(["none", ["==", "class", "primary"]])).matches(&road));
+    }
+
+    #[test]
+    fn bundled_styles_include_the_default() {
+        let registry = StyleRegistry::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/styles")).unwrap();
+        let style = registry.get(None).expect("default style");
+        assert_eq!(registry.get(Some(DEFAULT_STYLE)).unwrap().revision, style.revision);
+        assert!(style.background(10.0).is_some());
+        assert!(registry.names().contains(&"dark".to_string()));
+    }
+}
@@ -1,11 +1,28 @@
 pub mod auth;
+pub mod cluster_service;
+pub mod elevation_service;
+pub mod label_engine;
 pub mod map;
-pub mod routing;
+pub mod map_renderer;
+pub mod map_style;
+pub mod navigation_service;
+pub mod ogc_service;
+pub mod rerouting_service;
+pub mod routing_service;
 pub mod search;
+pub mod tile_service;
 pub mod user;
+pub mod voice_guidance;
 
 pub use auth::AuthService;
+pub use cluster_service::ClusterService;
+pub use elevation_service::ElevationService;
 pub use map::MapService;
-pub use routing::RoutingService;
+pub use map_renderer::MapRenderer;
+pub use navigation_service::NavigationService;
+pub use ogc_service::OgcService;
+pub use rerouting_service::ReroutingService;
+pub use routing_service::RoutingService;
 pub use search::SearchService;
+pub use tile_service::TileService;
 pub use user::UserService;
\ No newline at end of file
@@ -0,0 +1,643 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::models::route::{
+    Coordinate, ManeuverType, NavigationInstruction, Route, RouteSegment,
+};
+use crate::services::rerouting_service::{bearing, turn_angle, ReroutingService};
+use crate::services::voice_guidance::{AnnouncementStage, VoiceGuidance};
+
+/// Distances before a maneuver at which its instruction is announced, farthest first
+pub const ANNOUNCEMENT_THRESHOLDS_M: [f64; 3] = [1000.0, 250.0, 40.0];
+
+/// Distance from the route line beyond which a position counts as off-route
+pub const OFF_ROUTE_DISTANCE_M: f64 = 50.0;
+
+/// Closer to the route than `OFF_ROUTE_DISTANCE_M`, a position still counts as
+/// off-route beyond this distance when the heading disagrees with the route
+pub const HEADING_CHECK_DISTANCE_M: f64 = 20.0;
+
+/// Heading difference from the route direction that indicates a wrong turn
+pub const HEADING_MISMATCH_DEG: f64 = 90.0;
+
+/// Below this speed (m/s) GPS headings are too noisy to use
+pub const MIN_HEADING_SPEED: f64 = 2.0;
+
+/// Consecutive off-route positions required before deviation is reported,
+/// so a single noisy GPS fix does not trigger it
+pub const OFF_ROUTE_CONFIRMATIONS: u32 = 3;
+
+/// Off-route positions between reroute attempts while no route back has been
+/// found, e.g. on roads missing from the routing graph
+pub const REROUTE_RETRY_POSITIONS: u32 = 5;
+
+/// Distance from the destination at which the trip is considered complete
+pub const ARRIVAL_DISTANCE_M: f64 = 25.0;
+
+/// Segments ahead of the current one searched when matching a position
+const MATCH_LOOKAHEAD: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositionUpdate {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Direction of travel in degrees clockwise from north
+    pub heading: Option<f64>,
+    /// Speed in meters per second
+    pub speed: Option<f64>,
+}
+
+impl PositionUpdate {
+    pub fn coordinate(&self) -> Coordinate {
+        Coordinate::new(self.latitude, self.longitude)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteProgress {
+    pub segment_index: usize,
+    pub snapped_position: Coordinate,
+    pub distance_from_route: f64,
+    pub distance_traveled: f64,
+    pub distance_remaining: f64,
+    pub duration_remaining: i32,
+    /// Now plus `duration_remaining`
+    pub estimated_arrival: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NavigationEvent {
+    Progress(RouteProgress),
+    Instruction(NavigationInstruction),
+    OffRoute { distance_from_route: f64 },
+    Rerouted {
+        route: Route,
+        rejoins_original: bool,
+    },
+    Arrived,
+}
+
+/// Follows a single trip along its route.
+///
+/// Each position is matched to the nearest point on the route polyline, and
+/// progress, due announcements and deviation are derived from that match.
+#[derive(Debug, Clone)]
+pub struct NavigationTracker {
+    id: String,
+    route: Route,
+    current_position: Coordinate,
+    /// Distance along the route at the start of each segment
+    segment_offsets: Vec<f64>,
+    segment_lengths: Vec<f64>,
+    segment_index: usize,
+    announced: HashSet<(usize, usize)>,
+    off_route_count: u32,
+    arrived: bool,
+    voice: VoiceGuidance,
+}
+
+impl NavigationTracker {
+    pub fn new(route: Route) -> Self {
+        let id = Uuid::new_v4().to_string();
+        let origin = route.origin.clone();
+        Self::with_position(id, route, origin)
+    }
+
+    fn with_position(id: String, route: Route, current_position: Coordinate) -> Self {
+        let (segment_offsets, segment_lengths) = segment_geometry(&route);
+
+        Self {
+            id,
+            route,
+            current_position,
+            segment_offsets,
+            segment_lengths,
+            segment_index: 0,
+            announced: HashSet::new(),
+            off_route_count: 0,
+            arrived: false,
+            voice: VoiceGuidance::default(),
+        }
+    }
+
+    pub fn with_voice(mut self, voice: VoiceGuidance) -> Self {
+        self.voice = voice;
+        self
+    }
+
+    /// Continue the session on a new route, e.g. after rerouting
+    pub fn replace_route(&mut self, route: Route) {
+        let (segment_offsets, segment_lengths) = segment_geometry(&route);
+        self.route = route;
+        self.segment_offsets = segment_offsets;
+        self.segment_lengths = segment_lengths;
+        self.segment_index = 0;
+        self.announced.clear();
+        self.off_route_count = 0;
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn route(&self) -> &Route {
+        &self.route
+    }
+
+    pub fn current_position(&self) -> &Coordinate {
+        &self.current_position
+    }
+
+    pub fn segment_index(&self) -> usize {
+        self.segment_index
+    }
+
+    pub fn update(&mut self, position: &PositionUpdate) -> Vec<NavigationEvent> {
+        let mut events = Vec::new();
+        if self.arrived || self.route.segments.is_empty() {
+            return events;
+        }
+
+        let coordinate = position.coordinate();
+        let progress = self.match_position(&coordinate);
+        self.current_position = coordinate;
+
+        if self.deviates(position, &progress) {
+            self.off_route_count += 1;
+            if self.off_route_count == OFF_ROUTE_CONFIRMATIONS {
+                info!("Navigation session {} went off route", self.id);
+                events.push(NavigationEvent::OffRoute {
+                    distance_from_route: progress.distance_from_route,
+                });
+            }
+            return events;
+        }
+
+        self.off_route_count = 0;
+        self.segment_index = progress.segment_index;
+
+        let destination_distance = self.current_position.distance_to(&self.route.destination);
+        if progress.distance_remaining <= ARRIVAL_DISTANCE_M || destination_distance <= ARRIVAL_DISTANCE_M {
+            self.arrived = true;
+            events.push(NavigationEvent::Progress(progress));
+            events.push(NavigationEvent::Arrived);
+            return events;
+        }
+
+        if let Some(instruction) = self.due_instruction(&progress) {
+            events.push(NavigationEvent::Instruction(instruction));
+        }
+        events.push(NavigationEvent::Progress(progress));
+        events
+    }
+
+    fn deviates(&self, position: &PositionUpdate, progress: &RouteProgress) -> bool {
+        if progress.distance_from_route > OFF_ROUTE_DISTANCE_M {
+            return true;
+        }
+        if progress.distance_from_route <= HEADING_CHECK_DISTANCE_M {
+            return false;
+        }
+
+        match (position.heading, position.speed) {
+            (Some(heading), Some(speed)) if speed >= MIN_HEADING_SPEED => {
+                let segment = &self.route.segments[progress.segment_index];
+                let route_bearing = bearing(&segment.start, &segment.end);
+                turn_angle(route_bearing, heading).abs() > HEADING_MISMATCH_DEG
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the latest positions have been confirmed as off-route
+    pub fn is_off_route(&self) -> bool {
+        self.off_route_count >= OFF_ROUTE_CONFIRMATIONS
+    }
+
+    /// Whether a new route should be looked for after the latest position: once
+    /// deviation is confirmed, and again every `REROUTE_RETRY_POSITIONS`
+    /// positions for as long as it lasts
+    pub fn reroute_due(&self) -> bool {
+        self.is_off_route() && (self.off_route_count - OFF_ROUTE_CONFIRMATIONS) % REROUTE_RETRY_POSITIONS == 0
+    }
+
+    pub fn has_arrived(&self) -> bool {
+        self.arrived
+    }
+
+    fn match_position(&self, position: &Coordinate) -> RouteProgress {
+        let last = self.route.segments.len() - 1;
+        let window_end = (self.segment_index + MATCH_LOOKAHEAD).min(last);
+
+        let mut best = self.best_match(position, self.segment_index, window_end);
+        if best.1 > OFF_ROUTE_DISTANCE_M {
+            // The position may have jumped past the lookahead window, e.g. after
+            // a tunnel; fall back to searching the whole route.
+            let global = self.best_match(position, 0, last);
+            if global.1 < best.1 {
+                best = global;
+            }
+        }
+
+        let (index, distance_from_route, fraction, snapped) = best;
+        let distance_traveled = self.segment_offsets[index] + fraction * self.segment_lengths[index];
+        let total_length = self.segment_offsets[last] + self.segment_lengths[last];
+
+        let current = &self.route.segments[index];
+        let duration_remaining = (current.duration as f64 * (1.0 - fraction)).round() as i32
+            + self.route.segments[index + 1..].iter().map(|s| s.duration).sum::<i32>();
+
+        RouteProgress {
+            segment_index: index,
+            snapped_position: snapped,
+            distance_from_route,
+            distance_traveled,
+            distance_remaining: (total_length - distance_traveled).max(0.0),
+            duration_remaining,
+            estimated_arrival: Utc::now() + Duration::seconds(duration_remaining.into()),
+        }
+    }
+
+    fn best_match(&self, position: &Coordinate, from: usize, to: usize) -> (usize, f64, f64, Coordinate) {
+        (from..=to)
+            .map(|index| {
+                let segment = &self.route.segments[index];
+                let (fraction, snapped) = project_onto_segment(position, &segment.start, &segment.end);
+                (index, position.distance_to(&snapped), fraction, snapped)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("route has at least one segment")
+    }
+
+    /// The next maneuver's instruction if a new announcement threshold was crossed.
+    /// Segments that just continue straight are not maneuvers and are skipped.
+    fn due_instruction(&mut self, progress: &RouteProgress) -> Option<NavigationInstruction> {
+        let next_index = (progress.segment_index + 1..self.route.segments.len())
+            .find(|&index| !matches!(self.route.segments[index].maneuver, ManeuverType::Straight))?;
+        let distance = (self.segment_offsets[next_index] - progress.distance_traveled).max(0.0);
+
+        // Announce only the closest threshold crossed, and mark the farther ones
+        // as done so they are not replayed when positions arrive late.
+        let stage = ANNOUNCEMENT_THRESHOLDS_M
+            .iter()
+            .rposition(|&threshold| distance <= threshold)?;
+        if self.announced.contains(&(next_index, stage)) {
+            return None;
+        }
+        for earlier in 0..=stage {
+            self.announced.insert((next_index, earlier));
+        }
+
+        let stage = match stage {
+            0 => AnnouncementStage::Early,
+            s if s == ANNOUNCEMENT_THRESHOLDS_M.len() - 1 => AnnouncementStage::Now,
+            _ => AnnouncementStage::Approaching,
+        };
+
+        // A maneuver that follows closely is announced together with this one
+        let then = (next_index + 1..self.route.segments.len())
+            .find(|&index| !matches!(self.route.segments[index].maneuver, ManeuverType::Straight))
+            .filter(|&index| {
+                VoiceGuidance::should_combine(self.segment_offsets[index] - self.segment_offsets[next_index])
+            })
+            .map(|index| &self.route.segments[index]);
+
+        let segment = &self.route.segments[next_index];
+        let mut instruction = build_instruction(next_index, segment, distance);
+        instruction.voice_instruction = Some(self.voice.announcement(segment, distance, stage, then));
+
+        debug!("Announcing maneuver {} at {:.0}m in session {}", next_index, distance, self.id);
+        Some(instruction)
+    }
+}
+
+fn segment_geometry(route: &Route) -> (Vec<f64>, Vec<f64>) {
+    let lengths: Vec<f64> = route
+        .segments
+        .iter()
+        .map(|segment| segment.start.distance_to(&segment.end))
+        .collect();
+    let offsets = lengths
+        .iter()
+        .scan(0.0, |offset, length| {
+            let start = *offset;
+            *offset += length;
+            Some(start)
+        })
+        .collect();
+    (offsets, lengths)
+}
+
+pub fn build_instruction(segment_index: usize, segment: &RouteSegment, distance: f64) -> NavigationInstruction {
+    NavigationInstruction {
+        id: Uuid::new_v4().to_string(),
+        segment_index,
+        distance_to_instruction: distance,
+        instruction_text: segment.instruction.clone(),
+        voice_instruction: None,
+        icon: maneuver_icon(&segment.maneuver).to_string(),
+        coordinate: segment.start.clone(),
+    }
+}
+
+pub fn maneuver_icon(maneuver: &ManeuverType) -> &'static str {
+    match maneuver {
+        ManeuverType::Start => "depart",
+        ManeuverType::Straight => "straight",
+        ManeuverType::TurnLeft => "turn-left",
+        ManeuverType::TurnRight => "turn-right",
+        ManeuverType::TurnSlightLeft => "turn-slight-left",
+        ManeuverType::TurnSlightRight => "turn-slight-right",
+        ManeuverType::TurnSharpLeft => "turn-sharp-left",
+        ManeuverType::TurnSharpRight => "turn-sharp-right",
+        ManeuverType::UTurn => "uturn",
+        ManeuverType::Merge => "merge",
+        ManeuverType::RampLeft => "ramp-left",
+        ManeuverType::RampRight => "ramp-right",
+        ManeuverType::Fork => "fork",
+        ManeuverType::Roundabout => "roundabout",
+        ManeuverType::Exit => "exit",
+        ManeuverType::Arrive => "arrive",
+    }
+}
+
+/// Closest point to `point` on the segment `start`-`end`, with its fraction along
+/// the segment. Uses a local equirectangular projection, which is accurate for
+/// the short segments a route is made of.
+pub fn project_onto_segment(point: &Coordinate, start: &Coordinate, end: &Coordinate) -> (f64, Coordinate) {
+    let cos_lat = point.latitude.to_radians().cos();
+    let (ax, ay) = (start.longitude * cos_lat, start.latitude);
+    let (bx, by) = (end.longitude * cos_lat, end.latitude);
+    let (px, py) = (point.longitude * cos_lat, point.latitude);
+
+    let (dx, dy) = (bx - ax, by - ay);
+    let length_sq = dx * dx + dy * dy;
+    let fraction = if length_sq == 0.0 {
+        0.0
+    } else {
+        (((px - ax) * dx + (py - ay) * dy) / length_sq).clamp(0.0, 1.0)
+    };
+
+    let snapped = Coordinate::new(
+        start.latitude + fraction * (end.latitude - start.latitude),
+        start.longitude + fraction * (end.longitude - start.longitude),
+    );
+    (fraction, snapped)
+}
+
+/// Active navigation sessions, keyed by session id. The map is only locked
+/// to add, find and remove sessions; each session has its own lock, so
+/// position updates of different sessions don't wait on each other.
+pub struct NavigationService {
+    sessions: Arc<RwLock<HashMap<String, Arc<Mutex<NavigationTracker>>>>>,
+    rerouting: Option<Arc<ReroutingService>>,
+}
+
+impl NavigationService {
+    pub fn new() -> Self {
+        Self {
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            rerouting: None,
+        }
+    }
+
+    /// Reroute sessions automatically when they go off route
+    pub fn with_rerouting(mut self, rerouting: Arc<ReroutingService>) -> Self {
+        self.rerouting = Some(rerouting);
+        self
+    }
+
+    pub async fn start_session(&self, route: Route, voice: VoiceGuidance) -> String {
+        let tracker = NavigationTracker::new(route).with_voice(voice);
+        let id = tracker.id().to_string();
+        self.sessions.write().await.insert(id.clone(), Arc::new(Mutex::new(tracker)));
+        info!("Started navigation session {}", id);
+        id
+    }
+
+    pub async fn update_position(&self, session_id: &str, position: &PositionUpdate) -> Option<Vec<NavigationEvent>> {
+        let session = self.sessions.read().await.get(session_id)?.clone();
+        let (mut events, snapshot) = {
+            let mut tracker = session.lock().await;
+            let events = tracker.update(position);
+            let snapshot = tracker.reroute_due().then(|| {
+                (tracker.route().clone(), tracker.current_position().clone(), tracker.segment_index())
+            });
+            (events, snapshot)
+        };
+
+        // Routing can take a while, so it runs without holding the session lock
+        if let (Some(rerouting), Some((route, current_position, segment_index))) = (&self.rerouting, snapshot) {
+            match rerouting.reroute(&route, &current_position, segment_index).await {
+                Some(reroute) => {
+                    info!("Rerouted navigation session {}", session_id);
+                    session.lock().await.replace_route(reroute.route.clone());
+                    events.push(NavigationEvent::Rerouted {
+                        route: reroute.route,
+                        rejoins_original: reroute.rejoins_original,
+                    });
+                }
+                None => debug!(
+                    "No route back found for navigation session {}, retrying in {} positions",
+                    session_id, REROUTE_RETRY_POSITIONS
+                ),
+            }
+        }
+
+        Some(events)
+    }
+
+    pub async fn end_session(&self, session_id: &str) -> bool {
+        let ended = self.sessions.write().await.remove(session_id).is_some();
+        if ended {
+            info!("Ended navigation session {}", session_id);
+        }
+        ended
+    }
+
+    pub async fn active_sessions(&self) -> usize {
+        self.sessions.read().await.len()
+    }
+}
+
+impl Default for NavigationService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::models::route::RouteType;
+    use crate::services::rerouting_service::tests::{offset, slight_left_route, StraightLines};
+    use crate::services::rerouting_service::RoutePlanner;
+
+    fn fix(position: Coordinate) -> PositionUpdate {
+        PositionUpdate {
+            latitude: position.latitude,
+            longitude: position.longitude,
+            heading: None,
+            speed: None,
+        }
+    }
+
+    /// Feeds positions `north` and `east` meters from the test origin and
+    /// returns every event other than progress, labelled by type
+    async fn drive(service: &NavigationService, id: &str, trace: &[(f64, f64)]) -> Vec<NavigationEvent> {
+        let mut events = Vec::new();
+        for &(north, east) in trace {
+            let update = service.update_position(id, &fix(offset(north, east))).await.unwrap();
+            events.extend(update.into_iter().filter(|e| !matches!(e, NavigationEvent::Progress(_))));
+        }
+        events
+    }
+
+    fn off_route_and_reroutes(events: &[NavigationEvent]) -> Vec<&'static str> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                NavigationEvent::OffRoute { .. } => Some("off_route"),
+                NavigationEvent::Rerouted { .. } => Some("rerouted"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Routes only once enabled, like a graph missing the road the driver took
+    struct Unreachable(AtomicBool);
+
+    impl RoutePlanner for Unreachable {
+        fn route_between(&self, route_type: &RouteType, from: &Coordinate, to: &Coordinate) -> Option<Vec<RouteSegment>> {
+            if self.0.load(Ordering::SeqCst) {
+                StraightLines.route_between(route_type, from, to)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_the_route_without_deviating() {
+        let service = NavigationService::new().with_rerouting(Arc::new(ReroutingService::new(Arc::new(StraightLines))));
+        let id = service.start_session(slight_left_route(), VoiceGuidance::default()).await;
+
+        let trace: Vec<_> = (1..=20).map(|step| (0.0, step as f64 * 100.0)).collect();
+        let events = drive(&service, &id, &trace).await;
+
+        assert!(off_route_and_reroutes(&events).is_empty());
+        assert!(events.iter().any(|e| matches!(e, NavigationEvent::Instruction(_))));
+    }
+
+    #[tokio::test]
+    async fn reroutes_a_confirmed_deviation_back_onto_the_route() {
+        let service = NavigationService::new().with_rerouting(Arc::new(ReroutingService::new(Arc::new(StraightLines))));
+        let original = slight_left_route();
+        let id = service.start_session(original.clone(), VoiceGuidance::default()).await;
+
+        let on_route = drive(&service, &id, &[(0.0, 300.0), (0.0, 700.0), (0.0, 1050.0)]).await;
+        assert!(off_route_and_reroutes(&on_route).is_empty());
+
+        // A single stray fix is not a deviation
+        let stray = drive(&service, &id, &[(-90.0, 1100.0), (0.0, 1150.0)]).await;
+        assert!(off_route_and_reroutes(&stray).is_empty());
+
+        let events = drive(&service, &id, &[(-80.0, 1160.0), (-90.0, 1180.0), (-100.0, 1200.0)]).await;
+        assert_eq!(off_route_and_reroutes(&events), ["off_route", "rerouted"]);
+        let Some(NavigationEvent::Rerouted { route, rejoins_original }) = events.last() else {
+            panic!("expected a reroute, got {:?}", events);
+        };
+        assert!(*rejoins_original);
+        assert_eq!(route.segments.last().unwrap().start, original.segments[2].start);
+
+        // Following the new route back is not another deviation
+        let back = drive(&service, &id, &[(-90.0, 1400.0), (-60.0, 1600.0), (-30.0, 1800.0)]).await;
+        assert!(off_route_and_reroutes(&back).is_empty());
+    }
+
+    fn spoken(events: &[NavigationEvent]) -> Vec<String> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                NavigationEvent::Instruction(instruction) => instruction.voice_instruction.clone(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn announces_each_stage_of_a_maneuver_once() {
+        let service = NavigationService::new();
+        let id = service.start_session(slight_left_route(), VoiceGuidance::default()).await;
+
+        // The slight left is 2 km east of the start
+        let trace = [(0.0, 500.0), (0.0, 1050.0), (0.0, 1500.0), (0.0, 1800.0), (0.0, 1900.0), (0.0, 1980.0)];
+        let events = drive(&service, &id, &trace).await;
+        assert_eq!(
+            spoken(&events),
+            ["In 950 meters, bear left.", "In 200 meters, bear left.", "Now bear left."]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_stages_passed_between_positions() {
+        let service = NavigationService::new();
+        let id = service.start_session(slight_left_route(), VoiceGuidance::default()).await;
+
+        let events = drive(&service, &id, &[(0.0, 500.0), (0.0, 1970.0), (0.0, 1990.0)]).await;
+        assert_eq!(spoken(&events), ["Now bear left."]);
+    }
+
+    #[tokio::test]
+    async fn ending_a_session_leaves_the_others_running() {
+        let service = NavigationService::new();
+        let first = service.start_session(slight_left_route(), VoiceGuidance::default()).await;
+        let second = service.start_session(slight_left_route(), VoiceGuidance::default()).await;
+        assert_eq!(service.active_sessions().await, 2);
+
+        assert!(service.end_session(&first).await);
+        assert!(!service.end_session(&first).await);
+        assert!(service.update_position(&first, &fix(offset(0.0, 100.0))).await.is_none());
+
+        let events = service.update_position(&second, &fix(offset(0.0, 100.0))).await.unwrap();
+        assert!(events.iter().any(|e| matches!(e, NavigationEvent::Progress(_))));
+        assert_eq!(service.active_sessions().await, 1);
+    }
+
+    #[tokio::test]
+    async fn retries_rerouting_while_off_route() {
+        let planner = Arc::new(Unreachable(AtomicBool::new(false)));
+        let service = NavigationService::new().with_rerouting(Arc::new(ReroutingService::new(planner.clone())));
+        let id = service.start_session(slight_left_route(), VoiceGuidance::default()).await;
+        drive(&service, &id, &[(0.0, 500.0)]).await;
+
+        let mut east = 500.0;
+        let mut off_route = |count: u32| {
+            (0..count)
+                .map(|_| {
+                    east += 20.0;
+                    (-200.0, east)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let confirmed = drive(&service, &id, &off_route(OFF_ROUTE_CONFIRMATIONS)).await;
+        assert_eq!(off_route_and_reroutes(&confirmed), ["off_route"]);
+
+        planner.0.store(true, Ordering::SeqCst);
+        let waiting = drive(&service, &id, &off_route(REROUTE_RETRY_POSITIONS - 1)).await;
+        assert!(off_route_and_reroutes(&waiting).is_empty());
+
+        let retried = drive(&service, &id, &off_route(1)).await;
+        assert_eq!(off_route_and_reroutes(&retried), ["rerouted"]);
+    }
+}
@@ -0,0 +1,518 @@
+//! OGC WMTS and WMS on top of the tile service
+//!
+//! Layers are published in every configured tile matrix set. Requests in Web
+//! Mercator are served straight from the tile cache; other CRSs and free-form
+//! WMS maps are warped from cached Web Mercator tiles.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::stream::{self, StreamExt, TryStreamExt};
+use thiserror::Error;
+use tiny_skia::{Color, Pixmap, PixmapPaint, Transform};
+
+use googlemaps_clone::constants::MAX_ZOOM;
+use crate::models::tile::{Tile, TileCoordinate, TileFormat};
+use crate::services::map_renderer::{decode, encode};
+use crate::services::tile_service::{TerrainLayer, TileService};
+use crate::utils::tile_matrix::{Extent, TileMatrixSet};
+use crate::utils::tile_utils::TileCoord;
+use crate::utils::BoundingBox;
+
+mod capabilities;
+mod warp;
+
+/// Largest WMS image, in pixels along either side
+pub const MAX_MAP_SIZE: u32 = 4096;
+/// Source tiles fetched at once while warping
+const FETCH_CONCURRENCY: usize = 8;
+
+/// Web Mercator cuts off at this latitude
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+#[derive(Debug, Error)]
+pub enum OgcError {
+    #[error("Missing parameter: {0}")]
+    MissingParameter(String),
+    #[error("Invalid value for {0}: {1}")]
+    InvalidParameter(String, String),
+    #[error("Unknown layer: {0}")]
+    LayerNotDefined(String),
+    #[error("Unknown style {1} for layer {0}")]
+    StyleNotDefined(String, String),
+    #[error("Unsupported CRS: {0}")]
+    InvalidCrs(String),
+    #[error("Unsupported format: {0}")]
+    InvalidFormat(String),
+    #[error("Unknown tile matrix set: {0}")]
+    TileMatrixSetNotDefined(String),
+    #[error("Tile {0} is outside tile matrix set {1}")]
+    TileOutOfRange(String, String),
+    #[error("{0}")]
+    Unavailable(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl OgcError {
+    /// Exception code reported in OGC exception reports
+    pub fn code(&self) -> &'static str {
+        match self {
+            OgcError::MissingParameter(_) => "MissingParameterValue",
+            OgcError::InvalidParameter(..) => "InvalidParameterValue",
+            OgcError::LayerNotDefined(_) => "LayerNotDefined",
+            OgcError::StyleNotDefined(..) => "StyleNotDefined",
+            OgcError::InvalidCrs(_) => "InvalidCRS",
+            OgcError::InvalidFormat(_) => "InvalidFormat",
+            OgcError::TileMatrixSetNotDefined(_) => "InvalidParameterValue",
+            OgcError::TileOutOfRange(..) => "TileOutOfRange",
+            OgcError::Unavailable(_) | OgcError::Internal(_) => "NoApplicableCode",
+        }
+    }
+
+    /// The request parameter the exception is about, if any
+    pub fn locator(&self) -> Option<&str> {
+        match self {
+            OgcError::MissingParameter(name) | OgcError::InvalidParameter(name, _) => Some(name),
+            OgcError::LayerNotDefined(_) => Some("layer"),
+            OgcError::StyleNotDefined(..) => Some("style"),
+            OgcError::InvalidCrs(_) => Some("crs"),
+            OgcError::InvalidFormat(_) => Some("format"),
+            OgcError::TileMatrixSetNotDefined(_) => Some("tilematrixset"),
+            OgcError::TileOutOfRange(..) => Some("tilerow"),
+            OgcError::Unavailable(_) | OgcError::Internal(_) => None,
+        }
+    }
+}
+
+type OgcResult<T> = std::result::Result<T, OgcError>;
+
+/// Where a layer's images come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerSource {
+    /// The rendered base map, in any of the tile service's styles
+    BaseMap,
+    /// A transparent terrain overlay
+    Terrain(TerrainLayer),
+}
+
+/// A layer as advertised in the capabilities documents
+#[derive(Debug, Clone)]
+pub struct OgcLayer {
+    pub name: String,
+    pub title: String,
+    pub abstract_text: Option<String>,
+    pub source: LayerSource,
+    /// WGS84 extent with data
+    pub bounds: BoundingBox,
+}
+
+impl OgcLayer {
+    pub fn new(name: impl Into<String>, title: impl Into<String>, source: LayerSource) -> Self {
+        Self {
+            name: name.into(),
+            title: title.into(),
+            abstract_text: None,
+            source,
+            bounds: BoundingBox::new(MAX_LATITUDE, -MAX_LATITUDE, 180.0, -180.0),
+        }
+    }
+
+    pub fn with_abstract(mut self, abstract_text: impl Into<String>) -> Self {
+        self.abstract_text = Some(abstract_text.into());
+        self
+    }
+
+    pub fn with_bounds(mut self, bounds: BoundingBox) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    /// Terrain overlays are meant to be drawn over other layers
+    pub fn opaque(&self) -> bool {
+        self.source == LayerSource::BaseMap
+    }
+}
+
+/// A WMS GetMap request with its bounding box already in x/y axis order
+#[derive(Debug, Clone)]
+pub struct MapRequest {
+    /// Layers bottom to top, each with its style; `None` for the default
+    pub layers: Vec<(String, Option<String>)>,
+    pub crs: String,
+    pub extent: Extent,
+    pub width: u32,
+    pub height: u32,
+    pub format: TileFormat,
+    pub transparent: bool,
+    /// Background for opaque maps
+    pub background: [u8; 3],
+}
+
+pub struct OgcService {
+    tiles: Arc<TileService>,
+    /// Public URL the service is reached at, without a trailing slash
+    base_url: String,
+    title: String,
+    layers: Vec<OgcLayer>,
+    matrix_sets: Vec<Arc<TileMatrixSet>>,
+    mercator: Arc<TileMatrixSet>,
+}
+
+impl OgcService {
+    /// Publishes the base map, plus the terrain overlays when the tile service
+    /// has a DEM, in Web Mercator and CRS84
+    pub fn new(tiles: Arc<TileService>, base_url: impl Into<String>) -> Self {
+        let mut layers = vec![OgcLayer::new("basemap", "Base map", LayerSource::BaseMap)];
+        if tiles.has_terrain() {
+            layers.push(OgcLayer::new("hillshade", "Hillshade", LayerSource::Terrain(TerrainLayer::Hillshade)));
+            layers.push(OgcLayer::new("contours", "Contour lines", LayerSource::Terrain(TerrainLayer::Contours)));
+        }
+
+        let mercator = Arc::new(TileMatrixSet::web_mercator());
+        Self {
+            tiles,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            title: "RustMaps".to_string(),
+            layers,
+            matrix_sets: vec![mercator.clone(), Arc::new(TileMatrixSet::world_crs84())],
+            mercator,
+        }
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Replace the default layers
+    pub fn with_layers(mut self, layers: Vec<OgcLayer>) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Also publish every layer in `matrix_set`
+    pub fn with_matrix_set(mut self, matrix_set: TileMatrixSet) -> Self {
+        self.matrix_sets.retain(|existing| existing.identifier != matrix_set.identifier);
+        self.matrix_sets.push(Arc::new(matrix_set));
+        self
+    }
+
+    pub fn layers(&self) -> &[OgcLayer] {
+        &self.layers
+    }
+
+    pub fn layer(&self, name: &str) -> OgcResult<&OgcLayer> {
+        self.layers
+            .iter()
+            .find(|layer| layer.name == name)
+            .ok_or_else(|| OgcError::LayerNotDefined(name.to_string()))
+    }
+
+    pub fn matrix_set(&self, identifier: &str) -> OgcResult<&Arc<TileMatrixSet>> {
+        self.matrix_sets
+            .iter()
+            .find(|set| set.identifier == identifier)
+            .ok_or_else(|| OgcError::TileMatrixSetNotDefined(identifier.to_string()))
+    }
+
+    /// CRS codes WMS maps can be requested in. `CRS:84` is EPSG:4326 with
+    /// longitude first.
+    pub fn supported_crs(&self) -> Vec<String> {
+        let mut codes: Vec<String> = Vec::new();
+        for set in &self.matrix_sets {
+            if !codes.contains(&set.crs) {
+                codes.push(set.crs.clone());
+            }
+        }
+        if codes.iter().any(|code| code == "EPSG:4326") {
+            codes.push("CRS:84".to_string());
+        }
+        codes
+    }
+
+    /// The projection for a WMS CRS code
+    fn projection(&self, crs: &str) -> OgcResult<&Arc<TileMatrixSet>> {
+        let crs = if crs.eq_ignore_ascii_case("CRS:84") { "EPSG:4326" } else { crs };
+        self.matrix_sets
+            .iter()
+            .find(|set| set.crs.eq_ignore_ascii_case(crs))
+            .ok_or_else(|| OgcError::InvalidCrs(crs.to_string()))
+    }
+
+    /// Styles a layer can be drawn with and the one used by default
+    pub fn styles(&self, layer: &OgcLayer) -> (Vec<String>, String) {
+        match layer.source {
+            LayerSource::BaseMap => {
+                let names = self.tiles.style_names();
+                if names.is_empty() {
+                    return (vec!["default".to_string()], "default".to_string());
+                }
+                let default = names
+                    .iter()
+                    .find(|name| name.as_str() == crate::services::map_style::DEFAULT_STYLE)
+                    .unwrap_or(&names[0])
+                    .clone();
+                (names, default)
+            }
+            LayerSource::Terrain(_) => (vec!["default".to_string()], "default".to_string()),
+        }
+    }
+
+    /// `None` for the default style; an error for styles the layer lacks
+    fn resolve_style<'s>(&self, layer: &OgcLayer, style: Option<&'s str>) -> OgcResult<Option<&'s str>> {
+        let style = style.filter(|style| !style.is_empty() && !style.eq_ignore_ascii_case("default"));
+        if let Some(name) = style {
+            let (styles, _) = self.styles(layer);
+            if !styles.iter().any(|known| known == name) {
+                return Err(OgcError::StyleNotDefined(layer.name.clone(), name.to_string()));
+            }
+        }
+        Ok(style)
+    }
+
+    /// Web Mercator tile of `layer`, through the tile cache
+    async fn source_tile(
+        &self,
+        layer: &OgcLayer,
+        style: Option<&str>,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+    ) -> OgcResult<Option<Tile>> {
+        let tile = match layer.source {
+            LayerSource::BaseMap => {
+                let style = self
+                    .tiles
+                    .style(style)
+                    .ok_or_else(|| OgcError::StyleNotDefined(layer.name.clone(), style.unwrap_or("default").to_string()))?;
+                self.tiles.get_styled_tile(coordinate, format, style, 1).await
+            }
+            LayerSource::Terrain(terrain) => self.tiles.get_terrain_tile(coordinate, terrain, 1).await,
+        };
+        tile.map_err(|e| OgcError::Internal(format!("Failed to render {}: {}", layer.name, e)))
+    }
+
+    /// WMTS GetTile. Web Mercator tiles come from the tile cache as they
+    /// are; tiles of other matrix sets are warped from it.
+    pub async fn get_tile(
+        &self,
+        layer: &str,
+        style: Option<&str>,
+        matrix_set: &str,
+        coord: TileCoord,
+        format: TileFormat,
+    ) -> OgcResult<Vec<u8>> {
+        let layer = self.layer(layer)?;
+        let style = self.resolve_style(layer, style)?;
+        let matrix_set = self.matrix_set(matrix_set)?.clone();
+        let matrix = matrix_set
+            .matrix(coord.z)
+            .map_err(|_| OgcError::InvalidParameter("tilematrix".to_string(), coord.z.to_string()))?;
+        if coord.x >= matrix.matrix_width || coord.y >= matrix.matrix_height {
+            return Err(OgcError::TileOutOfRange(
+                format!("{}/{}/{}", coord.z, coord.y, coord.x),
+                matrix_set.identifier.clone(),
+            ));
+        }
+
+        if matrix_set.identifier == self.mercator.identifier {
+            let coordinate = TileCoordinate { x: coord.x, y: coord.y, z: coord.z };
+            // Terrain overlays only exist as PNG
+            let source_format = match layer.source {
+                LayerSource::BaseMap => format.clone(),
+                LayerSource::Terrain(_) => TileFormat::Png,
+            };
+            let Some(tile) = self.source_tile(layer, style, &coordinate, source_format.clone()).await? else {
+                return Err(OgcError::Unavailable(format!("Layer {} has no data", layer.name)));
+            };
+            let bytes: Vec<u8> = tile.data.into();
+            return if source_format == format { Ok(bytes) } else { reencode(bytes, format).await };
+        }
+
+        let extent = matrix_set
+            .tile_extent(&coord)
+            .map_err(|e| OgcError::Internal(e.to_string()))?;
+        let pixmap = self
+            .warp_layer(layer, style, &matrix_set, extent, matrix.tile_width, matrix.tile_height)
+            .await?;
+        encode_blocking(pixmap, format).await
+    }
+
+    /// WMS GetMap: each layer warped into the requested CRS and drawn in
+    /// order
+    pub async fn get_map(&self, request: &MapRequest) -> OgcResult<Vec<u8>> {
+        if request.width == 0 || request.height == 0 || request.width > MAX_MAP_SIZE || request.height > MAX_MAP_SIZE {
+            return Err(OgcError::InvalidParameter(
+                "width/height".to_string(),
+                format!("{}x{}, at most {} pixels per side", request.width, request.height, MAX_MAP_SIZE),
+            ));
+        }
+        let extent = request.extent;
+        if !(extent.min_x < extent.max_x && extent.min_y < extent.max_y) {
+            return Err(OgcError::InvalidParameter("bbox".to_string(), format!("{:?}", extent)));
+        }
+        if request.layers.is_empty() {
+            return Err(OgcError::MissingParameter("layers".to_string()));
+        }
+
+        let projection = self.projection(&request.crs)?.clone();
+        let mut layers = Vec::with_capacity(request.layers.len());
+        for (name, style) in &request.layers {
+            let layer = self.layer(name)?;
+            layers.push((layer, self.resolve_style(layer, style.as_deref())?));
+        }
+
+        let mut map = Pixmap::new(request.width, request.height)
+            .ok_or_else(|| OgcError::Internal("Empty map image".to_string()))?;
+        // JPEG has no alpha channel, so it always gets a background
+        if !request.transparent || request.format == TileFormat::Jpeg {
+            let [r, g, b] = request.background;
+            map.fill(Color::from_rgba8(r, g, b, 255));
+        }
+
+        for (layer, style) in layers {
+            let image = self
+                .warp_layer(layer, style, &projection, extent, request.width, request.height)
+                .await?;
+            map.draw_pixmap(0, 0, image.as_ref(), &PixmapPaint::default(), Transform::identity(), None);
+        }
+
+        encode_blocking(map, request.format.clone()).await
+    }
+
+    /// `layer` resampled from Web Mercator tiles into `extent` of
+    /// `projection`, `width` by `height` pixels
+    async fn warp_layer(
+        &self,
+        layer: &OgcLayer,
+        style: Option<&str>,
+        projection: &Arc<TileMatrixSet>,
+        extent: Extent,
+        width: u32,
+        height: u32,
+    ) -> OgcResult<Pixmap> {
+        let plan = {
+            let projection = projection.clone();
+            let mercator = self.mercator.clone();
+            tokio::task::spawn_blocking(move || warp::WarpPlan::new(&projection, &mercator, extent, width, height, MAX_ZOOM))
+                .await
+                .map_err(|e| OgcError::Internal(format!("Warp task failed: {}", e)))?
+        };
+
+        let sources: HashMap<(u32, u32), Pixmap> = stream::iter(plan.tiles.clone())
+            .map(|coord| async move {
+                let coordinate = TileCoordinate { x: coord.x, y: coord.y, z: coord.z };
+                let tile = self.source_tile(layer, style, &coordinate, TileFormat::Png).await?;
+                let image = tile
+                    .map(|tile| decode(&Vec::<u8>::from(tile.data)))
+                    .transpose()
+                    .map_err(|e| OgcError::Internal(e.to_string()))?;
+                Ok::<_, OgcError>(image.map(|image| ((coord.x, coord.y), image)))
+            })
+            .buffer_unordered(FETCH_CONCURRENCY)
+            .try_filter_map(|image| async move { Ok(image) })
+            .try_collect()
+            .await?;
+
+        tokio::task::spawn_blocking(move || plan.warp(&sources))
+            .await
+            .map_err(|e| OgcError::Internal(format!("Warp task failed: {}", e)))?
+    }
+}
+
+async fn encode_blocking(pixmap: Pixmap, format: TileFormat) -> OgcResult<Vec<u8>> {
+    tokio::task::spawn_blocking(move || encode(&pixmap, &format))
+        .await
+        .map_err(|e| OgcError::Internal(format!("Encode task failed: {}", e)))?
+        .map_err(|e| OgcError::Internal(e.to_string()))
+}
+
+async fn reencode(bytes: Vec<u8>, format: TileFormat) -> OgcResult<Vec<u8>> {
+    let pixmap = decode(&bytes).map_err(|e| OgcError::Internal(e.to_string()))?;
+    encode_blocking(pixmap, format).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn service() -> OgcService {
+        let tiles = TileService::new(Arc::new(Config::default())).unwrap();
+        OgcService::new(Arc::new(tiles), "https://maps.example.com/api/v1")
+    }
+
+    fn map_request() -> MapRequest {
+        MapRequest {
+            layers: vec![("basemap".to_string(), None)],
+            crs: "EPSG:3857".to_string(),
+            extent: Extent { min_x: 0.0, min_y: 0.0, max_x: 1000.0, max_y: 1000.0 },
+            width: 256,
+            height: 256,
+            format: TileFormat::Png,
+            transparent: false,
+            background: [255, 255, 255],
+        }
+    }
+
+    #[test]
+    fn publishes_terrain_only_with_a_dem() {
+        let service = service();
+        let names: Vec<&str> = service.layers().iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["basemap"]);
+        assert_eq!(service.supported_crs(), ["EPSG:3857", "EPSG:4326", "CRS:84"]);
+        assert!(matches!(service.layer("hillshade"), Err(OgcError::LayerNotDefined(_))));
+    }
+
+    #[test]
+    fn accepts_only_styles_the_layer_has() {
+        let service = service();
+        let layer = service.layer("basemap").unwrap();
+        assert_eq!(service.resolve_style(layer, Some("default")).unwrap(), None);
+        assert_eq!(service.resolve_style(layer, Some("")).unwrap(), None);
+        assert!(matches!(service.resolve_style(layer, Some("dark")), Err(OgcError::StyleNotDefined(..))));
+    }
+
+    #[tokio::test]
+    async fn rejects_maps_it_cannot_draw() {
+        let service = service();
+        let invalid = |change: fn(&mut MapRequest)| {
+            let mut request = map_request();
+            change(&mut request);
+            request
+        };
+
+        let cases = [
+            (invalid(|r| r.width = 0), "InvalidParameterValue"),
+            (invalid(|r| r.height = MAX_MAP_SIZE + 1), "InvalidParameterValue"),
+            (invalid(|r| r.extent.max_x = r.extent.min_x), "InvalidParameterValue"),
+            (invalid(|r| r.layers.clear()), "MissingParameterValue"),
+            (invalid(|r| r.crs = "EPSG:27700".to_string()), "InvalidCRS"),
+            (invalid(|r| r.layers[0].0 = "roads".to_string()), "LayerNotDefined"),
+            (invalid(|r| r.layers[0].1 = Some("dark".to_string())), "StyleNotDefined"),
+        ];
+        for (request, code) in cases {
+            let error = service.get_map(&request).await.unwrap_err();
+            assert_eq!(error.code(), code, "{:?}", request);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_tiles_outside_the_matrix_set() {
+        let service = service();
+        let get_tile = |matrix_set: &'static str, coord: TileCoord| {
+            let service = &service;
+            async move { service.get_tile("basemap", None, matrix_set, coord, TileFormat::Png).await.unwrap_err() }
+        };
+
+        let error = get_tile("WorldCRS84Quad", TileCoord::new(2, 0, 0)).await;
+        assert!(matches!(error, OgcError::TileOutOfRange(..)));
+        assert_eq!(error.locator(), Some("tilerow"));
+        assert!(matches!(get_tile("WebMercatorQuad", TileCoord::new(0, 1, 0)).await, OgcError::TileOutOfRange(..)));
+        assert!(matches!(
+            get_tile("WebMercatorQuad", TileCoord::new(0, 0, MAX_ZOOM + 1)).await,
+            OgcError::InvalidParameter(name, _) if name == "tilematrix"
+        ));
+        assert!(matches!(get_tile("OSGB36BNG", TileCoord::new(0, 0, 0)).await, OgcError::TileMatrixSetNotDefined(_)));
+    }
+}
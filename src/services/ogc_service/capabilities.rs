@@ -0,0 +1,481 @@
+//! WMTS 1.0.0 and WMS 1.3.0 capabilities documents
+
+use std::fmt::Write;
+
+use quick_xml::escape::escape;
+
+use super::{LayerSource, OgcLayer, OgcService, MAX_MAP_SIZE};
+use crate::utils::tile_matrix::{Extent, TileMatrixSet};
+use crate::utils::tile_utils::LatLng;
+use crate::utils::BoundingBox;
+
+pub const WMTS_NAMESPACE: &str = "http://www.opengis.net/wmts/1.0";
+pub const OWS_NAMESPACE: &str = "http://www.opengis.net/ows/1.1";
+pub const WMS_NAMESPACE: &str = "http://www.opengis.net/wms";
+pub const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+/// Points sampled along each side of a layer's bounds when projecting them
+const BOUNDS_SAMPLES: usize = 16;
+
+/// MIME type and file extension of each raster format a layer is offered in
+fn layer_formats(layer: &OgcLayer) -> &'static [(&'static str, &'static str)] {
+    match layer.source {
+        LayerSource::BaseMap => &[("image/png", "png"), ("image/jpeg", "jpg"), ("image/webp", "webp")],
+        // Overlays need their transparency
+        LayerSource::Terrain(_) => &[("image/png", "png")],
+    }
+}
+
+/// `urn:ogc:def:crs:EPSG::3857` for `EPSG:3857`, as WMTS expects. EPSG:4326
+/// tiles use longitude/latitude order, which is the CRS84 definition.
+pub fn crs_urn(crs: &str) -> String {
+    match crs.split_once(':') {
+        Some(("EPSG", "4326")) | Some(("OGC", "CRS84")) => "urn:ogc:def:crs:OGC:1.3:CRS84".to_string(),
+        Some(("EPSG", code)) => format!("urn:ogc:def:crs:EPSG::{}", code),
+        _ => crs.to_string(),
+    }
+}
+
+/// `urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible` for the scale set URI
+fn scale_set_urn(uri: &str) -> String {
+    match uri.rsplit_once('/') {
+        Some((_, name)) => format!("urn:ogc:def:wkss:OGC:1.0:{}", name),
+        None => uri.to_string(),
+    }
+}
+
+/// The box around `bounds` in the CRS of `matrix_set`, clipped to its extent.
+/// `None` when the bounds fall outside the CRS's area of use.
+fn projected_bounds(matrix_set: &TileMatrixSet, bounds: &BoundingBox) -> Option<Extent> {
+    let mut extent: Option<Extent> = None;
+    for i in 0..=BOUNDS_SAMPLES {
+        let t = i as f64 / BOUNDS_SAMPLES as f64;
+        let lat = bounds.south + (bounds.north - bounds.south) * t;
+        let lng = bounds.west + (bounds.east - bounds.west) * t;
+        let edge_points = [
+            LatLng::new(bounds.south, lng),
+            LatLng::new(bounds.north, lng),
+            LatLng::new(lat, bounds.west),
+            LatLng::new(lat, bounds.east),
+        ];
+
+        for point in edge_points {
+            let Ok((x, y)) = matrix_set.project(&point) else { continue };
+            if !(x.is_finite() && y.is_finite()) {
+                continue;
+            }
+            let grown = match extent {
+                Some(e) => Extent {
+                    min_x: e.min_x.min(x),
+                    min_y: e.min_y.min(y),
+                    max_x: e.max_x.max(x),
+                    max_y: e.max_y.max(y),
+                },
+                None => Extent { min_x: x, min_y: y, max_x: x, max_y: y },
+            };
+            extent = Some(grown);
+        }
+    }
+
+    let extent = extent?;
+    let limit = matrix_set.extent;
+    let clipped = Extent {
+        min_x: extent.min_x.max(limit.min_x),
+        min_y: extent.min_y.max(limit.min_y),
+        max_x: extent.max_x.min(limit.max_x),
+        max_y: extent.max_y.min(limit.max_y),
+    };
+    (clipped.min_x < clipped.max_x && clipped.min_y < clipped.max_y).then_some(clipped)
+}
+
+impl OgcService {
+    fn wmts_url(&self) -> String {
+        format!("{}/wmts", self.base_url)
+    }
+
+    fn wms_url(&self) -> String {
+        format!("{}/wms", self.base_url)
+    }
+
+    /// WMTS GetCapabilities response, offering KVP and RESTful access
+    pub fn wmts_capabilities(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<Capabilities xmlns=\"{}\" xmlns:ows=\"{}\" xmlns:xlink=\"{}\" version=\"1.0.0\">",
+            WMTS_NAMESPACE, OWS_NAMESPACE, XLINK_NAMESPACE
+        );
+
+        xml.push_str("  <ows:ServiceIdentification>\n");
+        let _ = writeln!(xml, "    <ows:Title>{}</ows:Title>", escape(&self.title));
+        xml.push_str("    <ows:ServiceType>OGC WMTS</ows:ServiceType>\n");
+        xml.push_str("    <ows:ServiceTypeVersion>1.0.0</ows:ServiceTypeVersion>\n");
+        xml.push_str("  </ows:ServiceIdentification>\n");
+
+        xml.push_str("  <ows:OperationsMetadata>\n");
+        for operation in ["GetCapabilities", "GetTile"] {
+            let _ = writeln!(xml, "    <ows:Operation name=\"{}\">", operation);
+            let _ = writeln!(xml, "      <ows:DCP><ows:HTTP><ows:Get xlink:href=\"{}?\">", escape(&self.wmts_url()));
+            xml.push_str("        <ows:Constraint name=\"GetEncoding\"><ows:AllowedValues><ows:Value>KVP</ows:Value></ows:AllowedValues></ows:Constraint>\n");
+            xml.push_str("      </ows:Get></ows:HTTP></ows:DCP>\n");
+            xml.push_str("    </ows:Operation>\n");
+        }
+        xml.push_str("  </ows:OperationsMetadata>\n");
+
+        xml.push_str("  <Contents>\n");
+        for layer in &self.layers {
+            self.write_wmts_layer(&mut xml, layer);
+        }
+        for matrix_set in &self.matrix_sets {
+            write_tile_matrix_set(&mut xml, matrix_set);
+        }
+        xml.push_str("  </Contents>\n");
+
+        let _ = writeln!(
+            xml,
+            "  <ServiceMetadataURL xlink:href=\"{}/1.0.0/WMTSCapabilities.xml\"/>",
+            escape(&self.wmts_url())
+        );
+        xml.push_str("</Capabilities>\n");
+        xml
+    }
+
+    fn write_wmts_layer(&self, xml: &mut String, layer: &OgcLayer) {
+        let bounds = &layer.bounds;
+        xml.push_str("    <Layer>\n");
+        let _ = writeln!(xml, "      <ows:Title>{}</ows:Title>", escape(&layer.title));
+        if let Some(abstract_text) = &layer.abstract_text {
+            let _ = writeln!(xml, "      <ows:Abstract>{}</ows:Abstract>", escape(abstract_text));
+        }
+        let _ = writeln!(
+            xml,
+            "      <ows:WGS84BoundingBox><ows:LowerCorner>{} {}</ows:LowerCorner><ows:UpperCorner>{} {}</ows:UpperCorner></ows:WGS84BoundingBox>",
+            bounds.west, bounds.south, bounds.east, bounds.north
+        );
+        let _ = writeln!(xml, "      <ows:Identifier>{}</ows:Identifier>", escape(&layer.name));
+
+        let (styles, default) = self.styles(layer);
+        for style in &styles {
+            let _ = writeln!(
+                xml,
+                "      <Style isDefault=\"{}\"><ows:Identifier>{}</ows:Identifier></Style>",
+                *style == default,
+                escape(style)
+            );
+        }
+
+        for (mime, _) in layer_formats(layer) {
+            let _ = writeln!(xml, "      <Format>{}</Format>", mime);
+        }
+        for matrix_set in &self.matrix_sets {
+            if projected_bounds(matrix_set, bounds).is_some() {
+                let _ = writeln!(
+                    xml,
+                    "      <TileMatrixSetLink><TileMatrixSet>{}</TileMatrixSet></TileMatrixSetLink>",
+                    escape(&matrix_set.identifier)
+                );
+            }
+        }
+        for (mime, extension) in layer_formats(layer) {
+            let _ = writeln!(
+                xml,
+                "      <ResourceURL format=\"{}\" resourceType=\"tile\" template=\"{}/{}/{{Style}}/{{TileMatrixSet}}/{{TileMatrix}}/{{TileRow}}/{{TileCol}}.{}\"/>",
+                mime,
+                escape(&self.wmts_url()),
+                escape(&layer.name),
+                extension
+            );
+        }
+        xml.push_str("    </Layer>\n");
+    }
+
+    /// WMS GetCapabilities response. Every layer is offered in each tile
+    /// matrix set's CRS, with per-CRS bounding boxes.
+    pub fn wms_capabilities(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<WMS_Capabilities xmlns=\"{}\" xmlns:xlink=\"{}\" version=\"1.3.0\">",
+            WMS_NAMESPACE, XLINK_NAMESPACE
+        );
+
+        xml.push_str("  <Service>\n");
+        xml.push_str("    <Name>WMS</Name>\n");
+        let _ = writeln!(xml, "    <Title>{}</Title>", escape(&self.title));
+        let _ = writeln!(xml, "    <OnlineResource xlink:type=\"simple\" xlink:href=\"{}\"/>", escape(&self.base_url));
+        let _ = writeln!(xml, "    <MaxWidth>{}</MaxWidth>", MAX_MAP_SIZE);
+        let _ = writeln!(xml, "    <MaxHeight>{}</MaxHeight>", MAX_MAP_SIZE);
+        xml.push_str("  </Service>\n");
+
+        let online_resource = format!(
+            "<DCPType><HTTP><Get><OnlineResource xlink:type=\"simple\" xlink:href=\"{}?\"/></Get></HTTP></DCPType>",
+            escape(&self.wms_url())
+        );
+        xml.push_str("  <Capability>\n");
+        xml.push_str("    <Request>\n");
+        let _ = writeln!(
+            xml,
+            "      <GetCapabilities><Format>text/xml</Format>{}</GetCapabilities>",
+            online_resource
+        );
+        let _ = writeln!(
+            xml,
+            "      <GetMap><Format>image/png</Format><Format>image/jpeg</Format><Format>image/webp</Format>{}</GetMap>",
+            online_resource
+        );
+        xml.push_str("    </Request>\n");
+        xml.push_str("    <Exception><Format>XML</Format></Exception>\n");
+
+        let supported_crs = self.supported_crs();
+        xml.push_str("    <Layer>\n");
+        let _ = writeln!(xml, "      <Title>{}</Title>", escape(&self.title));
+        for crs in &supported_crs {
+            let _ = writeln!(xml, "      <CRS>{}</CRS>", escape(crs));
+        }
+        write_geographic_bounds(&mut xml, &self.overall_bounds(), "      ");
+
+        for layer in &self.layers {
+            self.write_wms_layer(&mut xml, layer, &supported_crs);
+        }
+        xml.push_str("    </Layer>\n");
+        xml.push_str("  </Capability>\n");
+        xml.push_str("</WMS_Capabilities>\n");
+        xml
+    }
+
+    fn write_wms_layer(&self, xml: &mut String, layer: &OgcLayer, supported_crs: &[String]) {
+        let _ = writeln!(xml, "      <Layer queryable=\"0\" opaque=\"{}\">", u8::from(layer.opaque()));
+        let _ = writeln!(xml, "        <Name>{}</Name>", escape(&layer.name));
+        let _ = writeln!(xml, "        <Title>{}</Title>", escape(&layer.title));
+        if let Some(abstract_text) = &layer.abstract_text {
+            let _ = writeln!(xml, "        <Abstract>{}</Abstract>", escape(abstract_text));
+        }
+        write_geographic_bounds(xml, &layer.bounds, "        ");
+
+        for crs in supported_crs {
+            let Ok(matrix_set) = self.projection(crs) else { continue };
+            let Some(extent) = projected_bounds(matrix_set, &layer.bounds) else { continue };
+            // WMS 1.3.0 follows the CRS axis order, which is latitude first
+            // for EPSG:4326
+            let (min_x, min_y, max_x, max_y) = if crs == "EPSG:4326" {
+                (extent.min_y, extent.min_x, extent.max_y, extent.max_x)
+            } else {
+                (extent.min_x, extent.min_y, extent.max_x, extent.max_y)
+            };
+            let _ = writeln!(
+                xml,
+                "        <BoundingBox CRS=\"{}\" minx=\"{}\" miny=\"{}\" maxx=\"{}\" maxy=\"{}\"/>",
+                escape(crs),
+                min_x,
+                min_y,
+                max_x,
+                max_y
+            );
+        }
+
+        let (styles, default) = self.styles(layer);
+        // The default style is listed first, which is the one clients pick
+        let ordered = std::iter::once(&default).chain(styles.iter().filter(|style| **style != default));
+        for style in ordered {
+            let _ = writeln!(
+                xml,
+                "        <Style><Name>{}</Name><Title>{}</Title></Style>",
+                escape(style),
+                escape(style)
+            );
+        }
+        xml.push_str("      </Layer>\n");
+    }
+
+    /// The box enclosing every layer
+    fn overall_bounds(&self) -> BoundingBox {
+        self.layers.iter().fold(
+            BoundingBox::new(f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY),
+            |all, layer| {
+                BoundingBox::new(
+                    all.north.max(layer.bounds.north),
+                    all.south.min(layer.bounds.south),
+                    all.east.max(layer.bounds.east),
+                    all.west.min(layer.bounds.west),
+                )
+            },
+        )
+    }
+}
+
+fn write_geographic_bounds(xml: &mut String, bounds: &BoundingBox, indent: &str) {
+    if !(bounds.west.is_finite() && bounds.north.is_finite()) {
+        return;
+    }
+    let _ = writeln!(
+        xml,
+        "{}<EX_GeographicBoundingBox><westBoundLongitude>{}</westBoundLongitude><eastBoundLongitude>{}</eastBoundLongitude><southBoundLatitude>{}</southBoundLatitude><northBoundLatitude>{}</northBoundLatitude></EX_GeographicBoundingBox>",
+        indent, bounds.west, bounds.east, bounds.south, bounds.north
+    );
+}
+
+fn write_tile_matrix_set(xml: &mut String, matrix_set: &TileMatrixSet) {
+    xml.push_str("    <TileMatrixSet>\n");
+    let _ = writeln!(xml, "      <ows:Identifier>{}</ows:Identifier>", escape(&matrix_set.identifier));
+    let _ = writeln!(xml, "      <ows:SupportedCRS>{}</ows:SupportedCRS>", escape(&crs_urn(&matrix_set.crs)));
+    if let Some(scale_set) = &matrix_set.well_known_scale_set {
+        let _ = writeln!(xml, "      <WellKnownScaleSet>{}</WellKnownScaleSet>", escape(&scale_set_urn(scale_set)));
+    }
+    for matrix in &matrix_set.matrices {
+        xml.push_str("      <TileMatrix>\n");
+        let _ = writeln!(xml, "        <ows:Identifier>{}</ows:Identifier>", matrix.zoom);
+        let _ = writeln!(xml, "        <ScaleDenominator>{}</ScaleDenominator>", matrix.scale_denominator);
+        let _ = writeln!(xml, "        <TopLeftCorner>{} {}</TopLeftCorner>", matrix.top_left.0, matrix.top_left.1);
+        let _ = writeln!(xml, "        <TileWidth>{}</TileWidth>", matrix.tile_width);
+        let _ = writeln!(xml, "        <TileHeight>{}</TileHeight>", matrix.tile_height);
+        let _ = writeln!(xml, "        <MatrixWidth>{}</MatrixWidth>", matrix.matrix_width);
+        let _ = writeln!(xml, "        <MatrixHeight>{}</MatrixHeight>", matrix.matrix_height);
+        xml.push_str("      </TileMatrix>\n");
+    }
+    xml.push_str("    </TileMatrixSet>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use quick_xml::events::Event;
+    use quick_xml::Reader;
+
+    use super::*;
+    use googlemaps_clone::constants::MAX_ZOOM;
+    use crate::config::Config;
+    use crate::services::map_style::StyleRegistry;
+    use crate::services::tile_service::{TerrainLayer, TileService};
+
+    fn service() -> OgcService {
+        let styles = StyleRegistry::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/styles")).unwrap();
+        let tiles = TileService::new(Arc::new(Config::default())).unwrap().with_styles(Arc::new(styles));
+        OgcService::new(Arc::new(tiles), "https://maps.example.com/api/v1/").with_layers(vec![
+            OgcLayer::new("basemap", "Base map", LayerSource::BaseMap)
+                .with_abstract("Streets & places")
+                .with_bounds(BoundingBox::new(60.0, 40.0, 20.0, -10.0)),
+            OgcLayer::new("hillshade", "Hillshade", LayerSource::Terrain(TerrainLayer::Hillshade)),
+        ])
+    }
+
+    /// Text of every element named `name`, in document order
+    fn texts(xml: &str, name: &str) -> Vec<String> {
+        let mut reader = Reader::from_str(xml);
+        reader.trim_text(true);
+        let mut texts = Vec::new();
+        let mut inside = false;
+        loop {
+            match reader.read_event().expect("well-formed XML") {
+                Event::Start(start) => inside = start.name().as_ref() == name.as_bytes(),
+                Event::Text(text) if inside => texts.push(text.unescape().unwrap().into_owned()),
+                Event::End(_) => inside = false,
+                Event::Eof => return texts,
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn wmts_capabilities_list_layers_and_matrix_sets() {
+        let xml = service().wmts_capabilities();
+
+        assert_eq!(
+            texts(&xml, "ows:Identifier")[..4],
+            ["basemap", "dark", "high-contrast", "light"]
+        );
+        assert!(xml.contains("<Style isDefault=\"true\"><ows:Identifier>light</ows:Identifier></Style>"));
+        assert!(xml.contains("<ows:Abstract>Streets &amp; places</ows:Abstract>"));
+        assert!(xml.contains(
+            "<ows:LowerCorner>-10 40</ows:LowerCorner><ows:UpperCorner>20 60</ows:UpperCorner>"
+        ));
+        assert_eq!(texts(&xml, "TileMatrixSet"), ["WebMercatorQuad", "WorldCRS84Quad"].repeat(2));
+        assert_eq!(
+            texts(&xml, "ows:SupportedCRS"),
+            ["urn:ogc:def:crs:EPSG::3857", "urn:ogc:def:crs:OGC:1.3:CRS84"]
+        );
+        assert_eq!(
+            texts(&xml, "WellKnownScaleSet"),
+            ["urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible", "urn:ogc:def:wkss:OGC:1.0:GoogleCRS84Quad"]
+        );
+        assert_eq!(texts(&xml, "TileWidth").len(), 2 * (MAX_ZOOM as usize + 1));
+    }
+
+    #[test]
+    fn wmts_capabilities_advertise_rest_templates_per_format() {
+        let xml = service().wmts_capabilities();
+
+        assert_eq!(texts(&xml, "Format"), ["image/png", "image/jpeg", "image/webp", "image/png"]);
+        assert!(xml.contains(
+            "template=\"https://maps.example.com/api/v1/wmts/basemap/{Style}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.jpg\""
+        ));
+        assert!(xml.contains(
+            "template=\"https://maps.example.com/api/v1/wmts/hillshade/{Style}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.png\""
+        ));
+        assert!(!xml.contains("hillshade/{Style}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.jpg"));
+        assert!(xml.contains("<ows:Get xlink:href=\"https://maps.example.com/api/v1/wmts?\">"));
+    }
+
+    #[test]
+    fn wms_capabilities_order_bounding_boxes_by_crs_axes() {
+        let xml = service().wms_capabilities();
+
+        assert_eq!(texts(&xml, "CRS"), ["EPSG:3857", "EPSG:4326", "CRS:84"]);
+        assert_eq!(texts(&xml, "Name")[..2], ["WMS", "basemap"]);
+        assert!(xml.contains("<BoundingBox CRS=\"EPSG:4326\" minx=\"40\" miny=\"-10\" maxx=\"60\" maxy=\"20\"/>"));
+        assert!(xml.contains("<BoundingBox CRS=\"CRS:84\" minx=\"-10\" miny=\"40\" maxx=\"20\" maxy=\"60\"/>"));
+        assert!(xml.contains("<Layer queryable=\"0\" opaque=\"1\">"));
+        assert!(xml.contains("<Layer queryable=\"0\" opaque=\"0\">"));
+        assert!(xml.contains(&format!("<MaxWidth>{}</MaxWidth>", MAX_MAP_SIZE)));
+    }
+
+    #[test]
+    fn wms_capabilities_list_the_default_style_first() {
+        let xml = service().wms_capabilities();
+        let styles: Vec<String> = texts(&xml, "Title")
+            .into_iter()
+            .skip_while(|title| title != "Base map")
+            .skip(1)
+            .take(3)
+            .collect();
+        assert_eq!(styles, ["light", "dark", "high-contrast"]);
+    }
+
+    #[test]
+    fn projects_layer_bounds_within_the_crs_extent() {
+        let mercator = TileMatrixSet::web_mercator();
+        let world = BoundingBox::new(90.0, -90.0, 180.0, -180.0);
+        let clipped = projected_bounds(&mercator, &world).unwrap();
+        let limit = mercator.extent;
+        for (actual, expected) in [
+            (clipped.min_x, limit.min_x),
+            (clipped.min_y, limit.min_y),
+            (clipped.max_x, limit.max_x),
+            (clipped.max_y, limit.max_y),
+        ] {
+            assert!((actual - expected).abs() < 1.0, "{actual} != {expected}");
+        }
+
+        let europe = projected_bounds(&mercator, &BoundingBox::new(60.0, 40.0, 20.0, -10.0)).unwrap();
+        assert!((europe.min_x - -1_113_194.9).abs() < 1.0);
+        assert!((europe.max_y - 8_399_737.9).abs() < 1.0);
+
+        let crs84 = TileMatrixSet::world_crs84();
+        let extent = projected_bounds(&crs84, &BoundingBox::new(60.0, 40.0, 20.0, -10.0)).unwrap();
+        assert_eq!(extent, Extent { min_x: -10.0, min_y: 40.0, max_x: 20.0, max_y: 60.0 });
+    }
+
+    #[test]
+    fn writes_crs_and_scale_set_urns() {
+        assert_eq!(crs_urn("EPSG:3857"), "urn:ogc:def:crs:EPSG::3857");
+        assert_eq!(crs_urn("EPSG:4326"), "urn:ogc:def:crs:OGC:1.3:CRS84");
+        assert_eq!(crs_urn("custom"), "custom");
+        assert_eq!(
+            scale_set_urn("http://www.opengis.net/def/wkss/OGC/1.0/GoogleMapsCompatible"),
+            "urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible"
+        );
+    }
+}
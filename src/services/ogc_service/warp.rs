@@ -0,0 +1,275 @@
+//! Resampling Web Mercator tiles into another CRS
+//!
+//! Reprojecting every output pixel is slow with PROJ, so positions are only
+//! transformed on a coarse grid and interpolated in between. Pixels are then
+//! sampled bilinearly from the source tiles.
+
+use std::collections::{HashMap, HashSet};
+
+use tiny_skia::{ColorU8, Pixmap, PremultipliedColorU8};
+
+use super::{OgcError, OgcResult, MAX_LATITUDE};
+use googlemaps_clone::constants::TILE_SIZE;
+use crate::utils::tile_matrix::{Extent, TileMatrixSet};
+use crate::utils::tile_utils::TileCoord;
+
+/// Output pixels between exactly reprojected grid points
+const GRID_STEP: u32 = 16;
+/// Source tiles a single image may be warped from. Coarser zooms are used
+/// when an image would need more.
+const MAX_SOURCE_TILES: usize = 64;
+
+/// Which Web Mercator tiles an output image needs, and where each grid point
+/// lands in them
+pub(super) struct WarpPlan {
+    width: u32,
+    height: u32,
+    /// Grid point pixel edges along each axis
+    columns: Vec<f64>,
+    rows: Vec<f64>,
+    /// Web Mercator pixel position at `zoom` of each grid point, row by row;
+    /// `None` outside Web Mercator or where the CRS has no inverse
+    points: Vec<Option<(f64, f64)>>,
+    pub zoom: u8,
+    pub tiles: Vec<TileCoord>,
+}
+
+impl WarpPlan {
+    pub fn new(
+        projection: &TileMatrixSet,
+        mercator: &TileMatrixSet,
+        extent: Extent,
+        width: u32,
+        height: u32,
+        max_zoom: u8,
+    ) -> Self {
+        let columns = grid_edges(width);
+        let rows = grid_edges(height);
+
+        // Pixel positions at zoom 0; scaled to the chosen zoom below
+        let mut points = Vec::with_capacity(columns.len() * rows.len());
+        for &row in &rows {
+            for &column in &columns {
+                let x = extent.min_x + column / width as f64 * (extent.max_x - extent.min_x);
+                let y = extent.max_y - row / height as f64 * (extent.max_y - extent.min_y);
+                let point = projection
+                    .unproject((x, y))
+                    .ok()
+                    .filter(|position| position.lat.is_finite() && position.lng.is_finite())
+                    .filter(|position| position.lat.abs() <= MAX_LATITUDE)
+                    .and_then(|position| mercator.to_pixel_coord(&position, 0).ok())
+                    .map(|pixel| (pixel.x, pixel.y));
+                points.push(point);
+            }
+        }
+
+        let mut plan = Self {
+            width,
+            height,
+            columns,
+            rows,
+            points,
+            zoom: 0,
+            tiles: Vec::new(),
+        };
+
+        let mut zoom = plan.matching_zoom().min(max_zoom);
+        loop {
+            let tiles = plan.tiles_at(zoom);
+            if tiles.len() <= MAX_SOURCE_TILES || zoom == 0 {
+                plan.tiles = tiles;
+                break;
+            }
+            zoom -= 1;
+        }
+
+        let factor = (1u64 << zoom) as f64;
+        for point in plan.points.iter_mut().flatten() {
+            *point = (point.0 * factor, point.1 * factor);
+        }
+        plan.zoom = zoom;
+        plan
+    }
+
+    fn point(&self, column: usize, row: usize) -> Option<(f64, f64)> {
+        self.points[row * self.columns.len() + column]
+    }
+
+    /// The zoom whose pixels are no larger than the output's, judged at the
+    /// grid cell nearest the centre
+    fn matching_zoom(&self) -> u8 {
+        let (column, row) = ((self.columns.len() - 1) / 2, (self.rows.len() - 1) / 2);
+        let corner = self.point(column, row);
+        let right = self.point(column + 1, row);
+        let below = self.point(column, row + 1);
+        let (Some(corner), Some(right), Some(below)) = (corner, right, below) else {
+            return 0;
+        };
+
+        let output_dx = self.columns[column + 1] - self.columns[column];
+        let output_dy = self.rows[row + 1] - self.rows[row];
+        let along_x = ((right.0 - corner.0).powi(2) + (right.1 - corner.1).powi(2)).sqrt() / output_dx;
+        let along_y = ((below.0 - corner.0).powi(2) + (below.1 - corner.1).powi(2)).sqrt() / output_dy;
+        // Zoom 0 pixels per output pixel along the finer axis
+        let per_pixel = along_x.min(along_y);
+        if per_pixel.is_nan() || per_pixel <= 0.0 {
+            return 0;
+        }
+        (-per_pixel.log2()).ceil().clamp(0.0, u8::MAX as f64) as u8
+    }
+
+    /// Tiles at `zoom` under any grid cell
+    fn tiles_at(&self, zoom: u8) -> Vec<TileCoord> {
+        let factor = (1u64 << zoom) as f64;
+        let count = 1u32 << zoom;
+        let mut tiles = HashSet::new();
+
+        for row in 0..self.rows.len() - 1 {
+            for column in 0..self.columns.len() - 1 {
+                let corners = [
+                    self.point(column, row),
+                    self.point(column + 1, row),
+                    self.point(column, row + 1),
+                    self.point(column + 1, row + 1),
+                ];
+                let corners: Vec<(f64, f64)> = corners.iter().flatten().copied().collect();
+                if corners.len() < 4 {
+                    continue;
+                }
+                let tile = |value: f64| (value * factor / TILE_SIZE as f64).floor() as i64;
+                let min_x = tile(corners.iter().map(|c| c.0).fold(f64::INFINITY, f64::min));
+                let max_x = tile(corners.iter().map(|c| c.0).fold(f64::NEG_INFINITY, f64::max));
+                let min_y = tile(corners.iter().map(|c| c.1).fold(f64::INFINITY, f64::min));
+                let max_y = tile(corners.iter().map(|c| c.1).fold(f64::NEG_INFINITY, f64::max));
+                // Cells spanning the antimeridian are skipped like missing ones
+                if max_x - min_x > count as i64 / 2 {
+                    continue;
+                }
+
+                for y in min_y.max(0)..=max_y.min(count as i64 - 1) {
+                    for x in min_x..=max_x {
+                        tiles.insert(TileCoord::new(x.rem_euclid(count as i64) as u32, y as u32, zoom));
+                    }
+                }
+            }
+        }
+
+        let mut tiles: Vec<TileCoord> = tiles.into_iter().collect();
+        tiles.sort_by_key(|tile| (tile.y, tile.x));
+        tiles
+    }
+
+    /// Draw the output image from `sources`, keyed by tile column and row at
+    /// `zoom`. Missing tiles leave their part of the image transparent.
+    pub fn warp(&self, sources: &HashMap<(u32, u32), Pixmap>) -> OgcResult<Pixmap> {
+        let mut output =
+            Pixmap::new(self.width, self.height).ok_or_else(|| OgcError::Internal("Empty map image".to_string()))?;
+        let mosaic = Mosaic {
+            sources,
+            world: TILE_SIZE as f64 * (1u64 << self.zoom) as f64,
+        };
+        let width = self.width as usize;
+        let pixels = output.pixels_mut();
+
+        for row in 0..self.rows.len() - 1 {
+            for column in 0..self.columns.len() - 1 {
+                let (Some(top_left), Some(top_right), Some(bottom_left), Some(bottom_right)) = (
+                    self.point(column, row),
+                    self.point(column + 1, row),
+                    self.point(column, row + 1),
+                    self.point(column + 1, row + 1),
+                ) else {
+                    continue;
+                };
+                if (top_right.0 - top_left.0).abs() > mosaic.world / 2.0 || (bottom_right.0 - bottom_left.0).abs() > mosaic.world / 2.0 {
+                    continue;
+                }
+
+                let (left, right) = (self.columns[column], self.columns[column + 1]);
+                let (top, bottom) = (self.rows[row], self.rows[row + 1]);
+                for py in top as u32..bottom as u32 {
+                    let v = (py as f64 + 0.5 - top) / (bottom - top);
+                    for px in left as u32..right as u32 {
+                        let u = (px as f64 + 0.5 - left) / (right - left);
+                        let x = lerp(lerp(top_left.0, top_right.0, u), lerp(bottom_left.0, bottom_right.0, u), v);
+                        let y = lerp(lerp(top_left.1, top_right.1, u), lerp(bottom_left.1, bottom_right.1, u), v);
+                        pixels[py as usize * width + px as usize] = mosaic.sample(x, y);
+                    }
+                }
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Pixel edges of the reprojection grid along an axis `size` pixels long
+fn grid_edges(size: u32) -> Vec<f64> {
+    let mut edges: Vec<f64> = (0..size).step_by(GRID_STEP as usize).map(|edge| edge as f64).collect();
+    edges.push(size as f64);
+    edges
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// The source tiles as one image of the whole world at the plan's zoom
+struct Mosaic<'a> {
+    sources: &'a HashMap<(u32, u32), Pixmap>,
+    /// Width of the world in pixels
+    world: f64,
+}
+
+impl Mosaic<'_> {
+    fn pixel(&self, x: i64, y: i64) -> [f32; 4] {
+        let tile_size = TILE_SIZE as i64;
+        if y < 0 || y as f64 >= self.world {
+            return [0.0; 4];
+        }
+        let x = x.rem_euclid(self.world as i64);
+        let Some(tile) = self.sources.get(&((x / tile_size) as u32, (y / tile_size) as u32)) else {
+            return [0.0; 4];
+        };
+
+        // Tiles rendered at a higher scale are sampled at their own density
+        let factor = tile.width() as i64 / tile_size;
+        let (tx, ty) = ((x % tile_size) * factor, (y % tile_size) * factor);
+        match tile.pixel(tx as u32, ty as u32) {
+            Some(pixel) => [
+                pixel.red() as f32,
+                pixel.green() as f32,
+                pixel.blue() as f32,
+                pixel.alpha() as f32,
+            ],
+            None => [0.0; 4],
+        }
+    }
+
+    /// Bilinear sample at a world pixel position. Premultiplied channels
+    /// interpolate without dark fringes at transparent edges.
+    fn sample(&self, x: f64, y: f64) -> PremultipliedColorU8 {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = ((x - x0) as f32, (y - y0) as f32);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let corners = [
+            (self.pixel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+            (self.pixel(x0 + 1, y0), fx * (1.0 - fy)),
+            (self.pixel(x0, y0 + 1), (1.0 - fx) * fy),
+            (self.pixel(x0 + 1, y0 + 1), fx * fy),
+        ];
+        let mut channels = [0.0f32; 4];
+        for (pixel, weight) in corners {
+            for (channel, value) in channels.iter_mut().zip(pixel) {
+                *channel += value * weight;
+            }
+        }
+
+        let [r, g, b, a] = channels.map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+        // Rounding can leave a colour channel just above alpha
+        PremultipliedColorU8::from_rgba(r.min(a), g.min(a), b.min(a), a)
+            .unwrap_or_else(|| ColorU8::from_rgba(0, 0, 0, 0).premultiply())
+    }
+}
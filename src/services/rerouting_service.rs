@@ -0,0 +1,380 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::models::location::Coordinate as LocationCoordinate;
+use crate::models::route::{Coordinate, ManeuverType, Route, RouteSegment, RouteType};
+use crate::services::routing_service::{RouteRequest, RoutingService};
+
+/// Maneuver points ahead of the current position considered as rejoin points.
+/// Segments that continue straight are not maneuver points and are skipped.
+const REJOIN_CANDIDATES: usize = 4;
+
+/// Rejoin points farther than this from the current position are not tried
+const MAX_REJOIN_DISTANCE_M: f64 = 5000.0;
+
+/// How much longer (as a fraction) rejoining the original route may take than a
+/// fresh route to the destination before the fresh route is preferred
+const REJOIN_TOLERANCE: f64 = 0.15;
+
+/// Bearing change below which an edge continues straight rather than turning
+const STRAIGHT_TOLERANCE_DEG: f64 = 20.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Reroute {
+    pub route: Route,
+    /// Whether the new route leads back onto the original route rather than
+    /// replacing it entirely
+    pub rejoins_original: bool,
+    /// Index in `route.segments` where the original route resumes
+    pub rejoin_segment_index: Option<usize>,
+}
+
+/// Finds the routes a reroute is assembled from
+pub trait RoutePlanner: Send + Sync {
+    /// Segments of a route from `from` to `to` for the travel mode of
+    /// `route_type`, or `None` when the points are not connected
+    fn route_between(&self, route_type: &RouteType, from: &Coordinate, to: &Coordinate) -> Option<Vec<RouteSegment>>;
+}
+
+impl RoutePlanner for RoutingService {
+    fn route_between(&self, route_type: &RouteType, from: &Coordinate, to: &Coordinate) -> Option<Vec<RouteSegment>> {
+        let request = RouteRequest {
+            start: crate::models::Coordinate {
+                latitude: from.latitude,
+                longitude: from.longitude,
+            },
+            end: crate::models::Coordinate {
+                latitude: to.latitude,
+                longitude: to.longitude,
+            },
+            vehicle_type: vehicle_type(route_type).to_string(),
+            avoid_tolls: matches!(route_type, RouteType::AvoidTolls),
+            avoid_highways: matches!(route_type, RouteType::AvoidHighways),
+        };
+
+        let response = self.find_route(&request);
+        if !response.success || response.path.len() < 2 {
+            return None;
+        }
+
+        let path: Vec<Coordinate> = response
+            .path
+            .iter()
+            .map(|c| Coordinate::new(c.latitude, c.longitude))
+            .collect();
+        Some(segments_from_path(&path, response.duration))
+    }
+}
+
+/// Computes a new route for a driver who has left the planned one.
+///
+/// Candidate routes back to the next few maneuver points of the original route
+/// are compared with a fresh route to the destination. Rejoining is preferred
+/// unless it costs noticeably more time, so drivers keep the route they were
+/// shown where possible.
+pub struct ReroutingService {
+    planner: Arc<dyn RoutePlanner>,
+}
+
+impl ReroutingService {
+    pub fn new(planner: Arc<dyn RoutePlanner>) -> Self {
+        Self { planner }
+    }
+
+    pub async fn reroute(&self, original: &Route, position: &Coordinate, segment_index: usize) -> Option<Reroute> {
+        let planner = self.planner.clone();
+        let original = original.clone();
+        let position = position.clone();
+
+        tokio::task::spawn_blocking(move || plan_reroute(planner.as_ref(), &original, &position, segment_index))
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Reroute task failed: {}", e);
+                None
+            })
+    }
+}
+
+fn plan_reroute(planner: &dyn RoutePlanner, original: &Route, position: &Coordinate, segment_index: usize) -> Option<Reroute> {
+    let direct = planner.route_between(&original.route_type, position, &original.destination);
+
+    let maneuver_points = (segment_index + 1..original.segments.len())
+        .filter(|&index| !matches!(original.segments[index].maneuver, ManeuverType::Straight))
+        .take(REJOIN_CANDIDATES);
+
+    let mut best_rejoin: Option<(usize, Vec<RouteSegment>, f64)> = None;
+    for index in maneuver_points {
+        let rejoin_point = &original.segments[index].start;
+        if position.distance_to(rejoin_point) > MAX_REJOIN_DISTANCE_M {
+            break;
+        }
+
+        let Some(detour) = planner.route_between(&original.route_type, position, rejoin_point) else {
+            continue;
+        };
+        let remaining: i32 = original.segments[index..].iter().map(|s| s.duration).sum();
+        let cost = total_duration(&detour) as f64 + remaining as f64;
+
+        if best_rejoin.as_ref().is_none_or(|(_, _, best)| cost < *best) {
+            best_rejoin = Some((index, detour, cost));
+        }
+    }
+
+    let direct_cost = direct.as_ref().map(|segments| total_duration(segments) as f64);
+
+    match (best_rejoin, direct_cost) {
+        (Some((index, detour, cost)), direct_cost)
+            if direct_cost.is_none_or(|direct| cost <= direct * (1.0 + REJOIN_TOLERANCE)) =>
+        {
+            debug!("Rejoining original route at segment {}", index);
+            let rejoin_segment_index = detour.len();
+            let mut segments = detour;
+            segments.extend(original.segments[index..].iter().cloned());
+            Some(Reroute {
+                route: build_route(original, position, segments),
+                rejoins_original: true,
+                rejoin_segment_index: Some(rejoin_segment_index),
+            })
+        }
+        _ => {
+            let segments = direct?;
+            info!("Replacing route with a fresh route to the destination");
+            Some(Reroute {
+                route: build_route(original, position, segments),
+                rejoins_original: false,
+                rejoin_segment_index: None,
+            })
+        }
+    }
+}
+
+fn vehicle_type(route_type: &RouteType) -> &'static str {
+    match route_type {
+        RouteType::Walking => "foot",
+        RouteType::Cycling => "bicycle",
+        _ => "car",
+    }
+}
+
+/// Turn a routed path into one segment per edge, spreading the total duration
+/// by distance and deriving each maneuver from the bearing change
+pub fn segments_from_path(path: &[Coordinate], duration: f64) -> Vec<RouteSegment> {
+    let total_distance: f64 = path.windows(2).map(|w| w[0].distance_to(&w[1])).sum();
+    let seconds_per_meter = if total_distance > 0.0 { duration / total_distance } else { 0.0 };
+
+    let mut segments = Vec::with_capacity(path.len().saturating_sub(1));
+    let mut previous_bearing: Option<f64> = None;
+
+    for edge in path.windows(2) {
+        let (start, end) = (&edge[0], &edge[1]);
+        let distance = start.distance_to(end);
+        if distance == 0.0 {
+            continue;
+        }
+        let edge_bearing = bearing(start, end);
+
+        let maneuver = match previous_bearing {
+            None => ManeuverType::Start,
+            Some(previous) => turn_maneuver(turn_angle(previous, edge_bearing)),
+        };
+        previous_bearing = Some(edge_bearing);
+
+        segments.push(RouteSegment {
+            start: start.clone(),
+            end: end.clone(),
+            distance,
+            duration: (distance * seconds_per_meter).round() as i32,
+            instruction: maneuver_text(&maneuver).to_string(),
+            street_name: None,
+            maneuver,
+        });
+    }
+
+    segments
+}
+
+fn build_route(original: &Route, position: &Coordinate, segments: Vec<RouteSegment>) -> Route {
+    Route {
+        id: Uuid::new_v4().to_string(),
+        origin: position.clone(),
+        destination: original.destination.clone(),
+        waypoints: Vec::new(),
+        total_distance: segments.iter().map(|s| s.distance).sum(),
+        total_duration: total_duration(&segments),
+        segments,
+        route_type: original.route_type.clone(),
+        created_at: Utc::now(),
+        traffic_info: None,
+        elevation: None,
+    }
+}
+
+fn total_duration(segments: &[RouteSegment]) -> i32 {
+    segments.iter().map(|s| s.duration).sum()
+}
+
+/// Initial bearing between two route coordinates, in degrees from north
+pub fn bearing(from: &Coordinate, to: &Coordinate) -> f64 {
+    let from = LocationCoordinate {
+        latitude: from.latitude,
+        longitude: from.longitude,
+    };
+    let to = LocationCoordinate {
+        latitude: to.latitude,
+        longitude: to.longitude,
+    };
+    from.bearing_to(&to)
+}
+
+/// Signed difference between two bearings in the range (-180, 180]
+pub fn turn_angle(from_bearing: f64, to_bearing: f64) -> f64 {
+    let mut angle = (to_bearing - from_bearing) % 360.0;
+    if angle > 180.0 {
+        angle -= 360.0;
+    } else if angle <= -180.0 {
+        angle += 360.0;
+    }
+    angle
+}
+
+fn turn_maneuver(angle: f64) -> ManeuverType {
+    let left = angle < 0.0;
+    match angle.abs() {
+        m if m < STRAIGHT_TOLERANCE_DEG => ManeuverType::Straight,
+        m if m < 45.0 => if left { ManeuverType::TurnSlightLeft } else { ManeuverType::TurnSlightRight },
+        m if m < 135.0 => if left { ManeuverType::TurnLeft } else { ManeuverType::TurnRight },
+        m if m < 170.0 => if left { ManeuverType::TurnSharpLeft } else { ManeuverType::TurnSharpRight },
+        _ => ManeuverType::UTurn,
+    }
+}
+
+fn maneuver_text(maneuver: &ManeuverType) -> &'static str {
+    match maneuver {
+        ManeuverType::Start => "Head toward the route",
+        ManeuverType::TurnSlightLeft => "Turn slightly left",
+        ManeuverType::TurnSlightRight => "Turn slightly right",
+        ManeuverType::TurnLeft => "Turn left",
+        ManeuverType::TurnRight => "Turn right",
+        ManeuverType::TurnSharpLeft => "Turn sharp left",
+        ManeuverType::TurnSharpRight => "Turn sharp right",
+        ManeuverType::UTurn => "Make a U-turn",
+        _ => "Continue straight",
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Travel speed of test routes, in meters per second
+    const SPEED: f64 = 10.0;
+
+    /// A point `north` and `east` meters from an arbitrary origin
+    pub(crate) fn offset(north: f64, east: f64) -> Coordinate {
+        const METERS_PER_DEGREE: f64 = 111_195.0;
+        let latitude = 37.0 + north / METERS_PER_DEGREE;
+        let longitude = -122.0 + east / (METERS_PER_DEGREE * 37.0_f64.to_radians().cos());
+        Coordinate::new(latitude, longitude)
+    }
+
+    fn path_segments(path: &[Coordinate]) -> Vec<RouteSegment> {
+        let distance: f64 = path.windows(2).map(|w| w[0].distance_to(&w[1])).sum();
+        segments_from_path(path, distance / SPEED)
+    }
+
+    /// A route through `points` at a constant speed
+    pub(crate) fn route_through(points: &[Coordinate]) -> Route {
+        build_route(
+            &Route {
+                id: String::new(),
+                origin: points[0].clone(),
+                destination: points[points.len() - 1].clone(),
+                waypoints: Vec::new(),
+                segments: Vec::new(),
+                total_distance: 0.0,
+                total_duration: 0,
+                route_type: RouteType::Fastest,
+                created_at: Utc::now(),
+                traffic_info: None,
+                elevation: None,
+            },
+            &points[0],
+            path_segments(points),
+        )
+    }
+
+    /// Routes along the straight line between any two points
+    pub(crate) struct StraightLines;
+
+    impl RoutePlanner for StraightLines {
+        fn route_between(&self, _: &RouteType, from: &Coordinate, to: &Coordinate) -> Option<Vec<RouteSegment>> {
+            Some(path_segments(&[from.clone(), to.clone()]))
+        }
+    }
+
+    /// East for 2 km, then bearing slightly left for the last kilometer
+    pub(crate) fn slight_left_route() -> Route {
+        route_through(&[offset(0.0, 0.0), offset(0.0, 1000.0), offset(0.0, 2000.0), offset(500.0, 3000.0)])
+    }
+
+    #[test]
+    fn derives_maneuvers_from_bearings() {
+        let route = slight_left_route();
+        let maneuvers: Vec<_> = route.segments.iter().map(|s| format!("{:?}", s.maneuver)).collect();
+        assert_eq!(maneuvers, ["Start", "Straight", "TurnSlightLeft"]);
+        assert_eq!(route.total_duration, total_duration(&route.segments));
+    }
+
+    #[test]
+    fn prefers_rejoining_the_original_route() {
+        let original = slight_left_route();
+        let position = offset(-80.0, 1200.0);
+
+        let reroute = plan_reroute(&StraightLines, &original, &position, 1).unwrap();
+
+        assert!(reroute.rejoins_original);
+        let rejoin = reroute.rejoin_segment_index.unwrap();
+        assert_eq!(reroute.route.segments[rejoin].start, original.segments[2].start);
+        assert_eq!(reroute.route.segments.len(), rejoin + 1);
+        assert_eq!(reroute.route.origin, position);
+        assert_eq!(reroute.route.destination, original.destination);
+    }
+
+    #[test]
+    fn rejoins_at_maneuver_points_rather_than_straight_segments() {
+        let original = slight_left_route();
+
+        let reroute = plan_reroute(&StraightLines, &original, &offset(-80.0, 200.0), 0).unwrap();
+
+        // The straight segment starting 1 km in is not a rejoin candidate
+        let rejoin = reroute.rejoin_segment_index.unwrap();
+        assert_eq!(reroute.route.segments[rejoin].start, original.segments[2].start);
+    }
+
+    #[test]
+    fn replaces_the_route_when_rejoining_costs_too_much() {
+        // East for 1 km, then north for 1 km, left from 300 m behind the corner
+        let original = route_through(&[offset(0.0, 0.0), offset(0.0, 1000.0), offset(1000.0, 1000.0)]);
+        let position = offset(-300.0, 300.0);
+
+        let reroute = plan_reroute(&StraightLines, &original, &position, 0).unwrap();
+
+        assert!(!reroute.rejoins_original);
+        assert_eq!(reroute.rejoin_segment_index, None);
+        assert_eq!(reroute.route.segments.len(), 1);
+        assert_eq!(reroute.route.segments[0].end, original.destination);
+    }
+
+    #[test]
+    fn turn_angles_wrap_around_north() {
+        assert_eq!(turn_angle(350.0, 10.0), 20.0);
+        assert_eq!(turn_angle(10.0, 350.0), -20.0);
+        assert_eq!(turn_angle(0.0, 180.0), 180.0);
+        assert!(matches!(turn_maneuver(-90.0), ManeuverType::TurnLeft));
+        assert!(matches!(turn_maneuver(175.0), ManeuverType::UTurn));
+    }
+}
@@ -1,8 +1,12 @@
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::cmp::Ordering;
-use std::f64::INFINITY;
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use crate::models::{Node, Edge, Coordinate, RouteResult};
+use crate::models::route::{Coordinate as RouteCoordinate, ElevationProfile, ManeuverType};
+use crate::services::elevation_service::{self, ElevationService};
+use crate::services::rerouting_service::segments_from_path;
+use crate::utils::tile_utils::LatLng;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct AStarNode {
@@ -43,11 +47,14 @@ pub struct RouteResponse {
     pub instructions: Vec<String>,
     pub success: bool,
     pub error: Option<String>,
+    pub elevation: Option<ElevationProfile>,
 }
 
 pub struct RoutingService {
     nodes: HashMap<u64, Node>,
     edges: HashMap<u64, Vec<Edge>>,
+    node_elevations: HashMap<u64, f64>,
+    elevation: Option<Arc<ElevationService>>,
 }
 
 impl RoutingService {
@@ -55,15 +62,58 @@ impl RoutingService {
         Self {
             nodes: HashMap::new(),
             edges: HashMap::new(),
+            node_elevations: HashMap::new(),
+            elevation: None,
         }
     }
 
+    /// Use `elevation` for grade-aware walking and cycling routes and for the
+    /// profiles of computed routes. Nodes already loaded get their elevation
+    /// looked up now, later ones as `load_graph` adds them.
+    pub fn attach_elevation(&mut self, elevation: Arc<ElevationService>) {
+        self.elevation = Some(elevation);
+        self.lookup_node_elevations();
+    }
+
+    fn lookup_node_elevations(&mut self) {
+        let Some(elevation) = &self.elevation else {
+            return;
+        };
+        self.node_elevations = self
+            .nodes
+            .iter()
+            .filter_map(|(id, node)| {
+                elevation
+                    .elevation_at(node.latitude, node.longitude)
+                    .map(|value| (*id, value))
+            })
+            .collect();
+    }
+
+    /// Travel time multiplier for the grade between two nodes, 1.0 when either
+    /// has no elevation
+    pub fn grade_cost_factor(&self, from_node: u64, to_node: u64, distance_m: f64, vehicle_type: &str) -> f64 {
+        match (self.node_elevations.get(&from_node), self.node_elevations.get(&to_node)) {
+            (Some(&from), Some(&to)) => {
+                let grade = elevation_service::grade(from, to, distance_m);
+                elevation_service::grade_cost_factor(vehicle_type, grade)
+            }
+            _ => 1.0,
+        }
+    }
+
+    fn elevation_profile(&self, path: &[Coordinate]) -> Option<ElevationProfile> {
+        let points: Vec<(f64, f64)> = path.iter().map(|c| (c.latitude, c.longitude)).collect();
+        self.elevation.as_ref()?.profile(&points)
+    }
+
     pub fn load_graph(&mut self, nodes: Vec<Node>, edges: Vec<Edge>) {
         self.nodes = nodes.into_iter().map(|n| (n.id, n)).collect();
         
         for edge in edges {
-            self.edges.entry(edge.from_node).or_insert_with(Vec::new).push(edge);
+            self.edges.entry(edge.from_node).or_default().push(edge);
         }
+        self.lookup_node_elevations();
     }
 
     pub fn find_route(&self, request: &RouteRequest) -> RouteResponse {
@@ -76,6 +126,7 @@ impl RoutingService {
                 instructions: vec![],
                 success: false,
                 error: Some("Start location not found".to_string()),
+                elevation: None,
             }
         };
 
@@ -88,12 +139,14 @@ impl RoutingService {
                 instructions: vec![],
                 success: false,
                 error: Some("End location not found".to_string()),
+                elevation: None,
             }
         };
 
         match self.a_star(start_node, end_node, request) {
             Some(result) => {
                 let instructions = self.generate_instructions(&result.path);
+                let elevation = self.elevation_profile(&result.path);
                 RouteResponse {
                     path: result.path,
                     distance: result.distance,
@@ -101,6 +154,7 @@ impl RoutingService {
                     instructions,
                     success: true,
                     error: None,
+                    elevation,
                 }
             }
             None => RouteResponse {
@@ -110,6 +164,7 @@ impl RoutingService {
                 instructions: vec![],
                 success: false,
                 error: Some("No route found".to_string()),
+                elevation: None,
             }
         }
     }
@@ -117,4 +172,163 @@ impl RoutingService {
     fn a_star(&self, start: u64, goal: u64, request: &RouteRequest) -> Option<RouteResult> {
         let mut open_set = BinaryHeap::new();
         let mut closed_set = HashSet::new();
-        let mut g_
\ No newline at end of file
+        let mut g_scores: HashMap<u64, f64> = HashMap::new();
+        let mut distances: HashMap<u64, f64> = HashMap::new();
+        let mut came_from: HashMap<u64, u64> = HashMap::new();
+        let vehicle_type = request.vehicle_type.as_str();
+
+        g_scores.insert(start, 0.0);
+        distances.insert(start, 0.0);
+        open_set.push(AStarNode {
+            id: start,
+            g_score: 0.0,
+            f_score: self.heuristic(start, goal, vehicle_type),
+            parent: None,
+        });
+
+        while let Some(current) = open_set.pop() {
+            if !closed_set.insert(current.id) {
+                continue;
+            }
+            if current.id == goal {
+                return Some(RouteResult {
+                    path: self.reconstruct_path(&came_from, goal),
+                    distance: distances[&goal],
+                    duration: current.g_score,
+                });
+            }
+
+            for edge in self.edges.get(&current.id).into_iter().flatten() {
+                if closed_set.contains(&edge.to_node)
+                    || (request.avoid_tolls && edge.is_toll)
+                    || (request.avoid_highways && edge.is_highway)
+                {
+                    continue;
+                }
+
+                // Scores are travel times, so climbs slow walkers and cyclists
+                // down in the search itself, not just in the reported duration
+                let cost = edge.distance / travel_speed(edge, vehicle_type)
+                    * self.grade_cost_factor(edge.from_node, edge.to_node, edge.distance, vehicle_type);
+                let g_score = current.g_score + cost;
+                if g_score >= g_scores.get(&edge.to_node).copied().unwrap_or(f64::INFINITY) {
+                    continue;
+                }
+
+                g_scores.insert(edge.to_node, g_score);
+                distances.insert(edge.to_node, distances[&current.id] + edge.distance);
+                came_from.insert(edge.to_node, current.id);
+                open_set.push(AStarNode {
+                    id: edge.to_node,
+                    g_score,
+                    f_score: g_score + self.heuristic(edge.to_node, goal, vehicle_type),
+                    parent: Some(current.id),
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Lower bound on the travel time between two nodes: the straight line at
+    /// top speed, on the most favorable grade when elevations are known
+    fn heuristic(&self, from: u64, to: u64, vehicle_type: &str) -> f64 {
+        let (Some(from), Some(to)) = (self.nodes.get(&from), self.nodes.get(&to)) else {
+            return 0.0;
+        };
+        let distance = LatLng::new(from.latitude, from.longitude).distance_to(&LatLng::new(to.latitude, to.longitude));
+        let grade_factor = if self.node_elevations.is_empty() {
+            1.0
+        } else {
+            elevation_service::MIN_GRADE_COST_FACTOR
+        };
+        distance / max_speed(vehicle_type) * grade_factor
+    }
+
+    fn reconstruct_path(&self, came_from: &HashMap<u64, u64>, goal: u64) -> Vec<Coordinate> {
+        let mut ids = vec![goal];
+        while let Some(&previous) = came_from.get(&ids[ids.len() - 1]) {
+            ids.push(previous);
+        }
+
+        ids.iter()
+            .rev()
+            .filter_map(|id| self.nodes.get(id))
+            .map(|node| Coordinate {
+                latitude: node.latitude,
+                longitude: node.longitude,
+            })
+            .collect()
+    }
+
+    fn find_nearest_node(&self, coordinate: &Coordinate) -> Option<u64> {
+        let target = LatLng::new(coordinate.latitude, coordinate.longitude);
+        self.nodes
+            .values()
+            .map(|node| (node.id, target.distance_to(&LatLng::new(node.latitude, node.longitude))))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, _)| id)
+    }
+
+    /// One instruction per turn along the path, then the arrival
+    fn generate_instructions(&self, path: &[Coordinate]) -> Vec<String> {
+        if path.len() < 2 {
+            return Vec::new();
+        }
+
+        let path: Vec<RouteCoordinate> = path
+            .iter()
+            .map(|c| RouteCoordinate::new(c.latitude, c.longitude))
+            .collect();
+        let mut instructions: Vec<String> = segments_from_path(&path, 0.0)
+            .into_iter()
+            .filter(|segment| !matches!(segment.maneuver, ManeuverType::Straight))
+            .map(|segment| segment.instruction)
+            .collect();
+        instructions.push("Arrive at your destination".to_string());
+        instructions
+    }
+}
+
+/// Travel speeds in m/s for vehicles that do not follow the speed limit
+const WALKING_SPEED: f64 = 1.4;
+const CYCLING_SPEED: f64 = 4.5;
+
+/// Top driving speed, in m/s, assumed by the search heuristic; faster limits
+/// are capped to it so the heuristic never overestimates
+const MAX_DRIVING_SPEED: f64 = 130.0 / 3.6;
+
+fn max_speed(vehicle_type: &str) -> f64 {
+    match vehicle_type {
+        "foot" | "walking" => WALKING_SPEED,
+        "bicycle" | "cycling" => CYCLING_SPEED,
+        _ => MAX_DRIVING_SPEED,
+    }
+}
+
+fn travel_speed(edge: &Edge, vehicle_type: &str) -> f64 {
+    match vehicle_type {
+        "foot" | "walking" => WALKING_SPEED,
+        "bicycle" | "cycling" => CYCLING_SPEED,
+        _ => (edge.speed_limit / 3.6).clamp(1.0, MAX_DRIVING_SPEED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grade_cost_applies_only_between_nodes_with_elevations() {
+        let mut service = RoutingService::new();
+        service.node_elevations = HashMap::from([(1, 100.0), (2, 110.0)]);
+
+        let climb = service.grade_cost_factor(1, 2, 100.0, "bicycle");
+        let descent = service.grade_cost_factor(2, 1, 100.0, "bicycle");
+        assert!((climb - elevation_service::grade_cost_factor("bicycle", 0.1)).abs() < 1e-9);
+        assert!(descent < 1.0 && descent < climb);
+
+        assert_eq!(service.grade_cost_factor(1, 3, 100.0, "bicycle"), 1.0);
+        assert_eq!(service.grade_cost_factor(1, 2, 100.0, "car"), 1.0);
+    }
+}
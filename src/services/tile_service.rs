@@ -9,6 +9,38 @@ use tokio::time::{Duration, Instant};
 use image::{ImageBuffer, RgbImage, Rgb};
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn, error, debug};
+use moka::future::Cache;
+use googlemaps_clone::constants::{DEFAULT_CACHE_SIZE, DEFAULT_DISK_CACHE_SIZE};
+use crate::services::map_renderer::MapRenderer;
+use crate::services::map_style::StyleRegistry;
+use crate::utils::dem::DemStore;
+
+mod cache;
+mod heatmap;
+mod http_cache;
+mod invalidation;
+mod pyramid;
+mod render;
+mod static_map;
+mod terrain;
+mod tilejson;
+mod upstream;
+
+pub use cache::{DiskCache, DiskTierStats, MemoryTierStats, TileCacheStats};
+pub use heatmap::{
+    ColorRamp, HeatPoint, HeatmapLayer, HeatmapOptions, HeatmapSource, DEFAULT_HEATMAP_RADIUS, MAX_HEATMAP_RADIUS,
+};
+pub use http_cache::TileValidators;
+pub use invalidation::{AffectedTiles, InvalidatedTile, InvalidationSummary, TileRange};
+pub use pyramid::{PyramidConfig, PyramidPlan, MAX_COMPOSITE_LEVELS};
+pub use render::TileContentSource;
+pub use static_map::{
+    fit_bounds, StaticMapRequest, StaticMarker, StaticPath, StaticView, DEFAULT_PADDING, MAX_FIT_ZOOM, MAX_STATIC_MAP_PIXELS,
+    MAX_STATIC_MAP_SIZE, MAX_STATIC_MARKERS, MAX_STATIC_PATH_POINTS,
+};
+pub use terrain::TerrainLayer;
+pub use tilejson::{vector_layers, TileJson, VectorLayer, TILEJSON_VERSION, UPSTREAM_TILESET};
+pub use upstream::{UpstreamSource, UpstreamStats};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TileMetadata {
@@ -29,9 +61,18 @@ pub struct CachedTile {
 
 pub struct TileService {
     config: Arc<Config>,
-    memory_cache: Arc<RwLock<HashMap<String, CachedTile>>>,
+    memory_cache: Cache<String, CachedTile>,
+    disk_cache: Arc<DiskCache>,
     disk_cache_path: PathBuf,
     cache_stats: Arc<RwLock<CacheStats>>,
+    renderer: Arc<MapRenderer>,
+    source: Option<Arc<dyn TileContentSource>>,
+    styles: Option<Arc<StyleRegistry>>,
+    terrain: Option<Arc<DemStore>>,
+    heatmap: Option<Arc<dyn HeatmapSource>>,
+    upstream: Option<Arc<UpstreamSource>>,
+    pyramid: PyramidConfig,
+    attribution: Option<String>,
 }
 
 #[derive(Debug, Default)]
@@ -46,12 +87,22 @@ struct CacheStats {
 impl TileService {
     pub fn new(config: Arc<Config>) -> Result<Self> {
         let disk_cache_path = PathBuf::from(&config.tile_cache_dir);
+        let cache_stats = Arc::new(RwLock::new(CacheStats::default()));
         
         Ok(Self {
             config,
-            memory_cache: Arc::new(RwLock::new(HashMap::new())),
+            memory_cache: cache::memory_tier(DEFAULT_CACHE_SIZE as u64, cache_stats.clone()),
+            disk_cache: Arc::new(DiskCache::new(disk_cache_path.clone(), DEFAULT_DISK_CACHE_SIZE)),
             disk_cache_path,
-            cache_stats: Arc::new(RwLock::new(CacheStats::default())),
+            cache_stats,
+            renderer: Arc::new(MapRenderer::default()),
+            source: None,
+            styles: None,
+            terrain: None,
+            heatmap: None,
+            upstream: None,
+            pyramid: PyramidConfig::default(),
+            attribution: None,
         })
     }
 
@@ -62,40 +113,22 @@ impl TileService {
                 .map_err(|e| MapError::IoError(format!("Failed to create cache directory: {}", e)))?;
         }
 
-        // Load existing tiles from disk cache
-        self.load_disk_cache().await?;
+        // Index existing tiles in the disk cache and keep it within budget
+        self.disk_cache.scan().await?;
+        self.disk_cache.spawn_evictor();
 
         info!("Tile service initialized with cache directory: {:?}", self.disk_cache_path);
         Ok(())
     }
 
     pub async fn get_tile(&self, coordinate: &TileCoordinate, format: TileFormat) -> Result<Tile> {
-        let cache_key = self.generate_cache_key(coordinate, format);
+        let cache_key = self.generate_cache_key(coordinate, format.clone());
         
-        // Try memory cache first
-        if let Some(cached_tile) = self.get_from_memory_cache(&cache_key).await {
-            if cached_tile.expires_at > Instant::now() {
-                self.update_cache_stats(true, false).await;
-                return Ok(Tile {
-                    coordinate: coordinate.clone(),
-                    format,
-                    data: cached_tile.data,
-                    metadata: Some(cached_tile.metadata),
-                });
-            } else {
-                // Remove expired tile
-                self.remove_from_memory_cache(&cache_key).await;
-            }
-        }
-
-        // Try disk cache
-        if let Some(tile) = self.get_from_disk_cache(&cache_key).await? {
-            // Add back to memory cache
-            self.add_to_memory_cache(cache_key.clone(), &tile).await;
-            self.update_cache_stats(true, true).await;
+        // Try memory cache, then disk cache
+        if let Some(tile) = self.lookup_cached_tile(coordinate, format.clone(), &cache_key).await {
             return Ok(tile);
         }
 
-        // Generate new tile
-        self.update_cache_stats(false, false).await;
-        let tile = self.generate_tile(coordinate, format).await?
\ No newline at end of file
+        self.render_default_tile(coordinate, format, cache_key).await
+    }
+}
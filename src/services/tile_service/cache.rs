@@ -0,0 +1,753 @@
+//! Two-tier tile cache: a byte-budgeted memory tier and a size-capped disk tier
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use moka::future::Cache;
+use moka::notification::RemovalCause;
+use serde::Serialize;
+use tokio::fs;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use super::{CacheStats, CachedTile, TileMetadata, TileService, UpstreamStats};
+use googlemaps_clone::constants::CACHE_EXPIRY_HOURS;
+use crate::error::{MapError, Result};
+use crate::models::tile::{Tile, TileCoordinate, TileData, TileFormat};
+use crate::utils::tile_utils::TileCoord;
+
+/// Extension of cached tile files
+const TILE_EXTENSION: &str = "tile";
+/// Stands in for `/` in cache keys, which are flattened into one file name
+const KEY_SEPARATOR: char = '+';
+/// Eviction trims the disk tier to this fraction of its budget so that a
+/// busy cache is not evicting on every write
+const DISK_LOW_WATER: f64 = 0.9;
+
+/// Makes temporary file names unique across concurrent writes of one key
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub(super) fn cache_ttl() -> Duration {
+    Duration::from_secs(CACHE_EXPIRY_HOURS * 3600)
+}
+
+/// Memory tier holding at most `capacity_bytes` of tile data. moka evicts by
+/// TinyLFU admission and LRU order; size evictions are counted in `stats`.
+pub(super) fn memory_tier(capacity_bytes: u64, stats: Arc<RwLock<CacheStats>>) -> Cache<String, CachedTile> {
+    Cache::builder()
+        .max_capacity(capacity_bytes)
+        .weigher(|key: &String, tile: &CachedTile| {
+            (key.len() as u64 + tile.metadata.size_bytes).try_into().unwrap_or(u32::MAX)
+        })
+        .time_to_live(cache_ttl())
+        .eviction_listener(move |_key, _tile, cause| {
+            if cause == RemovalCause::Size {
+                if let Ok(mut stats) = stats.write() {
+                    stats.evictions += 1;
+                }
+                metrics::counter!("tile_cache_evictions_total", "tier" => "memory").increment(1);
+            }
+        })
+        .build()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TileCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub memory: MemoryTierStats,
+    pub disk: DiskTierStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<UpstreamStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryTierStats {
+    pub entries: u64,
+    pub size_bytes: u64,
+    pub capacity_bytes: u64,
+    pub evictions: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskTierStats {
+    pub entries: u64,
+    pub size_bytes: u64,
+    pub capacity_bytes: u64,
+    pub reads: u64,
+    pub writes: u64,
+    pub evictions: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DiskEntry {
+    coordinate: TileCoord,
+    size: u64,
+    /// Logical clock of the last read or write, for LRU ordering
+    last_access: u64,
+}
+
+#[derive(Debug, Default)]
+struct DiskIndex {
+    entries: HashMap<String, DiskEntry>,
+    total_bytes: u64,
+    clock: u64,
+}
+
+impl DiskIndex {
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_access = self.clock;
+        }
+    }
+
+    fn insert(&mut self, key: String, coordinate: TileCoord, size: u64) {
+        self.clock += 1;
+        let entry = DiskEntry { coordinate, size, last_access: self.clock };
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.total_bytes -= previous.size;
+        }
+        self.total_bytes += size;
+    }
+
+    fn remove(&mut self, key: &str) -> Option<DiskEntry> {
+        let entry = self.entries.remove(key)?;
+        self.total_bytes -= entry.size;
+        Some(entry)
+    }
+}
+
+/// Tiles stored as files under `root/{z}/{x}/{y}/`, one per cache key, so
+/// every variant of a tile can be found from its coordinate. Writes that push
+/// the tier over `max_bytes` wake the evictor task, which deletes the least
+/// recently used files. Files older than `ttl` are treated as misses and
+/// deleted when found.
+pub struct DiskCache {
+    root: PathBuf,
+    max_bytes: u64,
+    ttl: Duration,
+    index: Mutex<DiskIndex>,
+    over_budget: Notify,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl DiskCache {
+    pub fn new(root: PathBuf, max_bytes: u64) -> Self {
+        Self {
+            root,
+            max_bytes,
+            ttl: cache_ttl(),
+            index: Mutex::new(DiskIndex::default()),
+            over_budget: Notify::new(),
+            reads: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Replace the default time to live (`CACHE_EXPIRY_HOURS`)
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn is_expired(&self, written: SystemTime) -> bool {
+        written.elapsed().unwrap_or_default() >= self.ttl
+    }
+
+    /// Rebuild the index from the files already on disk, oldest first,
+    /// deleting expired tiles, and trim the tier if it is over budget
+    pub async fn scan(&self) -> Result<()> {
+        let mut found = Vec::new();
+        let mut pending = vec![self.root.clone()];
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(MapError::IoError(format!("Failed to read {:?}: {}", dir, e))),
+            };
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|e| MapError::IoError(format!("Failed to read {:?}: {}", dir, e)))?
+            {
+                let path = entry.path();
+                let Ok(metadata) = entry.metadata().await else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    pending.push(path);
+                } else if let Some((coordinate, key)) = self.key_for_path(&path) {
+                    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    if self.is_expired(modified) {
+                        remove_file(&path).await;
+                        continue;
+                    }
+                    found.push((modified, key, coordinate, metadata.len()));
+                }
+            }
+        }
+
+        found.sort_by_key(|(modified, ..)| *modified);
+        let over_budget = {
+            let mut index = self.lock_index();
+            for (_, key, coordinate, size) in found {
+                index.insert(key, coordinate, size);
+            }
+            info!(
+                "Disk tile cache holds {} tiles ({} of {} bytes)",
+                index.entries.len(),
+                index.total_bytes,
+                self.max_bytes
+            );
+            index.total_bytes > self.max_bytes
+        };
+
+        if over_budget {
+            self.evict().await;
+        }
+        Ok(())
+    }
+
+    /// The cached tile and when it was written, unless it has expired
+    pub async fn read(&self, key: &str, coordinate: TileCoord) -> Option<(Vec<u8>, SystemTime)> {
+        let path = self.path_for_key(key, coordinate)?;
+        let read = async {
+            let data = fs::read(&path).await?;
+            let modified = fs::metadata(&path).await?.modified()?;
+            Ok::<_, std::io::Error>((data, modified))
+        };
+        match read.await {
+            Ok((_, written)) if self.is_expired(written) => {
+                self.lock_index().remove(key);
+                remove_file(&path).await;
+                None
+            }
+            Ok(tile) => {
+                self.lock_index().touch(key);
+                self.reads.fetch_add(1, Ordering::Relaxed);
+                Some(tile)
+            }
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to read cached tile {:?}: {}", path, e);
+                }
+                self.lock_index().remove(key);
+                None
+            }
+        }
+    }
+
+    /// Store `data` under `key`. Keys that cannot be mapped to a path inside
+    /// the cache directory are not cached on disk.
+    pub async fn write(&self, key: &str, coordinate: TileCoord, data: &[u8]) -> Result<()> {
+        let Some(path) = self.path_for_key(key, coordinate) else {
+            debug!("Not caching tile with key {:?} on disk", key);
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(|e| MapError::IoError(format!("Failed to create {:?}: {}", parent, e)))?;
+        }
+        // Write then rename so readers never see a partial tile. The temporary
+        // name is unique so concurrent writers of one key cannot interleave.
+        let temp = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temp, data)
+            .await
+            .map_err(|e| MapError::IoError(format!("Failed to write {:?}: {}", temp, e)))?;
+        if let Err(e) = fs::rename(&temp, &path).await {
+            remove_file(&temp).await;
+            return Err(MapError::IoError(format!("Failed to write {:?}: {}", path, e)));
+        }
+
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        let over_budget = {
+            let mut index = self.lock_index();
+            index.insert(key.to_string(), coordinate, data.len() as u64);
+            index.total_bytes > self.max_bytes
+        };
+        if over_budget {
+            self.over_budget.notify_one();
+        }
+        Ok(())
+    }
+
+    pub async fn remove(&self, key: &str) {
+        let removed = self.lock_index().remove(key);
+        if let Some(entry) = removed {
+            if let Some(path) = self.path_for_key(key, entry.coordinate) {
+                remove_file(&path).await;
+            }
+        }
+    }
+
+    /// Delete every cached tile whose coordinate matches `predicate`.
+    /// Returns the number of tiles removed.
+    pub async fn remove_where<F>(&self, predicate: F) -> usize
+    where
+        F: Fn(&str, &TileCoord) -> bool,
+    {
+        let removed: Vec<(String, TileCoord)> = {
+            let mut index = self.lock_index();
+            let keys: Vec<(String, TileCoord)> = index
+                .entries
+                .iter()
+                .filter(|(key, entry)| predicate(key, &entry.coordinate))
+                .map(|(key, entry)| (key.clone(), entry.coordinate))
+                .collect();
+            for (key, _) in &keys {
+                index.remove(key);
+            }
+            keys
+        };
+
+        for (key, coordinate) in &removed {
+            if let Some(path) = self.path_for_key(key, *coordinate) {
+                remove_file(&path).await;
+            }
+        }
+        removed.len()
+    }
+
+    /// Run eviction in the background whenever a write exceeds the budget
+    pub fn spawn_evictor(self: &Arc<Self>) -> JoinHandle<()> {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            loop {
+                cache.over_budget.notified().await;
+                cache.evict().await;
+            }
+        })
+    }
+
+    /// Delete least recently used tiles until the tier is below its low-water
+    /// mark. Returns the number of tiles evicted.
+    pub async fn evict(&self) -> usize {
+        let target = (self.max_bytes as f64 * DISK_LOW_WATER) as u64;
+        let victims: Vec<(String, TileCoord)> = {
+            let mut index = self.lock_index();
+            if index.total_bytes <= self.max_bytes {
+                return 0;
+            }
+
+            let mut by_age: Vec<(u64, String)> = index
+                .entries
+                .iter()
+                .map(|(key, entry)| (entry.last_access, key.clone()))
+                .collect();
+            by_age.sort_unstable();
+
+            let mut victims = Vec::new();
+            for (_, key) in by_age {
+                if index.total_bytes <= target {
+                    break;
+                }
+                if let Some(entry) = index.remove(&key) {
+                    victims.push((key, entry.coordinate));
+                }
+            }
+            victims
+        };
+
+        for (key, coordinate) in &victims {
+            if let Some(path) = self.path_for_key(key, *coordinate) {
+                remove_file(&path).await;
+            }
+        }
+
+        let evicted = victims.len();
+        self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        metrics::counter!("tile_cache_evictions_total", "tier" => "disk").increment(evicted as u64);
+        debug!("Evicted {} tiles from the disk cache", evicted);
+        evicted
+    }
+
+    pub fn stats(&self) -> DiskTierStats {
+        let index = self.lock_index();
+        DiskTierStats {
+            entries: index.entries.len() as u64,
+            size_bytes: index.total_bytes,
+            capacity_bytes: self.max_bytes,
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lock_index(&self) -> std::sync::MutexGuard<'_, DiskIndex> {
+        self.index.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// `light-1a2b/@2x/png_14_2620_6332` for tile 14/2620/6332 maps to
+    /// `<root>/14/2620/6332/light-1a2b+@2x+png_14_2620_6332.tile`
+    fn path_for_key(&self, key: &str, coordinate: TileCoord) -> Option<PathBuf> {
+        let valid = key.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment.chars().all(|c| c.is_ascii_alphanumeric() || "-_.@".contains(c))
+        });
+        if !valid {
+            return None;
+        }
+
+        let file_name = format!("{}.{}", key.replace('/', &KEY_SEPARATOR.to_string()), TILE_EXTENSION);
+        Some(
+            self.root
+                .join(coordinate.z.to_string())
+                .join(coordinate.x.to_string())
+                .join(coordinate.y.to_string())
+                .join(file_name),
+        )
+    }
+
+    fn key_for_path(&self, path: &Path) -> Option<(TileCoord, String)> {
+        if path.extension()? != TILE_EXTENSION {
+            return None;
+        }
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let segments: Option<Vec<&str>> = relative
+            .components()
+            .map(|component| match component {
+                Component::Normal(segment) => segment.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        let [z, x, y, file_name] = segments?[..] else {
+            return None;
+        };
+        let coordinate = TileCoord::new(x.parse().ok()?, y.parse().ok()?, z.parse().ok()?);
+        let key = file_name
+            .strip_suffix(TILE_EXTENSION)?
+            .strip_suffix('.')?
+            .replace(KEY_SEPARATOR, "/");
+        Some((coordinate, key))
+    }
+}
+
+async fn remove_file(path: &Path) {
+    if let Err(e) = fs::remove_file(path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove cached tile {:?}: {}", path, e);
+        }
+    }
+}
+
+impl TileService {
+    /// Replace the default cache budgets (`DEFAULT_CACHE_SIZE` in memory,
+    /// `DEFAULT_DISK_CACHE_SIZE` on disk)
+    pub fn with_cache_budget(mut self, memory_bytes: u64, disk_bytes: u64) -> Self {
+        self.memory_cache = memory_tier(memory_bytes, self.cache_stats.clone());
+        self.disk_cache = Arc::new(
+            DiskCache::new(self.disk_cache_path.clone(), disk_bytes).with_ttl(self.disk_cache.ttl),
+        );
+        self
+    }
+
+    pub fn cache_stats(&self) -> TileCacheStats {
+        let (hits, misses, memory_evictions) = self
+            .cache_stats
+            .read()
+            .map(|stats| (stats.hits, stats.misses, stats.evictions))
+            .unwrap_or_default();
+        let lookups = hits + misses;
+
+        TileCacheStats {
+            hits,
+            misses,
+            hit_rate: if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 },
+            memory: MemoryTierStats {
+                entries: self.memory_cache.entry_count(),
+                size_bytes: self.memory_cache.weighted_size(),
+                capacity_bytes: self.memory_cache.policy().max_capacity().unwrap_or_default(),
+                evictions: memory_evictions,
+            },
+            disk: self.disk_cache.stats(),
+            upstream: self.upstream.as_ref().map(|upstream| upstream.stats()),
+        }
+    }
+
+    /// Look `cache_key` up in memory, then on disk, promoting disk hits into
+    /// memory
+    pub(super) async fn lookup_cached_tile(
+        &self,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+        cache_key: &str,
+    ) -> Option<Tile> {
+        if let Some(cached_tile) = self.memory_cache.get(cache_key).await {
+            if cached_tile.expires_at > Instant::now() {
+                self.record_lookup(true, false);
+                return Some(Tile {
+                    coordinate: coordinate.clone(),
+                    format,
+                    data: cached_tile.data,
+                    metadata: Some(cached_tile.metadata),
+                });
+            }
+            self.memory_cache.invalidate(cache_key).await;
+        }
+
+        let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+        if let Some((data, written)) = self.disk_cache.read(cache_key, coord).await {
+            self.record_lookup(true, true);
+            // A promoted tile expires when its disk copy would have
+            let expires_in = self.disk_cache.ttl.saturating_sub(written.elapsed().unwrap_or_default());
+            let tile = self
+                .cache_in_memory(
+                    coordinate,
+                    format,
+                    cache_key.to_string(),
+                    data,
+                    written.into(),
+                    expires_in,
+                )
+                .await;
+            return Some(tile);
+        }
+
+        self.record_lookup(false, false);
+        None
+    }
+
+    /// Store freshly rendered `data` in both tiers. A failed disk write only
+    /// costs a re-render later, so it is logged rather than returned.
+    pub(super) async fn store_cached_tile(
+        &self,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+        cache_key: String,
+        data: Vec<u8>,
+    ) -> Tile {
+        let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+        match self.disk_cache.write(&cache_key, coord, &data).await {
+            Ok(()) => {
+                if let Ok(mut stats) = self.cache_stats.write() {
+                    stats.disk_writes += 1;
+                }
+            }
+            Err(e) => warn!("Failed to cache tile {} on disk: {}", cache_key, e),
+        }
+        self.cache_in_memory(coordinate, format, cache_key, data, chrono::Utc::now(), cache_ttl())
+            .await
+    }
+
+    async fn cache_in_memory(
+        &self,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+        cache_key: String,
+        data: Vec<u8>,
+        created_at: chrono::DateTime<chrono::Utc>,
+        expires_in: Duration,
+    ) -> Tile {
+        let metadata = TileMetadata {
+            coordinate: coordinate.clone(),
+            format: format.clone(),
+            created_at,
+            last_accessed: chrono::Utc::now(),
+            size_bytes: data.len() as u64,
+            cache_key: cache_key.clone(),
+        };
+        let data = TileData::from(data);
+
+        self.memory_cache
+            .insert(
+                cache_key,
+                CachedTile {
+                    metadata: metadata.clone(),
+                    data: data.clone(),
+                    expires_at: Instant::now() + expires_in,
+                },
+            )
+            .await;
+
+        Tile {
+            coordinate: coordinate.clone(),
+            format,
+            data,
+            metadata: Some(metadata),
+        }
+    }
+
+    fn record_lookup(&self, hit: bool, disk: bool) {
+        if let Ok(mut stats) = self.cache_stats.write() {
+            match (hit, disk) {
+                (true, true) => {
+                    stats.hits += 1;
+                    stats.disk_reads += 1;
+                }
+                (true, false) => stats.hits += 1,
+                (false, _) => stats.misses += 1,
+            }
+        }
+        let (result, tier) = match (hit, disk) {
+            (true, true) => ("hit", "disk"),
+            (true, false) => ("hit", "memory"),
+            (false, _) => ("miss", "none"),
+        };
+        metrics::counter!("tile_cache_lookups_total", "result" => result, "tier" => tier).increment(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("disk-tiles-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn cached_tile(size: usize) -> CachedTile {
+        let coordinate = TileCoordinate { x: 0, y: 0, z: 0 };
+        CachedTile {
+            metadata: TileMetadata {
+                coordinate,
+                format: TileFormat::Png,
+                created_at: chrono::Utc::now(),
+                last_accessed: chrono::Utc::now(),
+                size_bytes: size as u64,
+                cache_key: String::new(),
+            },
+            data: TileData::from(vec![0; size]),
+            expires_at: Instant::now() + cache_ttl(),
+        }
+    }
+
+    const TILE: TileCoord = TileCoord { x: 2620, y: 6332, z: 14 };
+
+    #[tokio::test]
+    async fn memory_tier_keeps_tiles_within_the_byte_budget() {
+        let stats = Arc::new(RwLock::new(CacheStats::default()));
+        let cache = memory_tier(10_000, stats.clone());
+
+        for i in 0..20 {
+            cache.insert(format!("png_14_{}_6332", i), cached_tile(1_000)).await;
+        }
+        cache.insert("too-big".to_string(), cached_tile(20_000)).await;
+        cache.run_pending_tasks().await;
+
+        assert!(cache.weighted_size() <= 10_000);
+        assert!(cache.entry_count() <= 9);
+        assert!(!cache.contains_key("too-big"));
+        assert!(stats.read().unwrap().evictions >= 11);
+    }
+
+    #[tokio::test]
+    async fn round_trips_tiles_through_disk_and_rescans_them() {
+        let root = cache_dir();
+        let cache = DiskCache::new(root.clone(), 1 << 20);
+        let key = "light-1a2b/@2x/png_14_2620_6332";
+
+        cache.write(key, TILE, b"tile data").await.unwrap();
+        let (data, _) = cache.read(key, TILE).await.unwrap();
+        assert_eq!(data, b"tile data");
+        assert!(root.join("14/2620/6332/light-1a2b+@2x+png_14_2620_6332.tile").exists());
+
+        let reopened = DiskCache::new(root.clone(), 1 << 20);
+        reopened.scan().await.unwrap();
+        let stats = reopened.stats();
+        assert_eq!((stats.entries, stats.size_bytes), (1, 9));
+        assert_eq!(reopened.read(key, TILE).await.unwrap().0, b"tile data");
+
+        assert_eq!(reopened.remove_where(|_, coordinate| coordinate.z == 14).await, 1);
+        assert!(reopened.read(key, TILE).await.is_none());
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn skips_keys_that_cannot_be_file_names() {
+        let root = cache_dir();
+        let cache = DiskCache::new(root.clone(), 1 << 20);
+
+        for key in ["a//b", "a/b c", "a\\b", ""] {
+            cache.write(key, TILE, b"tile").await.unwrap();
+            assert!(cache.read(key, TILE).await.is_none());
+        }
+        assert_eq!(cache.stats().writes, 0);
+        assert!(!root.exists());
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_of_one_key_do_not_collide() {
+        let root = cache_dir();
+        let cache = DiskCache::new(root.clone(), 1 << 20);
+
+        let tiles: Vec<Vec<u8>> = (0..16).map(|i| vec![i; 64]).collect();
+        let writes = tiles.iter().map(|data| cache.write("png_14_2620_6332", TILE, data));
+        for result in futures::future::join_all(writes).await {
+            result.unwrap();
+        }
+
+        let mut files = std::fs::read_dir(root.join("14/2620/6332")).unwrap();
+        let file = files.next().unwrap().unwrap();
+        assert_eq!(file.file_name(), "png_14_2620_6332.tile");
+        assert!(files.next().is_none(), "temporary files were left behind");
+        assert_eq!(cache.stats().entries, 1);
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_tiles_below_the_low_water_mark() {
+        let root = cache_dir();
+        let cache = DiskCache::new(root.clone(), 300);
+
+        for key in ["a", "b", "c"] {
+            cache.write(key, TILE, &[0; 100]).await.unwrap();
+        }
+        // Reading `a` makes `b` the least recently used
+        assert!(cache.read("a", TILE).await.is_some());
+        cache.write("d", TILE, &[0; 100]).await.unwrap();
+
+        assert_eq!(cache.evict().await, 2);
+        assert!(cache.read("b", TILE).await.is_none());
+        assert!(cache.read("c", TILE).await.is_none());
+        assert!(cache.read("a", TILE).await.is_some());
+        assert!(cache.read("d", TILE).await.is_some());
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.size_bytes, stats.evictions), (2, 200, 2));
+        assert_eq!(cache.evict().await, 0);
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expires_tiles_older_than_the_ttl() {
+        let root = cache_dir();
+        let cache = DiskCache::new(root.clone(), 1 << 20).with_ttl(Duration::from_millis(50));
+
+        cache.write("a", TILE, b"tile").await.unwrap();
+        cache.write("b", TILE, b"tile").await.unwrap();
+        assert!(cache.read("a", TILE).await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(cache.read("b", TILE).await.is_none());
+        assert!(!root.join("14/2620/6332/b.tile").exists());
+        assert_eq!(cache.stats().entries, 1);
+
+        let reopened = DiskCache::new(root.clone(), 1 << 20).with_ttl(Duration::from_millis(50));
+        reopened.scan().await.unwrap();
+        assert_eq!(reopened.stats().entries, 0);
+        assert!(!root.join("14/2620/6332/a.tile").exists());
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+}
@@ -0,0 +1,563 @@
+//! Density heatmap overlays from point data
+//!
+//! Each point is spread over the tile's pixel grid with a quartic kernel, and
+//! the summed density is mapped through a color ramp into a transparent PNG
+//! meant to be drawn on top of the base map. Density is scaled
+//! logarithmically against a fixed saturation level rather than the tile's own
+//! peak, so neighbouring tiles use the same colors and show no seams.
+//!
+//! Heatmap data changes over time, so cache keys include the current refresh
+//! period of the layer and older renders simply stop being requested.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+use tracing::debug;
+
+use super::TileService;
+use googlemaps_clone::constants::{MAX_TILE_SCALE, TILE_SIZE};
+use crate::database::{Database, MapBounds};
+use crate::error::{MapError, Result};
+use crate::models::tile::{Tile, TileCoordinate, TileFormat};
+use crate::services::map_renderer::parse_color;
+use crate::utils::tile_utils::{LatLng, PixelCoord, TileCoord};
+
+/// Kernel radius in CSS pixels when none is requested
+pub const DEFAULT_HEATMAP_RADIUS: f32 = 20.0;
+pub const MAX_HEATMAP_RADIUS: f32 = 100.0;
+
+/// Density drawn with the last color of the ramp; a lone point has a
+/// density of 1 at its center
+const SATURATION_DENSITY: f64 = 40.0;
+
+/// Points are merged into cells this fraction of the radius wide before the
+/// kernel is applied, which bounds the work per tile at low zooms
+const CELL_FRACTION: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeatmapLayer {
+    /// Where people searched from, from `search_history`
+    Searches,
+    /// Current congestion at locations, from `traffic_data`
+    Traffic,
+}
+
+impl HeatmapLayer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HeatmapLayer::Searches => "searches",
+            HeatmapLayer::Traffic => "traffic",
+        }
+    }
+
+    /// How far back points are counted
+    pub fn window(&self) -> Duration {
+        match self {
+            HeatmapLayer::Searches => Duration::from_secs(30 * 24 * 3600),
+            HeatmapLayer::Traffic => Duration::from_secs(3600),
+        }
+    }
+
+    /// How long a rendered tile is served before it is drawn again
+    pub fn refresh_interval(&self) -> Duration {
+        match self {
+            HeatmapLayer::Searches => Duration::from_secs(3600),
+            HeatmapLayer::Traffic => Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// Time left at `now` until the current refresh period ends and tiles
+    /// are drawn again
+    pub fn fresh_for(&self, now: DateTime<Utc>) -> Duration {
+        let interval = self.refresh_interval().as_secs().max(1);
+        Duration::from_secs(interval - now.timestamp().rem_euclid(interval as i64) as u64)
+    }
+}
+
+/// A point with the amount it adds to the density
+#[derive(Debug, Clone, Copy)]
+pub struct HeatPoint {
+    pub position: LatLng,
+    pub weight: f64,
+}
+
+/// Supplies the points heatmaps are drawn from
+pub trait HeatmapSource: Send + Sync {
+    /// Points of `layer` inside `bounds` recorded since `since`. Points within
+    /// `cell_deg` degrees of each other may be merged into one with their
+    /// summed weight.
+    fn heat_points(
+        &self,
+        layer: HeatmapLayer,
+        bounds: MapBounds,
+        cell_deg: f64,
+        since: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<Vec<HeatPoint>>>;
+}
+
+impl HeatmapSource for Database {
+    fn heat_points(
+        &self,
+        layer: HeatmapLayer,
+        bounds: MapBounds,
+        cell_deg: f64,
+        since: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<Vec<HeatPoint>>> {
+        async move {
+            let rows = match layer {
+                HeatmapLayer::Searches => self.search_heat_points(&bounds, cell_deg, since).await,
+                HeatmapLayer::Traffic => self.traffic_heat_points(&bounds, cell_deg, since).await,
+            }
+            .map_err(|e| MapError::IoError(format!("{:#}", e)))?;
+
+            Ok(rows
+                .into_iter()
+                .map(|(lat, lng, weight)| HeatPoint {
+                    position: LatLng::new(lat, lng),
+                    weight,
+                })
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+/// Colors for densities from 0 to saturation, interpolated between stops
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorRamp {
+    stops: Vec<(f32, [u8; 4])>,
+}
+
+impl Default for ColorRamp {
+    /// Transparent blue through pale yellow to deep red
+    fn default() -> Self {
+        Self::named("default").expect("default ramp")
+    }
+}
+
+impl ColorRamp {
+    pub const NAMES: [&'static str; 3] = ["default", "fire", "viridis"];
+
+    pub fn named(name: &str) -> Option<Self> {
+        let spec = match name {
+            "default" => "0:#2166ac00,0.2:#67a9cf,0.4:#d1e5f0,0.6:#fddbc7,0.8:#ef8a62,1:#b2182b",
+            "fire" => "0:#00000000,0.25:#800000,0.5:#ff3300,0.75:#ffa500,1:#ffffcc",
+            "viridis" => "0:#44015400,0.25:#3b528b,0.5:#21918c,0.75:#5ec962,1:#fde725",
+            _ => return None,
+        };
+        Self::parse(spec)
+    }
+
+    /// Stops written as `position:color` pairs separated by commas, e.g.
+    /// `0:#0000ff00,0.5:#00ff00,1:#ff0000`. Positions run from 0 to 1 and
+    /// colors take an optional alpha.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut stops = spec
+            .split(',')
+            .map(|stop| {
+                let (position, color) = stop.trim().split_once(':')?;
+                let position: f32 = position.trim().parse().ok().filter(|p: &f32| (0.0..=1.0).contains(p))?;
+                let color = parse_color(color)?.to_color_u8();
+                Some((position, [color.red(), color.green(), color.blue(), color.alpha()]))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if stops.len() < 2 {
+            return None;
+        }
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(Self { stops })
+    }
+
+    /// Canonical text form, used in cache keys
+    pub fn key(&self) -> String {
+        self.stops
+            .iter()
+            .map(|(position, [r, g, b, a])| format!("{}-{:02x}{:02x}{:02x}{:02x}", position, r, g, b, a))
+            .collect::<Vec<_>>()
+            .join("_")
+    }
+
+    /// Color at `t` between 0 and 1
+    pub fn color_at(&self, t: f32) -> [u8; 4] {
+        let t = t.clamp(0.0, 1.0);
+        let upper = self.stops.iter().position(|(position, _)| *position >= t).unwrap_or(self.stops.len() - 1);
+        if upper == 0 {
+            return self.stops[0].1;
+        }
+        let (from_position, from) = self.stops[upper - 1];
+        let (to_position, to) = self.stops[upper];
+        let span = (to_position - from_position).max(f32::EPSILON);
+        let f = ((t - from_position) / span).clamp(0.0, 1.0);
+        std::array::from_fn(|i| (from[i] as f32 + (to[i] as f32 - from[i] as f32) * f).round() as u8)
+    }
+
+    /// Colors for 256 evenly spaced values of `t`
+    fn lookup_table(&self) -> Vec<[u8; 4]> {
+        (0..256).map(|i| self.color_at(i as f32 / 255.0)).collect()
+    }
+}
+
+/// How a heatmap tile is drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeatmapOptions {
+    /// Kernel radius in CSS pixels
+    pub radius: f32,
+    pub ramp: ColorRamp,
+}
+
+impl Default for HeatmapOptions {
+    fn default() -> Self {
+        Self {
+            radius: DEFAULT_HEATMAP_RADIUS,
+            ramp: ColorRamp::default(),
+        }
+    }
+}
+
+impl TileService {
+    /// Use `source` for the points of heatmap tiles
+    pub fn with_heatmap_source(mut self, source: Arc<dyn HeatmapSource>) -> Self {
+        self.heatmap = Some(source);
+        self
+    }
+
+    pub fn has_heatmaps(&self) -> bool {
+        self.heatmap.is_some()
+    }
+
+    /// Heatmap overlay of `layer` for `coordinate`, always a PNG of
+    /// `TILE_SIZE * scale` pixels. `None` when no heatmap source is
+    /// configured.
+    pub async fn get_heatmap_tile(
+        &self,
+        coordinate: &TileCoordinate,
+        layer: HeatmapLayer,
+        options: &HeatmapOptions,
+        scale: u8,
+    ) -> Result<Option<Tile>> {
+        let Some(source) = self.heatmap.clone() else {
+            return Ok(None);
+        };
+
+        let scale = scale.clamp(1, MAX_TILE_SCALE);
+        let radius = options.radius.clamp(1.0, MAX_HEATMAP_RADIUS);
+        let now = Utc::now();
+        let period = now.timestamp() / layer.refresh_interval().as_secs().max(1) as i64;
+        let cache_key = format!(
+            "heatmap/{}/{}/r{}/{}/@{}x/{}",
+            layer.as_str(),
+            period,
+            radius,
+            options.ramp.key(),
+            scale,
+            self.generate_cache_key(coordinate, TileFormat::Png)
+        );
+
+        let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+        let ramp = options.ramp.clone();
+        let tile = self
+            .get_or_render(coordinate, TileFormat::Png, cache_key, async move {
+                let world = TILE_SIZE as f64 * 2_f64.powi(coord.z as i32);
+                let degrees_per_pixel = 360.0 / world;
+                let since = now - chrono::Duration::from_std(layer.window()).unwrap_or_else(|_| chrono::Duration::zero());
+                let points = source
+                    .heat_points(
+                        layer,
+                        tile_bounds(coord, radius as f64),
+                        degrees_per_pixel * radius as f64 * CELL_FRACTION,
+                        since,
+                    )
+                    .await?;
+
+                let count = points.len();
+                let png = tokio::task::spawn_blocking(move || render_heatmap_tile(coord, &points, radius, &ramp, scale))
+                    .await
+                    .map_err(|e| MapError::IoError(format!("Heatmap render task failed: {}", e)))??;
+                debug!(
+                    "Rendered {} heatmap tile {}/{}/{} from {} points",
+                    layer.as_str(),
+                    coord.z,
+                    coord.x,
+                    coord.y,
+                    count
+                );
+                Ok(png)
+            })
+            .await?;
+        Ok(Some(tile))
+    }
+}
+
+/// The tile's area grown by `margin` CSS pixels on every side, so points
+/// just outside it still add to the density near its edges
+fn tile_bounds(coord: TileCoord, margin: f64) -> MapBounds {
+    let size = TILE_SIZE as f64;
+    let north_west = PixelCoord {
+        x: coord.x as f64 * size - margin,
+        y: coord.y as f64 * size - margin,
+    }
+    .to_lat_lng(coord.z);
+    let south_east = PixelCoord {
+        x: (coord.x + 1) as f64 * size + margin,
+        y: (coord.y + 1) as f64 * size + margin,
+    }
+    .to_lat_lng(coord.z);
+
+    MapBounds {
+        north: north_west.lat.min(90.0),
+        south: south_east.lat.max(-90.0),
+        east: south_east.lng.min(180.0),
+        west: north_west.lng.max(-180.0),
+    }
+}
+
+/// Draw `points` for `coord` as PNG bytes, `TILE_SIZE * scale` pixels
+/// square, with a quartic kernel `radius` CSS pixels wide
+pub fn render_heatmap_tile(
+    coord: TileCoord,
+    points: &[HeatPoint],
+    radius: f32,
+    ramp: &ColorRamp,
+    scale: u8,
+) -> Result<Vec<u8>> {
+    let size = TILE_SIZE as usize * scale as usize;
+    let radius = radius as f64 * scale as f64;
+    let origin_x = coord.x as f64 * TILE_SIZE as f64;
+    let origin_y = coord.y as f64 * TILE_SIZE as f64;
+
+    let mut density = vec![0f64; size * size];
+    for point in points.iter().filter(|point| point.weight > 0.0) {
+        let pixel = point.position.to_pixel_coord(coord.z);
+        let center_x = (pixel.x - origin_x) * scale as f64;
+        let center_y = (pixel.y - origin_y) * scale as f64;
+
+        let min_x = (center_x - radius).floor().max(0.0) as usize;
+        let max_x = (center_x + radius).ceil().min(size as f64 - 1.0);
+        let min_y = (center_y - radius).floor().max(0.0) as usize;
+        let max_y = (center_y + radius).ceil().min(size as f64 - 1.0);
+        if max_x < 0.0 || max_y < 0.0 {
+            continue;
+        }
+
+        for y in min_y..=max_y as usize {
+            let dy = y as f64 + 0.5 - center_y;
+            for x in min_x..=max_x as usize {
+                let dx = x as f64 + 0.5 - center_x;
+                let u = (dx * dx + dy * dy) / (radius * radius);
+                if u < 1.0 {
+                    density[y * size + x] += point.weight * (1.0 - u) * (1.0 - u);
+                }
+            }
+        }
+    }
+
+    let colors = ramp.lookup_table();
+    let saturation = SATURATION_DENSITY.ln_1p();
+    let mut image = RgbaImage::new(size as u32, size as u32);
+    for (index, value) in density.iter().enumerate() {
+        if *value <= 0.0 {
+            continue;
+        }
+        let t = (value.ln_1p() / saturation).min(1.0);
+        let color = colors[(t * 255.0).round() as usize];
+        image.put_pixel((index % size) as u32, (index / size) as u32, Rgba(color));
+    }
+
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(image)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(|e| MapError::IoError(format!("Failed to encode heatmap tile: {}", e)))?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn point(lat: f64, lng: f64, weight: f64) -> HeatPoint {
+        HeatPoint {
+            position: LatLng::new(lat, lng),
+            weight,
+        }
+    }
+
+    /// A point at world pixel `(x, y)` of `zoom`
+    fn point_at(x: f64, y: f64, zoom: u8, weight: f64) -> HeatPoint {
+        HeatPoint {
+            position: PixelCoord { x, y }.to_lat_lng(zoom),
+            weight,
+        }
+    }
+
+    fn render(coord: TileCoord, points: &[HeatPoint], scale: u8) -> RgbaImage {
+        let png = render_heatmap_tile(coord, points, DEFAULT_HEATMAP_RADIUS, &ColorRamp::default(), scale).unwrap();
+        image::load_from_memory(&png).unwrap().to_rgba8()
+    }
+
+    fn close(a: [u8; 4], b: [u8; 4], tolerance: u8) -> bool {
+        a.iter().zip(b).all(|(a, b)| a.abs_diff(b) <= tolerance)
+    }
+
+    struct FixedSource(Vec<HeatPoint>);
+
+    impl HeatmapSource for FixedSource {
+        fn heat_points(
+            &self,
+            _layer: HeatmapLayer,
+            _bounds: MapBounds,
+            _cell_deg: f64,
+            _since: DateTime<Utc>,
+        ) -> BoxFuture<'_, Result<Vec<HeatPoint>>> {
+            let points = self.0.clone();
+            async move { Ok(points) }.boxed()
+        }
+    }
+
+    #[test]
+    fn stays_fresh_until_the_next_refresh() {
+        let at = |seconds| Utc.timestamp_opt(seconds, 0).unwrap();
+        assert_eq!(HeatmapLayer::Traffic.fresh_for(at(1_800_000_000)), Duration::from_secs(300));
+        assert_eq!(HeatmapLayer::Traffic.fresh_for(at(1_800_000_299)), Duration::from_secs(1));
+        assert_eq!(HeatmapLayer::Searches.fresh_for(at(1_800_000_000 + 600)), Duration::from_secs(3000));
+    }
+
+    #[test]
+    fn parses_color_ramps() {
+        let ramp = ColorRamp::parse("1:#ff0000, 0:#0000ff00").unwrap();
+        assert_eq!(ramp.key(), "0-0000ff00_1-ff0000ff", "stops are sorted");
+        assert_eq!(ramp.color_at(0.0), [0, 0, 255, 0]);
+        assert_eq!(ramp.color_at(1.0), [255, 0, 0, 255]);
+        assert_eq!(ramp.color_at(0.5), [128, 0, 128, 128]);
+        assert_eq!(ramp.color_at(-1.0), ramp.color_at(0.0));
+        assert_eq!(ramp.color_at(2.0), ramp.color_at(1.0));
+
+        for spec in ["0:#ff0000", "0:#ff0000,1.5:#00ff00", "0:#ff0000,1:green", "0:#ff0000;1:#00ff00", ""] {
+            assert_eq!(ColorRamp::parse(spec), None, "{} was accepted", spec);
+        }
+    }
+
+    #[test]
+    fn has_every_named_ramp() {
+        for name in ColorRamp::NAMES {
+            let ramp = ColorRamp::named(name).unwrap();
+            assert_eq!(ramp.color_at(0.0)[3], 0, "{} starts transparent", name);
+            assert_eq!(ramp.color_at(1.0)[3], 255, "{} ends opaque", name);
+        }
+        assert_eq!(ColorRamp::named("rainbow"), None);
+        assert_eq!(ColorRamp::default(), ColorRamp::named("default").unwrap());
+    }
+
+    #[test]
+    fn spreads_a_point_over_the_kernel_radius() {
+        let coord = TileCoord::new(10, 20, 6);
+        let image = render(coord, &[point_at(10.0 * 256.0 + 128.0, 20.0 * 256.0 + 128.0, 6, 1.0)], 1);
+        assert_eq!(image.dimensions(), (TILE_SIZE, TILE_SIZE));
+
+        // A lone point has a density of 1 at its center
+        let expected = ColorRamp::default().color_at((2_f64.ln() / SATURATION_DENSITY.ln_1p()) as f32);
+        let center = image.get_pixel(128, 128).0;
+        assert!(close(center, expected, 2), "{:?} != {:?}", center, expected);
+
+        // Density falls off with distance and ends at the radius
+        let radius = DEFAULT_HEATMAP_RADIUS as u32;
+        assert!(image.get_pixel(128 + radius / 2, 128).0[3] < center[3]);
+        assert_eq!(image.get_pixel(128 + radius + 1, 128).0[3], 0);
+        assert_eq!(image.get_pixel(128, 128 - radius - 1).0[3], 0);
+        assert_eq!(image.get_pixel(128 + 5, 128), image.get_pixel(128 - 6, 128), "the kernel is symmetric");
+    }
+
+    #[test]
+    fn scales_the_kernel_with_the_tile() {
+        let coord = TileCoord::new(10, 20, 6);
+        let center = point_at(10.0 * 256.0 + 128.0, 20.0 * 256.0 + 128.0, 6, 1.0);
+        let single = render(coord, &[center], 1);
+        let retina = render(coord, &[center], 2);
+        assert_eq!(retina.dimensions(), (TILE_SIZE * 2, TILE_SIZE * 2));
+        // The same CSS pixel has about the same color at either density;
+        // device pixel centers are a quarter of a CSS pixel off
+        let (css, device) = (single.get_pixel(128 + 10, 128).0, retina.get_pixel(256 + 20, 256).0);
+        assert!(close(css, device, 8), "{:?} != {:?}", css, device);
+        assert_eq!(retina.get_pixel(256 + 2 * DEFAULT_HEATMAP_RADIUS as u32 + 1, 256).0[3], 0);
+    }
+
+    #[test]
+    fn saturates_by_weight_and_skips_empty_points() {
+        let coord = TileCoord::new(10, 20, 6);
+        let (x, y) = (10.0 * 256.0 + 128.0, 20.0 * 256.0 + 128.0);
+        let light = render(coord, &[point_at(x, y, 6, 1.0)], 1);
+        let heavy = render(coord, &[point_at(x, y, 6, 5.0)], 1);
+        let saturated = render(coord, &[point_at(x, y, 6, SATURATION_DENSITY * 10.0)], 1);
+
+        assert_ne!(light.get_pixel(128, 128), heavy.get_pixel(128, 128));
+        assert_eq!(saturated.get_pixel(128, 128).0, ColorRamp::default().color_at(1.0));
+
+        let empty = render(coord, &[point_at(x, y, 6, 0.0), point_at(x, y, 6, -3.0)], 1);
+        assert!(empty.pixels().all(|pixel| pixel.0[3] == 0));
+    }
+
+    #[test]
+    fn draws_points_across_tile_edges_without_seams() {
+        // On the edge between two tiles, and near a third one
+        let points = [
+            point_at(11.0 * 256.0, 20.0 * 256.0 + 100.0, 6, 3.0),
+            point_at(10.0 * 256.0 + 50.0, 21.0 * 256.0 + 5.0, 6, 1.0),
+        ];
+        let left = render(TileCoord::new(10, 20, 6), &points, 1);
+        let right = render(TileCoord::new(11, 20, 6), &points, 1);
+        for y in 80..120 {
+            assert_eq!(left.get_pixel(255, y), right.get_pixel(0, y), "row {}", y);
+        }
+        assert!(left.get_pixel(255, 100).0[3] > 0);
+        assert!(left.get_pixel(50, 255).0[3] > 0, "a point just below the tile still shows");
+    }
+
+    #[test]
+    fn grows_tile_bounds_by_the_margin() {
+        let coord = TileCoord::new(10, 20, 6);
+        let tile = tile_bounds(coord, 0.0);
+        let grown = tile_bounds(coord, 20.0);
+        assert!(grown.north > tile.north && grown.south < tile.south);
+        assert!(grown.east > tile.east && grown.west < tile.west);
+
+        let north_west = PixelCoord { x: 10.0 * 256.0, y: 20.0 * 256.0 }.to_lat_lng(6);
+        assert!((tile.north - north_west.lat).abs() < 1e-9 && (tile.west - north_west.lng).abs() < 1e-9);
+
+        // Edge tiles stay inside the world
+        let corner = tile_bounds(TileCoord::new(0, 0, 0), 50.0);
+        assert_eq!((corner.west, corner.east), (-180.0, 180.0));
+        assert!(corner.north <= 90.0 && corner.south >= -90.0);
+    }
+
+    #[tokio::test]
+    async fn renders_tiles_only_with_a_source() {
+        let coordinate = TileCoordinate { x: 10, y: 20, z: 6 };
+        let service = TileService::new(Arc::new(Config::default())).unwrap();
+        assert!(!service.has_heatmaps());
+        let tile = service
+            .get_heatmap_tile(&coordinate, HeatmapLayer::Traffic, &HeatmapOptions::default(), 1)
+            .await
+            .unwrap();
+        assert!(tile.is_none());
+
+        let source = FixedSource(vec![point_at(10.0 * 256.0 + 128.0, 20.0 * 256.0 + 128.0, 6, 1.0)]);
+        let service = service.with_heatmap_source(Arc::new(source));
+        assert!(service.has_heatmaps());
+        let tile = service
+            .get_heatmap_tile(&coordinate, HeatmapLayer::Traffic, &HeatmapOptions::default(), 5)
+            .await
+            .unwrap()
+            .unwrap();
+        let image = image::load_from_memory(&Vec::<u8>::from(tile.data)).unwrap();
+        let size = TILE_SIZE * MAX_TILE_SCALE as u32;
+        assert_eq!((image.width(), image.height()), (size, size), "the scale is clamped");
+    }
+}
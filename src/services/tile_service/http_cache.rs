@@ -0,0 +1,206 @@
+//! HTTP validators and freshness for tile responses
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use super::TileService;
+use crate::models::tile::Tile;
+use crate::services::map_style::fnv1a;
+
+/// Format of HTTP dates (RFC 9110 IMF-fixdate)
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Validators sent with a tile so clients can revalidate with a conditional GET
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileValidators {
+    /// Strong entity tag derived from the encoded tile, quotes included
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+}
+
+impl TileValidators {
+    pub fn last_modified_header(&self) -> String {
+        self.last_modified.format(HTTP_DATE_FORMAT).to_string()
+    }
+
+    /// Whether a request carrying these conditional headers can be answered
+    /// with `304 Not Modified`. As in RFC 9110, `If-Modified-Since` is only
+    /// considered when there is no `If-None-Match`.
+    pub fn not_modified(&self, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> bool {
+        if let Some(if_none_match) = if_none_match {
+            return if_none_match.split(',').map(str::trim).any(|tag| {
+                // Weak comparison: `W/"abc"` matches `"abc"`
+                tag == "*" || tag.trim_start_matches("W/") == self.etag
+            });
+        }
+
+        match if_modified_since.and_then(|date| DateTime::parse_from_rfc2822(date).ok()) {
+            // HTTP dates have whole-second precision
+            Some(since) => self.last_modified.timestamp() <= since.timestamp(),
+            None => false,
+        }
+    }
+}
+
+impl TileService {
+    /// ETag from the tile's content hash and Last-Modified from when it was
+    /// rendered
+    pub fn validators(&self, tile: &Tile) -> TileValidators {
+        let bytes: Vec<u8> = tile.data.clone().into();
+        let last_modified = tile
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.created_at)
+            .unwrap_or_else(Utc::now);
+
+        TileValidators {
+            etag: format!("\"{:016x}\"", fnv1a(&bytes)),
+            // Drop sub-second precision so it round-trips through headers
+            last_modified: Utc
+                .timestamp_opt(last_modified.timestamp(), 0)
+                .single()
+                .unwrap_or(last_modified),
+        }
+    }
+
+    /// `Cache-Control` for tiles at `zoom`. Low zoom tiles cover large areas
+    /// and barely change when individual places are edited, so they are kept
+    /// longer; street-level tiles are revalidated more often.
+    pub fn cache_control(&self, zoom: u8) -> String {
+        let max_age = match zoom {
+            0..=8 => 7 * 24 * 3600,
+            9..=12 => 24 * 3600,
+            13..=15 => 6 * 3600,
+            _ => 3600,
+        };
+        cache_policy(max_age)
+    }
+
+    /// `Cache-Control` for tiles drawn from live data, such as heatmaps,
+    /// which are only fresh for `fresh_for` whatever their zoom
+    pub fn live_cache_control(&self, fresh_for: Duration) -> String {
+        cache_policy(fresh_for.as_secs().max(1))
+    }
+}
+
+/// Shared by every tile response, so all of them allow serving a stale tile
+/// for half its lifetime while it is revalidated
+fn cache_policy(max_age: u64) -> String {
+    format!("public, max-age={}, stale-while-revalidate={}", max_age, max_age / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::models::tile::{TileCoordinate, TileData, TileFormat};
+    use crate::services::tile_service::TileMetadata;
+
+    fn service() -> TileService {
+        TileService::new(Arc::new(Config::default())).unwrap()
+    }
+
+    fn tile(data: &[u8], created_at: DateTime<Utc>) -> Tile {
+        let coordinate = TileCoordinate { x: 2620, y: 6332, z: 14 };
+        Tile {
+            coordinate: coordinate.clone(),
+            format: TileFormat::Png,
+            data: TileData::from(data.to_vec()),
+            metadata: Some(TileMetadata {
+                coordinate,
+                format: TileFormat::Png,
+                created_at,
+                last_accessed: created_at,
+                size_bytes: data.len() as u64,
+                cache_key: "png_14_2620_6332".to_string(),
+            }),
+        }
+    }
+
+    fn validators() -> TileValidators {
+        TileValidators {
+            etag: "\"00000000075bcd15\"".to_string(),
+            last_modified: Utc.with_ymd_and_hms(2026, 10, 18, 13, 33, 17).unwrap(),
+        }
+    }
+
+    #[test]
+    fn etag_depends_only_on_the_tile_bytes() {
+        let service = service();
+        let rendered = Utc.with_ymd_and_hms(2026, 10, 18, 13, 33, 17).unwrap();
+        let etag = service.validators(&tile(b"tile", rendered)).etag;
+
+        assert_eq!(etag.len(), 18);
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(service.validators(&tile(b"tile", Utc::now())).etag, etag);
+        assert_ne!(service.validators(&tile(b"tile!", rendered)).etag, etag);
+    }
+
+    #[test]
+    fn last_modified_is_the_render_time_to_the_second() {
+        let rendered = Utc.with_ymd_and_hms(2026, 10, 18, 13, 33, 17).unwrap() + chrono::Duration::milliseconds(750);
+        let validators = service().validators(&tile(b"tile", rendered));
+
+        assert_eq!(validators.last_modified.timestamp_subsec_nanos(), 0);
+        assert_eq!(validators.last_modified_header(), "Sun, 18 Oct 2026 13:33:17 GMT");
+    }
+
+    #[test]
+    fn if_none_match_compares_entity_tags_weakly() {
+        let validators = validators();
+        for header in ["\"00000000075bcd15\"", "W/\"00000000075bcd15\"", "\"other\", \"00000000075bcd15\"", "*"] {
+            assert!(validators.not_modified(Some(header), None), "{header}");
+        }
+        assert!(!validators.not_modified(Some("\"other\""), None));
+        assert!(!validators.not_modified(Some("00000000075bcd15"), None));
+    }
+
+    #[test]
+    fn if_modified_since_is_ignored_when_if_none_match_is_sent() {
+        let validators = validators();
+        let later = "Mon, 19 Oct 2026 00:00:00 GMT";
+        assert!(validators.not_modified(None, Some(later)));
+        assert!(!validators.not_modified(Some("\"other\""), Some(later)));
+    }
+
+    #[test]
+    fn if_modified_since_compares_whole_seconds() {
+        let validators = validators();
+        let header = validators.last_modified_header();
+        assert!(validators.not_modified(None, Some(&header)));
+        assert!(validators.not_modified(None, Some("Sun, 18 Oct 2026 13:33:18 GMT")));
+        assert!(!validators.not_modified(None, Some("Sun, 18 Oct 2026 13:33:16 GMT")));
+        assert!(!validators.not_modified(None, Some("yesterday")));
+        assert!(!validators.not_modified(None, None));
+    }
+
+    #[test]
+    fn low_zoom_tiles_are_cached_longer() {
+        let service = service();
+        assert_eq!(service.cache_control(4), "public, max-age=604800, stale-while-revalidate=302400");
+        assert_eq!(service.cache_control(18), "public, max-age=3600, stale-while-revalidate=1800");
+
+        let max_age = |zoom| {
+            let policy = service.cache_control(zoom);
+            let value = policy.split(", ").find_map(|part| part.strip_prefix("max-age=")).unwrap().to_string();
+            value.parse::<u64>().unwrap()
+        };
+        assert!((0..18).all(|zoom| max_age(zoom) >= max_age(zoom + 1)));
+    }
+
+    #[test]
+    fn live_tiles_are_cached_only_while_fresh() {
+        let service = service();
+        assert_eq!(
+            service.live_cache_control(Duration::from_secs(300)),
+            "public, max-age=300, stale-while-revalidate=150"
+        );
+        assert_eq!(
+            service.live_cache_control(Duration::from_millis(200)),
+            "public, max-age=1, stale-while-revalidate=0"
+        );
+    }
+}
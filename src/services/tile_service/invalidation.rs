@@ -0,0 +1,223 @@
+//! Dropping cached tiles when the map data under them changes
+
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+use super::TileService;
+use googlemaps_clone::constants::TILE_SIZE;
+use crate::database::MapDataChanges;
+use crate::models::tile::{TileCoordinate, TileFormat};
+use crate::services::label_engine::TILE_BUFFER;
+use crate::utils::tile_utils::{LatLng, TileCoord};
+
+/// Web Mercator cuts off at this latitude
+const MAX_LATITUDE: f64 = 85.051_128_78;
+/// Changes touching more points than this are tracked by their bounding box
+/// rather than point by point
+const MAX_TRACKED_POINTS: usize = 1024;
+const RERENDER_CONCURRENCY: usize = 4;
+
+/// Tiles `min_x..=max_x` by `min_y..=max_y` at zoom `z`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileRange {
+    pub z: u8,
+    pub min_x: u32,
+    pub max_x: u32,
+    pub min_y: u32,
+    pub max_y: u32,
+}
+
+impl TileRange {
+    /// Tiles whose content can change when something inside the box changes.
+    /// Labels are placed up to `TILE_BUFFER` pixels beyond a tile's edge, so
+    /// the box is grown by that much at this zoom.
+    pub fn covering(south: f64, west: f64, north: f64, east: f64, zoom: u8) -> Self {
+        let (corner_a, corner_b) = LatLng::new(north, west).to_tile_coord(zoom).to_bounds();
+        let buffer = TILE_BUFFER as f64 / TILE_SIZE as f64;
+        let lat_buffer = (corner_a.lat - corner_b.lat).abs() * buffer;
+        let lng_buffer = (corner_a.lng - corner_b.lng).abs() * buffer;
+
+        let north_west = clamped(north + lat_buffer, west - lng_buffer).to_tile_coord(zoom);
+        let south_east = clamped(south - lat_buffer, east + lng_buffer).to_tile_coord(zoom);
+        let last = (1u32 << zoom) - 1;
+
+        Self {
+            z: zoom,
+            min_x: north_west.x.min(last),
+            max_x: south_east.x.min(last),
+            min_y: north_west.y.min(last),
+            max_y: south_east.y.min(last),
+        }
+    }
+
+    pub fn contains(&self, coord: &TileCoord) -> bool {
+        coord.z == self.z
+            && (self.min_x..=self.max_x).contains(&coord.x)
+            && (self.min_y..=self.max_y).contains(&coord.y)
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = TileCoord> + '_ {
+        (self.min_x..=self.max_x)
+            .flat_map(move |x| (self.min_y..=self.max_y).map(move |y| TileCoord::new(x, y, self.z)))
+    }
+}
+
+fn clamped(lat: f64, lng: f64) -> LatLng {
+    LatLng::new(lat.clamp(-MAX_LATITUDE, MAX_LATITUDE), lng.clamp(-180.0, 179.999_999))
+}
+
+/// The tiles at every served zoom level affected by a set of changed positions
+#[derive(Debug, Default)]
+pub struct AffectedTiles {
+    tiles: HashSet<TileCoord>,
+    areas: Vec<TileRange>,
+}
+
+impl AffectedTiles {
+    /// Tiles over `points` at each of `zooms`, which should span every level
+    /// the pyramid serves, so overzoomed and composited tiles go stale too
+    pub fn from_points(points: &[LatLng], zooms: RangeInclusive<u8>) -> Self {
+        let points: Vec<LatLng> = points.iter().copied().filter(LatLng::is_valid).collect();
+        let mut affected = Self::default();
+
+        if points.len() > MAX_TRACKED_POINTS {
+            let south = points.iter().map(|p| p.lat).fold(f64::INFINITY, f64::min);
+            let north = points.iter().map(|p| p.lat).fold(f64::NEG_INFINITY, f64::max);
+            let west = points.iter().map(|p| p.lng).fold(f64::INFINITY, f64::min);
+            let east = points.iter().map(|p| p.lng).fold(f64::NEG_INFINITY, f64::max);
+            affected.areas = zooms
+                .map(|zoom| TileRange::covering(south, west, north, east, zoom))
+                .collect();
+            return affected;
+        }
+
+        for point in &points {
+            for zoom in zooms.clone() {
+                let range = TileRange::covering(point.lat, point.lng, point.lat, point.lng, zoom);
+                affected.tiles.extend(range.tiles());
+            }
+        }
+        affected
+    }
+
+    pub fn contains(&self, coord: &TileCoord) -> bool {
+        self.tiles.contains(coord) || self.areas.iter().any(|area| area.contains(coord))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty() && self.areas.is_empty()
+    }
+}
+
+/// A tile dropped from the memory cache, kept so it can be re-rendered
+#[derive(Debug, Clone)]
+pub struct InvalidatedTile {
+    pub coordinate: TileCoordinate,
+    pub format: TileFormat,
+    pub cache_key: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InvalidationSummary {
+    pub memory: usize,
+    pub disk: usize,
+}
+
+impl TileService {
+    /// Drop every cached tile in `affected` from both tiers. Terrain tiles do
+    /// not depend on map data and are kept. Returns the tiles that were in
+    /// memory, which are the ones worth re-rendering.
+    pub async fn invalidate_tiles(&self, affected: &AffectedTiles) -> (InvalidationSummary, Vec<InvalidatedTile>) {
+        if affected.is_empty() {
+            return (InvalidationSummary::default(), Vec::new());
+        }
+
+        let stale: Vec<InvalidatedTile> = self
+            .memory_cache
+            .iter()
+            .filter(|(key, tile)| {
+                let coordinate = &tile.metadata.coordinate;
+                !super::terrain::is_terrain_key(key)
+                    && affected.contains(&TileCoord::new(coordinate.x, coordinate.y, coordinate.z))
+            })
+            .map(|(key, tile)| InvalidatedTile {
+                coordinate: tile.metadata.coordinate.clone(),
+                format: tile.metadata.format.clone(),
+                cache_key: key.to_string(),
+            })
+            .collect();
+        for tile in &stale {
+            self.memory_cache.invalidate(&tile.cache_key).await;
+        }
+
+        let disk = self
+            .disk_cache
+            .remove_where(|key, coordinate| !super::terrain::is_terrain_key(key) && affected.contains(coordinate))
+            .await;
+
+        let summary = InvalidationSummary { memory: stale.len(), disk };
+        debug!("Invalidated {} tiles in memory and {} on disk", summary.memory, summary.disk);
+        (summary, stale)
+    }
+
+    /// Re-render invalidated base map tiles so the next request is a cache
+    /// hit. Tiles drawn with a style that has since been edited are skipped.
+    pub fn rerender_tiles(self: &Arc<Self>, tiles: Vec<InvalidatedTile>) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            stream::iter(tiles)
+                .for_each_concurrent(RERENDER_CONCURRENCY, |tile| {
+                    let service = service.clone();
+                    async move {
+                        let Some((revision, scale)) = super::render::parse_styled_cache_key(&tile.cache_key) else {
+                            return;
+                        };
+                        let Some(style) = service.style_by_revision(revision) else {
+                            return;
+                        };
+                        if let Err(e) = service.get_styled_tile(&tile.coordinate, tile.format, style, scale).await {
+                            warn!("Failed to re-render tile {}: {}", tile.cache_key, e);
+                        }
+                    }
+                })
+                .await;
+        })
+    }
+
+    /// Invalidate tiles as `changes` reports edits to the map data, and
+    /// re-render the ones that were in memory when `rerender` is set
+    pub fn watch_data_changes(self: &Arc<Self>, mut changes: MapDataChanges, rerender: bool) -> JoinHandle<()> {
+        let service = self.clone();
+        let zooms = self.pyramid.served_zooms();
+        tokio::spawn(async move {
+            loop {
+                let batch = match changes.next_batch().await {
+                    Ok(batch) => batch,
+                    Err(e) => {
+                        error!("Stopped watching map data changes: {:#}", e);
+                        return;
+                    }
+                };
+
+                let points: Vec<LatLng> = batch.iter().flat_map(|change| change.points.iter().copied()).collect();
+                let (summary, stale) = service.invalidate_tiles(&AffectedTiles::from_points(&points, zooms.clone())).await;
+                info!(
+                    "{} map data changes invalidated {} cached tiles in memory and {} on disk",
+                    batch.len(),
+                    summary.memory,
+                    summary.disk
+                );
+
+                if rerender && !stale.is_empty() {
+                    service.rerender_tiles(stale);
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,351 @@
+//! Serving zoom levels outside the pre-rendered part of the tile pyramid
+
+use std::ops::RangeInclusive;
+
+use futures::future::{try_join_all, BoxFuture, FutureExt};
+use tiny_skia::{FilterQuality, Pixmap, PixmapPaint, Transform};
+
+use super::TileService;
+use googlemaps_clone::constants::{MAX_ZOOM, MIN_ZOOM};
+use crate::error::{MapError, Result};
+use crate::models::tile::{Tile, TileCoordinate, TileFormat};
+use crate::services::map_renderer::{decode, encode};
+use crate::utils::tile_utils::TileCoord;
+
+/// Produces the tile at a coordinate, or `None` when there is none
+pub(super) type TileFetch<'a> = dyn Fn(TileCoordinate) -> BoxFuture<'a, Result<Option<Tile>>> + Send + Sync + 'a;
+
+/// Levels below `min_zoom` that are composited; each level multiplies the
+/// stored tiles drawn for one request by four
+pub const MAX_COMPOSITE_LEVELS: u8 = 2;
+
+/// The zoom levels tiles are stored or rendered at. Requests above
+/// `max_zoom` are overzoomed from the nearest stored ancestor, at most
+/// `max_overzoom` levels up; requests below `min_zoom` are composited from
+/// their four children, at most `MAX_COMPOSITE_LEVELS` levels down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PyramidConfig {
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub max_overzoom: u8,
+}
+
+impl Default for PyramidConfig {
+    fn default() -> Self {
+        Self {
+            min_zoom: MIN_ZOOM,
+            max_zoom: MAX_ZOOM,
+            max_overzoom: 4,
+        }
+    }
+}
+
+/// How a tile at a given zoom is produced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PyramidPlan {
+    Stored,
+    Overzoom { ancestor: TileCoord },
+    Composite,
+    OutOfRange,
+}
+
+impl PyramidConfig {
+    /// Every zoom level a tile can be requested at, from the deepest
+    /// composited level to the last overzoomed one
+    pub fn served_zooms(&self) -> RangeInclusive<u8> {
+        self.min_zoom.saturating_sub(MAX_COMPOSITE_LEVELS)..=self.max_zoom.saturating_add(self.max_overzoom)
+    }
+
+    pub fn plan(&self, coord: TileCoord) -> PyramidPlan {
+        if coord.z > self.max_zoom {
+            let levels = coord.z - self.max_zoom;
+            if levels > self.max_overzoom {
+                return PyramidPlan::OutOfRange;
+            }
+            let ancestor = TileCoord::new(coord.x >> levels, coord.y >> levels, self.max_zoom);
+            PyramidPlan::Overzoom { ancestor }
+        } else if coord.z < self.min_zoom {
+            if self.min_zoom - coord.z > MAX_COMPOSITE_LEVELS {
+                return PyramidPlan::OutOfRange;
+            }
+            PyramidPlan::Composite
+        } else {
+            PyramidPlan::Stored
+        }
+    }
+}
+
+/// The four tiles one zoom level below `coordinate`, in reading order
+fn children(coordinate: &TileCoordinate) -> [TileCoordinate; 4] {
+    let (x, y, z) = (coordinate.x * 2, coordinate.y * 2, coordinate.z + 1);
+    [
+        TileCoordinate { x, y, z },
+        TileCoordinate { x: x + 1, y, z },
+        TileCoordinate { x, y: y + 1, z },
+        TileCoordinate { x: x + 1, y: y + 1, z },
+    ]
+}
+
+fn to_coordinate(coord: TileCoord) -> TileCoordinate {
+    TileCoordinate { x: coord.x, y: coord.y, z: coord.z }
+}
+
+/// Crop the part of `ancestor_image` covering `coord` and scale it back up to
+/// the ancestor's pixel size
+pub fn overzoom_image(ancestor_image: &[u8], ancestor: TileCoord, coord: TileCoord, format: &TileFormat) -> Result<Vec<u8>> {
+    let source = decode(ancestor_image).map_err(|e| MapError::IoError(e.to_string()))?;
+    let size = source.width();
+    let factor = (1u32 << (coord.z - ancestor.z)) as f32;
+    let span = size as f32 / factor;
+    let offset_x = (coord.x - (ancestor.x << (coord.z - ancestor.z))) as f32 * span;
+    let offset_y = (coord.y - (ancestor.y << (coord.z - ancestor.z))) as f32 * span;
+
+    let mut pixmap = Pixmap::new(size, source.height()).ok_or_else(|| MapError::IoError("Empty tile image".to_string()))?;
+    let paint = PixmapPaint {
+        quality: FilterQuality::Bicubic,
+        ..PixmapPaint::default()
+    };
+    let transform = Transform::from_row(factor, 0.0, 0.0, factor, -offset_x * factor, -offset_y * factor);
+    pixmap.draw_pixmap(0, 0, source.as_ref(), &paint, transform, None);
+
+    encode(&pixmap, format).map_err(|e| MapError::IoError(e.to_string()))
+}
+
+/// Draw four child tiles, in reading order, into one tile of the same pixel
+/// size. Missing children leave their quadrant transparent.
+pub fn composite_image(children: &[Option<Vec<u8>>], format: &TileFormat) -> Result<Vec<u8>> {
+    let decoded = children
+        .iter()
+        .map(|child| child.as_deref().map(decode).transpose())
+        .collect::<std::result::Result<Vec<Option<Pixmap>>, _>>()
+        .map_err(|e| MapError::IoError(e.to_string()))?;
+
+    let size = decoded
+        .iter()
+        .flatten()
+        .map(|child| child.width())
+        .next()
+        .ok_or_else(|| MapError::IoError("No child tiles to composite".to_string()))?;
+    let mut pixmap = Pixmap::new(size, size).ok_or_else(|| MapError::IoError("Empty tile image".to_string()))?;
+    let paint = PixmapPaint {
+        quality: FilterQuality::Bilinear,
+        ..PixmapPaint::default()
+    };
+
+    for (index, child) in decoded.iter().enumerate() {
+        let Some(child) = child else { continue };
+        let half = size as f32 / 2.0;
+        let scale = half / child.width() as f32;
+        let (column, row) = ((index % 2) as f32, (index / 2) as f32);
+        let transform = Transform::from_row(scale, 0.0, 0.0, scale, column * half, row * half);
+        pixmap.draw_pixmap(0, 0, child.as_ref(), &paint, transform, None);
+    }
+
+    encode(&pixmap, format).map_err(|e| MapError::IoError(e.to_string()))
+}
+
+impl TileService {
+    pub fn with_pyramid(mut self, pyramid: PyramidConfig) -> Self {
+        self.pyramid = pyramid;
+        self
+    }
+
+    pub fn pyramid(&self) -> PyramidConfig {
+        self.pyramid
+    }
+
+    /// `get_tile` extended to every zoom level: stored tiles are served as
+    /// they are, deeper zooms are cropped from a stored ancestor and shallower
+    /// ones are composited from their children
+    pub fn get_pyramid_tile<'a>(&'a self, coordinate: &TileCoordinate, format: TileFormat) -> BoxFuture<'a, Result<Option<Tile>>> {
+        let coordinate = coordinate.clone();
+        async move {
+            let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+            let cache_key = format!("pyramid/{}", self.generate_cache_key(&coordinate, format.clone()));
+
+            match self.pyramid.plan(coord) {
+                PyramidPlan::Stored => self.get_tile(&coordinate, format).await.map(Some),
+                PyramidPlan::OutOfRange => Ok(None),
+                PyramidPlan::Overzoom { ancestor } => {
+                    let stored = |ancestor: TileCoordinate| {
+                        let format = format.clone();
+                        async move { self.get_tile(&ancestor, format).await.map(Some) }.boxed()
+                    };
+                    self.overzoom_tile(&coordinate, format.clone(), cache_key, ancestor, &stored).await
+                }
+                PyramidPlan::Composite => {
+                    let child = |child: TileCoordinate| self.get_pyramid_tile(&child, format.clone());
+                    self.composite_tile(&coordinate, format.clone(), cache_key, &child).await
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Tile at `coordinate` cut from the `ancestor` tile produced by `fetch`
+    pub(super) async fn overzoom_tile(
+        &self,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+        cache_key: String,
+        ancestor: TileCoord,
+        fetch: &TileFetch<'_>,
+    ) -> Result<Option<Tile>> {
+        if let Some(tile) = self.lookup_cached_tile(coordinate, format.clone(), &cache_key).await {
+            return Ok(Some(tile));
+        }
+        let Some(parent) = fetch(to_coordinate(ancestor)).await? else {
+            return Ok(None);
+        };
+
+        let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+        let render_format = format.clone();
+        let image = tokio::task::spawn_blocking(move || {
+            let bytes: Vec<u8> = parent.data.into();
+            overzoom_image(&bytes, ancestor, coord, &render_format)
+        })
+        .await
+        .map_err(|e| MapError::IoError(format!("Overzoom task failed: {}", e)))??;
+
+        Ok(Some(self.store_cached_tile(coordinate, format, cache_key, image).await))
+    }
+
+    /// Tile at `coordinate` built from the four children produced by `fetch`.
+    /// `None` when none of the children exist.
+    pub(super) async fn composite_tile(
+        &self,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+        cache_key: String,
+        fetch: &TileFetch<'_>,
+    ) -> Result<Option<Tile>> {
+        if let Some(tile) = self.lookup_cached_tile(coordinate, format.clone(), &cache_key).await {
+            return Ok(Some(tile));
+        }
+
+        let children: Vec<Option<Vec<u8>>> = try_join_all(children(coordinate).map(|child| fetch(child)))
+            .await?
+            .into_iter()
+            .map(|tile| tile.map(|tile| tile.data.into()))
+            .collect();
+        if children.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+
+        let render_format = format.clone();
+        let image = tokio::task::spawn_blocking(move || composite_image(&children, &render_format))
+            .await
+            .map_err(|e| MapError::IoError(format!("Composite task failed: {}", e)))??;
+
+        Ok(Some(self.store_cached_tile(coordinate, format, cache_key, image).await))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_skia::{Color, Rect};
+
+    use super::*;
+
+    const PYRAMID: PyramidConfig = PyramidConfig {
+        min_zoom: 4,
+        max_zoom: 14,
+        max_overzoom: 2,
+    };
+
+    const RED: [u8; 4] = [0xff, 0x00, 0x00, 0xff];
+    const GREEN: [u8; 4] = [0x00, 0xff, 0x00, 0xff];
+    const BLUE: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+    const WHITE: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+
+    /// A `size` pixel PNG split into quadrants of the given colors, in
+    /// reading order
+    fn quadrants(size: u32, colors: [[u8; 4]; 4]) -> Vec<u8> {
+        let mut pixmap = Pixmap::new(size, size).unwrap();
+        let half = size as f32 / 2.0;
+        for (index, [r, g, b, a]) in colors.into_iter().enumerate() {
+            let (column, row) = ((index % 2) as f32, (index / 2) as f32);
+            let rect = Rect::from_xywh(column * half, row * half, half, half).unwrap();
+            let mut paint = tiny_skia::Paint::default();
+            paint.set_color(Color::from_rgba8(r, g, b, a));
+            pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+        }
+        encode(&pixmap, &TileFormat::Png).unwrap()
+    }
+
+    fn solid(size: u32, color: [u8; 4]) -> Vec<u8> {
+        quadrants(size, [color; 4])
+    }
+
+    fn pixel(png: &[u8], x: u32, y: u32) -> [u8; 4] {
+        let color = decode(png).unwrap().pixel(x, y).unwrap().demultiply();
+        [color.red(), color.green(), color.blue(), color.alpha()]
+    }
+
+    #[test]
+    fn serves_composited_stored_and_overzoomed_levels() {
+        assert_eq!(PYRAMID.served_zooms(), 2..=16);
+        let default = PyramidConfig::default();
+        assert_eq!(default.served_zooms(), MIN_ZOOM.saturating_sub(MAX_COMPOSITE_LEVELS)..=MAX_ZOOM + 4);
+    }
+
+    #[test]
+    fn plans_each_zoom_level() {
+        assert_eq!(PYRAMID.plan(TileCoord::new(5, 6, 4)), PyramidPlan::Stored);
+        assert_eq!(PYRAMID.plan(TileCoord::new(2620, 6332, 14)), PyramidPlan::Stored);
+        assert_eq!(
+            PYRAMID.plan(TileCoord::new(10483, 25331, 16)),
+            PyramidPlan::Overzoom { ancestor: TileCoord::new(2620, 6332, 14) }
+        );
+        assert_eq!(PYRAMID.plan(TileCoord::new(20966, 50662, 17)), PyramidPlan::OutOfRange);
+        assert_eq!(PYRAMID.plan(TileCoord::new(1, 1, 3)), PyramidPlan::Composite);
+        assert_eq!(PYRAMID.plan(TileCoord::new(0, 0, 2)), PyramidPlan::Composite);
+        assert_eq!(PYRAMID.plan(TileCoord::new(0, 0, 1)), PyramidPlan::OutOfRange);
+    }
+
+    #[test]
+    fn children_are_in_reading_order() {
+        let [top_left, top_right, bottom_left, bottom_right] = children(&TileCoordinate { x: 3, y: 5, z: 7 });
+        assert_eq!((top_left.x, top_left.y, top_left.z), (6, 10, 8));
+        assert_eq!((top_right.x, top_right.y), (7, 10));
+        assert_eq!((bottom_left.x, bottom_left.y), (6, 11));
+        assert_eq!((bottom_right.x, bottom_right.y, bottom_right.z), (7, 11, 8));
+    }
+
+    #[test]
+    fn overzoom_crops_and_enlarges_the_ancestor() {
+        let ancestor = TileCoord::new(3, 5, 7);
+        let image = quadrants(256, [RED, GREEN, BLUE, WHITE]);
+
+        let top_right = overzoom_image(&image, ancestor, TileCoord::new(7, 10, 8), &TileFormat::Png).unwrap();
+        assert_eq!(decode(&top_right).unwrap().width(), 256);
+        assert_eq!(pixel(&top_right, 128, 128), GREEN);
+
+        // Two levels down, the second column of the second row is still red
+        let inner = overzoom_image(&image, ancestor, TileCoord::new(13, 21, 9), &TileFormat::Png).unwrap();
+        assert_eq!(pixel(&inner, 32, 32), RED);
+        assert_eq!(pixel(&inner, 224, 224), RED);
+    }
+
+    #[test]
+    fn composites_children_into_quadrants() {
+        let children = [Some(solid(256, RED)), Some(solid(256, GREEN)), Some(solid(256, BLUE)), None];
+        let image = composite_image(&children, &TileFormat::Png).unwrap();
+
+        assert_eq!(decode(&image).unwrap().width(), 256);
+        assert_eq!(pixel(&image, 64, 64), RED);
+        assert_eq!(pixel(&image, 192, 64), GREEN);
+        assert_eq!(pixel(&image, 64, 192), BLUE);
+        assert_eq!(pixel(&image, 192, 192)[3], 0, "a missing child leaves its quadrant transparent");
+    }
+
+    #[test]
+    fn composite_keeps_the_children_pixel_size() {
+        let children = [Some(solid(512, RED)), None, None, Some(solid(512, BLUE))];
+        let image = composite_image(&children, &TileFormat::Png).unwrap();
+        assert_eq!(decode(&image).unwrap().width(), 512);
+        assert_eq!(pixel(&image, 384, 384), BLUE);
+
+        assert!(composite_image(&[None, None, None, None], &TileFormat::Png).is_err());
+    }
+}
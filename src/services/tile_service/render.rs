@@ -0,0 +1,275 @@
+//! Raster rendering of base map tiles
+
+use std::future::Future;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use tracing::debug;
+
+use super::pyramid::PyramidPlan;
+use super::TileService;
+use googlemaps_clone::constants::MAX_TILE_SCALE;
+use crate::database::{Database, MapBounds};
+use crate::error::{MapError, Result};
+use crate::models::map_tile::{Label, LabelType, TileMetadata as TileContents};
+use crate::models::tile::{Tile, TileCoordinate, TileFormat};
+use crate::services::map_renderer::{MapRenderer, Viewport};
+use crate::services::map_style::{StyleRegistry, StyleSheet};
+use crate::utils::geo::{BoundingBox, LatLng};
+use crate::utils::tile_utils::TileCoord;
+
+/// Most locations labeled on one tile; the best rated are kept
+const MAX_TILE_LOCATIONS: i64 = 200;
+/// Locations this far outside a tile, as a fraction of its size, are included
+/// so labels crossing the edge are drawn on both sides of it
+const LOCATION_BUFFER: f64 = 0.25;
+const LOCATION_LABEL_SIZE: f32 = 12.0;
+const LOCATION_LABEL_COLOR: &str = "#333333";
+const LOCATION_LABEL_FONT: &str = "DejaVu Sans";
+
+/// Supplies the map data drawn into raster tiles
+pub trait TileContentSource: Send + Sync {
+    fn tile_contents(&self, coord: TileCoord) -> BoxFuture<'_, Result<TileContents>>;
+}
+
+/// Tiles drawn from the database: shared locations are labeled by name
+impl TileContentSource for Database {
+    fn tile_contents(&self, coord: TileCoord) -> BoxFuture<'_, Result<TileContents>> {
+        async move {
+            let north_west = coord.to_lat_lng();
+            let south_east = TileCoord::new(coord.x + 1, coord.y + 1, coord.z).to_lat_lng();
+            let bounds = BoundingBox::new(north_west.lat, south_east.lat, south_east.lng, north_west.lng);
+
+            let (lat_buffer, lng_buffer) = (
+                (bounds.north - bounds.south) * LOCATION_BUFFER,
+                (bounds.east - bounds.west) * LOCATION_BUFFER,
+            );
+            let search = MapBounds {
+                north: bounds.north + lat_buffer,
+                south: bounds.south - lat_buffer,
+                east: bounds.east + lng_buffer,
+                west: bounds.west - lng_buffer,
+            };
+
+            let locations = self
+                .tile_locations(&search, MAX_TILE_LOCATIONS)
+                .await
+                .map_err(|e| MapError::IoError(format!("{:#}", e)))?;
+
+            let labels = locations
+                .into_iter()
+                .map(|location| Label {
+                    id: location.id.to_string(),
+                    text: location.name,
+                    position: LatLng::new(location.latitude, location.longitude),
+                    label_type: LabelType::Poi,
+                    font_size: LOCATION_LABEL_SIZE,
+                    font_family: LOCATION_LABEL_FONT.to_string(),
+                    color: LOCATION_LABEL_COLOR.to_string(),
+                })
+                .collect();
+
+            Ok(TileContents {
+                bounds,
+                features: Vec::new(),
+                roads: Vec::new(),
+                labels,
+                pois: Vec::new(),
+                style_version: String::new(),
+                data_version: String::new(),
+            })
+        }
+        .boxed()
+    }
+}
+
+/// Split a key built by `styled_cache_key` into the style revision and scale
+pub(super) fn parse_styled_cache_key(key: &str) -> Option<(&str, u8)> {
+    let mut parts = key.splitn(3, '/');
+    let revision = parts.next()?;
+    let scale = parts.next()?.strip_prefix('@')?.strip_suffix('x')?.parse().ok()?;
+    parts.next()?;
+    Some((revision, scale))
+}
+
+impl TileService {
+    pub fn with_source(mut self, source: Arc<dyn TileContentSource>) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn with_styles(mut self, styles: Arc<StyleRegistry>) -> Self {
+        self.styles = Some(styles);
+        self
+    }
+
+    /// The named style, or the default style when `name` is `None`
+    pub fn style(&self, name: Option<&str>) -> Option<Arc<StyleSheet>> {
+        self.styles.as_ref()?.get(name)
+    }
+
+    pub fn style_names(&self) -> Vec<String> {
+        self.styles.as_ref().map(|styles| styles.names()).unwrap_or_default()
+    }
+
+    /// Cache key for a tile drawn with `style` at `scale`; editing a style
+    /// changes its revision, so stale renders are never served
+    pub fn styled_cache_key(&self, coordinate: &TileCoordinate, format: TileFormat, style: &StyleSheet, scale: u8) -> String {
+        format!("{}/@{}x/{}", style.revision, scale, self.generate_cache_key(coordinate, format))
+    }
+
+    /// The loaded style whose current revision is `revision`
+    pub fn style_by_revision(&self, revision: &str) -> Option<Arc<StyleSheet>> {
+        let styles = self.styles.as_ref()?;
+        styles
+            .names()
+            .iter()
+            .filter_map(|name| styles.get(Some(name)))
+            .find(|style| style.revision == revision)
+    }
+
+    /// Base map tile drawn with `style`, `TILE_SIZE * scale` pixels square.
+    /// Zooms past the pyramid's `max_zoom` are drawn from the data of the
+    /// stored ancestor, and zooms below `min_zoom` are composited from their
+    /// children. `None` when no content source is configured.
+    pub fn get_styled_tile<'a>(
+        &'a self,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+        style: Arc<StyleSheet>,
+        scale: u8,
+    ) -> BoxFuture<'a, Result<Option<Tile>>> {
+        let coordinate = coordinate.clone();
+        async move {
+            if self.source.is_none() {
+                return Ok(None);
+            }
+
+            let scale = scale.clamp(1, MAX_TILE_SCALE);
+            let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+            match self.pyramid.plan(coord) {
+                PyramidPlan::Stored => self.render_styled_tile(&coordinate, coord, format, style, scale).await.map(Some),
+                PyramidPlan::Overzoom { ancestor } => {
+                    self.render_styled_tile(&coordinate, ancestor, format, style, scale).await.map(Some)
+                }
+                PyramidPlan::OutOfRange => Ok(None),
+                PyramidPlan::Composite => {
+                    let cache_key = self.styled_cache_key(&coordinate, format.clone(), &style, scale);
+                    let child = |child: TileCoordinate| self.get_styled_tile(&child, format.clone(), style.clone(), scale);
+                    self.composite_tile(&coordinate, format.clone(), cache_key, &child).await
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Draw `coordinate` from the map data of `data_coord`, which is the tile
+    /// itself or, when overzooming, an ancestor whose data covers it
+    async fn render_styled_tile(
+        &self,
+        coordinate: &TileCoordinate,
+        data_coord: TileCoord,
+        format: TileFormat,
+        style: Arc<StyleSheet>,
+        scale: u8,
+    ) -> Result<Tile> {
+        let Some(source) = self.source.clone() else {
+            return Err(MapError::IoError("No tile content source is configured".to_string()));
+        };
+
+        let cache_key = self.styled_cache_key(coordinate, format.clone(), &style, scale);
+        let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+        let renderer = self.renderer.clone();
+        let render_format = format.clone();
+
+        self.get_or_render(coordinate, format, cache_key, async move {
+            let contents = source.tile_contents(data_coord).await?;
+            tokio::task::spawn_blocking(move || {
+                render_tile_image(&renderer, coord, data_coord, &contents, Some(&style), &render_format, scale)
+            })
+            .await
+            .map_err(|e| MapError::IoError(format!("Tile render task failed: {}", e)))?
+        })
+        .await
+    }
+
+    /// Stored tile drawn in the default style, for `get_tile` on a cache miss
+    pub(super) async fn render_default_tile(
+        &self,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+        cache_key: String,
+    ) -> Result<Tile> {
+        let Some(source) = self.source.clone() else {
+            return Err(MapError::IoError("No tile content source is configured".to_string()));
+        };
+
+        let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+        let style = self.styles.as_ref().and_then(|styles| styles.get(None));
+        let renderer = self.renderer.clone();
+        let render_format = format.clone();
+
+        let contents = source.tile_contents(coord).await?;
+        let image = tokio::task::spawn_blocking(move || {
+            render_tile_image(&renderer, coord, coord, &contents, style.as_deref(), &render_format, 1)
+        })
+        .await
+        .map_err(|e| MapError::IoError(format!("Tile render task failed: {}", e)))??;
+
+        Ok(self.store_cached_tile(coordinate, format, cache_key, image).await)
+    }
+
+    /// Serve `cache_key` from the memory or disk cache, or produce it with
+    /// `render` and cache the result in both
+    pub(super) async fn get_or_render<F>(
+        &self,
+        coordinate: &TileCoordinate,
+        format: TileFormat,
+        cache_key: String,
+        render: F,
+    ) -> Result<Tile>
+    where
+        F: Future<Output = Result<Vec<u8>>>,
+    {
+        if let Some(tile) = self.lookup_cached_tile(coordinate, format.clone(), &cache_key).await {
+            return Ok(tile);
+        }
+
+        let data = render.await?;
+        let tile = self.store_cached_tile(coordinate, format, cache_key, data).await;
+        Ok(tile)
+    }
+}
+
+/// Draw `contents`, the map data of `data_coord`, into the tile at `coord` and
+/// encode it in `format`. Runs on the calling thread, so async callers move it
+/// to the blocking pool.
+fn render_tile_image(
+    renderer: &MapRenderer,
+    coord: TileCoord,
+    data_coord: TileCoord,
+    contents: &TileContents,
+    style: Option<&StyleSheet>,
+    format: &TileFormat,
+    scale: u8,
+) -> Result<Vec<u8>> {
+    let viewport = Viewport::for_tile(coord, scale.clamp(1, MAX_TILE_SCALE) as f32);
+    let image = renderer
+        .render_encoded(&viewport, contents, style, format)
+        .map_err(|e| MapError::IoError(format!("Failed to render tile: {}", e)))?;
+
+    debug!(
+        "Rendered {}/{}/{}@{}x from {}/{}/{} with {} features and {} roads ({} bytes)",
+        coord.z,
+        coord.x,
+        coord.y,
+        scale,
+        data_coord.z,
+        data_coord.x,
+        data_coord.y,
+        contents.features.len(),
+        contents.roads.len(),
+        image.len()
+    );
+    Ok(image)
+}
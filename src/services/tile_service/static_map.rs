@@ -0,0 +1,531 @@
+//! Static map images: base map tiles stitched into one picture with markers
+//! and paths drawn on top
+
+use std::sync::Arc;
+
+use futures::stream::{self, StreamExt};
+use tiny_skia::{Color, FillRule, LineCap, LineJoin, Paint, PathBuilder, Pixmap, PixmapPaint, Stroke, Transform};
+use tracing::warn;
+
+use super::TileService;
+use googlemaps_clone::constants::{DEFAULT_LAT, DEFAULT_LNG, DEFAULT_ZOOM, MAX_TILE_SCALE, MAX_ZOOM, MIN_ZOOM, TILE_SIZE};
+use crate::error::{MapError, Result};
+use crate::models::tile::{TileCoordinate, TileFormat};
+use crate::services::label_engine::TextPaint;
+use crate::services::map_renderer::{decode, encode, MapRenderer, Viewport};
+use crate::utils::tile_utils::{LatLng, PixelCoord};
+
+/// Largest static map, in CSS pixels along either side
+pub const MAX_STATIC_MAP_SIZE: u32 = 2048;
+/// Largest static map in device pixels, `width * height * scale²`
+pub const MAX_STATIC_MAP_PIXELS: u64 = 4096 * 4096;
+/// Most markers one static map draws
+pub const MAX_STATIC_MARKERS: usize = 256;
+/// Most path points one static map draws, over all of its paths
+pub const MAX_STATIC_PATH_POINTS: usize = 4096;
+/// Zoom used when fitting bounds that are a single point or very small
+pub const MAX_FIT_ZOOM: u8 = 16;
+/// Space kept between fitted overlays and the image edge, in CSS pixels
+pub const DEFAULT_PADDING: f64 = 32.0;
+
+const TILE_FETCH_CONCURRENCY: usize = 8;
+/// Web Mercator cuts off at this latitude
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+/// Pin head radius and height of the pin tip below the head, in CSS pixels
+const MARKER_RADIUS: f32 = 10.0;
+const MARKER_HEIGHT: f32 = 26.0;
+const MARKER_LABEL_SIZE: f32 = 11.0;
+
+/// What part of the world the image shows
+#[derive(Debug, Clone, Copy)]
+pub enum StaticView {
+    Center { center: LatLng, zoom: u8 },
+    /// The highest zoom at which the box fits, centred on it
+    Bounds { south_west: LatLng, north_east: LatLng },
+    /// Fit every marker and path, or the default view when there are none
+    Auto,
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticMarker {
+    pub position: LatLng,
+    pub color: Color,
+    /// Short text drawn inside the pin, usually one character
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticPath {
+    pub points: Vec<LatLng>,
+    pub color: Color,
+    /// Line width in CSS pixels
+    pub weight: f32,
+    /// Fills the path as a polygon when set
+    pub fill: Option<Color>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticMapRequest {
+    pub view: StaticView,
+    /// Size in CSS pixels
+    pub width: u32,
+    pub height: u32,
+    /// Device pixel ratio
+    pub scale: u8,
+    pub style: Option<String>,
+    pub markers: Vec<StaticMarker>,
+    pub paths: Vec<StaticPath>,
+    /// Margin kept free when fitting bounds, in CSS pixels
+    pub padding: f64,
+}
+
+fn clamp_latitude(position: &LatLng) -> LatLng {
+    LatLng::new(position.lat.clamp(-MAX_LATITUDE, MAX_LATITUDE), position.lng)
+}
+
+/// Zoom and world pixel centre at which the box from `south_west` to
+/// `north_east` fits in `width` by `height` pixels with `padding` on each
+/// side. Boxes whose west edge is east of their east edge cross the
+/// antimeridian.
+pub fn fit_bounds(south_west: &LatLng, north_east: &LatLng, width: u32, height: u32, padding: f64) -> (PixelCoord, u8) {
+    let available_width = (width as f64 - 2.0 * padding).max(1.0);
+    let available_height = (height as f64 - 2.0 * padding).max(1.0);
+
+    let fit_at = |zoom: u8| {
+        let south_west = clamp_latitude(south_west).to_pixel_coord(zoom);
+        let north_east = clamp_latitude(north_east).to_pixel_coord(zoom);
+        let world = TILE_SIZE as f64 * 2_f64.powi(zoom as i32);
+        let mut span_x = north_east.x - south_west.x;
+        if span_x < 0.0 {
+            span_x += world;
+        }
+        let span_y = south_west.y - north_east.y;
+        let center = PixelCoord {
+            x: (south_west.x + span_x / 2.0).rem_euclid(world),
+            y: north_east.y + span_y / 2.0,
+        };
+        (center, span_x <= available_width && span_y <= available_height)
+    };
+
+    for zoom in (MIN_ZOOM..=MAX_FIT_ZOOM.min(MAX_ZOOM)).rev() {
+        let (center, fits) = fit_at(zoom);
+        if fits {
+            return (center, zoom);
+        }
+    }
+    (fit_at(MIN_ZOOM).0, MIN_ZOOM)
+}
+
+/// The box around every marker and path point
+fn overlay_bounds(markers: &[StaticMarker], paths: &[StaticPath]) -> Option<(LatLng, LatLng)> {
+    let points: Vec<&LatLng> = markers
+        .iter()
+        .map(|marker| &marker.position)
+        .chain(paths.iter().flat_map(|path| path.points.iter()))
+        .collect();
+    if points.is_empty() {
+        return None;
+    }
+
+    let south = points.iter().map(|p| p.lat).fold(f64::INFINITY, f64::min);
+    let north = points.iter().map(|p| p.lat).fold(f64::NEG_INFINITY, f64::max);
+    let west = points.iter().map(|p| p.lng).fold(f64::INFINITY, f64::min);
+    let east = points.iter().map(|p| p.lng).fold(f64::NEG_INFINITY, f64::max);
+    Some((LatLng::new(south, west), LatLng::new(north, east)))
+}
+
+impl StaticMapRequest {
+    /// Size of the image in device pixels
+    pub fn device_pixels(&self) -> u64 {
+        let scale = self.scale.clamp(1, MAX_TILE_SCALE) as u64;
+        self.width as u64 * self.height as u64 * scale * scale
+    }
+
+    /// Points over all of the paths
+    pub fn path_points(&self) -> usize {
+        self.paths.iter().map(|path| path.points.len()).sum()
+    }
+
+    /// The area drawn, with its top-left corner on a whole CSS pixel so
+    /// tiles land on the device pixel grid
+    pub fn viewport(&self) -> Viewport {
+        let (center, zoom) = match self.view {
+            StaticView::Center { center, zoom } => (clamp_latitude(&center).to_pixel_coord(zoom), zoom),
+            StaticView::Bounds { south_west, north_east } => {
+                fit_bounds(&south_west, &north_east, self.width, self.height, self.padding)
+            }
+            StaticView::Auto => match overlay_bounds(&self.markers, &self.paths) {
+                Some((south_west, north_east)) => {
+                    // Leave room above the topmost point for a pin
+                    let padding = self.padding.max(if self.markers.is_empty() { 0.0 } else { MARKER_HEIGHT as f64 });
+                    fit_bounds(&south_west, &north_east, self.width, self.height, padding)
+                }
+                None => (LatLng::new(DEFAULT_LAT, DEFAULT_LNG).to_pixel_coord(DEFAULT_ZOOM), DEFAULT_ZOOM),
+            },
+        };
+
+        Viewport {
+            origin: PixelCoord {
+                x: (center.x - self.width as f64 / 2.0).round(),
+                y: (center.y - self.height as f64 / 2.0).round(),
+            },
+            zoom,
+            width: self.width,
+            height: self.height,
+            scale: self.scale as f32,
+        }
+    }
+}
+
+/// Device pixel position of `position` in `viewport`. Longitudes are moved
+/// by whole worlds to the copy nearest the viewport's centre, so overlays
+/// near the antimeridian are drawn where the map shows them.
+fn to_device(viewport: &Viewport, position: &LatLng) -> (f32, f32) {
+    let pixel = clamp_latitude(position).to_pixel_coord(viewport.zoom);
+    let world = TILE_SIZE as f64 * 2_f64.powi(viewport.zoom as i32);
+    let center_x = viewport.origin.x + viewport.width as f64 / 2.0;
+    let x = pixel.x + ((center_x - pixel.x) / world).round() * world;
+
+    (
+        ((x - viewport.origin.x) * viewport.scale as f64) as f32,
+        ((pixel.y - viewport.origin.y) * viewport.scale as f64) as f32,
+    )
+}
+
+fn solid(color: Color) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color(color);
+    paint.anti_alias = true;
+    paint
+}
+
+fn draw_path(pixmap: &mut Pixmap, viewport: &Viewport, path: &StaticPath) {
+    let mut builder = PathBuilder::new();
+    for (index, point) in path.points.iter().enumerate() {
+        let (x, y) = to_device(viewport, point);
+        if index == 0 {
+            builder.move_to(x, y);
+        } else {
+            builder.line_to(x, y);
+        }
+    }
+    if path.fill.is_some() {
+        builder.close();
+    }
+    let Some(line) = builder.finish() else { return };
+
+    if let Some(fill) = path.fill {
+        pixmap.fill_path(&line, &solid(fill), FillRule::EvenOdd, Transform::identity(), None);
+    }
+    let stroke = Stroke {
+        width: path.weight * viewport.scale,
+        line_cap: LineCap::Round,
+        line_join: LineJoin::Round,
+        ..Stroke::default()
+    };
+    pixmap.stroke_path(&line, &solid(path.color), &stroke, Transform::identity(), None);
+}
+
+/// A pin whose tip is on the marker's position
+fn draw_marker(pixmap: &mut Pixmap, viewport: &Viewport, marker: &StaticMarker, renderer: &MapRenderer) {
+    let scale = viewport.scale;
+    let (x, y) = to_device(viewport, &marker.position);
+    let radius = MARKER_RADIUS * scale;
+    let head_y = y - (MARKER_HEIGHT - MARKER_RADIUS) * scale;
+
+    let mut builder = PathBuilder::new();
+    builder.move_to(x, y);
+    builder.line_to(x - radius * 0.7, head_y + radius * 0.7);
+    builder.line_to(x + radius * 0.7, head_y + radius * 0.7);
+    builder.close();
+    builder.push_circle(x, head_y, radius);
+    let Some(pin) = builder.finish() else { return };
+
+    // The outline is stroked first and the fill covers its inner half, so
+    // the edges where the head and tip overlap don't show
+    let outline = Stroke {
+        width: 3.0 * scale,
+        line_join: LineJoin::Round,
+        ..Stroke::default()
+    };
+    pixmap.stroke_path(&pin, &solid(Color::from_rgba8(0x3c, 0x3c, 0x3c, 0xff)), &outline, Transform::identity(), None);
+    pixmap.fill_path(&pin, &solid(marker.color), FillRule::Winding, Transform::identity(), None);
+
+    match marker.label.as_deref().filter(|label| !label.is_empty()) {
+        Some(label) => {
+            let paint = TextPaint {
+                color: Some(Color::WHITE),
+                halo: marker.color,
+                halo_width: 0.0,
+                size: Some(MARKER_LABEL_SIZE),
+                filter: None,
+            };
+            let text = renderer.label_engine().centered_text(label, (x, head_y), &paint, scale);
+            renderer.label_engine().draw(pixmap, &[text]);
+        }
+        None => {
+            if let Some(dot) = PathBuilder::from_circle(x, head_y, radius * 0.35) {
+                pixmap.fill_path(&dot, &solid(Color::WHITE), FillRule::Winding, Transform::identity(), None);
+            }
+        }
+    }
+}
+
+impl TileService {
+    /// Render `request` as a PNG. The base map comes from the same tiles
+    /// `get_styled_tile` serves, or from the upstream when proxying; paths
+    /// are drawn over it and markers on top.
+    pub async fn render_static_map(self: &Arc<Self>, request: &StaticMapRequest) -> Result<Vec<u8>> {
+        if request.width == 0 || request.height == 0 || request.width > MAX_STATIC_MAP_SIZE || request.height > MAX_STATIC_MAP_SIZE {
+            return Err(MapError::IoError(format!(
+                "Static maps must be between 1 and {} pixels per side",
+                MAX_STATIC_MAP_SIZE
+            )));
+        }
+        let scale = request.scale.clamp(1, MAX_TILE_SCALE);
+        if request.device_pixels() > MAX_STATIC_MAP_PIXELS {
+            return Err(MapError::IoError(format!(
+                "Static maps must be at most {} device pixels",
+                MAX_STATIC_MAP_PIXELS
+            )));
+        }
+        if request.markers.len() > MAX_STATIC_MARKERS || request.path_points() > MAX_STATIC_PATH_POINTS {
+            return Err(MapError::IoError(format!(
+                "Static maps draw at most {} markers and {} path points",
+                MAX_STATIC_MARKERS, MAX_STATIC_PATH_POINTS
+            )));
+        }
+        let viewport = Viewport {
+            scale: scale as f32,
+            ..request.viewport()
+        };
+
+        let style = match self.upstream {
+            Some(_) => None,
+            None => Some(
+                self.style(request.style.as_deref())
+                    .ok_or_else(|| MapError::IoError(format!("Unknown style: {}", request.style.as_deref().unwrap_or("default"))))?,
+            ),
+        };
+
+        // Tiles under the viewport, with the device pixel offset each is drawn at
+        let tile_size = TILE_SIZE as f64;
+        let tiles_per_side = 1i64 << viewport.zoom;
+        let first_x = (viewport.origin.x / tile_size).floor() as i64;
+        let last_x = ((viewport.origin.x + viewport.width as f64 - 1.0) / tile_size).floor() as i64;
+        let first_y = ((viewport.origin.y / tile_size).floor() as i64).max(0);
+        let last_y = (((viewport.origin.y + viewport.height as f64 - 1.0) / tile_size).floor() as i64).min(tiles_per_side - 1);
+        let placements: Vec<(TileCoordinate, i32, i32)> = (first_y..=last_y)
+            .flat_map(|y| (first_x..=last_x).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let coordinate = TileCoordinate {
+                    x: x.rem_euclid(tiles_per_side) as u32,
+                    y: y as u32,
+                    z: viewport.zoom,
+                };
+                let offset_x = ((x as f64 * tile_size - viewport.origin.x) * scale as f64).round() as i32;
+                let offset_y = ((y as f64 * tile_size - viewport.origin.y) * scale as f64).round() as i32;
+                (coordinate, offset_x, offset_y)
+            })
+            .collect();
+
+        let tiles: Vec<(Option<Pixmap>, i32, i32)> = stream::iter(placements)
+            .map(|(coordinate, offset_x, offset_y)| {
+                let style = style.clone();
+                async move {
+                    let tile = match style {
+                        Some(style) => self.get_styled_tile(&coordinate, TileFormat::Png, style, scale).await,
+                        None => self.get_proxied_tile(&coordinate, scale).await,
+                    };
+                    let image = match tile {
+                        Ok(Some(tile)) => decode(&Vec::<u8>::from(tile.data)).ok(),
+                        Ok(None) => None,
+                        Err(e) => {
+                            warn!("Static map is missing tile {}/{}/{}: {}", coordinate.z, coordinate.x, coordinate.y, e);
+                            None
+                        }
+                    };
+                    (image, offset_x, offset_y)
+                }
+            })
+            .buffer_unordered(TILE_FETCH_CONCURRENCY)
+            .collect()
+            .await;
+
+        let renderer = self.renderer.clone();
+        let markers = request.markers.clone();
+        let paths = request.paths.clone();
+        tokio::task::spawn_blocking(move || {
+            let (width, height) = (viewport.pixel_width(), viewport.pixel_height());
+            let mut pixmap = Pixmap::new(width, height).ok_or_else(|| MapError::IoError("Empty static map".to_string()))?;
+
+            let tile_pixels = TILE_SIZE as f32 * scale as f32;
+            for (image, x, y) in tiles.iter() {
+                let Some(image) = image else { continue };
+                // Upstream tiles may not come at the requested density
+                let fit = tile_pixels / image.width() as f32;
+                let transform = Transform::from_row(fit, 0.0, 0.0, fit, *x as f32, *y as f32);
+                pixmap.draw_pixmap(0, 0, image.as_ref(), &PixmapPaint::default(), transform, None);
+            }
+
+            for path in &paths {
+                draw_path(&mut pixmap, &viewport, path);
+            }
+            for marker in &markers {
+                draw_marker(&mut pixmap, &viewport, marker, &renderer);
+            }
+
+            encode(&pixmap, &TileFormat::Png).map_err(|e| MapError::IoError(format!("Failed to encode static map: {}", e)))
+        })
+        .await
+        .map_err(|e| MapError::IoError(format!("Static map task failed: {}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn request(view: StaticView) -> StaticMapRequest {
+        StaticMapRequest {
+            view,
+            width: 400,
+            height: 300,
+            scale: 1,
+            style: None,
+            markers: Vec::new(),
+            paths: Vec::new(),
+            padding: DEFAULT_PADDING,
+        }
+    }
+
+    fn marker(lat: f64, lng: f64) -> StaticMarker {
+        StaticMarker {
+            position: LatLng::new(lat, lng),
+            color: Color::BLACK,
+            label: None,
+        }
+    }
+
+    fn path(points: usize) -> StaticPath {
+        StaticPath {
+            points: (0..points).map(|i| LatLng::new(52.0, 13.0 + i as f64 * 0.001)).collect(),
+            color: Color::BLACK,
+            weight: 2.0,
+            fill: None,
+        }
+    }
+
+    #[test]
+    fn counts_device_pixels_at_the_clamped_scale() {
+        let mut request = request(StaticView::Auto);
+        assert_eq!(request.device_pixels(), 400 * 300);
+        request.scale = 2;
+        assert_eq!(request.device_pixels(), 400 * 300 * 4);
+        request.scale = 0;
+        assert_eq!(request.device_pixels(), 400 * 300);
+        request.scale = MAX_TILE_SCALE + 5;
+        let max = MAX_TILE_SCALE as u64;
+        assert_eq!(request.device_pixels(), 400 * 300 * max * max);
+
+        // The largest side at 2x is exactly at the pixel limit, 3x is over it
+        request.width = MAX_STATIC_MAP_SIZE;
+        request.height = MAX_STATIC_MAP_SIZE;
+        request.scale = 2;
+        assert_eq!(request.device_pixels(), MAX_STATIC_MAP_PIXELS);
+        request.scale = 3;
+        assert!(request.device_pixels() > MAX_STATIC_MAP_PIXELS);
+    }
+
+    #[test]
+    fn counts_path_points_over_every_path() {
+        let mut request = request(StaticView::Auto);
+        assert_eq!(request.path_points(), 0);
+        request.paths = vec![path(2), path(5), path(3)];
+        assert_eq!(request.path_points(), 10);
+    }
+
+    #[test]
+    fn fits_a_single_point_at_the_max_fit_zoom() {
+        let point = LatLng::new(52.52, 13.40);
+        let (center, zoom) = fit_bounds(&point, &point, 400, 300, DEFAULT_PADDING);
+        assert_eq!(zoom, MAX_FIT_ZOOM);
+        let expected = point.to_pixel_coord(MAX_FIT_ZOOM);
+        assert!((center.x - expected.x).abs() < 1e-6 && (center.y - expected.y).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fits_boxes_across_the_antimeridian() {
+        let (_, zoom) = fit_bounds(&LatLng::new(-10.0, -10.0), &LatLng::new(10.0, 10.0), 300, 300, DEFAULT_PADDING);
+        let (center, crossing_zoom) =
+            fit_bounds(&LatLng::new(-10.0, 170.0), &LatLng::new(10.0, -170.0), 300, 300, DEFAULT_PADDING);
+        assert_eq!(zoom, 4);
+        assert_eq!(crossing_zoom, zoom, "a 20° box fits the same way on either side");
+
+        // Centred on the antimeridian, which is the left edge of the world
+        let world = TILE_SIZE as f64 * 2_f64.powi(crossing_zoom as i32);
+        assert!(center.x.min(world - center.x) < 1.0, "centre x is {}", center.x);
+
+        // Padding that leaves no room falls back to the lowest zoom
+        let (_, zoom) = fit_bounds(&LatLng::new(-60.0, -170.0), &LatLng::new(60.0, 170.0), 100, 100, 50.0);
+        assert_eq!(zoom, MIN_ZOOM);
+    }
+
+    #[test]
+    fn auto_view_fits_every_overlay() {
+        let viewport = request(StaticView::Auto).viewport();
+        assert_eq!(viewport.zoom, DEFAULT_ZOOM, "no overlays shows the default view");
+
+        let mut request = request(StaticView::Auto);
+        request.markers = vec![marker(52.52, 13.40), marker(52.40, 13.10)];
+        request.paths = vec![StaticPath {
+            points: vec![LatLng::new(52.45, 13.60), LatLng::new(52.50, 13.20)],
+            ..path(0)
+        }];
+        let viewport = request.viewport();
+        assert!(viewport.zoom > MIN_ZOOM && viewport.zoom < MAX_FIT_ZOOM);
+        for point in request.markers.iter().map(|marker| &marker.position).chain(&request.paths[0].points) {
+            let (x, y) = to_device(&viewport, point);
+            assert!((0.0..=400.0).contains(&x) && (0.0..=300.0).contains(&y), "{:?} is at {},{}", point, x, y);
+        }
+        // Every pin head is inside the image too
+        for marker in &request.markers {
+            let (_, y) = to_device(&viewport, &marker.position);
+            assert!(y >= MARKER_HEIGHT);
+        }
+    }
+
+    #[test]
+    fn draws_overlays_on_the_copy_of_the_world_in_view() {
+        let viewport = request(StaticView::Center {
+            center: LatLng::new(0.0, -179.5),
+            zoom: 6,
+        })
+        .viewport();
+        let (x, _) = to_device(&viewport, &LatLng::new(0.0, 179.5));
+        assert!((0.0..=400.0).contains(&x), "x is {}", x);
+        assert!(x < 200.0, "west of the centre");
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_over_the_limits() {
+        let service = Arc::new(TileService::new(Arc::new(Config::default())).unwrap());
+
+        let mut oversized = request(StaticView::Auto);
+        oversized.width = MAX_STATIC_MAP_SIZE + 1;
+        let mut dense = request(StaticView::Auto);
+        dense.width = MAX_STATIC_MAP_SIZE;
+        dense.height = MAX_STATIC_MAP_SIZE;
+        dense.scale = 3;
+        let mut crowded = request(StaticView::Auto);
+        crowded.markers = vec![marker(52.52, 13.40); MAX_STATIC_MARKERS + 1];
+        let mut long = request(StaticView::Auto);
+        long.paths = vec![path(MAX_STATIC_PATH_POINTS / 2), path(MAX_STATIC_PATH_POINTS / 2 + 1)];
+
+        for request in [oversized, dense, crowded, long] {
+            assert!(matches!(service.render_static_map(&request).await, Err(MapError::IoError(_))));
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Hillshade and contour overlays rendered from the DEM
+//!
+//! Terrain tiles are transparent PNGs meant to be drawn on top of the base map.
+//! They go through the same memory and disk cache as base tiles, under their own
+//! cache keys.
+
+use std::f64::consts::PI;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::TileService;
+use googlemaps_clone::constants::{MAX_TILE_SCALE, TILE_SIZE};
+use crate::error::{MapError, Result};
+use crate::models::tile::{Tile, TileCoordinate, TileFormat};
+use crate::utils::dem::DemStore;
+use crate::utils::tile_utils::TileCoord;
+
+/// Light direction in degrees clockwise from north
+const SUN_AZIMUTH_DEG: f64 = 315.0;
+
+/// Light elevation above the horizon in degrees
+const SUN_ALTITUDE_DEG: f64 = 45.0;
+
+/// Maximum opacity of hillshade shadows and highlights
+const SHADE_OPACITY: f64 = 0.6;
+
+/// Every n-th contour is drawn as a darker index contour
+const INDEX_CONTOUR_EVERY: i64 = 5;
+
+const EARTH_CIRCUMFERENCE_M: f64 = 40_075_016.686;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerrainLayer {
+    Hillshade,
+    Contours,
+}
+
+impl TerrainLayer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TerrainLayer::Hillshade => "hillshade",
+            TerrainLayer::Contours => "contours",
+        }
+    }
+}
+
+/// Whether `key` was built by `get_terrain_tile`
+pub(super) fn is_terrain_key(key: &str) -> bool {
+    [TerrainLayer::Hillshade, TerrainLayer::Contours]
+        .iter()
+        .any(|layer| key.strip_prefix(layer.as_str()).is_some_and(|rest| rest.starts_with('/')))
+}
+
+impl TileService {
+    /// Use `dem` as the elevation source for terrain tiles
+    pub fn with_terrain(mut self, dem: Arc<DemStore>) -> Self {
+        self.terrain = Some(dem);
+        self
+    }
+
+    pub fn has_terrain(&self) -> bool {
+        self.terrain.is_some()
+    }
+
+    /// Hillshade or contour overlay for `coordinate`, always a PNG of
+    /// `TILE_SIZE * scale` pixels. `None` when no DEM is configured.
+    pub async fn get_terrain_tile(&self, coordinate: &TileCoordinate, layer: TerrainLayer, scale: u8) -> Result<Option<Tile>> {
+        let Some(dem) = self.terrain.clone() else {
+            return Ok(None);
+        };
+
+        let scale = scale.clamp(1, MAX_TILE_SCALE);
+        let cache_key = format!(
+            "{}/@{}x/{}",
+            layer.as_str(),
+            scale,
+            self.generate_cache_key(coordinate, TileFormat::Png)
+        );
+        let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+
+        let tile = self
+            .get_or_render(coordinate, TileFormat::Png, cache_key, async move {
+                let png = tokio::task::spawn_blocking(move || render_terrain_tile(&dem, coord, layer, scale))
+                    .await
+                    .map_err(|e| MapError::IoError(format!("Terrain render task failed: {}", e)))??;
+                debug!("Rendered {} tile {}/{}/{}", layer.as_str(), coord.z, coord.x, coord.y);
+                Ok(png)
+            })
+            .await?;
+        Ok(Some(tile))
+    }
+}
+
+/// Render one terrain layer for `coord` as PNG bytes, `TILE_SIZE * scale`
+/// pixels square. Areas without DEM coverage are left transparent.
+pub fn render_terrain_tile(dem: &DemStore, coord: TileCoord, layer: TerrainLayer, scale: u8) -> Result<Vec<u8>> {
+    let size = TILE_SIZE as usize * scale as usize;
+    let grid = ElevationGrid::sample(dem, coord, size);
+
+    let image = match layer {
+        TerrainLayer::Hillshade => hillshade(&grid, coord, scale),
+        TerrainLayer::Contours => contours(&grid, coord.z),
+    };
+
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(image)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(|e| MapError::IoError(format!("Failed to encode terrain tile: {}", e)))?;
+    Ok(png.into_inner())
+}
+
+/// Elevations at pixel centers of a tile, with a one pixel border so that
+/// gradients can be computed at the tile edges without seams
+struct ElevationGrid {
+    size: usize,
+    values: Vec<Option<f64>>,
+}
+
+impl ElevationGrid {
+    fn sample(dem: &DemStore, coord: TileCoord, size: usize) -> Self {
+        let world_pixels = size as f64 * 2_f64.powi(coord.z as i32);
+        let stride = size + 2;
+        let mut values = Vec::with_capacity(stride * stride);
+
+        for row in 0..stride {
+            let y = coord.y as f64 * size as f64 + row as f64 - 0.5;
+            let lat = (PI * (1.0 - 2.0 * y / world_pixels)).sinh().atan().to_degrees();
+            for col in 0..stride {
+                let x = coord.x as f64 * size as f64 + col as f64 - 0.5;
+                let lng = x / world_pixels * 360.0 - 180.0;
+                values.push(dem.elevation_at(lat, lng));
+            }
+        }
+
+        Self { size, values }
+    }
+
+    /// Elevation at tile pixel (`x`, `y`); -1 and `size` address the border
+    fn at(&self, x: isize, y: isize) -> Option<f64> {
+        let stride = self.size + 2;
+        self.values[(y + 1) as usize * stride + (x + 1) as usize]
+    }
+}
+
+/// Ground distance covered by one device pixel at the tile's center latitude
+fn meters_per_pixel(coord: TileCoord, scale: u8) -> f64 {
+    let n = 2_f64.powi(coord.z as i32);
+    let y = (coord.y as f64 + 0.5) / n;
+    let lat = (PI * (1.0 - 2.0 * y)).sinh().atan();
+    EARTH_CIRCUMFERENCE_M * lat.cos() / (n * TILE_SIZE as f64 * scale as f64)
+}
+
+/// Horn's method hillshade, drawn as black shadows and white highlights
+/// relative to flat ground so the base map stays readable underneath
+fn hillshade(grid: &ElevationGrid, coord: TileCoord, scale: u8) -> RgbaImage {
+    let size = grid.size;
+    let cell = meters_per_pixel(coord, scale);
+    let azimuth = (360.0 - SUN_AZIMUTH_DEG + 90.0).to_radians();
+    let zenith = (90.0 - SUN_ALTITUDE_DEG).to_radians();
+    let flat = zenith.cos();
+
+    let mut image = RgbaImage::new(size as u32, size as u32);
+    for y in 0..size as isize {
+        for x in 0..size as isize {
+            let Some(center) = grid.at(x, y) else {
+                continue;
+            };
+            let e = |dx: isize, dy: isize| grid.at(x + dx, y + dy).unwrap_or(center);
+
+            let dz_dx = ((e(1, -1) + 2.0 * e(1, 0) + e(1, 1)) - (e(-1, -1) + 2.0 * e(-1, 0) + e(-1, 1))) / (8.0 * cell);
+            let dz_dy = ((e(-1, 1) + 2.0 * e(0, 1) + e(1, 1)) - (e(-1, -1) + 2.0 * e(0, -1) + e(1, -1))) / (8.0 * cell);
+
+            let slope = dz_dx.hypot(dz_dy).atan();
+            let aspect = dz_dy.atan2(-dz_dx);
+            let shade = (zenith.cos() * slope.cos() + zenith.sin() * slope.sin() * (azimuth - aspect).cos()).max(0.0);
+
+            let pixel = if shade < flat {
+                let alpha = (flat - shade) / flat * SHADE_OPACITY;
+                Rgba([0, 0, 0, (alpha * 255.0).round() as u8])
+            } else {
+                let alpha = (shade - flat) / (1.0 - flat) * SHADE_OPACITY * 0.5;
+                Rgba([255, 255, 255, (alpha * 255.0).round() as u8])
+            };
+            image.put_pixel(x as u32, y as u32, pixel);
+        }
+    }
+    image
+}
+
+/// Contour spacing in meters, coarser as the map zooms out
+pub fn contour_interval(zoom: u8) -> f64 {
+    match zoom {
+        z if z >= 15 => 10.0,
+        z if z >= 13 => 20.0,
+        z if z >= 11 => 50.0,
+        z if z >= 9 => 100.0,
+        _ => 200.0,
+    }
+}
+
+/// Contour lines drawn where neighboring pixels fall into different elevation
+/// bands, with every `INDEX_CONTOUR_EVERY`-th line emphasized
+fn contours(grid: &ElevationGrid, zoom: u8) -> RgbaImage {
+    let size = grid.size;
+    let interval = contour_interval(zoom);
+    let band = |elevation: f64| (elevation / interval).floor() as i64;
+
+    let mut image = RgbaImage::new(size as u32, size as u32);
+    for y in 0..size as isize {
+        for x in 0..size as isize {
+            let Some(center) = grid.at(x, y) else {
+                continue;
+            };
+            let here = band(center);
+
+            // The line belongs to the lower pixel of each crossing so it is one
+            // pixel wide and continuous across tile edges
+            let crossing = [(1, 0), (0, 1), (-1, 0), (0, -1)]
+                .iter()
+                .filter_map(|&(dx, dy)| grid.at(x + dx, y + dy))
+                .map(band)
+                .filter(|&other| other > here)
+                .min();
+
+            if let Some(upper) = crossing {
+                let pixel = if upper % INDEX_CONTOUR_EVERY == 0 {
+                    Rgba([120, 85, 50, 220])
+                } else {
+                    Rgba([150, 110, 70, 140])
+                };
+                image.put_pixel(x as u32, y as u32, pixel);
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tile 512/511 at zoom 10 lies inside the DEM tile N00E000
+    const COVERED: TileCoord = TileCoord { x: 512, y: 511, z: 10 };
+
+    /// A grid whose elevation at pixel (`x`, `y`) is `elevation(x, y)`
+    fn grid(size: usize, elevation: impl Fn(f64, f64) -> f64) -> ElevationGrid {
+        let stride = size + 2;
+        let values = (0..stride * stride)
+            .map(|i| Some(elevation((i % stride) as f64 - 1.0, (i / stride) as f64 - 1.0)))
+            .collect();
+        ElevationGrid { size, values }
+    }
+
+    fn alphas(image: &RgbaImage) -> Vec<u8> {
+        image.pixels().map(|pixel| pixel.0[3]).collect()
+    }
+
+    fn decode(png: &[u8]) -> RgbaImage {
+        image::load_from_memory(png).expect("terrain tiles are PNGs").to_rgba8()
+    }
+
+    #[test]
+    fn leaves_flat_ground_unshaded() {
+        let image = hillshade(&grid(8, |_, _| 120.0), COVERED, 1);
+        assert!(alphas(&image).iter().all(|&alpha| alpha == 0));
+    }
+
+    #[test]
+    fn lights_slopes_facing_the_sun_and_shades_the_others() {
+        // Rising to the south-east faces the sun in the north-west
+        let lit = hillshade(&grid(8, |x, y| 10.0 * (x + y)), COVERED, 1);
+        let shaded = hillshade(&grid(8, |x, y| -10.0 * (x + y)), COVERED, 1);
+
+        let lit_pixel = lit.get_pixel(4, 4);
+        let shaded_pixel = shaded.get_pixel(4, 4);
+        assert_eq!(&lit_pixel.0[..3], &[255, 255, 255]);
+        assert_eq!(&shaded_pixel.0[..3], &[0, 0, 0]);
+        assert!(lit_pixel.0[3] > 0 && shaded_pixel.0[3] > 0);
+        assert!(shaded_pixel.0[3] <= (SHADE_OPACITY * 255.0).round() as u8);
+    }
+
+    #[test]
+    fn leaves_pixels_without_coverage_transparent() {
+        let mut grid = grid(4, |x, _| 10.0 * x);
+        grid.values.iter_mut().for_each(|value| *value = None);
+        assert!(alphas(&hillshade(&grid, COVERED, 1)).iter().all(|&alpha| alpha == 0));
+        assert!(alphas(&contours(&grid, 14)).iter().all(|&alpha| alpha == 0));
+    }
+
+    #[test]
+    fn draws_one_contour_per_band_crossing() {
+        // Elevation rises 4 m per pixel, crossing a 20 m band every 5 pixels
+        let image = contours(&grid(30, |x, _| 4.0 * x + 1.0), 13);
+        let lines: Vec<u32> = (0..30).filter(|&x| image.get_pixel(x, 10).0[3] > 0).collect();
+        assert_eq!(lines, vec![4, 9, 14, 19, 24, 29]);
+
+        // The line up to 100 m, the fifth 20 m band, is an index contour
+        assert_eq!(image.get_pixel(4, 10).0, [150, 110, 70, 140]);
+        assert_eq!(image.get_pixel(24, 10).0, [120, 85, 50, 220]);
+    }
+
+    #[test]
+    fn coarsens_contour_interval_when_zooming_out() {
+        assert_eq!(contour_interval(16), 10.0);
+        assert_eq!(contour_interval(13), 20.0);
+        assert_eq!(contour_interval(11), 50.0);
+        assert_eq!(contour_interval(9), 100.0);
+        assert_eq!(contour_interval(3), 200.0);
+    }
+
+    #[test]
+    fn encodes_terrain_tiles_as_scaled_pngs() {
+        let directory = std::env::temp_dir().join(format!("terrain-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // A 3x3 DEM rising from 0 m in the north-west to 2000 m in the south-east
+        let samples: Vec<u8> = [0i16, 500, 1000, 500, 1000, 1500, 1000, 1500, 2000]
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect();
+        std::fs::write(directory.join("N00E000.hgt"), samples).unwrap();
+        let dem = DemStore::new(&directory);
+
+        let hillshade = decode(&render_terrain_tile(&dem, COVERED, TerrainLayer::Hillshade, 2).unwrap());
+        assert_eq!(hillshade.dimensions(), (TILE_SIZE * 2, TILE_SIZE * 2));
+        assert!(alphas(&hillshade).iter().any(|&alpha| alpha > 0));
+
+        let contours = decode(&render_terrain_tile(&dem, COVERED, TerrainLayer::Contours, 1).unwrap());
+        assert_eq!(contours.dimensions(), (TILE_SIZE, TILE_SIZE));
+        assert!(alphas(&contours).iter().any(|&alpha| alpha > 0));
+
+        let uncovered = TileCoord::new(100, 100, 10);
+        let empty = decode(&render_terrain_tile(&dem, uncovered, TerrainLayer::Hillshade, 1).unwrap());
+        assert!(alphas(&empty).iter().all(|&alpha| alpha == 0));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn recognizes_terrain_cache_keys() {
+        assert!(is_terrain_key("hillshade/@2x/10/512/511.png"));
+        assert!(is_terrain_key("contours/@1x/10/512/511.png"));
+        assert!(!is_terrain_key("hillshades/@1x/10/512/511.png"));
+        assert!(!is_terrain_key("light-0123/@1x/10/512/511.png"));
+    }
+}
@@ -0,0 +1,174 @@
+//! TileJSON 3.0 descriptions of the tilesets the service publishes
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::{TerrainLayer, TileService};
+use googlemaps_clone::constants::{DEFAULT_LAT, DEFAULT_LNG, DEFAULT_ZOOM, MAX_ZOOM, MIN_ZOOM};
+use crate::models::map_tile::{FeatureType, RoadType};
+use crate::models::tile::TileFormat;
+use crate::services::map_style::class_of;
+
+pub const TILEJSON_VERSION: &str = "3.0.0";
+
+/// Name of the base map tileset when tiles are proxied from an upstream
+/// server, which has no styles
+pub const UPSTREAM_TILESET: &str = "basemap";
+
+/// Web Mercator cuts off at this latitude
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TileJson {
+    pub tilejson: &'static str,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+    pub scheme: &'static str,
+    pub tiles: Vec<String>,
+    pub minzoom: u8,
+    pub maxzoom: u8,
+    /// West, south, east, north
+    pub bounds: [f64; 4],
+    /// Longitude, latitude, zoom
+    pub center: [f64; 3],
+}
+
+/// A layer of map data the tiles are drawn from, with the properties style
+/// filters can match on. Every published tileset is raster, so these are
+/// listed with the styles rather than as TileJSON `vector_layers`.
+#[derive(Debug, Clone, Serialize)]
+pub struct VectorLayer {
+    pub id: &'static str,
+    pub description: &'static str,
+    pub minzoom: u8,
+    pub maxzoom: u8,
+    pub fields: BTreeMap<&'static str, String>,
+}
+
+/// Description of a `class` field listing the lowercase names of `values`
+fn class_field<T: Serialize>(values: &[T]) -> String {
+    let names: Vec<String> = values
+        .iter()
+        .filter_map(class_of)
+        .filter_map(|name| name.as_str().map(str::to_string))
+        .collect();
+    format!("Feature class, one of: {}", names.join(", "))
+}
+
+/// The source layers of the base map, matching the `source-layer`s styles
+/// refer to
+pub fn vector_layers() -> Vec<VectorLayer> {
+    vec![
+        VectorLayer {
+            id: "features",
+            description: "Areas and outlines such as buildings, water and parks",
+            minzoom: MIN_ZOOM,
+            maxzoom: MAX_ZOOM,
+            fields: BTreeMap::from([("class", class_field(&FeatureType::ALL))]),
+        },
+        VectorLayer {
+            id: "roads",
+            description: "Road centrelines, also used to place road name labels",
+            minzoom: MIN_ZOOM,
+            maxzoom: MAX_ZOOM,
+            fields: BTreeMap::from([
+                ("class", class_field(&RoadType::ALL)),
+                ("surface", "Road surface, e.g. paved or gravel".to_string()),
+                ("name", "Road name".to_string()),
+                ("oneway", "Whether traffic only flows in the drawn direction".to_string()),
+                ("lanes", "Number of lanes".to_string()),
+            ]),
+        },
+        VectorLayer {
+            id: "labels",
+            description: "Point labels for places and points of interest",
+            minzoom: MIN_ZOOM,
+            maxzoom: MAX_ZOOM,
+            fields: BTreeMap::from([
+                ("class", "Label type".to_string()),
+                ("name", "Label text".to_string()),
+            ]),
+        },
+    ]
+}
+
+fn extension(format: &TileFormat) -> &'static str {
+    match format {
+        TileFormat::Png => "png",
+        TileFormat::Jpeg => "jpg",
+        TileFormat::Webp => "webp",
+        TileFormat::Vector => "pbf",
+    }
+}
+
+impl TileService {
+    /// Attribution shown by clients for every tileset
+    pub fn with_attribution(mut self, attribution: impl Into<String>) -> Self {
+        self.attribution = Some(attribution.into());
+        self
+    }
+
+    /// Names of the published tilesets: one per style, or the proxied
+    /// upstream, followed by the terrain overlays when a DEM is configured
+    pub fn tilesets(&self) -> Vec<String> {
+        let mut names = if self.upstream.is_some() {
+            vec![UPSTREAM_TILESET.to_string()]
+        } else if self.source.is_some() {
+            self.style_names()
+        } else {
+            Vec::new()
+        };
+        if self.terrain.is_some() {
+            names.extend([TerrainLayer::Hillshade, TerrainLayer::Contours].map(|layer| layer.as_str().to_string()));
+        }
+        names
+    }
+
+    /// TileJSON for `tileset`, with tile URLs under `base_url`. `None` for
+    /// unknown tilesets.
+    pub fn tilejson(&self, tileset: &str, base_url: &str) -> Option<TileJson> {
+        if !self.tilesets().iter().any(|name| name == tileset) {
+            return None;
+        }
+        let base_url = base_url.trim_end_matches('/');
+
+        let terrain = [TerrainLayer::Hillshade, TerrainLayer::Contours]
+            .into_iter()
+            .find(|layer| layer.as_str() == tileset);
+        let (tiles, description) = if let Some(layer) = terrain {
+            (
+                format!("{}/tiles/terrain/{}/{{z}}/{{x}}/{{y}}.png", base_url, layer.as_str()),
+                format!("Transparent {} overlay", layer.as_str()),
+            )
+        } else if let Some(upstream) = &self.upstream {
+            (
+                format!("{}/tiles/{{z}}/{{x}}/{{y}}.{}", base_url, extension(&upstream.format())),
+                "Base map".to_string(),
+            )
+        } else {
+            (
+                format!("{}/tiles/{{z}}/{{x}}/{{y}}.png?style={}", base_url, tileset),
+                format!("Base map in the {} style", tileset),
+            )
+        };
+
+        Some(TileJson {
+            tilejson: TILEJSON_VERSION,
+            name: tileset.to_string(),
+            description: Some(description),
+            version: "1.0.0",
+            attribution: self.attribution.clone(),
+            scheme: "xyz",
+            tiles: vec![tiles],
+            minzoom: MIN_ZOOM,
+            maxzoom: MAX_ZOOM,
+            bounds: [-180.0, -MAX_LATITUDE, 180.0, MAX_LATITUDE],
+            center: [DEFAULT_LNG, DEFAULT_LAT, DEFAULT_ZOOM as f64],
+        })
+    }
+}
@@ -0,0 +1,466 @@
+//! Caching proxy for an upstream XYZ tile server
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use reqwest::{header, Client, StatusCode};
+use serde::Serialize;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, warn};
+
+use super::{TileMetadata, TileService};
+use googlemaps_clone::constants::{MAX_TILE_SCALE, REQUEST_TIMEOUT};
+use crate::error::{MapError, Result};
+use crate::models::tile::{Tile, TileCoordinate, TileData, TileFormat};
+use crate::utils::tile_utils::TileCoord;
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// Upstream tiles are served without revalidation for this long
+const DEFAULT_FRESH_FOR: Duration = Duration::from_secs(3600);
+/// After going stale, tiles are still served for this long while they are
+/// refreshed in the background
+const DEFAULT_STALE_FOR: Duration = Duration::from_secs(24 * 3600);
+
+/// Outcome of one upstream fetch, shared by every caller that joined it.
+/// `None` when the upstream has no tile at that address.
+type Flight = Shared<BoxFuture<'static, std::result::Result<Option<Arc<Vec<u8>>>, String>>>;
+
+/// An XYZ tile server such as `https://tiles.example.com/{z}/{x}/{y}{r}.png`.
+/// `{r}` becomes `@2x` or `@3x` for high-DPI tiles and is empty otherwise.
+pub struct UpstreamSource {
+    url_template: String,
+    format: TileFormat,
+    client: Client,
+    max_retries: u32,
+    initial_backoff: Duration,
+    fresh_for: Duration,
+    stale_for: Duration,
+    in_flight: Mutex<HashMap<String, Flight>>,
+    fetches: AtomicU64,
+    coalesced: AtomicU64,
+    failures: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStats {
+    pub fetches: u64,
+    pub coalesced: u64,
+    pub failures: u64,
+    pub in_flight: usize,
+}
+
+impl UpstreamSource {
+    pub fn new(url_template: impl Into<String>, format: TileFormat) -> Result<Self> {
+        let url_template = url_template.into();
+        if !["{z}", "{x}", "{y}"].iter().all(|placeholder| url_template.contains(placeholder)) {
+            return Err(MapError::IoError(format!(
+                "Upstream URL template must contain {{z}}, {{x}} and {{y}}: {}",
+                url_template
+            )));
+        }
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .user_agent("RustMaps/1.0")
+            .build()
+            .map_err(|e| MapError::IoError(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Self {
+            url_template,
+            format,
+            client,
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            fresh_for: DEFAULT_FRESH_FOR,
+            stale_for: DEFAULT_STALE_FOR,
+            in_flight: Mutex::new(HashMap::new()),
+            fetches: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        })
+    }
+
+    /// Retry failed fetches up to `max_retries` times, doubling the delay from
+    /// `initial_backoff`
+    pub fn with_retries(mut self, max_retries: u32, initial_backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Serve cached tiles as-is for `fresh_for`, then serve them stale for up
+    /// to `stale_for` while refreshing in the background
+    pub fn with_freshness(mut self, fresh_for: Duration, stale_for: Duration) -> Self {
+        self.fresh_for = fresh_for;
+        self.stale_for = stale_for;
+        self
+    }
+
+    pub fn format(&self) -> TileFormat {
+        self.format.clone()
+    }
+
+    pub fn tile_url(&self, coord: TileCoord, scale: u8) -> String {
+        let retina = if scale > 1 { format!("@{}x", scale) } else { String::new() };
+        self.url_template
+            .replace("{z}", &coord.z.to_string())
+            .replace("{x}", &coord.x.to_string())
+            .replace("{y}", &coord.y.to_string())
+            .replace("{r}", &retina)
+    }
+
+    pub fn stats(&self) -> UpstreamStats {
+        UpstreamStats {
+            fetches: self.fetches.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            in_flight: self.lock_in_flight().len(),
+        }
+    }
+
+    /// Join the fetch already running for `key`, or start one with `start`
+    fn join_or_start<F>(&self, key: &str, start: F) -> Flight
+    where
+        F: FnOnce() -> BoxFuture<'static, std::result::Result<Option<Arc<Vec<u8>>>, String>>,
+    {
+        let mut in_flight = self.lock_in_flight();
+        if let Some(flight) = in_flight.get(key) {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+            return flight.clone();
+        }
+
+        let flight = start().shared();
+        in_flight.insert(key.to_string(), flight.clone());
+        flight
+    }
+
+    fn finish(&self, key: &str) {
+        self.lock_in_flight().remove(key);
+    }
+
+    fn lock_in_flight(&self) -> std::sync::MutexGuard<'_, HashMap<String, Flight>> {
+        self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// GET the tile, retrying connection errors, timeouts, 429s and 5xx
+    /// responses with exponential backoff. A `Retry-After` in seconds is
+    /// honoured up to `MAX_BACKOFF`.
+    async fn fetch(&self, coord: TileCoord, scale: u8) -> Result<Option<Vec<u8>>> {
+        let url = self.tile_url(coord, scale);
+        let mut attempt = 0;
+
+        loop {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            let (error, retry_after) = match self.client.get(&url).send().await {
+                Ok(response) if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::NO_CONTENT) => {
+                    return Ok(None);
+                }
+                Ok(response) if response.status().is_success() => match response.bytes().await {
+                    Ok(bytes) => return Ok(Some(bytes.to_vec())),
+                    Err(e) => (format!("Failed to read upstream tile {}: {}", url, e), None),
+                },
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS || response.status().is_server_error() => {
+                    let retry_after = response
+                        .headers()
+                        .get(header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse().ok())
+                        .map(Duration::from_secs);
+                    (format!("Upstream returned {} for {}", response.status(), url), retry_after)
+                }
+                Ok(response) => {
+                    self.failures.fetch_add(1, Ordering::Relaxed);
+                    return Err(MapError::IoError(format!("Upstream returned {} for {}", response.status(), url)));
+                }
+                Err(e) => (format!("Failed to fetch upstream tile {}: {}", url, e), None),
+            };
+
+            if attempt >= self.max_retries {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(MapError::IoError(error));
+            }
+
+            let backoff = retry_after.unwrap_or(self.initial_backoff * 2u32.saturating_pow(attempt));
+            attempt += 1;
+            debug!("{}; retry {} of {} in {:?}", error, attempt, self.max_retries, backoff);
+            sleep(backoff.min(MAX_BACKOFF)).await;
+        }
+    }
+}
+
+impl TileService {
+    /// Serve base map tiles from `upstream` instead of rendering them
+    pub fn with_upstream(mut self, upstream: UpstreamSource) -> Self {
+        self.upstream = Some(Arc::new(upstream));
+        self
+    }
+
+    pub fn upstream(&self) -> Option<&UpstreamSource> {
+        self.upstream.as_deref()
+    }
+
+    /// Tile from the upstream server, through the cache. Fresh tiles are
+    /// served directly; stale ones are served while a background fetch
+    /// refreshes them, and past `stale_for` they are refetched first but
+    /// still served if the upstream is failing. `None` when no upstream is
+    /// configured or it has no such tile.
+    pub async fn get_proxied_tile(self: &Arc<Self>, coordinate: &TileCoordinate, scale: u8) -> Result<Option<Tile>> {
+        let Some(upstream) = self.upstream.clone() else {
+            return Ok(None);
+        };
+
+        let scale = scale.clamp(1, MAX_TILE_SCALE);
+        let cache_key = format!("upstream/@{}x/{}", scale, self.generate_cache_key(coordinate, upstream.format()));
+
+        let Some(cached) = self.lookup_cached_tile(coordinate, upstream.format(), &cache_key).await else {
+            return self.fetch_upstream_tile(coordinate, scale, cache_key).await;
+        };
+
+        let age = cached
+            .metadata
+            .as_ref()
+            .and_then(|metadata| (chrono::Utc::now() - metadata.created_at).to_std().ok())
+            .unwrap_or_default();
+
+        if age < upstream.fresh_for {
+            return Ok(Some(cached));
+        }
+
+        if age < upstream.fresh_for + upstream.stale_for {
+            let service = self.clone();
+            let coordinate = coordinate.clone();
+            tokio::spawn(async move {
+                if let Err(e) = service.fetch_upstream_tile(&coordinate, scale, cache_key).await {
+                    warn!("Failed to revalidate upstream tile: {}", e);
+                }
+            });
+            return Ok(Some(cached));
+        }
+
+        match self.fetch_upstream_tile(coordinate, scale, cache_key).await {
+            Ok(tile) => Ok(tile),
+            Err(e) => {
+                warn!("Serving stale tile after upstream failure: {}", e);
+                Ok(Some(cached))
+            }
+        }
+    }
+
+    /// Fetch from upstream and cache the result. Concurrent calls for the same
+    /// key share one request.
+    async fn fetch_upstream_tile(self: &Arc<Self>, coordinate: &TileCoordinate, scale: u8, cache_key: String) -> Result<Option<Tile>> {
+        let Some(upstream) = self.upstream.clone() else {
+            return Ok(None);
+        };
+
+        let flight = upstream.join_or_start(&cache_key, || {
+            let service = self.clone();
+            let upstream = upstream.clone();
+            let coordinate = coordinate.clone();
+            let cache_key = cache_key.clone();
+            async move {
+                let coord = TileCoord::new(coordinate.x, coordinate.y, coordinate.z);
+                let fetched = upstream.fetch(coord, scale).await;
+                let result = match fetched {
+                    Ok(Some(data)) => {
+                        service
+                            .store_cached_tile(&coordinate, upstream.format(), cache_key.clone(), data.clone())
+                            .await;
+                        Ok(Some(Arc::new(data)))
+                    }
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e.to_string()),
+                };
+                upstream.finish(&cache_key);
+                result
+            }
+            .boxed()
+        });
+
+        let Some(data) = flight.await.map_err(MapError::IoError)? else {
+            return Ok(None);
+        };
+
+        let now = chrono::Utc::now();
+        let format = upstream.format();
+        Ok(Some(Tile {
+            coordinate: coordinate.clone(),
+            format: format.clone(),
+            data: TileData::from(data.as_ref().clone()),
+            metadata: Some(TileMetadata {
+                coordinate: coordinate.clone(),
+                format,
+                created_at: now,
+                last_accessed: now,
+                size_bytes: data.len() as u64,
+                cache_key,
+            }),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::atomic::AtomicUsize;
+
+    use axum::extract::State;
+    use axum::routing::get;
+    use axum::Router;
+    use futures::future::join_all;
+    use tokio::net::TcpListener;
+    use tokio::time::{timeout, Instant};
+
+    use super::*;
+    use crate::config::Config;
+
+    /// A local tile server. Each tile body names the request that served it,
+    /// so refreshed tiles can be told apart from cached ones.
+    #[derive(Clone, Default)]
+    struct TestUpstream {
+        hits: Arc<AtomicUsize>,
+        /// Statuses answered, in order, before tiles are served again
+        failures: Arc<Mutex<VecDeque<u16>>>,
+        delay: Duration,
+    }
+
+    impl TestUpstream {
+        fn with_delay(delay: Duration) -> Self {
+            Self { delay, ..Self::default() }
+        }
+
+        fn hits(&self) -> usize {
+            self.hits.load(Ordering::SeqCst)
+        }
+
+        fn fail_next(&self, statuses: &[u16]) {
+            self.failures.lock().unwrap().extend(statuses);
+        }
+
+        /// Serve on a free local port and return the tile URL template
+        async fn start(&self) -> String {
+            let app = Router::new().route("/:z/:x/:y", get(serve_tile)).with_state(self.clone());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            format!("http://{}/{{z}}/{{x}}/{{y}}.png", address)
+        }
+    }
+
+    async fn serve_tile(State(upstream): State<TestUpstream>) -> (axum::http::StatusCode, Vec<u8>) {
+        let hit = upstream.hits.fetch_add(1, Ordering::SeqCst) + 1;
+        sleep(upstream.delay).await;
+        let failure = upstream.failures.lock().unwrap().pop_front();
+        match failure {
+            Some(status) => (axum::http::StatusCode::from_u16(status).unwrap(), Vec::new()),
+            None => (axum::http::StatusCode::OK, format!("tile {}", hit).into_bytes()),
+        }
+    }
+
+    fn tile_service(upstream: UpstreamSource) -> Arc<TileService> {
+        let cache_dir = std::env::temp_dir().join(format!("upstream-tiles-{}", uuid::Uuid::new_v4()));
+        let config = Config {
+            tile_cache_dir: cache_dir.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        Arc::new(TileService::new(Arc::new(config)).unwrap().with_upstream(upstream))
+    }
+
+    fn body(tile: Option<Tile>) -> String {
+        let data: Vec<u8> = tile.expect("upstream has the tile").data.into();
+        String::from_utf8(data).unwrap()
+    }
+
+    const COORDINATE: TileCoordinate = TileCoordinate { x: 1310, y: 3166, z: 13 };
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_upstream_fetch() {
+        let server = TestUpstream::with_delay(Duration::from_millis(100));
+        let service = tile_service(UpstreamSource::new(server.start().await, TileFormat::Png).unwrap());
+
+        let requests = (0..8).map(|_| service.get_proxied_tile(&COORDINATE, 1));
+        let tiles = join_all(requests).await;
+
+        assert_eq!(server.hits(), 1);
+        for tile in tiles {
+            assert_eq!(body(tile.unwrap()), "tile 1");
+        }
+        assert_eq!(service.upstream().unwrap().stats().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_and_rate_limits_with_backoff() {
+        let server = TestUpstream::default();
+        server.fail_next(&[503, 429]);
+        let upstream = UpstreamSource::new(server.start().await, TileFormat::Png)
+            .unwrap()
+            .with_retries(3, Duration::from_millis(20));
+        let service = tile_service(upstream);
+
+        let started = Instant::now();
+        let tile = service.get_proxied_tile(&COORDINATE, 1).await.unwrap();
+
+        assert_eq!(body(tile), "tile 3");
+        assert_eq!(server.hits(), 3);
+        // 20ms before the first retry, 40ms before the second
+        assert!(started.elapsed() >= Duration::from_millis(60), "retried after {:?}", started.elapsed());
+        assert_eq!(service.upstream().unwrap().stats().failures, 0);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_retry() {
+        let server = TestUpstream::default();
+        server.fail_next(&[500, 500, 500]);
+        let upstream = UpstreamSource::new(server.start().await, TileFormat::Png)
+            .unwrap()
+            .with_retries(2, Duration::from_millis(1));
+        let service = tile_service(upstream);
+
+        assert!(service.get_proxied_tile(&COORDINATE, 1).await.is_err());
+        assert_eq!(server.hits(), 3);
+        assert_eq!(service.upstream().unwrap().stats().failures, 1);
+    }
+
+    #[tokio::test]
+    async fn serves_stale_tiles_while_refreshing_in_the_background() {
+        let server = TestUpstream::with_delay(Duration::from_millis(100));
+        let upstream = UpstreamSource::new(server.start().await, TileFormat::Png)
+            .unwrap()
+            .with_freshness(Duration::ZERO, Duration::from_secs(3600));
+        let service = tile_service(upstream);
+        assert_eq!(body(service.get_proxied_tile(&COORDINATE, 1).await.unwrap()), "tile 1");
+
+        // Stale: answered from the cache without waiting on the upstream
+        let started = Instant::now();
+        assert_eq!(body(service.get_proxied_tile(&COORDINATE, 1).await.unwrap()), "tile 1");
+        assert!(started.elapsed() < Duration::from_millis(100), "waited {:?}", started.elapsed());
+
+        timeout(Duration::from_secs(5), async {
+            while server.hits() < 2 || service.upstream().unwrap().stats().in_flight > 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("background refresh finishes");
+        assert_eq!(body(service.get_proxied_tile(&COORDINATE, 1).await.unwrap()), "tile 2");
+    }
+
+    #[tokio::test]
+    async fn serves_expired_tiles_when_the_upstream_fails() {
+        let server = TestUpstream::default();
+        let upstream = UpstreamSource::new(server.start().await, TileFormat::Png)
+            .unwrap()
+            .with_retries(1, Duration::from_millis(1))
+            .with_freshness(Duration::ZERO, Duration::ZERO);
+        let service = tile_service(upstream);
+        assert_eq!(body(service.get_proxied_tile(&COORDINATE, 1).await.unwrap()), "tile 1");
+
+        server.fail_next(&[502, 502]);
+        assert_eq!(body(service.get_proxied_tile(&COORDINATE, 1).await.unwrap()), "tile 1");
+        assert_eq!(server.hits(), 3);
+    }
+}
@@ -0,0 +1,321 @@
+//! Spoken-style phrases for turn-by-turn navigation
+//!
+//! Announcements are staged by the distance left to a maneuver and come out as
+//! plain text or, for engines that support it, SSML.
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::route::{ManeuverType, RouteSegment};
+
+/// Maneuvers closer together than this are announced as one instruction
+pub const COMBINE_DISTANCE_M: f64 = 150.0;
+
+const METERS_PER_FOOT: f64 = 0.3048;
+const METERS_PER_MILE: f64 = 1609.344;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceUnits {
+    #[default]
+    Metric,
+    Imperial,
+}
+
+/// When an announcement is made relative to its maneuver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnouncementStage {
+    /// Well ahead of the maneuver, to prepare the driver
+    Early,
+    /// Shortly before the maneuver
+    Approaching,
+    /// At the maneuver
+    Now,
+}
+
+/// Produces spoken-style phrases for navigation instructions
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct VoiceGuidance {
+    #[serde(default)]
+    pub units: DistanceUnits,
+    /// Wrap phrases in SSML for text-to-speech engines that support it
+    #[serde(default)]
+    pub ssml: bool,
+}
+
+impl VoiceGuidance {
+    pub fn new(units: DistanceUnits, ssml: bool) -> Self {
+        Self { units, ssml }
+    }
+
+    /// The phrase for `segment`'s maneuver, `distance_m` ahead. When `then` is
+    /// given, its maneuver follows closely and is appended to the same phrase.
+    pub fn announcement(
+        &self,
+        segment: &RouteSegment,
+        distance_m: f64,
+        stage: AnnouncementStage,
+        then: Option<&RouteSegment>,
+    ) -> String {
+        let action = self.action(segment, stage);
+
+        let mut sentence = match stage {
+            AnnouncementStage::Now => match segment.maneuver {
+                ManeuverType::Arrive => action,
+                _ => format!("Now {}", action),
+            },
+            _ => format!("In {}, {}", self.distance_phrase(distance_m), action),
+        };
+
+        if let Some(then) = then.filter(|_| stage != AnnouncementStage::Early) {
+            let follow_up = self.action(then, AnnouncementStage::Approaching);
+            if self.ssml {
+                sentence = format!("{}.<break time=\"300ms\"/>Then {}", sentence, follow_up);
+            } else {
+                sentence = format!("{}, then {}", sentence, follow_up);
+            }
+        }
+
+        if self.ssml {
+            format!("<speak>{}.</speak>", sentence)
+        } else {
+            format!("{}.", sentence)
+        }
+    }
+
+    /// Whether two consecutive maneuvers are close enough to announce together
+    pub fn should_combine(gap_m: f64) -> bool {
+        gap_m <= COMBINE_DISTANCE_M
+    }
+
+    fn action(&self, segment: &RouteSegment, stage: AnnouncementStage) -> String {
+        let street = segment.street_name.as_deref().filter(|name| !name.trim().is_empty());
+        let onto = |verb: &str| match street {
+            Some(name) => format!("{} onto {}", verb, self.street(name)),
+            None => verb.to_string(),
+        };
+
+        match segment.maneuver {
+            ManeuverType::Start => match street {
+                Some(name) => format!("head out on {}", self.street(name)),
+                None => "head out on the route".to_string(),
+            },
+            ManeuverType::Straight => onto("continue straight"),
+            ManeuverType::TurnLeft => onto("turn left"),
+            ManeuverType::TurnRight => onto("turn right"),
+            ManeuverType::TurnSlightLeft => onto("bear left"),
+            ManeuverType::TurnSlightRight => onto("bear right"),
+            ManeuverType::TurnSharpLeft => onto("turn sharp left"),
+            ManeuverType::TurnSharpRight => onto("turn sharp right"),
+            ManeuverType::UTurn => "make a U-turn".to_string(),
+            ManeuverType::Merge => onto("merge"),
+            ManeuverType::RampLeft => onto("take the ramp on the left"),
+            ManeuverType::RampRight => onto("take the ramp on the right"),
+            ManeuverType::Fork => onto("keep at the fork"),
+            ManeuverType::Roundabout => onto("enter the roundabout and take the exit"),
+            ManeuverType::Exit => onto("take the exit"),
+            ManeuverType::Arrive => match stage {
+                AnnouncementStage::Now => "You have arrived at your destination".to_string(),
+                _ => "you will arrive at your destination".to_string(),
+            },
+        }
+    }
+
+    fn street(&self, name: &str) -> String {
+        if self.ssml {
+            escape_ssml(name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Distance rounded the way people say it: "300 meters", "1.5 kilometers",
+    /// "500 feet", "a quarter mile"
+    pub fn distance_phrase(&self, distance_m: f64) -> String {
+        match self.units {
+            DistanceUnits::Metric => {
+                let step = if distance_m < 100.0 { 10.0 } else { 50.0 };
+                let meters = ((distance_m / step).round() * step).max(step);
+                // Rounding can carry 975 m and up to 1000, which is said in kilometers
+                if meters < 1000.0 {
+                    format!("{} meters", meters as u32)
+                } else {
+                    let kilometers = (distance_m / 100.0).round() / 10.0;
+                    if kilometers == 1.0 {
+                        "1 kilometer".to_string()
+                    } else if kilometers.fract() == 0.0 {
+                        format!("{} kilometers", kilometers as u32)
+                    } else {
+                        format!("{:.1} kilometers", kilometers)
+                    }
+                }
+            }
+            DistanceUnits::Imperial => {
+                let feet = distance_m / METERS_PER_FOOT;
+                let step = if feet < 300.0 { 50.0 } else { 100.0 };
+                let rounded = ((feet / step).round() * step).max(step);
+                if rounded < 1000.0 {
+                    format!("{} feet", rounded as u32)
+                } else {
+                    let quarters = ((distance_m / METERS_PER_MILE) * 4.0).round() as u32;
+                    match quarters {
+                        0 | 1 => "a quarter mile".to_string(),
+                        2 => "half a mile".to_string(),
+                        3 => "three quarters of a mile".to_string(),
+                        4 => "1 mile".to_string(),
+                        q if q % 4 == 0 => format!("{} miles", q / 4),
+                        q => format!("{} miles", q as f64 / 4.0),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn escape_ssml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::route::Coordinate;
+
+    fn phrase(units: DistanceUnits, distance_m: f64) -> String {
+        VoiceGuidance::new(units, false).distance_phrase(distance_m)
+    }
+
+    fn segment(maneuver: ManeuverType, street_name: Option<&str>) -> RouteSegment {
+        RouteSegment {
+            start: Coordinate::new(0.0, 0.0),
+            end: Coordinate::new(0.0, 0.001),
+            distance: 111.0,
+            duration: 10,
+            instruction: String::new(),
+            street_name: street_name.map(str::to_string),
+            maneuver,
+        }
+    }
+
+    #[test]
+    fn phrases_each_announcement_stage() {
+        let voice = VoiceGuidance::default();
+        let left = segment(ManeuverType::TurnLeft, Some("Main Street"));
+
+        assert_eq!(
+            voice.announcement(&left, 1000.0, AnnouncementStage::Early, None),
+            "In 1 kilometer, turn left onto Main Street."
+        );
+        assert_eq!(
+            voice.announcement(&left, 240.0, AnnouncementStage::Approaching, None),
+            "In 250 meters, turn left onto Main Street."
+        );
+        assert_eq!(
+            voice.announcement(&left, 30.0, AnnouncementStage::Now, None),
+            "Now turn left onto Main Street."
+        );
+
+        let arrive = segment(ManeuverType::Arrive, None);
+        assert_eq!(
+            voice.announcement(&arrive, 200.0, AnnouncementStage::Approaching, None),
+            "In 200 meters, you will arrive at your destination."
+        );
+        assert_eq!(
+            voice.announcement(&arrive, 10.0, AnnouncementStage::Now, None),
+            "You have arrived at your destination."
+        );
+    }
+
+    #[test]
+    fn leaves_out_blank_street_names() {
+        let voice = VoiceGuidance::new(DistanceUnits::Imperial, false);
+        assert_eq!(
+            voice.announcement(&segment(ManeuverType::TurnSlightRight, Some("  ")), 800.0, AnnouncementStage::Early, None),
+            "In half a mile, bear right."
+        );
+        assert_eq!(
+            voice.announcement(&segment(ManeuverType::UTurn, Some("Main Street")), 20.0, AnnouncementStage::Now, None),
+            "Now make a U-turn."
+        );
+    }
+
+    #[test]
+    fn appends_a_closely_following_maneuver_with_then() {
+        let voice = VoiceGuidance::default();
+        let left = segment(ManeuverType::TurnLeft, Some("Main Street"));
+        let right = segment(ManeuverType::TurnRight, Some("Oak Avenue"));
+
+        assert_eq!(
+            voice.announcement(&left, 250.0, AnnouncementStage::Approaching, Some(&right)),
+            "In 250 meters, turn left onto Main Street, then turn right onto Oak Avenue."
+        );
+        assert_eq!(
+            voice.announcement(&left, 30.0, AnnouncementStage::Now, Some(&right)),
+            "Now turn left onto Main Street, then turn right onto Oak Avenue."
+        );
+        // Too far ahead for the second maneuver to be worth mentioning yet
+        assert_eq!(
+            voice.announcement(&left, 1000.0, AnnouncementStage::Early, Some(&right)),
+            "In 1 kilometer, turn left onto Main Street."
+        );
+
+        assert!(VoiceGuidance::should_combine(COMBINE_DISTANCE_M));
+        assert!(!VoiceGuidance::should_combine(COMBINE_DISTANCE_M + 1.0));
+    }
+
+    #[test]
+    fn wraps_ssml_and_escapes_street_names() {
+        let voice = VoiceGuidance::new(DistanceUnits::Metric, true);
+        let right = segment(ManeuverType::TurnRight, Some("Smith & Sons <Rd>"));
+        assert_eq!(
+            voice.announcement(&right, 20.0, AnnouncementStage::Now, None),
+            "<speak>Now turn right onto Smith &amp; Sons &lt;Rd&gt;.</speak>"
+        );
+
+        let left = segment(ManeuverType::TurnLeft, Some("Main Street"));
+        let exit = segment(ManeuverType::Exit, Some("O'Farrell St"));
+        assert_eq!(
+            voice.announcement(&left, 250.0, AnnouncementStage::Approaching, Some(&exit)),
+            "<speak>In 250 meters, turn left onto Main Street.<break time=\"300ms\"/>Then take the exit onto O&apos;Farrell St.</speak>"
+        );
+
+        // Plain text is left as written
+        assert_eq!(
+            VoiceGuidance::default().announcement(&right, 20.0, AnnouncementStage::Now, None),
+            "Now turn right onto Smith & Sons <Rd>."
+        );
+    }
+
+    #[test]
+    fn rounds_metric_distances() {
+        assert_eq!(phrase(DistanceUnits::Metric, 3.0), "10 meters");
+        assert_eq!(phrase(DistanceUnits::Metric, 84.0), "80 meters");
+        assert_eq!(phrase(DistanceUnits::Metric, 312.0), "300 meters");
+        assert_eq!(phrase(DistanceUnits::Metric, 974.0), "950 meters");
+        assert_eq!(phrase(DistanceUnits::Metric, 1520.0), "1.5 kilometers");
+        assert_eq!(phrase(DistanceUnits::Metric, 12_040.0), "12 kilometers");
+    }
+
+    #[test]
+    fn switches_to_kilometers_when_meters_round_up_to_1000() {
+        for distance in [975.0, 990.0, 999.9, 1000.0, 1040.0] {
+            assert_eq!(phrase(DistanceUnits::Metric, distance), "1 kilometer", "{}", distance);
+        }
+    }
+
+    #[test]
+    fn rounds_imperial_distances() {
+        assert_eq!(phrase(DistanceUnits::Imperial, 10.0), "50 feet");
+        assert_eq!(phrase(DistanceUnits::Imperial, 200.0), "700 feet");
+        assert_eq!(phrase(DistanceUnits::Imperial, 400.0), "a quarter mile");
+        assert_eq!(phrase(DistanceUnits::Imperial, 800.0), "half a mile");
+        assert_eq!(phrase(DistanceUnits::Imperial, 1609.0), "1 mile");
+        assert_eq!(phrase(DistanceUnits::Imperial, 4023.0), "2.5 miles");
+        // 980 feet rounds up to 1000, which is said in miles
+        assert_eq!(phrase(DistanceUnits::Imperial, 298.7), "a quarter mile");
+    }
+}
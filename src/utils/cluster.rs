@@ -0,0 +1,560 @@
+//! Hierarchical point clustering for dense marker layers
+//!
+//! Points are clustered greedily one zoom at a time from the most detailed
+//! level up, the way supercluster does it: every node that is not yet part of
+//! a cluster absorbs its unclustered neighbours within `radius` pixels, and
+//! the result becomes the input of the next zoom out. Nodes are kept in the
+//! Web Mercator pixel coordinates of their own zoom, so the radius covers the
+//! same distance on screen at every level.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::tile_utils::{LatLng, PixelCoord};
+use super::BoundingBox;
+
+/// Web Mercator cuts off at this latitude
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterOptions {
+    /// Points closer than this many CSS pixels at a zoom are clustered
+    pub radius: f64,
+    /// Fewest points a cluster may hold
+    pub min_points: usize,
+    pub min_zoom: u8,
+    /// Most detailed zoom that still clusters; above it every point is shown
+    pub max_zoom: u8,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        Self {
+            radius: 60.0,
+            min_points: 2,
+            min_zoom: 0,
+            max_zoom: 16,
+        }
+    }
+}
+
+/// Identifies a cluster across zooms: the zoom it was formed at and its
+/// position in that level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ClusterId(u64);
+
+impl ClusterId {
+    fn new(zoom: u8, index: usize) -> Self {
+        Self(((index as u64) << 5) | zoom as u64)
+    }
+
+    /// Zoom the cluster was formed at. It is shown at this zoom and the ones
+    /// below until it merges into a larger cluster.
+    pub fn zoom(&self) -> u8 {
+        (self.0 & 0x1f) as u8
+    }
+
+    fn index(&self) -> usize {
+        (self.0 >> 5) as usize
+    }
+}
+
+impl fmt::Display for ClusterId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A cluster or a single point as shown at one zoom
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClusterItem<'a, T> {
+    Cluster {
+        id: ClusterId,
+        /// Centroid of the clustered points
+        position: LatLng,
+        count: usize,
+        /// Zoom at which the cluster splits into its children
+        expansion_zoom: u8,
+        /// The clustered point nearest the centroid
+        representative: &'a T,
+    },
+    Point {
+        position: LatLng,
+        item: &'a T,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    /// Pixel position at the level's zoom; the centroid for clusters
+    position: PixelCoord,
+    count: usize,
+    /// `None` for single points
+    id: Option<ClusterId>,
+    /// Index into the input points of the member nearest the centroid, and
+    /// of the point itself for single points
+    representative: usize,
+    /// Nodes on the next zoom in that this one was made from
+    children: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Level {
+    nodes: Vec<Node>,
+    /// Node indices bucketed by `radius`-sized cells
+    grid: HashMap<(i64, i64), Vec<usize>>,
+    cell_size: f64,
+}
+
+impl Level {
+    fn new(nodes: Vec<Node>, cell_size: f64) -> Self {
+        let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (index, node) in nodes.iter().enumerate() {
+            grid.entry(cell(&node.position, cell_size)).or_default().push(index);
+        }
+        Self { nodes, grid, cell_size }
+    }
+
+    /// Nodes inside the pixel rectangle from `min` to `max`
+    fn range(&self, min: PixelCoord, max: PixelCoord) -> Vec<usize> {
+        let (min_cell, max_cell) = (cell(&min, self.cell_size), cell(&max, self.cell_size));
+        let cells = (max_cell.0 - min_cell.0 + 1).saturating_mul(max_cell.1 - min_cell.1 + 1);
+        let inside = |node: &Node| {
+            node.position.x >= min.x && node.position.x <= max.x && node.position.y >= min.y && node.position.y <= max.y
+        };
+
+        // Large views at high zooms span more cells than there are nodes
+        if cells > self.nodes.len() as i64 {
+            return (0..self.nodes.len()).filter(|&index| inside(&self.nodes[index])).collect();
+        }
+        (min_cell.0..=max_cell.0)
+            .flat_map(|x| (min_cell.1..=max_cell.1).map(move |y| (x, y)))
+            .filter_map(|key| self.grid.get(&key))
+            .flatten()
+            .copied()
+            .filter(|&index| inside(&self.nodes[index]))
+            .collect()
+    }
+
+    /// Nodes within `radius` pixels of `center`
+    fn within(&self, center: &PixelCoord, radius: f64) -> Vec<usize> {
+        let min = PixelCoord { x: center.x - radius, y: center.y - radius };
+        let max = PixelCoord { x: center.x + radius, y: center.y + radius };
+        self.range(min, max)
+            .into_iter()
+            .filter(|&index| squared_distance(&self.nodes[index].position, center) <= radius * radius)
+            .collect()
+    }
+}
+
+fn cell(position: &PixelCoord, cell_size: f64) -> (i64, i64) {
+    ((position.x / cell_size).floor() as i64, (position.y / cell_size).floor() as i64)
+}
+
+fn squared_distance(a: &PixelCoord, b: &PixelCoord) -> f64 {
+    (a.x - b.x).powi(2) + (a.y - b.y).powi(2)
+}
+
+fn pixel(position: &LatLng, zoom: u8) -> PixelCoord {
+    LatLng::new(position.lat.clamp(-MAX_LATITUDE, MAX_LATITUDE), position.lng).to_pixel_coord(zoom)
+}
+
+/// Build the level for `zoom` from `source`, the level for `zoom + 1`.
+/// `leaves` is the unclustered level, indexed like the input points.
+fn cluster_level(options: &ClusterOptions, leaves: &[Node], source: &Level, zoom: u8) -> Level {
+    let leaf_zoom = options.max_zoom + 1;
+    // Pixel radius on the source level, which is twice as large
+    let radius = options.radius * 2.0;
+    let mut clustered = vec![false; source.nodes.len()];
+    let mut nodes = Vec::new();
+
+    for (index, node) in source.nodes.iter().enumerate() {
+        if clustered[index] {
+            continue;
+        }
+        clustered[index] = true;
+
+        let neighbours: Vec<usize> = source
+            .within(&node.position, radius)
+            .into_iter()
+            .filter(|&neighbour| !clustered[neighbour])
+            .collect();
+        let count = node.count + neighbours.iter().map(|&neighbour| source.nodes[neighbour].count).sum::<usize>();
+
+        if neighbours.is_empty() || count < options.min_points {
+            nodes.push(Node {
+                position: PixelCoord { x: node.position.x / 2.0, y: node.position.y / 2.0 },
+                children: vec![index],
+                ..node.clone()
+            });
+            continue;
+        }
+
+        let mut children = vec![index];
+        let (mut x, mut y) = (node.position.x * node.count as f64, node.position.y * node.count as f64);
+        for &neighbour in &neighbours {
+            clustered[neighbour] = true;
+            let member = &source.nodes[neighbour];
+            x += member.position.x * member.count as f64;
+            y += member.position.y * member.count as f64;
+            children.push(neighbour);
+        }
+        let centroid = PixelCoord { x: x / count as f64, y: y / count as f64 };
+
+        // Compare on the leaf level, where the representatives' own
+        // positions are stored
+        let to_leaf = 2_f64.powi((leaf_zoom - zoom - 1) as i32);
+        let leaf_centroid = PixelCoord { x: centroid.x * to_leaf, y: centroid.y * to_leaf };
+        let representative = children
+            .iter()
+            .map(|&child| source.nodes[child].representative)
+            .min_by(|&a, &b| {
+                squared_distance(&leaves[a].position, &leaf_centroid)
+                    .total_cmp(&squared_distance(&leaves[b].position, &leaf_centroid))
+            })
+            .unwrap_or(node.representative);
+
+        nodes.push(Node {
+            position: PixelCoord { x: centroid.x / 2.0, y: centroid.y / 2.0 },
+            count,
+            id: Some(ClusterId::new(zoom, nodes.len())),
+            representative,
+            children,
+        });
+    }
+
+    Level::new(nodes, options.radius)
+}
+
+/// Clusters of `T` for every zoom from `min_zoom` to `max_zoom`, built once
+/// and queried per viewport
+#[derive(Debug, Clone)]
+pub struct ClusterIndex<T> {
+    options: ClusterOptions,
+    points: Vec<(LatLng, T)>,
+    /// One level per zoom from `min_zoom` to `max_zoom + 1`; the last holds
+    /// the input points unclustered
+    levels: Vec<Level>,
+}
+
+impl<T> ClusterIndex<T> {
+    /// Cluster `points`, skipping any outside the valid coordinate range
+    pub fn new(points: impl IntoIterator<Item = (LatLng, T)>, options: ClusterOptions) -> Self {
+        let max_zoom = options.max_zoom.min(30);
+        let options = ClusterOptions {
+            radius: options.radius.max(1.0),
+            min_points: options.min_points.max(2),
+            min_zoom: options.min_zoom.min(max_zoom),
+            max_zoom,
+        };
+        let points: Vec<(LatLng, T)> = points.into_iter().filter(|(position, _)| position.is_valid()).collect();
+
+        let leaf_zoom = options.max_zoom + 1;
+        let leaves = points
+            .iter()
+            .enumerate()
+            .map(|(index, (position, _))| Node {
+                position: pixel(position, leaf_zoom),
+                count: 1,
+                id: None,
+                representative: index,
+                children: Vec::new(),
+            })
+            .collect();
+
+        let mut levels = vec![Level::new(leaves, options.radius)];
+        for zoom in (options.min_zoom..=options.max_zoom).rev() {
+            let next = cluster_level(&options, &levels[0].nodes, levels.last().expect("leaf level"), zoom);
+            levels.push(next);
+        }
+        levels.reverse();
+
+        Self { options, points, levels }
+    }
+
+    pub fn options(&self) -> &ClusterOptions {
+        &self.options
+    }
+
+    /// The indexed points with their values, in input order
+    pub fn points(&self) -> &[(LatLng, T)] {
+        &self.points
+    }
+
+    /// Number of points indexed
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    fn level(&self, zoom: u8) -> Option<&Level> {
+        self.levels.get(zoom.checked_sub(self.options.min_zoom)? as usize)
+    }
+
+    /// Clusters and points inside `bounds` at `zoom`. Zooms past `max_zoom`
+    /// show every point. Bounds whose west edge is east of their east edge
+    /// cross the antimeridian.
+    pub fn clusters(&self, bounds: &BoundingBox, zoom: u8) -> Vec<ClusterItem<'_, T>> {
+        let zoom = zoom.clamp(self.options.min_zoom, self.options.max_zoom + 1);
+        let Some(level) = self.level(zoom) else {
+            return Vec::new();
+        };
+
+        let north_west = pixel(&LatLng::new(bounds.north, bounds.west), zoom);
+        let south_east = pixel(&LatLng::new(bounds.south, bounds.east), zoom);
+        let ranges = if bounds.west <= bounds.east {
+            vec![(north_west.x, south_east.x)]
+        } else {
+            let world = 256.0 * 2_f64.powi(zoom as i32);
+            vec![(north_west.x, world), (0.0, south_east.x)]
+        };
+
+        ranges
+            .into_iter()
+            .flat_map(|(min_x, max_x)| {
+                level.range(PixelCoord { x: min_x, y: north_west.y }, PixelCoord { x: max_x, y: south_east.y })
+            })
+            .map(|index| self.item(&level.nodes[index], zoom))
+            .collect()
+    }
+
+    fn item(&self, node: &Node, zoom: u8) -> ClusterItem<'_, T> {
+        let (position, item) = &self.points[node.representative];
+        match node.id {
+            Some(id) => ClusterItem::Cluster {
+                id,
+                position: node.position.to_lat_lng(zoom),
+                count: node.count,
+                expansion_zoom: id.zoom() + 1,
+                representative: item,
+            },
+            None => ClusterItem::Point { position: *position, item },
+        }
+    }
+
+    fn node(&self, id: ClusterId) -> Option<&Node> {
+        self.level(id.zoom())?.nodes.get(id.index()).filter(|node| node.id == Some(id))
+    }
+
+    /// Zoom at which the cluster breaks apart, for zooming in on a click
+    pub fn expansion_zoom(&self, id: ClusterId) -> Option<u8> {
+        self.node(id).map(|_| id.zoom() + 1)
+    }
+
+    /// What the cluster splits into one zoom further in
+    pub fn children(&self, id: ClusterId) -> Option<Vec<ClusterItem<'_, T>>> {
+        let node = self.node(id)?;
+        let zoom = id.zoom() + 1;
+        let level = self.level(zoom)?;
+        Some(node.children.iter().map(|&child| self.item(&level.nodes[child], zoom)).collect())
+    }
+
+    /// The points in the cluster, skipping `offset` and returning at most
+    /// `limit`
+    pub fn leaves(&self, id: ClusterId, limit: usize, offset: usize) -> Option<Vec<&T>> {
+        let node = self.node(id)?;
+        let mut points = Vec::new();
+        self.collect_leaves(node, id.zoom(), &mut points);
+        Some(points.into_iter().skip(offset).take(limit).map(|index| &self.points[index].1).collect())
+    }
+
+    fn collect_leaves(&self, node: &Node, zoom: u8, points: &mut Vec<usize>) {
+        if node.id.is_none() {
+            points.push(node.representative);
+            return;
+        }
+        let Some(level) = self.level(zoom + 1) else { return };
+        for &child in &node.children {
+            self.collect_leaves(&level.nodes[child], zoom + 1, points);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORLD: BoundingBox = BoundingBox {
+        north: 85.0,
+        south: -85.0,
+        east: 180.0,
+        west: -180.0,
+    };
+
+    /// Points `spacing` degrees of longitude apart along the equator
+    fn row(count: usize, spacing: f64) -> Vec<(LatLng, usize)> {
+        (0..count).map(|i| (LatLng::new(0.0, i as f64 * spacing), i)).collect()
+    }
+
+    fn count(items: &[ClusterItem<'_, usize>]) -> usize {
+        items
+            .iter()
+            .map(|item| match item {
+                ClusterItem::Cluster { count, .. } => *count,
+                ClusterItem::Point { .. } => 1,
+            })
+            .sum()
+    }
+
+    fn cluster_ids(items: &[ClusterItem<'_, usize>]) -> Vec<ClusterId> {
+        items
+            .iter()
+            .filter_map(|item| match item {
+                ClusterItem::Cluster { id, .. } => Some(*id),
+                ClusterItem::Point { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cluster_ids_hold_their_zoom_and_index() {
+        let id = ClusterId::new(17, 123_456);
+        assert_eq!(id.zoom(), 17);
+        assert_eq!(id.index(), 123_456);
+        assert_eq!(id.to_string(), ((123_456u64 << 5) | 17).to_string());
+    }
+
+    #[test]
+    fn clusters_points_closer_than_the_radius() {
+        // 0.01° is 58 pixels at zoom 13 and 116 at zoom 14
+        let index = ClusterIndex::new(row(2, 0.01), ClusterOptions::default());
+
+        let items = index.clusters(&WORLD, 13);
+        assert_eq!(items.len(), 1);
+        let ClusterItem::Cluster { id, count, expansion_zoom, position, .. } = items[0] else {
+            panic!("expected a cluster, got {:?}", items[0]);
+        };
+        assert_eq!((id.zoom(), count, expansion_zoom), (13, 2, 14));
+        assert!((position.lng - 0.005).abs() < 1e-9 && position.lat.abs() < 1e-9, "centroid {:?}", position);
+        assert_eq!(index.expansion_zoom(id), Some(14));
+
+        let items = index.clusters(&WORLD, 14);
+        assert_eq!(items.len(), 2);
+        assert!(items.iter().all(|item| matches!(item, ClusterItem::Point { .. })));
+    }
+
+    #[test]
+    fn keeps_every_point_at_every_zoom() {
+        let points: Vec<(LatLng, usize)> = (0..500)
+            .map(|i| (LatLng::new((i % 25) as f64 * 0.3 - 4.0, (i / 25) as f64 * 0.3 + 10.0), i))
+            .collect();
+        let index = ClusterIndex::new(points, ClusterOptions::default());
+        assert_eq!(index.len(), 500);
+
+        let mut previous = 0;
+        for zoom in 0..=18 {
+            let items = index.clusters(&WORLD, zoom);
+            assert_eq!(count(&items), 500, "zoom {}", zoom);
+            assert!(items.len() >= previous, "zoom {} shows fewer items than the zoom before", zoom);
+            previous = items.len();
+        }
+        assert_eq!(previous, 500, "past max_zoom every point is shown");
+    }
+
+    #[test]
+    fn skips_invalid_points() {
+        let mut points = row(3, 1.0);
+        points.push((LatLng::new(91.0, 0.0), 3));
+        points.push((LatLng::new(0.0, -181.0), 4));
+        let index = ClusterIndex::new(points, ClusterOptions::default());
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.points().iter().map(|(_, value)| *value).collect::<Vec<_>>(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn needs_min_points_to_form_a_cluster() {
+        let options = ClusterOptions { min_points: 3, ..ClusterOptions::default() };
+        let index = ClusterIndex::new(row(2, 0.01), options);
+        assert!(cluster_ids(&index.clusters(&WORLD, 0)).is_empty());
+
+        let index = ClusterIndex::new(row(3, 0.01), options);
+        let items = index.clusters(&WORLD, 0);
+        assert_eq!(items.len(), 1);
+        assert!(matches!(items[0], ClusterItem::Cluster { count: 3, .. }));
+    }
+
+    #[test]
+    fn represents_clusters_by_the_point_nearest_the_centroid() {
+        // Close enough to form one cluster at max_zoom
+        let index = ClusterIndex::new(row(3, 0.0001), ClusterOptions::default());
+        let items = index.clusters(&WORLD, 10);
+        assert!(matches!(items[..], [ClusterItem::Cluster { count: 3, representative: &1, .. }]));
+    }
+
+    #[test]
+    fn expands_clusters_into_children_and_leaves() {
+        // Two pairs 0.01° wide, 1° apart: the pairs split at zoom 14, and
+        // merge into one cluster at zoom 6, where 1° is 57 pixels
+        let points = vec![
+            (LatLng::new(0.0, 0.0), 0),
+            (LatLng::new(0.0, 0.01), 1),
+            (LatLng::new(0.0, 1.0), 2),
+            (LatLng::new(0.0, 1.01), 3),
+        ];
+        let index = ClusterIndex::new(points, ClusterOptions::default());
+
+        let top = cluster_ids(&index.clusters(&WORLD, 0));
+        assert_eq!(top.len(), 1);
+        let id = top[0];
+        assert_eq!(id.zoom(), 6);
+        assert_eq!(index.expansion_zoom(id), Some(7));
+
+        let children = index.children(id).unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(count(&children), 4);
+        for child in cluster_ids(&children) {
+            assert_eq!(child.zoom(), 13);
+            assert_eq!(index.expansion_zoom(child), Some(14));
+            let mut leaves: Vec<usize> = index.leaves(child, usize::MAX, 0).unwrap().into_iter().copied().collect();
+            leaves.sort_unstable();
+            assert!(leaves == [0, 1] || leaves == [2, 3], "leaves {:?}", leaves);
+        }
+
+        let mut leaves: Vec<usize> = index.leaves(id, usize::MAX, 0).unwrap().into_iter().copied().collect();
+        leaves.sort_unstable();
+        assert_eq!(leaves, [0, 1, 2, 3]);
+        assert_eq!(index.leaves(id, 2, 1).unwrap().len(), 2);
+        assert!(index.leaves(id, 10, 4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_cluster_ids() {
+        let index = ClusterIndex::new(row(2, 0.01), ClusterOptions::default());
+        let id = cluster_ids(&index.clusters(&WORLD, 13))[0];
+        let unknown = ClusterId::new(13, id.index() + 1);
+        let wrong_zoom = ClusterId::new(25, id.index());
+        for id in [unknown, wrong_zoom] {
+            assert_eq!(index.expansion_zoom(id), None);
+            assert!(index.children(id).is_none());
+            assert!(index.leaves(id, 10, 0).is_none());
+        }
+    }
+
+    #[test]
+    fn queries_bounds_across_the_antimeridian() {
+        let points = vec![
+            (LatLng::new(10.0, 179.9), 0),
+            (LatLng::new(10.0, -179.9), 1),
+            (LatLng::new(10.0, 0.0), 2),
+        ];
+        let index = ClusterIndex::new(points, ClusterOptions::default());
+        let bounds = BoundingBox::new(11.0, 9.0, -179.0, 179.0);
+        let mut found: Vec<usize> = index
+            .clusters(&bounds, 17)
+            .iter()
+            .map(|item| match item {
+                ClusterItem::Point { item, .. } => **item,
+                ClusterItem::Cluster { .. } => panic!("nothing clusters past max_zoom"),
+            })
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, [0, 1]);
+    }
+}
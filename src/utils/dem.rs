@@ -0,0 +1,232 @@
+//! Digital elevation model tiles in SRTM `.hgt` format
+//!
+//! Each file covers one degree of latitude and longitude and is named after its
+//! south-west corner, e.g. `N37W123.hgt`. Samples are big-endian `i16` meters in
+//! rows from north to south; SRTM1 files are 3601x3601 and SRTM3 files 1201x1201.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use moka::sync::Cache;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+/// Sample value used by SRTM for missing data
+pub const HGT_VOID: i16 = -32768;
+
+/// Memory budget for loaded tiles, about ten SRTM1 or eighty SRTM3 tiles
+pub const DEM_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum DemError {
+    #[error("Failed to read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("{0} has {1} bytes, which is not a square SRTM grid")]
+    InvalidSize(PathBuf, usize),
+}
+
+#[derive(Debug)]
+pub struct HgtTile {
+    /// Latitude of the southern edge
+    pub south: i32,
+    /// Longitude of the western edge
+    pub west: i32,
+    pub size: usize,
+    samples: Vec<i16>,
+}
+
+impl HgtTile {
+    pub fn load(path: &Path, south: i32, west: i32) -> Result<Self, DemError> {
+        let bytes = fs::read(path).map_err(|e| DemError::Io(path.to_path_buf(), e))?;
+        Self::from_bytes(&bytes, south, west).ok_or_else(|| DemError::InvalidSize(path.to_path_buf(), bytes.len()))
+    }
+
+    pub fn from_bytes(bytes: &[u8], south: i32, west: i32) -> Option<Self> {
+        let count = bytes.len() / 2;
+        let size = (count as f64).sqrt() as usize;
+        if bytes.len() % 2 != 0 || size < 2 || size * size != count {
+            return None;
+        }
+
+        let samples = bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+
+        Some(Self { south, west, size, samples })
+    }
+
+    fn sample(&self, row: usize, col: usize) -> Option<f64> {
+        let value = self.samples[row * self.size + col];
+        (value != HGT_VOID).then_some(value as f64)
+    }
+
+    /// Memory held by the samples
+    pub fn byte_size(&self) -> usize {
+        self.samples.len() * std::mem::size_of::<i16>()
+    }
+
+    /// Bilinearly interpolated elevation in meters. Void samples are skipped and
+    /// the remaining neighbors reweighted; `None` when all four are void.
+    pub fn elevation_at(&self, lat: f64, lng: f64) -> Option<f64> {
+        let cells = (self.size - 1) as f64;
+        let row = ((self.south as f64 + 1.0 - lat) * cells).clamp(0.0, cells);
+        let col = ((lng - self.west as f64) * cells).clamp(0.0, cells);
+
+        let (row0, col0) = (row.floor() as usize, col.floor() as usize);
+        let (row1, col1) = ((row0 + 1).min(self.size - 1), (col0 + 1).min(self.size - 1));
+        let (dy, dx) = (row - row0 as f64, col - col0 as f64);
+
+        let neighbors = [
+            (self.sample(row0, col0), (1.0 - dx) * (1.0 - dy)),
+            (self.sample(row0, col1), dx * (1.0 - dy)),
+            (self.sample(row1, col0), (1.0 - dx) * dy),
+            (self.sample(row1, col1), dx * dy),
+        ];
+
+        let (sum, weight) = neighbors
+            .iter()
+            .filter_map(|(value, weight)| value.map(|v| (v * weight, *weight)))
+            .fold((0.0, 0.0), |(sum, total), (value, weight)| (sum + value, total + weight));
+
+        if weight > 0.0 {
+            Some(sum / weight)
+        } else {
+            neighbors.iter().find_map(|(value, _)| *value)
+        }
+    }
+}
+
+/// Lazily loaded set of `.hgt` tiles from a directory. Loaded tiles are kept
+/// up to a byte budget, least recently used first out; missing tiles are
+/// remembered too so the directory is not probed on every lookup.
+pub struct DemStore {
+    directory: PathBuf,
+    tiles: Cache<(i32, i32), Option<Arc<HgtTile>>>,
+}
+
+impl DemStore {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self::with_capacity(directory, DEM_CACHE_BYTES)
+    }
+
+    pub fn with_capacity(directory: impl Into<PathBuf>, capacity_bytes: u64) -> Self {
+        Self {
+            directory: directory.into(),
+            tiles: Cache::builder()
+                .max_capacity(capacity_bytes)
+                .weigher(|_, tile: &Option<Arc<HgtTile>>| {
+                    tile.as_ref()
+                        .map_or(1, |tile| tile.byte_size().try_into().unwrap_or(u32::MAX))
+                })
+                .build(),
+        }
+    }
+
+    /// Elevation in meters, or `None` outside the available tiles
+    pub fn elevation_at(&self, lat: f64, lng: f64) -> Option<f64> {
+        if !(-90.0..90.0).contains(&lat) || !(-180.0..180.0).contains(&lng) {
+            return None;
+        }
+        self.tile(lat.floor() as i32, lng.floor() as i32)?.elevation_at(lat, lng)
+    }
+
+    pub fn tile(&self, south: i32, west: i32) -> Option<Arc<HgtTile>> {
+        self.tiles.get_with((south, west), || self.load(south, west))
+    }
+
+    fn load(&self, south: i32, west: i32) -> Option<Arc<HgtTile>> {
+        let path = self.directory.join(tile_file_name(south, west));
+        if !path.exists() {
+            return None;
+        }
+
+        match HgtTile::load(&path, south, west) {
+            Ok(tile) => {
+                debug!("Loaded DEM tile {:?} ({}x{})", path, tile.size, tile.size);
+                Some(Arc::new(tile))
+            }
+            Err(e) => {
+                warn!("Ignoring DEM tile: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// File name of the tile whose south-west corner is at (`south`, `west`)
+pub fn tile_file_name(south: i32, west: i32) -> String {
+    format!(
+        "{}{:02}{}{:03}.hgt",
+        if south >= 0 { 'N' } else { 'S' },
+        south.abs(),
+        if west >= 0 { 'E' } else { 'W' },
+        west.abs()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x3 tile, rows from north to south
+    fn grid(samples: [i16; 9]) -> Vec<u8> {
+        samples.iter().flat_map(|value| value.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn rejects_grids_that_are_not_square() {
+        assert!(HgtTile::from_bytes(&[0; 16], 37, -123).is_none());
+        assert!(HgtTile::from_bytes(&[0; 17], 37, -123).is_none());
+        assert!(HgtTile::from_bytes(&[0; 2], 37, -123).is_none());
+        assert_eq!(HgtTile::from_bytes(&[0; 18], 37, -123).unwrap().size, 3);
+    }
+
+    #[test]
+    fn samples_corners_and_interpolates_between_them() {
+        let tile = HgtTile::from_bytes(&grid([100, 200, 300, 400, 500, 600, 700, 800, 900]), 37, -123).unwrap();
+
+        // North-west is the first sample, south-east the last
+        assert_eq!(tile.elevation_at(38.0, -123.0), Some(100.0));
+        assert_eq!(tile.elevation_at(37.0, -122.0), Some(900.0));
+        assert_eq!(tile.elevation_at(37.5, -122.5), Some(500.0));
+        assert_eq!(tile.elevation_at(37.75, -122.75), Some(300.0));
+    }
+
+    #[test]
+    fn skips_void_samples() {
+        let tile = HgtTile::from_bytes(&grid([HGT_VOID, 200, 0, 400, 0, 0, 0, 0, 0]), 37, -123).unwrap();
+        // Halfway between the void corner and its neighbors only they count
+        let value = tile.elevation_at(37.75, -122.75).unwrap();
+        assert!((value - 200.0).abs() < 1e-9, "{}", value);
+
+        let void = HgtTile::from_bytes(&grid([HGT_VOID; 9]), 37, -123).unwrap();
+        assert_eq!(void.elevation_at(37.5, -122.5), None);
+    }
+
+    #[test]
+    fn names_tiles_after_their_south_west_corner() {
+        assert_eq!(tile_file_name(37, -123), "N37W123.hgt");
+        assert_eq!(tile_file_name(-9, 5), "S09E005.hgt");
+    }
+
+    #[test]
+    fn loads_tiles_from_the_directory_within_the_budget() {
+        let directory = std::env::temp_dir().join(format!("dem-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("N37W123.hgt"), grid([100; 9])).unwrap();
+        fs::write(directory.join("N38W123.hgt"), grid([200; 9])).unwrap();
+
+        let store = DemStore::with_capacity(&directory, 18);
+        assert_eq!(store.elevation_at(37.5, -122.5), Some(100.0));
+        assert_eq!(store.elevation_at(38.5, -122.5), Some(200.0));
+        assert_eq!(store.elevation_at(10.5, 10.5), None);
+        assert_eq!(store.elevation_at(90.0, 0.0), None);
+
+        store.tiles.run_pending_tasks();
+        assert!(store.tiles.weighted_size() <= 18);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
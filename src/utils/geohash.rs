@@ -0,0 +1,462 @@
+//! Geohash encoding, decoding and neighbor lookup
+//!
+//! Geohashes interleave longitude and latitude bits into a base32 string so that
+//! nearby points share a common prefix. The proximity search uses this to turn a
+//! radius query into a handful of prefix range scans on an ordinary B-tree index.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+/// Length of a degree of latitude on the sphere used for haversine distances
+const METERS_PER_DEGREE: f64 = 6_371_000.0 * std::f64::consts::PI / 180.0;
+
+pub const MAX_PRECISION: usize = 12;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum GeohashError {
+    #[error("Invalid geohash character: {0}")]
+    InvalidCharacter(char),
+    #[error("Geohash precision must be between 1 and {MAX_PRECISION}, got {0}")]
+    InvalidPrecision(usize),
+    #[error("Coordinate out of range: ({0}, {1})")]
+    InvalidCoordinate(f64, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 8] = [
+        Direction::North,
+        Direction::NorthEast,
+        Direction::East,
+        Direction::SouthEast,
+        Direction::South,
+        Direction::SouthWest,
+        Direction::West,
+        Direction::NorthWest,
+    ];
+
+    fn offsets(self) -> (f64, f64) {
+        match self {
+            Direction::North => (1.0, 0.0),
+            Direction::NorthEast => (1.0, 1.0),
+            Direction::East => (0.0, 1.0),
+            Direction::SouthEast => (-1.0, 1.0),
+            Direction::South => (-1.0, 0.0),
+            Direction::SouthWest => (-1.0, -1.0),
+            Direction::West => (0.0, -1.0),
+            Direction::NorthWest => (1.0, -1.0),
+        }
+    }
+}
+
+/// The rectangular cell covered by a geohash
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeohashCell {
+    pub north: f64,
+    pub south: f64,
+    pub east: f64,
+    pub west: f64,
+}
+
+impl GeohashCell {
+    pub fn center(&self) -> (f64, f64) {
+        ((self.north + self.south) / 2.0, (self.east + self.west) / 2.0)
+    }
+
+    pub fn lat_span(&self) -> f64 {
+        self.north - self.south
+    }
+
+    pub fn lng_span(&self) -> f64 {
+        self.east - self.west
+    }
+}
+
+pub fn encode(lat: f64, lng: f64, precision: usize) -> Result<String, GeohashError> {
+    if precision == 0 || precision > MAX_PRECISION {
+        return Err(GeohashError::InvalidPrecision(precision));
+    }
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return Err(GeohashError::InvalidCoordinate(lat, lng));
+    }
+
+    let (mut lat_min, mut lat_max) = (-90.0, 90.0);
+    let (mut lng_min, mut lng_max) = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut even_bit = true;
+
+    while hash.len() < precision {
+        let mut index = 0usize;
+        for _ in 0..5 {
+            index <<= 1;
+            if even_bit {
+                let mid = (lng_min + lng_max) / 2.0;
+                if lng >= mid {
+                    index |= 1;
+                    lng_min = mid;
+                } else {
+                    lng_max = mid;
+                }
+            } else {
+                let mid = (lat_min + lat_max) / 2.0;
+                if lat >= mid {
+                    index |= 1;
+                    lat_min = mid;
+                } else {
+                    lat_max = mid;
+                }
+            }
+            even_bit = !even_bit;
+        }
+        hash.push(BASE32[index] as char);
+    }
+
+    Ok(hash)
+}
+
+pub fn decode_bounds(hash: &str) -> Result<GeohashCell, GeohashError> {
+    if hash.is_empty() || hash.len() > MAX_PRECISION {
+        return Err(GeohashError::InvalidPrecision(hash.len()));
+    }
+
+    let (mut lat_min, mut lat_max) = (-90.0, 90.0);
+    let (mut lng_min, mut lng_max) = (-180.0, 180.0);
+    let mut even_bit = true;
+
+    for c in hash.chars() {
+        let index = char_index(c)?;
+        for bit in (0..5).rev() {
+            let set = (index >> bit) & 1 == 1;
+            if even_bit {
+                let mid = (lng_min + lng_max) / 2.0;
+                if set {
+                    lng_min = mid;
+                } else {
+                    lng_max = mid;
+                }
+            } else {
+                let mid = (lat_min + lat_max) / 2.0;
+                if set {
+                    lat_min = mid;
+                } else {
+                    lat_max = mid;
+                }
+            }
+            even_bit = !even_bit;
+        }
+    }
+
+    Ok(GeohashCell {
+        north: lat_max,
+        south: lat_min,
+        east: lng_max,
+        west: lng_min,
+    })
+}
+
+/// Decode a geohash to the center of its cell
+pub fn decode(hash: &str) -> Result<(f64, f64), GeohashError> {
+    decode_bounds(hash).map(|cell| cell.center())
+}
+
+/// The adjacent cell of the same precision, or `None` when it would lie beyond a pole.
+/// Longitude wraps across the antimeridian.
+pub fn neighbor(hash: &str, direction: Direction) -> Result<Option<String>, GeohashError> {
+    let cell = decode_bounds(hash)?;
+    let (lat, lng) = cell.center();
+    let (dlat, dlng) = direction.offsets();
+
+    let neighbor_lat = lat + dlat * cell.lat_span();
+    if !(-90.0..=90.0).contains(&neighbor_lat) {
+        return Ok(None);
+    }
+    let neighbor_lng = wrap_longitude(lng + dlng * cell.lng_span());
+
+    encode(neighbor_lat, neighbor_lng, hash.len()).map(Some)
+}
+
+/// All existing neighbors of a cell, in `Direction::ALL` order
+pub fn neighbors(hash: &str) -> Result<Vec<String>, GeohashError> {
+    let mut cells = Vec::with_capacity(8);
+    for direction in Direction::ALL {
+        if let Some(cell) = neighbor(hash, direction)? {
+            if !cells.contains(&cell) {
+                cells.push(cell);
+            }
+        }
+    }
+    Ok(cells)
+}
+
+/// The longest precision whose cells are at least `radius_m` tall and wide at `lat`,
+/// so that a circle of that radius never spans more than a 3x3 block of cells.
+pub fn precision_for_radius(lat: f64, radius_m: f64) -> usize {
+    let cos_lat = lat.to_radians().cos().max(1e-6);
+
+    (1..=MAX_PRECISION)
+        .rev()
+        .find(|&precision| {
+            let (lat_span, lng_span) = cell_span(precision);
+            lat_span * METERS_PER_DEGREE >= radius_m
+                && lng_span * METERS_PER_DEGREE * cos_lat >= radius_m
+        })
+        .unwrap_or(1)
+}
+
+/// Geohash cells that together cover the circle of `radius_m` around a point.
+/// Every point within the radius is guaranteed to fall in one of the returned cells.
+pub fn cover_radius(lat: f64, lng: f64, radius_m: f64) -> Result<Vec<String>, GeohashError> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return Err(GeohashError::InvalidCoordinate(lat, lng));
+    }
+
+    let precision = precision_for_radius(lat, radius_m);
+    let (lat_span, lng_span) = cell_span(precision);
+
+    let dlat = radius_m / METERS_PER_DEGREE;
+    let north = (lat + dlat).min(90.0);
+    let south = (lat - dlat).max(-90.0);
+
+    // Use the widest latitude in the box for the longitude extent; near the poles
+    // the circle wraps all the way around.
+    let max_abs_lat = north.abs().max(south.abs());
+    let cos_lat = max_abs_lat.to_radians().cos();
+    let dlng = if cos_lat < 1e-6 {
+        180.0
+    } else {
+        (radius_m / (METERS_PER_DEGREE * cos_lat)).min(180.0)
+    };
+
+    // A circle spanning every longitude is walked from -180 so that wrapping
+    // does not fold its two ends onto the same cell
+    let (west, lng_extent) = if dlng >= 180.0 { (-180.0, 360.0) } else { (lng - dlng, 2.0 * dlng) };
+
+    let mut cells = Vec::new();
+    let lat_steps = ((north - south) / lat_span).ceil() as usize + 1;
+    let lng_steps = (lng_extent / lng_span).ceil() as usize + 1;
+
+    for i in 0..=lat_steps {
+        let cell_lat = (south + i as f64 * lat_span).min(north);
+        for j in 0..=lng_steps {
+            let cell_lng = wrap_longitude((west + j as f64 * lng_span).min(west + lng_extent));
+            let cell = encode(cell_lat, cell_lng, precision)?;
+            if !cells.contains(&cell) {
+                cells.push(cell);
+            }
+        }
+    }
+
+    Ok(cells)
+}
+
+/// Cell height and width in degrees at a given precision
+pub fn cell_span(precision: usize) -> (f64, f64) {
+    let bits = precision * 5;
+    let lng_bits = bits.div_ceil(2);
+    let lat_bits = bits / 2;
+    (180.0 / 2f64.powi(lat_bits as i32), 360.0 / 2f64.powi(lng_bits as i32))
+}
+
+fn char_index(c: char) -> Result<usize, GeohashError> {
+    BASE32
+        .iter()
+        .position(|&b| b as char == c.to_ascii_lowercase())
+        .ok_or(GeohashError::InvalidCharacter(c))
+}
+
+fn wrap_longitude(lng: f64) -> f64 {
+    let mut wrapped = lng;
+    while wrapped > 180.0 {
+        wrapped -= 360.0;
+    }
+    while wrapped < -180.0 {
+        wrapped += 360.0;
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tile_utils::LatLng;
+
+    /// Deterministic xorshift so failures are reproducible without a `rand` dependency
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_f64(&mut self) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn range(&mut self, min: f64, max: f64) -> f64 {
+            min + self.next_f64() * (max - min)
+        }
+    }
+
+    fn distance_m(from: (f64, f64), to: (f64, f64)) -> f64 {
+        LatLng::new(from.0, from.1).distance_to(&LatLng::new(to.0, to.1))
+    }
+
+    /// A point `distance` meters from `origin` along `bearing` degrees
+    fn destination(origin: (f64, f64), bearing: f64, distance: f64) -> (f64, f64) {
+        let angular = distance / 6_371_000.0;
+        let (lat1, lng1, bearing) = (origin.0.to_radians(), origin.1.to_radians(), bearing.to_radians());
+        let lat2 = (lat1.sin() * angular.cos() + lat1.cos() * angular.sin() * bearing.cos()).asin();
+        let lng2 = lng1
+            + (bearing.sin() * angular.sin() * lat1.cos()).atan2(angular.cos() - lat1.sin() * lat2.sin());
+        (lat2.to_degrees(), wrap_longitude(lng2.to_degrees()))
+    }
+
+    #[test]
+    fn encodes_known_value() {
+        assert_eq!(encode(57.64911, 10.40744, 11).unwrap(), "u4pruydqqvj");
+        assert_eq!(encode(-90.0, -180.0, 4).unwrap(), "0000");
+        assert_eq!(encode(90.0, 180.0, 4).unwrap(), "zzzz");
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert_eq!(encode(91.0, 0.0, 5), Err(GeohashError::InvalidCoordinate(91.0, 0.0)));
+        assert_eq!(encode(0.0, 0.0, 0), Err(GeohashError::InvalidPrecision(0)));
+        assert_eq!(encode(0.0, 0.0, MAX_PRECISION + 1), Err(GeohashError::InvalidPrecision(MAX_PRECISION + 1)));
+        assert_eq!(decode("u4a"), Err(GeohashError::InvalidCharacter('a')));
+    }
+
+    #[test]
+    fn decode_round_trips_encode() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..1000 {
+            let (lat, lng) = (rng.range(-90.0, 90.0), rng.range(-180.0, 180.0));
+            for precision in 1..=MAX_PRECISION {
+                let hash = encode(lat, lng, precision).unwrap();
+                let cell = decode_bounds(&hash).unwrap();
+                assert!(cell.south <= lat && lat <= cell.north, "{} not in {:?}", lat, cell);
+                assert!(cell.west <= lng && lng <= cell.east, "{} not in {:?}", lng, cell);
+
+                let (lat_span, lng_span) = cell_span(precision);
+                assert!((cell.lat_span() - lat_span).abs() < 1e-9);
+                assert!((cell.lng_span() - lng_span).abs() < 1e-9);
+
+                let (center_lat, center_lng) = decode(&hash).unwrap();
+                assert_eq!(encode(center_lat, center_lng, precision).unwrap(), hash);
+            }
+        }
+    }
+
+    #[test]
+    fn decoding_is_case_insensitive() {
+        assert_eq!(decode_bounds("U4PRUY").unwrap(), decode_bounds("u4pruy").unwrap());
+    }
+
+    #[test]
+    fn neighbors_share_an_edge() {
+        assert_eq!(neighbor("ezzz", Direction::East).unwrap().as_deref(), Some("spbp"));
+        assert_eq!(neighbor("ezzz", Direction::North).unwrap().as_deref(), Some("gbpb"));
+        assert_eq!(neighbor("spbp", Direction::West).unwrap().as_deref(), Some("ezzz"));
+
+        let cell = decode_bounds("u4pruy").unwrap();
+        let north = decode_bounds(&neighbor("u4pruy", Direction::North).unwrap().unwrap()).unwrap();
+        let south_west = decode_bounds(&neighbor("u4pruy", Direction::SouthWest).unwrap().unwrap()).unwrap();
+        assert_eq!(north.south, cell.north);
+        assert_eq!(north.west, cell.west);
+        assert_eq!(south_west.north, cell.south);
+        assert_eq!(south_west.east, cell.west);
+        assert_eq!(neighbors("u4pruy").unwrap().len(), 8);
+    }
+
+    #[test]
+    fn neighbors_wrap_across_the_antimeridian() {
+        let east_edge = encode(10.0, 179.99, 5).unwrap();
+        let across = decode_bounds(&neighbor(&east_edge, Direction::East).unwrap().unwrap()).unwrap();
+        assert_eq!(across.west, -180.0);
+
+        let west_edge = encode(10.0, -179.99, 5).unwrap();
+        let across = decode_bounds(&neighbor(&west_edge, Direction::West).unwrap().unwrap()).unwrap();
+        assert_eq!(across.east, 180.0);
+        assert_eq!(neighbors(&west_edge).unwrap().len(), 8);
+    }
+
+    #[test]
+    fn neighbors_stop_at_the_poles() {
+        let top = encode(89.99, 0.0, 4).unwrap();
+        assert_eq!(neighbor(&top, Direction::North).unwrap(), None);
+        assert_eq!(neighbor(&top, Direction::NorthEast).unwrap(), None);
+        assert_eq!(neighbors(&top).unwrap().len(), 5);
+
+        let bottom = encode(-89.99, 0.0, 4).unwrap();
+        assert_eq!(neighbor(&bottom, Direction::South).unwrap(), None);
+        assert_eq!(neighbors(&bottom).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn cover_radius_contains_points_on_the_circle() {
+        let centers = [(0.0, 0.0), (52.52, 13.405), (-33.87, 151.21), (0.0, 179.999), (-12.0, -179.9), (89.5, 45.0), (-89.9, -120.0)];
+        for &center in &centers {
+            for radius in [10.0, 250.0, 1_000.0, 25_000.0, 400_000.0] {
+                let cells = cover_radius(center.0, center.1, radius).unwrap();
+                assert!(!cells.is_empty());
+                for step in 0..72 {
+                    let point = destination(center, step as f64 * 5.0, radius * 0.9999);
+                    let hash = encode(point.0, point.1, MAX_PRECISION).unwrap();
+                    assert!(
+                        cells.iter().any(|cell| hash.starts_with(cell.as_str())),
+                        "{:?} at {} m from {:?} is outside {:?}",
+                        point,
+                        radius,
+                        center,
+                        cells
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cover_radius_stays_small() {
+        let cells = cover_radius(52.52, 13.405, 1_000.0).unwrap();
+        assert!(cells.len() <= 16, "{} cells", cells.len());
+        assert!(cells.iter().all(|cell| cell.len() == precision_for_radius(52.52, 1_000.0)));
+    }
+
+    /// The prefix scan in `Database::search_nearby` must never drop a location
+    /// that a brute-force haversine filter over every row would return
+    #[test]
+    fn prefix_candidates_cover_brute_force_results() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let center = (rng.range(-85.0, 85.0), rng.range(-180.0, 180.0));
+            let radius = 10f64.powf(rng.range(1.0, 5.5));
+            let cells = cover_radius(center.0, center.1, radius).unwrap();
+
+            for _ in 0..200 {
+                let point = destination(center, rng.range(0.0, 360.0), radius * rng.range(0.0, 1.5));
+                let within = distance_m(center, point) <= radius;
+                let hash = encode(point.0, point.1, MAX_PRECISION).unwrap();
+                let candidate = cells.iter().any(|cell| hash.starts_with(cell.as_str()));
+                assert!(
+                    candidate || !within,
+                    "{:?} is {} m from {:?} (radius {}) but not a candidate",
+                    point,
+                    distance_m(center, point),
+                    center,
+                    radius
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,528 @@
+//! GPX 1.1 reading and writing
+//!
+//! Only the parts of the format that handheld GPS units exchange are supported:
+//! waypoints, routes and tracks with their names, descriptions, elevation and time.
+
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const GPX_NAMESPACE: &str = "http://www.topografix.com/GPX/1/1";
+pub const GPX_CREATOR: &str = "googlemaps-clone";
+
+/// Upper bound on points accepted from an uploaded file
+pub const MAX_IMPORT_POINTS: usize = 100_000;
+
+#[derive(Debug, Error)]
+pub enum GpxError {
+    #[error("Malformed XML: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("Document root is not a <gpx> element")]
+    NotGpx,
+    #[error("Unsupported GPX version: {0}")]
+    UnsupportedVersion(String),
+    #[error("<{0}> is missing the {1} attribute")]
+    MissingAttribute(String, &'static str),
+    #[error("Invalid {0} value: {1}")]
+    InvalidValue(&'static str, String),
+    #[error("Document contains more than {MAX_IMPORT_POINTS} points")]
+    TooManyPoints,
+    #[error("Document contains no waypoints, routes or tracks")]
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GpxPoint {
+    pub lat: f64,
+    pub lon: f64,
+    pub elevation: Option<f64>,
+    pub time: Option<DateTime<Utc>>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+impl GpxPoint {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self {
+            lat,
+            lon,
+            elevation: None,
+            time: None,
+            name: None,
+            description: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GpxRoute {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub points: Vec<GpxPoint>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GpxTrack {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub segments: Vec<Vec<GpxPoint>>,
+}
+
+impl GpxTrack {
+    pub fn points(&self) -> impl Iterator<Item = &GpxPoint> {
+        self.segments.iter().flatten()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GpxDocument {
+    pub name: Option<String>,
+    pub waypoints: Vec<GpxPoint>,
+    pub routes: Vec<GpxRoute>,
+    pub tracks: Vec<GpxTrack>,
+}
+
+impl GpxDocument {
+    pub fn point_count(&self) -> usize {
+        self.waypoints.len()
+            + self.routes.iter().map(|r| r.points.len()).sum::<usize>()
+            + self.tracks.iter().map(|t| t.points().count()).sum::<usize>()
+    }
+
+    /// Every route, then every track segment, as a separate line. Segments
+    /// of a track are kept apart because the gap between them was never
+    /// travelled: the receiver lost its fix or recording was paused. Lines
+    /// of a split track are numbered after it, e.g. "Morning ride (2/3)".
+    pub fn lines(&self) -> Vec<GpxRoute> {
+        let mut lines = self.routes.clone();
+        for track in &self.tracks {
+            let segments: Vec<_> = track.segments.iter().filter(|segment| !segment.is_empty()).collect();
+            let count = segments.len();
+            for (i, segment) in segments.into_iter().enumerate() {
+                let name = match &track.name {
+                    Some(name) if count > 1 => Some(format!("{} ({}/{})", name, i + 1, count)),
+                    name => name.clone(),
+                };
+                lines.push(GpxRoute {
+                    name,
+                    description: track.description.clone(),
+                    points: segment.clone(),
+                });
+            }
+        }
+        lines
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<gpx version=\"1.1\" creator=\"{}\" xmlns=\"{}\">",
+            GPX_CREATOR, GPX_NAMESPACE
+        );
+
+        if let Some(name) = &self.name {
+            let _ = writeln!(xml, "  <metadata><name>{}</name></metadata>", escape(name));
+        }
+
+        for waypoint in &self.waypoints {
+            write_point(&mut xml, "wpt", waypoint, 1);
+        }
+
+        for route in &self.routes {
+            xml.push_str("  <rte>\n");
+            write_name_desc(&mut xml, &route.name, &route.description, 2);
+            for point in &route.points {
+                write_point(&mut xml, "rtept", point, 2);
+            }
+            xml.push_str("  </rte>\n");
+        }
+
+        for track in &self.tracks {
+            xml.push_str("  <trk>\n");
+            write_name_desc(&mut xml, &track.name, &track.description, 2);
+            for segment in &track.segments {
+                xml.push_str("    <trkseg>\n");
+                for point in segment {
+                    write_point(&mut xml, "trkpt", point, 3);
+                }
+                xml.push_str("    </trkseg>\n");
+            }
+            xml.push_str("  </trk>\n");
+        }
+
+        xml.push_str("</gpx>\n");
+        xml
+    }
+
+    pub fn parse(input: &str) -> Result<Self, GpxError> {
+        let mut reader = Reader::from_str(input);
+        reader.trim_text(true);
+
+        let mut document = GpxDocument::default();
+        let mut path: Vec<String> = Vec::new();
+        let mut point: Option<GpxPoint> = None;
+        let mut route: Option<GpxRoute> = None;
+        let mut track: Option<GpxTrack> = None;
+        let mut segment: Option<Vec<GpxPoint>> = None;
+        let mut seen_root = false;
+        let mut points = 0usize;
+        // Character data of the innermost open element, which may arrive as
+        // several text and CDATA events
+        let mut text = String::new();
+
+        loop {
+            let event = reader.read_event()?;
+            let (start, is_empty) = match &event {
+                Event::Start(e) => (Some(e.clone()), false),
+                Event::Empty(e) => (Some(e.clone()), true),
+                _ => (None, false),
+            };
+
+            if let Some(element) = start {
+                let name = local_name(&element);
+                if !seen_root {
+                    if name != "gpx" {
+                        return Err(GpxError::NotGpx);
+                    }
+                    check_version(&element)?;
+                    seen_root = true;
+                }
+
+                match name.as_str() {
+                    "wpt" | "rtept" | "trkpt" => {
+                        points += 1;
+                        if points > MAX_IMPORT_POINTS {
+                            return Err(GpxError::TooManyPoints);
+                        }
+                        point = Some(parse_point(&element, &name)?);
+                    }
+                    "rte" => route = Some(GpxRoute::default()),
+                    "trk" => track = Some(GpxTrack::default()),
+                    "trkseg" => segment = Some(Vec::new()),
+                    _ => {}
+                }
+
+                text.clear();
+                if is_empty {
+                    finish_element(&name, &mut document, &mut point, &mut route, &mut track, &mut segment);
+                } else {
+                    path.push(name);
+                }
+                continue;
+            }
+
+            match event {
+                Event::Text(data) => text.push_str(&data.unescape()?),
+                Event::CData(data) => text.push_str(&reader.decoder().decode(&data)?),
+                Event::End(_) => {
+                    let Some(current) = path.pop() else {
+                        continue;
+                    };
+                    let value = std::mem::take(&mut text);
+
+                    if !value.is_empty() {
+                        match (path.last().map(String::as_str), current.as_str()) {
+                            (Some("wpt" | "rtept" | "trkpt"), field) => {
+                                if let Some(point) = point.as_mut() {
+                                    set_point_field(point, field, value)?;
+                                }
+                            }
+                            (Some("rte"), field) => {
+                                if let Some(route) = route.as_mut() {
+                                    set_name_desc(&mut route.name, &mut route.description, field, value);
+                                }
+                            }
+                            (Some("trk"), field) => {
+                                if let Some(track) = track.as_mut() {
+                                    set_name_desc(&mut track.name, &mut track.description, field, value);
+                                }
+                            }
+                            (Some("metadata"), "name") => document.name = Some(value),
+                            _ => {}
+                        }
+                    }
+
+                    finish_element(&current, &mut document, &mut point, &mut route, &mut track, &mut segment);
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if !seen_root {
+            return Err(GpxError::NotGpx);
+        }
+        if document.point_count() == 0 {
+            return Err(GpxError::Empty);
+        }
+
+        Ok(document)
+    }
+}
+
+fn finish_element(
+    name: &str,
+    document: &mut GpxDocument,
+    point: &mut Option<GpxPoint>,
+    route: &mut Option<GpxRoute>,
+    track: &mut Option<GpxTrack>,
+    segment: &mut Option<Vec<GpxPoint>>,
+) {
+    match name {
+        "wpt" => document.waypoints.extend(point.take()),
+        "rtept" => {
+            if let (Some(route), Some(point)) = (route.as_mut(), point.take()) {
+                route.points.push(point);
+            }
+        }
+        "trkpt" => {
+            if let (Some(segment), Some(point)) = (segment.as_mut(), point.take()) {
+                segment.push(point);
+            }
+        }
+        "trkseg" => {
+            if let (Some(track), Some(segment)) = (track.as_mut(), segment.take()) {
+                if !segment.is_empty() {
+                    track.segments.push(segment);
+                }
+            }
+        }
+        "rte" => document.routes.extend(route.take().filter(|r| !r.points.is_empty())),
+        "trk" => document.tracks.extend(track.take().filter(|t| !t.segments.is_empty())),
+        _ => {}
+    }
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).into_owned()
+}
+
+fn check_version(element: &BytesStart) -> Result<(), GpxError> {
+    if let Some(version) = element.try_get_attribute("version")? {
+        let version = version.unescape_value()?.into_owned();
+        if version != "1.1" && version != "1.0" {
+            return Err(GpxError::UnsupportedVersion(version));
+        }
+    }
+    Ok(())
+}
+
+fn parse_point(element: &BytesStart, name: &str) -> Result<GpxPoint, GpxError> {
+    let lat = coordinate_attribute(element, name, "lat")?;
+    let lon = coordinate_attribute(element, name, "lon")?;
+
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(GpxError::InvalidValue("lat", lat.to_string()));
+    }
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(GpxError::InvalidValue("lon", lon.to_string()));
+    }
+
+    Ok(GpxPoint::new(lat, lon))
+}
+
+fn coordinate_attribute(element: &BytesStart, name: &str, attribute: &'static str) -> Result<f64, GpxError> {
+    let value = element
+        .try_get_attribute(attribute)?
+        .ok_or_else(|| GpxError::MissingAttribute(name.to_string(), attribute))?
+        .unescape_value()?
+        .into_owned();
+
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or(GpxError::InvalidValue(attribute, value))
+}
+
+fn set_point_field(point: &mut GpxPoint, field: &str, value: String) -> Result<(), GpxError> {
+    match field {
+        "ele" => {
+            let elevation = value.trim().parse::<f64>().map_err(|_| GpxError::InvalidValue("ele", value.clone()))?;
+            point.elevation = Some(elevation);
+        }
+        "time" => {
+            let time = DateTime::parse_from_rfc3339(value.trim())
+                .map_err(|_| GpxError::InvalidValue("time", value.clone()))?;
+            point.time = Some(time.with_timezone(&Utc));
+        }
+        "name" => point.name = Some(value),
+        "desc" => point.description = Some(value),
+        _ => {}
+    }
+    Ok(())
+}
+
+fn set_name_desc(name: &mut Option<String>, description: &mut Option<String>, field: &str, value: String) {
+    match field {
+        "name" => *name = Some(value),
+        "desc" => *description = Some(value),
+        _ => {}
+    }
+}
+
+fn write_point(xml: &mut String, tag: &str, point: &GpxPoint, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let _ = write!(xml, "{}<{} lat=\"{:.7}\" lon=\"{:.7}\"", indent, tag, point.lat, point.lon);
+
+    if point.elevation.is_none() && point.time.is_none() && point.name.is_none() && point.description.is_none() {
+        xml.push_str("/>\n");
+        return;
+    }
+
+    xml.push_str(">\n");
+    if let Some(elevation) = point.elevation {
+        let _ = writeln!(xml, "{}  <ele>{:.1}</ele>", indent, elevation);
+    }
+    if let Some(time) = point.time {
+        let _ = writeln!(xml, "{}  <time>{}</time>", indent, time.to_rfc3339());
+    }
+    write_name_desc(xml, &point.name, &point.description, depth + 1);
+    let _ = writeln!(xml, "{}</{}>", indent, tag);
+}
+
+fn write_name_desc(xml: &mut String, name: &Option<String>, description: &Option<String>, depth: usize) {
+    let indent = "  ".repeat(depth);
+    if let Some(name) = name {
+        let _ = writeln!(xml, "{}<name>{}</name>", indent, escape(name));
+    }
+    if let Some(description) = description {
+        let _ = writeln!(xml, "{}<desc>{}</desc>", indent, escape(description));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> GpxDocument {
+        let path = format!("{}/tests/fixtures/gpx/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let xml = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        GpxDocument::parse(&xml).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parses_waypoints() {
+        let document = fixture("waypoints.gpx");
+
+        assert_eq!(document.name.as_deref(), Some("Golden Gate Park"));
+        assert_eq!(document.waypoints.len(), 3);
+        assert!(document.routes.is_empty() && document.tracks.is_empty());
+
+        let conservatory = &document.waypoints[0];
+        assert_eq!((conservatory.lat, conservatory.lon), (37.7694, -122.4862));
+        assert_eq!(conservatory.elevation, Some(52.0));
+        assert_eq!(conservatory.time, Some(time("2024-05-04T09:15:00Z")));
+        assert_eq!(conservatory.name.as_deref(), Some("Conservatory of Flowers"));
+        assert_eq!(conservatory.description.as_deref(), Some("Victorian greenhouse & gardens"));
+
+        assert_eq!(document.waypoints[1].name.as_deref(), Some("de Young Museum"));
+        assert_eq!(document.waypoints[2], GpxPoint::new(37.7655, -122.4712));
+    }
+
+    #[test]
+    fn parses_multi_segment_tracks_and_routes() {
+        let document = fixture("multi_segment_track.gpx");
+
+        assert_eq!(document.tracks.len(), 1);
+        let track = &document.tracks[0];
+        assert_eq!(track.name.as_deref(), Some("Morning ride"));
+        assert_eq!(track.description.as_deref(), Some("Paused at the ferry building"));
+        // The trailing empty segment is dropped
+        assert_eq!(track.segments.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 2]);
+        assert_eq!(track.segments[1][1].elevation, Some(2.8));
+        assert_eq!(track.segments[1][1].time, Some(time("2024-05-04T07:24:30Z")));
+
+        assert_eq!(document.routes.len(), 1);
+        assert_eq!(document.routes[0].name.as_deref(), Some("Return"));
+        assert_eq!(document.routes[0].points.len(), 2);
+        assert_eq!(document.point_count(), 7);
+    }
+
+    #[test]
+    fn splits_tracks_into_a_line_per_segment() {
+        let document = fixture("multi_segment_track.gpx");
+        let lines = document.lines();
+
+        let names: Vec<_> = lines.iter().map(|line| line.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("Return"), Some("Morning ride (1/2)"), Some("Morning ride (2/2)")]);
+        assert_eq!(lines.iter().map(|line| line.points.len()).collect::<Vec<_>>(), vec![2, 3, 2]);
+        assert_eq!(lines[1].points, document.tracks[0].segments[0]);
+        assert_eq!(lines[2].points, document.tracks[0].segments[1]);
+        assert_eq!(lines[2].description.as_deref(), Some("Paused at the ferry building"));
+
+        // A single-segment track keeps its own name
+        let document = fixture("namespaced.gpx");
+        let lines = document.lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].name.as_deref(), Some("Lunch run"));
+    }
+
+    #[test]
+    fn keeps_cdata_names_and_descriptions() {
+        let document = fixture("cdata_names.gpx");
+
+        assert_eq!(document.name.as_deref(), Some("Trip <2024>"));
+        let tower = &document.waypoints[0];
+        assert_eq!(tower.name.as_deref(), Some("Tour Eiffel & Champ-de-Mars"));
+        assert_eq!(tower.description.as_deref(), Some("<b>Open</b> 9:30 - 23:45"));
+        // Text and CDATA in one element are joined
+        assert_eq!(document.tracks[0].name.as_deref(), Some("Seine <walk>"));
+    }
+
+    #[test]
+    fn parses_prefixed_elements_and_skips_extensions() {
+        let document = fixture("namespaced.gpx");
+
+        let track = &document.tracks[0];
+        assert_eq!(track.name.as_deref(), Some("Lunch run"));
+        assert_eq!(track.segments.len(), 1);
+        let first = &track.segments[0][0];
+        assert_eq!((first.lat, first.lon, first.elevation), (51.5007, -0.1246, Some(11.0)));
+        assert_eq!(first.name, None);
+        assert_eq!(track.segments[0][1].elevation, Some(9.5));
+    }
+
+    #[test]
+    fn export_round_trips_through_import() {
+        for name in ["waypoints.gpx", "multi_segment_track.gpx", "cdata_names.gpx", "namespaced.gpx"] {
+            let document = fixture(name);
+            let reparsed = GpxDocument::parse(&document.to_xml()).unwrap();
+            assert_eq!(reparsed, document, "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        assert!(matches!(GpxDocument::parse("<kml/>"), Err(GpxError::NotGpx)));
+        assert!(matches!(
+            GpxDocument::parse(r#"<gpx version="2.0"><wpt lat="1" lon="2"/></gpx>"#),
+            Err(GpxError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(GpxDocument::parse(r#"<gpx version="1.1"></gpx>"#), Err(GpxError::Empty)));
+        assert!(matches!(
+            GpxDocument::parse(r#"<gpx><wpt lat="91" lon="0"/></gpx>"#),
+            Err(GpxError::InvalidValue("lat", _))
+        ));
+        assert!(matches!(
+            GpxDocument::parse(r#"<gpx><wpt lon="0"/></gpx>"#),
+            Err(GpxError::MissingAttribute(_, "lat"))
+        ));
+        assert!(matches!(
+            GpxDocument::parse(r#"<gpx><wpt lat="1" lon="2"><ele>high</ele></wpt></gpx>"#),
+            Err(GpxError::InvalidValue("ele", _))
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+//! KML 2.2 export
+//!
+//! Writes the same waypoint/route/track model used for GPX so that every export
+//! endpoint can offer both formats from one conversion.
+
+use std::fmt::Write;
+
+use quick_xml::escape::escape;
+
+use super::gpx::{GpxDocument, GpxPoint};
+
+pub const KML_NAMESPACE: &str = "http://www.opengis.net/kml/2.2";
+
+pub fn to_kml(document: &GpxDocument) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(xml, "<kml xmlns=\"{}\">", KML_NAMESPACE);
+    xml.push_str("  <Document>\n");
+
+    if let Some(name) = &document.name {
+        let _ = writeln!(xml, "    <name>{}</name>", escape(name));
+    }
+
+    for waypoint in &document.waypoints {
+        xml.push_str("    <Placemark>\n");
+        write_name_desc(&mut xml, &waypoint.name, &waypoint.description);
+        let _ = writeln!(
+            xml,
+            "      <Point><coordinates>{}</coordinates></Point>",
+            coordinate(waypoint)
+        );
+        xml.push_str("    </Placemark>\n");
+    }
+
+    for route in &document.routes {
+        xml.push_str("    <Placemark>\n");
+        write_name_desc(&mut xml, &route.name, &route.description);
+        write_line_string(&mut xml, &route.points, "      ");
+        xml.push_str("    </Placemark>\n");
+    }
+
+    for track in &document.tracks {
+        xml.push_str("    <Placemark>\n");
+        write_name_desc(&mut xml, &track.name, &track.description);
+        if track.segments.len() == 1 {
+            write_line_string(&mut xml, &track.segments[0], "      ");
+        } else {
+            xml.push_str("      <MultiGeometry>\n");
+            for segment in &track.segments {
+                write_line_string(&mut xml, segment, "        ");
+            }
+            xml.push_str("      </MultiGeometry>\n");
+        }
+        xml.push_str("    </Placemark>\n");
+    }
+
+    xml.push_str("  </Document>\n");
+    xml.push_str("</kml>\n");
+    xml
+}
+
+fn coordinate(point: &GpxPoint) -> String {
+    match point.elevation {
+        Some(elevation) => format!("{:.7},{:.7},{:.1}", point.lon, point.lat, elevation),
+        None => format!("{:.7},{:.7}", point.lon, point.lat),
+    }
+}
+
+fn write_line_string(xml: &mut String, points: &[GpxPoint], indent: &str) {
+    let coordinates: Vec<String> = points.iter().map(coordinate).collect();
+    let _ = writeln!(
+        xml,
+        "{}<LineString><tessellate>1</tessellate><coordinates>{}</coordinates></LineString>",
+        indent,
+        coordinates.join(" ")
+    );
+}
+
+fn write_name_desc(xml: &mut String, name: &Option<String>, description: &Option<String>) {
+    if let Some(name) = name {
+        let _ = writeln!(xml, "      <name>{}</name>", escape(name));
+    }
+    if let Some(description) = description {
+        let _ = writeln!(xml, "      <description>{}</description>", escape(description));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quick_xml::events::Event;
+    use quick_xml::Reader;
+
+    use super::*;
+
+    fn fixture(path: &str) -> String {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), path);
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    fn gpx_fixture(name: &str) -> GpxDocument {
+        GpxDocument::parse(&fixture(&format!("gpx/{}.gpx", name))).unwrap()
+    }
+
+    /// Placemark names and coordinate tuples, in document order
+    fn read_placemarks(kml: &str) -> Vec<(Option<String>, Vec<Vec<f64>>)> {
+        let mut reader = Reader::from_str(kml);
+        reader.trim_text(true);
+        let mut placemarks = Vec::new();
+        let mut element = String::new();
+
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(e) => {
+                    element = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                    if element == "Placemark" {
+                        placemarks.push((None, Vec::new()));
+                    }
+                }
+                Event::Text(text) => {
+                    let Some((name, coordinates)) = placemarks.last_mut() else {
+                        continue;
+                    };
+                    let text = text.unescape().unwrap();
+                    match element.as_str() {
+                        "name" => *name = Some(text.into_owned()),
+                        "coordinates" => coordinates.extend(
+                            text.split_whitespace()
+                                .map(|tuple| tuple.split(',').map(|v| v.parse().unwrap()).collect()),
+                        ),
+                        _ => {}
+                    }
+                }
+                Event::End(_) => element.clear(),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        placemarks
+    }
+
+    fn tuple(point: &GpxPoint) -> Vec<f64> {
+        let mut tuple = vec![point.lon, point.lat];
+        tuple.extend(point.elevation);
+        tuple
+    }
+
+    #[test]
+    fn matches_golden_files() {
+        for name in ["waypoints", "multi_segment_track", "cdata_names"] {
+            let expected = fixture(&format!("kml/{}.kml", name));
+            assert_eq!(to_kml(&gpx_fixture(name)), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn writes_tracks_with_several_segments_as_multi_geometry() {
+        let kml = to_kml(&gpx_fixture("multi_segment_track"));
+        assert_eq!(kml.matches("<MultiGeometry>").count(), 1);
+        assert_eq!(kml.matches("<LineString>").count(), 3);
+
+        let single = to_kml(&gpx_fixture("cdata_names"));
+        assert!(!single.contains("<MultiGeometry>"));
+    }
+
+    #[test]
+    fn keeps_names_and_coordinates() {
+        for name in ["waypoints", "multi_segment_track", "cdata_names", "namespaced"] {
+            let document = gpx_fixture(name);
+
+            let expected: Vec<_> = document
+                .waypoints
+                .iter()
+                .map(|waypoint| (waypoint.name.clone(), vec![tuple(waypoint)]))
+                .chain(
+                    document
+                        .routes
+                        .iter()
+                        .map(|route| (route.name.clone(), route.points.iter().map(tuple).collect())),
+                )
+                .chain(
+                    document
+                        .tracks
+                        .iter()
+                        .map(|track| (track.name.clone(), track.points().map(tuple).collect())),
+                )
+                .collect();
+
+            assert_eq!(read_placemarks(&to_kml(&document)), expected, "{}", name);
+        }
+    }
+}
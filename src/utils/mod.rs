@@ -6,7 +6,14 @@
 pub mod coordinates;
 pub mod distance;
 pub mod validation;
+pub mod cluster;
+pub mod dem;
 pub mod geohash;
+pub mod gpx;
+pub mod kml;
+pub mod polyline;
+pub mod tile_matrix;
+pub mod tile_utils;
 pub mod cache;
 pub mod config;
 pub mod error;
@@ -0,0 +1,146 @@
+//! Encoded polyline format
+//!
+//! Implements the Google encoded polyline algorithm. Precision 5 is the format
+//! used by Google and most web clients; precision 6 is used by OSRM and Valhalla
+//! for sub-meter accuracy.
+
+use thiserror::Error;
+
+pub const PRECISION_5: u32 = 5;
+pub const PRECISION_6: u32 = 6;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PolylineError {
+    #[error("Invalid polyline character at position {0}")]
+    InvalidCharacter(usize),
+    #[error("Polyline ended in the middle of a value")]
+    Truncated,
+    #[error("Decoded coordinate out of range: ({0}, {1})")]
+    OutOfRange(f64, f64),
+}
+
+/// Encode `(lat, lng)` pairs as a polyline string
+pub fn encode(coordinates: &[(f64, f64)], precision: u32) -> String {
+    let factor = 10_f64.powi(precision as i32);
+    let mut encoded = String::with_capacity(coordinates.len() * 8);
+    let (mut prev_lat, mut prev_lng) = (0i64, 0i64);
+
+    for &(lat, lng) in coordinates {
+        let lat = (lat * factor).round() as i64;
+        let lng = (lng * factor).round() as i64;
+        encode_value(lat - prev_lat, &mut encoded);
+        encode_value(lng - prev_lng, &mut encoded);
+        prev_lat = lat;
+        prev_lng = lng;
+    }
+
+    encoded
+}
+
+/// Decode a polyline string into `(lat, lng)` pairs
+pub fn decode(encoded: &str, precision: u32) -> Result<Vec<(f64, f64)>, PolylineError> {
+    let factor = 10_f64.powi(precision as i32);
+    let bytes = encoded.as_bytes();
+    let mut coordinates = Vec::new();
+    let mut index = 0;
+    let (mut lat, mut lng) = (0i64, 0i64);
+
+    while index < bytes.len() {
+        lat += decode_value(bytes, &mut index)?;
+        lng += decode_value(bytes, &mut index)?;
+
+        let coordinate = (lat as f64 / factor, lng as f64 / factor);
+        if !(-90.0..=90.0).contains(&coordinate.0) || !(-180.0..=180.0).contains(&coordinate.1) {
+            return Err(PolylineError::OutOfRange(coordinate.0, coordinate.1));
+        }
+        coordinates.push(coordinate);
+    }
+
+    Ok(coordinates)
+}
+
+fn encode_value(value: i64, output: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 };
+    while value >= 0x20 {
+        output.push((((value & 0x1f) | 0x20) as u8 + 63) as char);
+        value >>= 5;
+    }
+    output.push((value as u8 + 63) as char);
+}
+
+fn decode_value(bytes: &[u8], index: &mut usize) -> Result<i64, PolylineError> {
+    let mut result = 0i64;
+    let mut shift = 0;
+
+    loop {
+        let byte = *bytes.get(*index).ok_or(PolylineError::Truncated)?;
+        if !(63..=126).contains(&byte) || shift > 60 {
+            return Err(PolylineError::InvalidCharacter(*index));
+        }
+        *index += 1;
+
+        let chunk = (byte - 63) as i64;
+        result |= (chunk & 0x1f) << shift;
+        shift += 5;
+
+        if chunk < 0x20 {
+            break;
+        }
+    }
+
+    Ok(if result & 1 != 0 { !(result >> 1) } else { result >> 1 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example from Google's polyline algorithm documentation
+    const GOOGLE_EXAMPLE: &str = "_p~iF~ps|U_ulLnnqC_mqNvxq`@";
+    const GOOGLE_POINTS: [(f64, f64); 3] = [(38.5, -120.2), (40.7, -120.95), (43.252, -126.453)];
+
+    #[test]
+    fn encodes_google_example() {
+        assert_eq!(encode(&GOOGLE_POINTS, PRECISION_5), GOOGLE_EXAMPLE);
+    }
+
+    #[test]
+    fn decodes_google_example() {
+        let decoded = decode(GOOGLE_EXAMPLE, PRECISION_5).unwrap();
+        assert_eq!(decoded.len(), GOOGLE_POINTS.len());
+        for (decoded, expected) in decoded.iter().zip(GOOGLE_POINTS) {
+            assert!((decoded.0 - expected.0).abs() < 1e-9);
+            assert!((decoded.1 - expected.1).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn round_trips_at_both_precisions() {
+        let path = [(52.520008, 13.404954), (-33.868820, 151.209296), (0.0, 0.0), (-89.999999, 179.999999)];
+        for precision in [PRECISION_5, PRECISION_6] {
+            let tolerance = 0.5 / 10_f64.powi(precision as i32) + 1e-12;
+            let decoded = decode(&encode(&path, precision), precision).unwrap();
+            for (decoded, expected) in decoded.iter().zip(path) {
+                assert!((decoded.0 - expected.0).abs() <= tolerance);
+                assert!((decoded.1 - expected.1).abs() <= tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_characters_outside_the_alphabet() {
+        assert_eq!(decode("_p~iF\x7f", PRECISION_5), Err(PolylineError::InvalidCharacter(5)));
+        assert_eq!(decode("_p~iF ps|U", PRECISION_5), Err(PolylineError::InvalidCharacter(5)));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        assert_eq!(decode("_p~iF~ps|", PRECISION_5), Err(PolylineError::Truncated));
+    }
+
+    #[test]
+    fn rejects_out_of_range_coordinates() {
+        let encoded = encode(&[(95.0, 0.0)], PRECISION_5);
+        assert!(matches!(decode(&encoded, PRECISION_5), Err(PolylineError::OutOfRange(..))));
+    }
+}
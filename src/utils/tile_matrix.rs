@@ -0,0 +1,562 @@
+//! Tile matrix sets: how a CRS is cut into tiles at each zoom level, following
+//! the OGC Two Dimensional Tile Matrix Set standard used by WMTS.
+//!
+//! `tile_utils` assumes Web Mercator throughout; the `*_in` conversions here
+//! take the tile matrix set explicitly.
+
+use std::f64::consts::PI;
+use std::sync::Mutex;
+
+use proj::Proj;
+use serde::Serialize;
+use thiserror::Error;
+
+use googlemaps_clone::constants::{MAX_ZOOM, TILE_SIZE};
+use crate::utils::tile_utils::{LatLng, PixelCoord, TileCoord};
+
+/// WGS84 semi-major axis, the sphere radius Web Mercator uses
+const EARTH_RADIUS: f64 = 6_378_137.0;
+/// Half the width of the Web Mercator plane in metres
+const WEB_MERCATOR_EXTENT: f64 = PI * EARTH_RADIUS;
+/// Metres per degree at the equator, for scale denominators of degree CRSs
+const METRES_PER_DEGREE: f64 = 2.0 * PI * EARTH_RADIUS / 360.0;
+/// Pixel size the OGC standards define scale denominators against
+const STANDARDIZED_PIXEL_SIZE: f64 = 0.00028;
+
+#[derive(Debug, Error)]
+pub enum ProjectionError {
+    #[error("Failed to set up projection to {0}: {1}")]
+    Create(String, String),
+    #[error("Failed to transform coordinates: {0}")]
+    Transform(String),
+    #[error("Tile matrix set {0} has no zoom level {1}")]
+    UnknownZoom(String, u8),
+    #[error("Position is outside tile matrix set {0}")]
+    OutOfBounds(String),
+}
+
+/// An axis-aligned box in CRS units
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Extent {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+/// One zoom level of a tile matrix set
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileMatrix {
+    pub zoom: u8,
+    /// CRS units per pixel
+    pub resolution: f64,
+    pub scale_denominator: f64,
+    /// Top-left corner of tile (0, 0) in CRS units
+    pub top_left: (f64, f64),
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub matrix_width: u32,
+    pub matrix_height: u32,
+}
+
+enum Projection {
+    WebMercator,
+    PlateCarree,
+    /// Forward and inverse transformations between WGS84 and the CRS. `Proj`
+    /// is not `Sync`, so each is behind a lock.
+    Proj { forward: Mutex<Proj>, inverse: Mutex<Proj> },
+}
+
+pub struct TileMatrixSet {
+    pub identifier: String,
+    /// CRS code such as `EPSG:3857`
+    pub crs: String,
+    pub extent: Extent,
+    /// OGC well-known scale set the matrices follow, if any
+    pub well_known_scale_set: Option<String>,
+    pub matrices: Vec<TileMatrix>,
+    projection: Projection,
+}
+
+impl std::fmt::Debug for TileMatrixSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TileMatrixSet")
+            .field("identifier", &self.identifier)
+            .field("crs", &self.crs)
+            .field("extent", &self.extent)
+            .field("matrices", &self.matrices.len())
+            .finish()
+    }
+}
+
+impl TileMatrixSet {
+    /// EPSG:3857, one tile at zoom 0. Matches `tile_utils`.
+    pub fn web_mercator() -> Self {
+        let matrices = (0..=MAX_ZOOM)
+            .map(|zoom| {
+                let tiles = 1u32 << zoom;
+                let resolution = 2.0 * WEB_MERCATOR_EXTENT / (TILE_SIZE as f64 * tiles as f64);
+                TileMatrix {
+                    zoom,
+                    resolution,
+                    scale_denominator: resolution / STANDARDIZED_PIXEL_SIZE,
+                    top_left: (-WEB_MERCATOR_EXTENT, WEB_MERCATOR_EXTENT),
+                    tile_width: TILE_SIZE,
+                    tile_height: TILE_SIZE,
+                    matrix_width: tiles,
+                    matrix_height: tiles,
+                }
+            })
+            .collect();
+
+        Self {
+            identifier: "WebMercatorQuad".to_string(),
+            crs: "EPSG:3857".to_string(),
+            extent: Extent {
+                min_x: -WEB_MERCATOR_EXTENT,
+                min_y: -WEB_MERCATOR_EXTENT,
+                max_x: WEB_MERCATOR_EXTENT,
+                max_y: WEB_MERCATOR_EXTENT,
+            },
+            well_known_scale_set: Some("http://www.opengis.net/def/wkss/OGC/1.0/GoogleMapsCompatible".to_string()),
+            matrices,
+            projection: Projection::WebMercator,
+        }
+    }
+
+    /// Plate carrée in longitude/latitude (EPSG:4326 with CRS84 axis order),
+    /// two tiles side by side at zoom 0
+    pub fn world_crs84() -> Self {
+        let matrices = (0..=MAX_ZOOM)
+            .map(|zoom| {
+                let rows = 1u32 << zoom;
+                let resolution = 180.0 / (TILE_SIZE as f64 * rows as f64);
+                TileMatrix {
+                    zoom,
+                    resolution,
+                    scale_denominator: resolution * METRES_PER_DEGREE / STANDARDIZED_PIXEL_SIZE,
+                    top_left: (-180.0, 90.0),
+                    tile_width: TILE_SIZE,
+                    tile_height: TILE_SIZE,
+                    matrix_width: rows * 2,
+                    matrix_height: rows,
+                }
+            })
+            .collect();
+
+        Self {
+            identifier: "WorldCRS84Quad".to_string(),
+            crs: "EPSG:4326".to_string(),
+            extent: Extent {
+                min_x: -180.0,
+                min_y: -90.0,
+                max_x: 180.0,
+                max_y: 90.0,
+            },
+            well_known_scale_set: Some("http://www.opengis.net/def/wkss/OGC/1.0/GoogleCRS84Quad".to_string()),
+            matrices,
+            projection: Projection::PlateCarree,
+        }
+    }
+
+    /// A tile matrix set in any projected CRS proj knows, such as a national
+    /// grid. Zoom `z` uses `resolutions[z]` (CRS units per pixel) with its
+    /// top-left corner at `origin`; the CRS is assumed to be in metres.
+    pub fn custom(
+        identifier: &str,
+        crs: &str,
+        extent: Extent,
+        origin: (f64, f64),
+        resolutions: &[f64],
+    ) -> Result<Self, ProjectionError> {
+        let forward = Proj::new_known_crs("EPSG:4326", crs, None)
+            .map_err(|e| ProjectionError::Create(crs.to_string(), e.to_string()))?;
+        let inverse = Proj::new_known_crs(crs, "EPSG:4326", None)
+            .map_err(|e| ProjectionError::Create(crs.to_string(), e.to_string()))?;
+
+        let span = TILE_SIZE as f64;
+        let matrices = resolutions
+            .iter()
+            .enumerate()
+            .map(|(zoom, &resolution)| TileMatrix {
+                zoom: zoom as u8,
+                resolution,
+                scale_denominator: resolution / STANDARDIZED_PIXEL_SIZE,
+                top_left: origin,
+                tile_width: TILE_SIZE,
+                tile_height: TILE_SIZE,
+                matrix_width: ((extent.max_x - origin.0) / (resolution * span)).ceil().max(1.0) as u32,
+                matrix_height: ((origin.1 - extent.min_y) / (resolution * span)).ceil().max(1.0) as u32,
+            })
+            .collect();
+
+        Ok(Self {
+            identifier: identifier.to_string(),
+            crs: crs.to_string(),
+            extent,
+            well_known_scale_set: None,
+            matrices,
+            projection: Projection::Proj {
+                forward: Mutex::new(forward),
+                inverse: Mutex::new(inverse),
+            },
+        })
+    }
+
+    /// The Ordnance Survey's tiling of the British National Grid (EPSG:27700)
+    pub fn british_national_grid() -> Result<Self, ProjectionError> {
+        let resolutions: Vec<f64> = (0..14).map(|zoom| 896.0 / 2f64.powi(zoom)).collect();
+        Self::custom(
+            "OSGB36BNG",
+            "EPSG:27700",
+            Extent {
+                min_x: -238_375.0,
+                min_y: 0.0,
+                max_x: 900_000.0,
+                max_y: 1_376_256.0,
+            },
+            (-238_375.0, 1_376_256.0),
+            &resolutions,
+        )
+    }
+
+    /// The built-in set with this identifier or CRS code
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "WebMercatorQuad" | "EPSG:3857" => Some(Self::web_mercator()),
+            "WorldCRS84Quad" | "EPSG:4326" | "CRS84" => Some(Self::world_crs84()),
+            "OSGB36BNG" | "EPSG:27700" => Self::british_national_grid().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn matrix(&self, zoom: u8) -> Result<&TileMatrix, ProjectionError> {
+        self.matrices
+            .get(zoom as usize)
+            .ok_or_else(|| ProjectionError::UnknownZoom(self.identifier.clone(), zoom))
+    }
+
+    pub fn max_zoom(&self) -> u8 {
+        self.matrices.len().saturating_sub(1) as u8
+    }
+
+    /// WGS84 position to CRS coordinates
+    pub fn project(&self, position: &LatLng) -> Result<(f64, f64), ProjectionError> {
+        match &self.projection {
+            Projection::WebMercator => {
+                let lat = position.lat.clamp(-85.051_128_78, 85.051_128_78).to_radians();
+                Ok((
+                    EARTH_RADIUS * position.lng.to_radians(),
+                    EARTH_RADIUS * (PI / 4.0 + lat / 2.0).tan().ln(),
+                ))
+            }
+            Projection::PlateCarree => Ok((position.lng, position.lat)),
+            Projection::Proj { forward, .. } => lock(forward)
+                .convert((position.lng, position.lat))
+                .map_err(|e| ProjectionError::Transform(e.to_string())),
+        }
+    }
+
+    /// CRS coordinates to a WGS84 position
+    pub fn unproject(&self, (x, y): (f64, f64)) -> Result<LatLng, ProjectionError> {
+        match &self.projection {
+            Projection::WebMercator => Ok(LatLng::new(
+                (2.0 * (y / EARTH_RADIUS).exp().atan() - PI / 2.0).to_degrees(),
+                (x / EARTH_RADIUS).to_degrees(),
+            )),
+            Projection::PlateCarree => Ok(LatLng::new(y, x)),
+            Projection::Proj { inverse, .. } => {
+                let (lng, lat) = lock(inverse)
+                    .convert((x, y))
+                    .map_err(|e| ProjectionError::Transform(e.to_string()))?;
+                Ok(LatLng::new(lat, lng))
+            }
+        }
+    }
+
+    /// Pixel position of `position` within the whole matrix at `zoom`
+    pub fn to_pixel_coord(&self, position: &LatLng, zoom: u8) -> Result<PixelCoord, ProjectionError> {
+        let matrix = self.matrix(zoom)?;
+        let (x, y) = self.project(position)?;
+        Ok(PixelCoord {
+            x: (x - matrix.top_left.0) / matrix.resolution,
+            y: (matrix.top_left.1 - y) / matrix.resolution,
+        })
+    }
+
+    pub fn pixel_to_lat_lng(&self, pixel: &PixelCoord, zoom: u8) -> Result<LatLng, ProjectionError> {
+        let matrix = self.matrix(zoom)?;
+        self.unproject((
+            matrix.top_left.0 + pixel.x * matrix.resolution,
+            matrix.top_left.1 - pixel.y * matrix.resolution,
+        ))
+    }
+
+    pub fn to_tile_coord(&self, position: &LatLng, zoom: u8) -> Result<TileCoord, ProjectionError> {
+        let matrix = self.matrix(zoom)?;
+        let pixel = self.to_pixel_coord(position, zoom)?;
+        let (width, height) = (matrix.tile_width as f64, matrix.tile_height as f64);
+        let column = (pixel.x / width).floor();
+        let row = (pixel.y / height).floor();
+
+        // A point exactly on the far edge belongs to the last tile
+        let column = if pixel.x == matrix.matrix_width as f64 * width { column - 1.0 } else { column };
+        let row = if pixel.y == matrix.matrix_height as f64 * height { row - 1.0 } else { row };
+        if column < 0.0 || row < 0.0 || column >= matrix.matrix_width as f64 || row >= matrix.matrix_height as f64 {
+            return Err(ProjectionError::OutOfBounds(self.identifier.clone()));
+        }
+
+        Ok(TileCoord::new(column as u32, row as u32, zoom))
+    }
+
+    /// The tile's extent in CRS units
+    pub fn tile_extent(&self, coord: &TileCoord) -> Result<Extent, ProjectionError> {
+        let matrix = self.matrix(coord.z)?;
+        let width = matrix.tile_width as f64 * matrix.resolution;
+        let height = matrix.tile_height as f64 * matrix.resolution;
+        let min_x = matrix.top_left.0 + coord.x as f64 * width;
+        let max_y = matrix.top_left.1 - coord.y as f64 * height;
+
+        Ok(Extent {
+            min_x,
+            min_y: max_y - height,
+            max_x: min_x + width,
+            max_y,
+        })
+    }
+
+    /// South-west and north-east corners of the WGS84 box enclosing the tile
+    pub fn tile_bounds(&self, coord: &TileCoord) -> Result<(LatLng, LatLng), ProjectionError> {
+        let extent = self.tile_extent(coord)?;
+        let corners = [
+            self.unproject((extent.min_x, extent.min_y))?,
+            self.unproject((extent.min_x, extent.max_y))?,
+            self.unproject((extent.max_x, extent.min_y))?,
+            self.unproject((extent.max_x, extent.max_y))?,
+        ];
+
+        let south = corners.iter().map(|c| c.lat).fold(f64::INFINITY, f64::min);
+        let north = corners.iter().map(|c| c.lat).fold(f64::NEG_INFINITY, f64::max);
+        let west = corners.iter().map(|c| c.lng).fold(f64::INFINITY, f64::min);
+        let east = corners.iter().map(|c| c.lng).fold(f64::NEG_INFINITY, f64::max);
+        Ok((LatLng::new(south, west), LatLng::new(north, east)))
+    }
+
+    /// Description in the OGC Tile Matrix Set JSON encoding
+    pub fn metadata(&self) -> TileMatrixSetMetadata {
+        TileMatrixSetMetadata {
+            id: self.identifier.clone(),
+            crs: crs_uri(&self.crs),
+            well_known_scale_set: self.well_known_scale_set.clone(),
+            ordered_axes: if matches!(self.projection, Projection::PlateCarree) {
+                ["Lon", "Lat"]
+            } else {
+                ["E", "N"]
+            },
+            tile_matrices: self
+                .matrices
+                .iter()
+                .map(|matrix| TileMatrixMetadata {
+                    id: matrix.zoom.to_string(),
+                    scale_denominator: matrix.scale_denominator,
+                    cell_size: matrix.resolution,
+                    corner_of_origin: "topLeft",
+                    point_of_origin: [matrix.top_left.0, matrix.top_left.1],
+                    tile_width: matrix.tile_width,
+                    tile_height: matrix.tile_height,
+                    matrix_width: matrix.matrix_width,
+                    matrix_height: matrix.matrix_height,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn lock(proj: &Mutex<Proj>) -> std::sync::MutexGuard<'_, Proj> {
+    proj.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// `EPSG:3857` as an OGC CRS URI. EPSG:4326 tiles use longitude/latitude
+/// order, which is the CRS84 definition.
+pub fn crs_uri(crs: &str) -> String {
+    match crs.split_once(':') {
+        Some(("EPSG", "4326")) | Some(("OGC", "CRS84")) => "http://www.opengis.net/def/crs/OGC/1.3/CRS84".to_string(),
+        Some(("EPSG", code)) => format!("http://www.opengis.net/def/crs/EPSG/0/{}", code),
+        _ => crs.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TileMatrixSetMetadata {
+    pub id: String,
+    pub crs: String,
+    #[serde(rename = "wellKnownScaleSet", skip_serializing_if = "Option::is_none")]
+    pub well_known_scale_set: Option<String>,
+    pub ordered_axes: [&'static str; 2],
+    pub tile_matrices: Vec<TileMatrixMetadata>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TileMatrixMetadata {
+    pub id: String,
+    pub scale_denominator: f64,
+    pub cell_size: f64,
+    pub corner_of_origin: &'static str,
+    pub point_of_origin: [f64; 2],
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub matrix_width: u32,
+    pub matrix_height: u32,
+}
+
+impl LatLng {
+    pub fn to_tile_coord_in(&self, tile_matrix_set: &TileMatrixSet, zoom: u8) -> Result<TileCoord, ProjectionError> {
+        tile_matrix_set.to_tile_coord(self, zoom)
+    }
+
+    pub fn to_pixel_coord_in(&self, tile_matrix_set: &TileMatrixSet, zoom: u8) -> Result<PixelCoord, ProjectionError> {
+        tile_matrix_set.to_pixel_coord(self, zoom)
+    }
+}
+
+impl TileCoord {
+    pub fn to_bounds_in(&self, tile_matrix_set: &TileMatrixSet) -> Result<(LatLng, LatLng), ProjectionError> {
+        tile_matrix_set.tile_bounds(self)
+    }
+}
+
+impl PixelCoord {
+    pub fn to_lat_lng_in(&self, tile_matrix_set: &TileMatrixSet, zoom: u8) -> Result<LatLng, ProjectionError> {
+        tile_matrix_set.pixel_to_lat_lng(self, zoom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{actual} is not within {tolerance} of {expected}");
+    }
+
+    const SAN_FRANCISCO: LatLng = LatLng { lat: 37.7749, lng: -122.4194 };
+
+    #[test]
+    fn web_mercator_matches_tile_utils() {
+        let set = TileMatrixSet::web_mercator();
+        for zoom in [0, 5, 12, MAX_ZOOM] {
+            assert_eq!(SAN_FRANCISCO.to_tile_coord_in(&set, zoom).unwrap(), SAN_FRANCISCO.to_tile_coord(zoom));
+
+            let pixel = SAN_FRANCISCO.to_pixel_coord_in(&set, zoom).unwrap();
+            let expected = SAN_FRANCISCO.to_pixel_coord(zoom);
+            assert_close(pixel.x, expected.x, 1e-6);
+            assert_close(pixel.y, expected.y, 1e-6);
+        }
+    }
+
+    #[test]
+    fn web_mercator_matrices_halve_resolution_per_zoom() {
+        let set = TileMatrixSet::web_mercator();
+        assert_eq!(set.max_zoom(), MAX_ZOOM);
+
+        let zero = set.matrix(0).unwrap();
+        assert_eq!((zero.matrix_width, zero.matrix_height), (1, 1));
+        assert_close(zero.resolution, 156_543.033_928, 1e-6);
+        assert_close(zero.scale_denominator, 559_082_264.028_7, 1e-3);
+        for pair in set.matrices.windows(2) {
+            assert_close(pair[0].resolution / pair[1].resolution, 2.0, 1e-12);
+            assert_eq!(pair[1].matrix_width, pair[0].matrix_width * 2);
+        }
+        assert!(matches!(set.matrix(MAX_ZOOM + 1), Err(ProjectionError::UnknownZoom(_, _))));
+    }
+
+    #[test]
+    fn crs84_has_two_tiles_at_zoom_zero() {
+        let set = TileMatrixSet::world_crs84();
+        let zero = set.matrix(0).unwrap();
+        assert_eq!((zero.matrix_width, zero.matrix_height), (2, 1));
+        assert_close(zero.scale_denominator, 279_541_132.014_4, 1e-3);
+
+        assert_eq!(LatLng::new(10.0, -10.0).to_tile_coord_in(&set, 0).unwrap(), TileCoord::new(0, 0, 0));
+        assert_eq!(LatLng::new(10.0, 10.0).to_tile_coord_in(&set, 0).unwrap(), TileCoord::new(1, 0, 0));
+        assert_eq!(LatLng::new(-45.0, 45.0).to_tile_coord_in(&set, 1).unwrap(), TileCoord::new(2, 1, 1));
+    }
+
+    #[test]
+    fn far_edges_belong_to_the_last_tile() {
+        let set = TileMatrixSet::world_crs84();
+        assert_eq!(LatLng::new(-90.0, 180.0).to_tile_coord_in(&set, 2).unwrap(), TileCoord::new(7, 3, 2));
+        assert!(matches!(
+            LatLng::new(0.0, 180.5).to_tile_coord_in(&set, 2),
+            Err(ProjectionError::OutOfBounds(_))
+        ));
+    }
+
+    #[test]
+    fn pixels_round_trip_through_positions() {
+        for set in [TileMatrixSet::web_mercator(), TileMatrixSet::world_crs84()] {
+            let pixel = SAN_FRANCISCO.to_pixel_coord_in(&set, 14).unwrap();
+            let position = pixel.to_lat_lng_in(&set, 14).unwrap();
+            assert_close(position.lat, SAN_FRANCISCO.lat, 1e-9);
+            assert_close(position.lng, SAN_FRANCISCO.lng, 1e-9);
+        }
+    }
+
+    #[test]
+    fn tile_extents_tile_the_plane() {
+        let set = TileMatrixSet::web_mercator();
+        let extent = set.tile_extent(&TileCoord::new(1, 0, 1)).unwrap();
+        assert_eq!(
+            extent,
+            Extent {
+                min_x: 0.0,
+                min_y: 0.0,
+                max_x: WEB_MERCATOR_EXTENT,
+                max_y: WEB_MERCATOR_EXTENT,
+            }
+        );
+
+        let (south_west, north_east) = TileCoord::new(1, 0, 1).to_bounds_in(&set).unwrap();
+        assert_close(south_west.lat, 0.0, 1e-9);
+        assert_close(south_west.lng, 0.0, 1e-9);
+        assert_close(north_east.lat, 85.051_128_78, 1e-6);
+        assert_close(north_east.lng, 180.0, 1e-9);
+    }
+
+    #[test]
+    fn british_national_grid_follows_the_os_tiling() {
+        let set = TileMatrixSet::builtin("EPSG:27700").unwrap();
+        assert_eq!(set.identifier, "OSGB36BNG");
+        assert_eq!(set.max_zoom(), 13);
+
+        let zero = set.matrix(0).unwrap();
+        assert_eq!((zero.matrix_width, zero.matrix_height), (5, 6));
+        assert_eq!(set.matrix(13).unwrap().resolution, 896.0 / 8192.0);
+
+        // Nelson's Column, grid reference TQ 30009 80438
+        let nelsons_column = LatLng::new(51.507_74, -0.128_00);
+        let (easting, northing) = set.project(&nelsons_column).unwrap();
+        assert_close(easting, 530_009.0, 100.0);
+        assert_close(northing, 180_438.0, 100.0);
+        assert_eq!(nelsons_column.to_tile_coord_in(&set, 0).unwrap(), TileCoord::new(3, 5, 0));
+
+        let position = set.unproject((easting, northing)).unwrap();
+        assert_close(position.lat, nelsons_column.lat, 1e-6);
+        assert_close(position.lng, nelsons_column.lng, 1e-6);
+    }
+
+    #[test]
+    fn describes_sets_in_the_ogc_json_encoding() {
+        let metadata = serde_json::to_value(TileMatrixSet::world_crs84().metadata()).unwrap();
+        assert_eq!(metadata["id"], "WorldCRS84Quad");
+        assert_eq!(metadata["crs"], "http://www.opengis.net/def/crs/OGC/1.3/CRS84");
+        assert_eq!(metadata["orderedAxes"], serde_json::json!(["Lon", "Lat"]));
+        assert_eq!(metadata["tileMatrices"][0]["pointOfOrigin"], serde_json::json!([-180.0, 90.0]));
+        assert_eq!(metadata["tileMatrices"][0]["matrixWidth"], 2);
+
+        assert_eq!(crs_uri("EPSG:27700"), "http://www.opengis.net/def/crs/EPSG/0/27700");
+        assert_eq!(crs_uri("urn:custom"), "urn:custom");
+    }
+}
@@ -7,7 +7,7 @@ pub struct LatLng {
     pub lng: f64,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TileCoord {
     pub x: u32,
     pub y: u32,
@@ -66,6 +66,17 @@ impl LatLng {
     }
 }
 
+impl PixelCoord {
+    /// Inverse of `LatLng::to_pixel_coord`
+    pub fn to_lat_lng(&self, zoom: u8) -> LatLng {
+        let size = 2_f64.powi(zoom as i32) * 256.0;
+        let lng = self.x / size * 360.0 - 180.0;
+        let lat_rad = ((PI * (1.0 - 2.0 * self.y / size)).sinh()).atan();
+
+        LatLng::new(lat_rad.to_degrees(), lng)
+    }
+}
+
 impl TileCoord {
     pub fn new(x: u32, y: u32, z: u8) -> Self {
         Self { x, y, z }